@@ -1,9 +1,17 @@
+use clap::{CommandFactory, FromArgMatches, parser::ValueSource};
+
 #[derive(clap::ValueEnum, Clone, Debug, Copy)]
 pub enum CargoEnv {
     Development,
     Production,
 }
 
+#[derive(clap::ValueEnum, Clone, Debug, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
 #[derive(clap::Parser)]
 pub struct AppConfig {
     // production or development
@@ -33,6 +41,23 @@ pub struct AppConfig {
     #[clap(long, env)]
     pub access_token_secret: String,
 
+    // comma seperated list of old secrets that signatures are still accepted for. new urls are
+    // always signed with access_token_secret, drop these once the old urls have expired
+    #[clap(long, env, default_value = "")]
+    pub access_token_fallback_secrets: String,
+
+    // only accept a signed url from the client it was signed for, the client id is worked out
+    // again from the request and has to match the `client` param, unsigned proxy requests are
+    // turned away too. leave it off when lots of viewers are on mobile, carrier nat moves them
+    // between ips mid stream
+    #[clap(long, env)]
+    pub strict_client_binding: bool,
+
+    // seconds past its expiry a signed url still works, so a long live event doesn't cut off
+    // viewers who started early. badly expired urls are still rejected, 0 turns it off
+    #[clap(long, env, default_value = "0")]
+    pub signature_expiry_grace_seconds: i64,
+
     // below are all secrets that are db specific, they're used to sign sessions and keys
     // #[clap(long, env)]
     // pub refresh_token_secret: String,
@@ -41,11 +66,12 @@ pub struct AppConfig {
     // pub registration_key_secret: String,
 
     // this should be either * for allowing everything, or a comma seperated list of domains like
-    // example.com,something.com
+    // example.com,something.com. a domain also lets its subdomains through
     #[clap(long, env)]
     pub cors_origin: String,
 
-    // same as above but used for preview environments to stress or test the api.
+    // same as above but used for preview environments to stress or test the api. these match on
+    // the end of the host, so -preview.pages.dev lets every generated preview url through
     #[clap(long, env)]
     pub preview_cors_origin: String,
 
@@ -56,6 +82,465 @@ pub struct AppConfig {
     // optional sentry integration
     #[clap(long, env)]
     pub sentry_dsn: Option<String>,
+
+    // text for reading logs yourself, json (one object per line, with the current span's fields)
+    // for log aggregators
+    #[clap(long, env, value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+
+    // comma seperated list of url patterns that skip the proxy cache entirely (no lookup, no
+    // store). plain entries are substring matches, entries prefixed with re: are regexes
+    // like 'adsegment,re:/keyframe_\d+\.ts$'
+    #[clap(long, env, default_value = "")]
+    pub proxy_cache_bypass_patterns: String,
+
+    // comma seperated list of schemas (sports, captions) whose upstream requests send
+    // Connection: close instead of reusing pooled connections. pooling stays on for everything else
+    #[clap(long, env, default_value = "")]
+    pub upstream_close_connection_schemas: String,
+
+    // reject proxy requests with a schema other than sports or captions with a 400. off, they're
+    // proxied as sports like they always were, which old signed urls might still rely on
+    #[clap(long, env)]
+    pub strict_schema: bool,
+
+    // comma seperated upstream hosts urls can be signed for and proxied to, * for any. entries
+    // starting with a dot match subdomains too (e.g. ".poocloud.in"). playlists point at their
+    // segment cdns so those need to be in here as well
+    #[clap(long, env, default_value = "*")]
+    pub allowed_proxy_hosts: String,
+
+    // how long urls from the sign endpoint stay valid
+    #[clap(long, env, default_value = "12")]
+    pub signed_url_expiry_hours: i64,
+
+    // client headers passed through to the upstream, comma seperated schema:header entries
+    // (e.g. "sports:accept-language,captions:accept"). the fixed schema headers win on conflict
+    // and hop-by-hop/credential/range/conditional headers are never forwarded
+    #[clap(long, env, default_value = "")]
+    pub forward_client_headers: String,
+
+    // one info line per proxy request (method, path, client, upstream host, status, bytes,
+    // duration, cache outcome). off by default since it's a line per segment
+    #[clap(long, env)]
+    pub access_log: bool,
+
+    // seconds an upstream host is skipped after a connection failure (dns, refused, connect
+    // timeout). requests for it fail fast with a 503 until then
+    #[clap(long, env, default_value = "10")]
+    pub upstream_unreachable_cooldown_seconds: u64,
+
+    // max active requests to a single upstream host (proxy + prefetch), 0 for no cap. anything
+    // over it waits up to upstream_queue_timeout_ms for a slot and then gets a 503
+    #[clap(long, env, default_value = "100")]
+    pub upstream_max_connections_per_host: usize,
+
+    #[clap(long, env, default_value = "2000")]
+    pub upstream_queue_timeout_ms: u64,
+
+    // max upstream requests per second across every host (proxy, prefetch and ppvs.su), 0 for no
+    // cap. up to a second's worth can go out at once, anything over that waits its turn
+    #[clap(long, env, default_value = "0")]
+    pub upstream_max_requests_per_second: u32,
+
+    // lets upstream fetches use HTTP/2 (with an adaptive window) when the origin offers it over
+    // tls, HTTP/1.1 is still used for anything that doesn't
+    #[clap(long, env)]
+    pub upstream_http2: bool,
+
+    // comma seperated hosts that get HTTP/2 over plain http without the upgrade dance (prior
+    // knowledge h2c). only for origins known to speak it, anything else will fail to connect
+    #[clap(long, env, default_value = "")]
+    pub upstream_h2c_hosts: String,
+
+    // comma seperated host:kind pairs (kind is m3u8, ts or mp4) for origins that send the wrong
+    // Content-Type, bodies from the host are treated as that kind no matter what the header says.
+    // e.g. ".badcdn.net:ts"
+    #[clap(long, env, default_value = "")]
+    pub upstream_content_type_overrides: String,
+
+    // Cache-Control sent with proxied playlists. live ones (no #EXT-X-ENDLIST) default to no-store
+    // so nothing between us and the player holds on to an old live edge, vod ones can be cached
+    #[clap(long, env, default_value = "no-store")]
+    pub playlist_live_cache_control: String,
+
+    #[clap(long, env, default_value = "public, max-age=30")]
+    pub playlist_vod_cache_control: String,
+
+    // compression level for responses to clients that accept zstd/gzip, clamped to 1-22 for zstd
+    // and 0-9 for gzip. playlists are text and shrink a lot more at a high level for barely any
+    // cpu, segments are already compressed video so they get a fast one
+    #[clap(long, env, default_value = "9")]
+    pub playlist_compression_level: i32,
+
+    #[clap(long, env, default_value = "1")]
+    pub segment_compression_level: i32,
+
+    // per request timeouts for upstream fetches, playlists (.m3u8) get the short one so a slow
+    // origin doesn't stall live playback, segments and everything else get the long one
+    #[clap(long, env, default_value = "8000")]
+    pub upstream_playlist_timeout_ms: u64,
+
+    #[clap(long, env, default_value = "60000")]
+    pub upstream_segment_timeout_ms: u64,
+
+    // times an upstream response that got cut off mid transfer (partial gzip/zstd) is fetched
+    // again before giving up. malformed bodies aren't retried
+    #[clap(long, env, default_value = "2")]
+    pub upstream_decode_retries: u32,
+
+    // times a proxied request is sent again when upstream doesn't answer (connection error,
+    // timeout) or answers 502/503/504, 0 turns it off. 4xx are never retried
+    #[clap(long, env, default_value = "2")]
+    pub upstream_retries: u32,
+
+    // wait before the first retry, doubled for each one after (plus some jitter)
+    #[clap(long, env, default_value = "200")]
+    pub upstream_retry_base_delay_ms: u64,
+
+    // when an upstream playlist fetch fails, serve the last cached copy if it expired less than
+    // this many seconds ago instead of erroring. 0 turns it off
+    #[clap(long, env, default_value = "30")]
+    pub proxy_stale_if_error_seconds: u64,
+
+    // cached segments of a live playlist older than this are refetched instead of served, a new
+    // viewer at the live edge shouldn't get minutes old video. vod segments keep the full TTL.
+    // 0 turns it off
+    #[clap(long, env, default_value = "30")]
+    pub proxy_live_segment_max_age_seconds: u64,
+
+    // a prefetch still marked in flight after this long is treated as stuck and dropped, so
+    // requests for that segment stop waiting on it. keep it above the segment timeout, 0 turns it off
+    #[clap(long, env, default_value = "90")]
+    pub proxy_inflight_max_age_seconds: u64,
+
+    // how long raw playlists and segments stay in the proxy cache. playlists of a live stream
+    // change every few seconds so keep that one short
+    #[clap(long, env, default_value = "10")]
+    pub proxy_m3u8_ttl_seconds: u64,
+
+    #[clap(long, env, default_value = "300")]
+    pub proxy_segment_ttl_seconds: u64,
+
+    // every cached playlist, segment and games fetch lives up to this many percent shorter or
+    // longer than its ttl, so things cached in the same burst don't all expire (and get refetched)
+    // at once. capped at 50, 0 turns it off
+    #[clap(long, env, default_value = "10")]
+    pub cache_ttl_jitter_percent: u64,
+
+    // how long a segment upstream answered 404/410 for is remembered, requests for it in that
+    // time get a 404 straight away instead of going upstream again. 5xx are never remembered, 0
+    // turns it off
+    #[clap(long, env, default_value = "5")]
+    pub proxy_negative_ttl_seconds: u64,
+
+    // when a cached playlist runs out only one request refetches it, the others wait up to this
+    // many ms for it to show up in the cache before fetching it themselves. 0 turns it off
+    #[clap(long, env, default_value = "3000")]
+    pub proxy_m3u8_lock_ms: u64,
+
+    // bytes of hot segments each instance keeps in memory in front of Redis, the least recently
+    // used ones are dropped first. 0 turns it off. invalidating a stream only clears the copies of
+    // the instance that got the request, the others keep serving theirs until the segment TTL
+    #[clap(long, env, default_value = "0")]
+    pub proxy_l1_cache_max_bytes: usize,
+
+    // segment prefetch concurrency, the global cap across all clients and how much of it a
+    // single client's playlist can take up at once
+    #[clap(long, env, default_value = "5")]
+    pub prefetch_max_concurrent: usize,
+
+    #[clap(long, env, default_value = "2")]
+    pub prefetch_max_per_client: usize,
+
+    // prefetched segments are written to the store in batches, one round trip per batch instead
+    // of one per segment. a batch is written once it has this many segments or this many ms after
+    // its first one landed, whichever comes first. a batch size of 1 writes every segment as soon
+    // as it's fetched
+    #[clap(long, env, default_value = "8")]
+    pub prefetch_write_batch_size: usize,
+
+    #[clap(long, env, default_value = "50")]
+    pub prefetch_write_batch_ms: u64,
+
+    // check every packet of a .ts segment for the mpeg-ts sync byte before it's cached. html
+    // error pages and tiny bodies are always rejected, this catches corrupt/cut off ts too
+    #[clap(long, env)]
+    pub proxy_verify_ts_sync: bool,
+
+    // what the client id (rate limiting, url signing) is derived from: "ip", "ip_ua" or "header".
+    // header uses X-Client-Id when present and falls back to ip_ua, only use it behind a gateway
+    // that controls that header
+    #[clap(long, env, default_value = "ip_ua")]
+    pub client_id_strategy: String,
+
+    // comma seperated cidrs/ips of the proxies in front of us (fly's edge, load balancers).
+    // X-Forwarded-For and X-Real-IP are only believed when the connection comes from one of
+    // these, and their hops are skipped when looking for the client in X-Forwarded-For
+    #[clap(
+        long,
+        env,
+        default_value = "10.0.0.0/8,172.16.0.0/12,192.168.0.0/16,127.0.0.0/8,fc00::/7,::1/128"
+    )]
+    pub trusted_proxies: String,
+
+    // refuse proxy requests from crawlers, http libraries and clients without a user agent with
+    // a 401. native players (AppleCoreMedia, ExoPlayer, VLC, ...) and browsers still get through
+    #[clap(long, env)]
+    pub enforce_user_agent_policy: bool,
+
+    // comma seperated user agent substrings (case insensitive) that always get through, checked
+    // before anything else
+    #[clap(long, env, default_value = "")]
+    pub user_agent_allow_list: String,
+
+    // comma seperated user agent substrings (case insensitive) refused on top of the built in bots
+    #[clap(long, env, default_value = "")]
+    pub user_agent_deny_list: String,
+
+    // health reports degraded once the last successful games refresh is older than this. the
+    // cache refreshes hourly so the default allows one missed refresh
+    #[clap(long, env, default_value = "7200")]
+    pub refresh_stale_after_seconds: i64,
+
+    // status and message sent for every denied request (bad signature, blocked, etc.) instead of
+    // the specific error. setting either one turns it on, status defaults to 403
+    #[clap(long, env)]
+    pub denial_status: Option<u16>,
+
+    #[clap(long, env)]
+    pub denial_message: Option<String>,
+
+    // bearer token for the /api/v1/admin routes, admin routes are disabled when it's not set.
+    // generate it the same way as the access token secret
+    #[clap(long, env)]
+    pub admin_token: Option<String>,
+
+    // make the audio/subtitle rendition matching the client's Accept-Language the default when
+    // rewriting master playlists. renditions are left alone when nothing matches
+    #[clap(long, env)]
+    pub playlist_language_preselect: bool,
+
+    // let clients ask for `decrypt=true` on a playlist, its AES-128 keys get stripped and the
+    // segments decrypted here for players without HLS-AES. costs a key fetch and a decrypt per
+    // uncached segment
+    #[clap(long, env)]
+    pub proxy_segment_decryption: bool,
+
+    // playlists bigger than this many bytes get rewritten on the blocking thread pool so signing a
+    // huge vod playlist doesn't stall other requests, 0 keeps every playlist inline
+    #[clap(long, env, default_value = "65536")]
+    pub playlist_blocking_threshold_bytes: usize,
+
+    // how long the signed urls of variants/renditions in a master stay valid. kept shorter than
+    // the 12h segments get so a player can't keep polling a playlist long after the event is over
+    #[clap(long, env, default_value = "4")]
+    pub playlist_url_expiry_hours: i64,
+
+    // how many emptied body/playlist buffers the proxy keeps around for reuse (per kind), 0 turns
+    // the reuse off. buffers that grew past the max bytes are freed instead of kept
+    #[clap(long, env, default_value = "32")]
+    pub buffer_pool_size: usize,
+
+    #[clap(long, env, default_value = "2097152")]
+    pub buffer_pool_max_buffer_bytes: usize,
+
+    // ppvs.su api to get games from, point it at a mirror when the main one is down
+    #[clap(long, env, default_value = "https://api.ppv.to")]
+    pub ppvsu_api_base: String,
+
+    // what the games are cached under, change it when the api base points at a different source
+    // so its games don't get mixed up with the old ones
+    #[clap(long, env, default_value = "ppvsu")]
+    pub ppvsu_provider_key: String,
+
+    // comma seperated rotation:counter pairs for decrypting ppvs.su video links, tried in order
+    // until one gives a valid url. add the new one in front when upstream changes the scheme
+    #[clap(long, env, default_value = "71:1,71:0")]
+    pub ppvsu_decrypt_variants: String,
+
+    // comma seperated extensions a decrypted ppvs.su video link can end in, the first one that
+    // gives a valid url wins
+    #[clap(long, env, default_value = ".m3u8,.mpd,.ts")]
+    pub ppvsu_stream_extensions: String,
+
+    // the admin triggered "resolve all live links now" run, how many links it fetches at once
+    // and how long it waits between starting each fetch. keep it slow, this is what gets us banned
+    #[clap(long, env, default_value = "2")]
+    pub ppvsu_resolve_max_concurrent: usize,
+
+    #[clap(long, env, default_value = "500")]
+    pub ppvsu_resolve_interval_ms: u64,
+
+    // how long a decrypted ppvs.su video link is cached before it's fetched (and decrypted) again
+    #[clap(long, env, default_value = "300")]
+    pub ppvsu_video_link_ttl_seconds: u64,
+
+    // after this many failed requests in a row to a ppvs.su host (403/429/5xx or no answer at
+    // all) every node stops calling it for the cooldown and serves the games it has. the next
+    // request after the cooldown is a probe that closes or reopens it. 0 turns it off
+    #[clap(long, env, default_value = "5")]
+    pub ppvsu_breaker_failures: u32,
+
+    #[clap(long, env, default_value = "300")]
+    pub ppvsu_breaker_cooldown_seconds: u64,
+
+    // how long a games fetch (and every game in it) counts as fresh before requests refetch it
+    #[clap(long, env, default_value = "3600")]
+    pub games_cache_ttl_seconds: u64,
+
+    // when a refresh fails and the stale games are served instead, it's retried in the background
+    // this many seconds later. one retry at a time, the next request served stale schedules another
+    #[clap(long, env, default_value = "30")]
+    pub games_stale_retry_seconds: u64,
+
+    // refetch the games in the background so requests never wait on ppvs.su for a stale cache.
+    // with several nodes on one redis only one of them refetches each round
+    #[clap(long, env)]
+    pub games_refresh_enabled: bool,
+
+    // how often the background refresh runs, plus up to the jitter so nodes started at the same
+    // time don't all race for the lock at once. keep it under refresh_stale_after_seconds
+    #[clap(long, env, default_value = "1800")]
+    pub games_refresh_interval_seconds: u64,
+
+    #[clap(long, env, default_value = "30000")]
+    pub games_refresh_jitter_ms: u64,
+
+    // the background refresh deletes games that ended more than this long ago, ppvs.su keeps
+    // listing them for a while and nothing else cleans them up
+    #[clap(long, env, default_value = "7200")]
+    pub games_expiry_grace_seconds: u64,
+
+    // how long in-flight requests get to finish once a shutdown signal came in before they're cut
+    // off. this plus the hook timeout should fit in fly's kill_timeout
+    #[clap(long, env, default_value = "10000")]
+    pub shutdown_drain_timeout_ms: u64,
+
+    // how long shutdown hooks (flushing metrics, persisting hot keys, releasing locks) get to
+    // finish once the server stopped taking requests before the process exits anyway
+    #[clap(long, env, default_value = "5000")]
+    pub shutdown_hook_timeout_ms: u64,
+
+    // how long a client request gets to come up with a response before it's answered with a 504.
+    // it has to cover a whole upstream fetch of a large segment and stay longer than fly's health
+    // check timeout (10s). streaming the body back isn't counted
+    #[clap(long, env, default_value = "60")]
+    pub request_timeout_seconds: u64,
+
+    // biggest request body a client can send, the edge only takes small json bodies. bigger ones
+    // get a 413
+    #[clap(long, env, default_value = "1048576")]
+    pub max_request_body_bytes: usize,
+
+    // how many of the most recent upstream requests (host, status, latency, proxied or not) are
+    // kept for the admin attempts endpoint, handy for seeing what led up to an ip ban
+    #[clap(long, env, default_value = "200")]
+    pub upstream_attempt_log_size: usize,
+
+    // send the client's Range header upstream for mp4 urls and relay the 206 as is instead of
+    // fetching the whole file and slicing it here. hosts that ignore ranges still get sliced
+    #[clap(long, env)]
+    pub upstream_forward_mp4_ranges: bool,
+
+    // how requests are counted per rate limit window: "fixed" (a counter that resets, can let up to
+    // twice the max through around a reset) or "sliding" (a log of the last window, stricter but
+    // one entry per request in redis)
+    #[clap(long, env, default_value = "fixed")]
+    pub rate_limit_strategy: String,
+
+    // requests a client can make per rate limit window before getting a 429
+    #[clap(long, env, default_value = "500")]
+    pub rate_limit_max_requests: u32,
+
+    // requests one ip can make per rate limit window across all its client ids, so rotating the
+    // user agent doesn't get a fresh bucket. leave room for viewers behind one nat, 0 turns it off
+    #[clap(long, env, default_value = "2000")]
+    pub rate_limit_max_requests_per_ip: u32,
+
+    #[clap(long, env, default_value = "60")]
+    pub rate_limit_window_seconds: u64,
+
+    // error score a client can reach before it's timed out, each failure adds the weight of its
+    // kind (a 404 half that) and the same one again right after counts double. clients past the
+    // ratio min requests also need the error ratio to be over the max before they are
+    #[clap(long, env, default_value = "50")]
+    pub rate_limit_max_errors: u32,
+
+    #[clap(long, env, default_value = "0.2")]
+    pub rate_limit_max_error_ratio: f64,
+
+    #[clap(long, env, default_value = "250")]
+    pub rate_limit_ratio_min_requests: u32,
+
+    #[clap(long, env, default_value = "600")]
+    pub rate_limit_error_window_seconds: u64,
+
+    // the error score halves every this many seconds without new errors
+    #[clap(long, env, default_value = "120")]
+    pub rate_limit_error_half_life_seconds: u64,
+
+    // comma seperated kind=weight overrides for what each failure adds to the error score, like
+    // 'decode_failure=5,upstream_server_error=0.1'. kinds left out keep their default:
+    // upstream_client_error=1, upstream_server_error=0, request_failed=0, decode_failure=3
+    #[clap(long, env, default_value = "")]
+    pub rate_limit_error_weights: String,
+
+    // how long a timed out client stays timed out
+    #[clap(long, env, default_value = "300")]
+    pub rate_limit_timeout_seconds: u64,
+}
+
+impl AppConfig {
+    /// parses args and env, then fills in the per environment defaults. exits with the usage
+    /// error when something required is missing, same as `AppConfig::parse`
+    pub fn load() -> Self {
+        Self::try_load_from(std::env::args_os()).unwrap_or_else(|e| e.exit())
+    }
+
+    pub fn try_load_from<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let matches = Self::command().try_get_matches_from(args)?;
+        let config = Self::from_arg_matches(&matches)?;
+
+        Ok(config.with_env_defaults(|id| {
+            matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        }))
+    }
+
+    /// applies the defaults that depend on cargo_env. precedence, highest first:
+    ///
+    /// 1. a flag or env var that was actually set
+    /// 2. the cargo_env default below
+    /// 3. the default_value on the field
+    ///
+    /// development turns the access log on and keeps prefetch to 2 at a time so a local run
+    /// doesn't hammer upstream. production sends the plain 403 denial for every rejected request so
+    /// a client can't tell a bad signature from a ban
+    pub fn with_env_defaults(mut self, is_explicit: impl Fn(&str) -> bool) -> Self {
+        match self.cargo_env {
+            CargoEnv::Development => {
+                if !is_explicit("access_log") {
+                    self.access_log = true;
+                }
+                if !is_explicit("prefetch_max_concurrent") {
+                    self.prefetch_max_concurrent = 2;
+                }
+            }
+            CargoEnv::Production => {
+                if !is_explicit("denial_status") && !is_explicit("denial_message") {
+                    self.denial_status = Some(403);
+                }
+            }
+        }
+        self
+    }
 }
 
 impl Default for AppConfig {
@@ -68,12 +553,102 @@ impl Default for AppConfig {
             redis_url: "".to_string(),
             // run_migrations: false,
             access_token_secret: "default-access-secret".to_string(),
+            access_token_fallback_secrets: "".to_string(),
+            strict_client_binding: false,
+            signature_expiry_grace_seconds: 0,
             // refresh_token_secret: "default-refresh-secret".to_string(),
             // registration_key_secret: "default-registration-secret".to_string(),
             cors_origin: "*".to_string(),
             preview_cors_origin: "*".to_string(),
             // seed: false,
             sentry_dsn: None,
+            log_format: LogFormat::Text,
+            proxy_cache_bypass_patterns: "".to_string(),
+            upstream_close_connection_schemas: "".to_string(),
+            strict_schema: false,
+            allowed_proxy_hosts: "*".to_string(),
+            signed_url_expiry_hours: 12,
+            forward_client_headers: "".to_string(),
+            access_log: false,
+            upstream_unreachable_cooldown_seconds: 10,
+            upstream_max_connections_per_host: 100,
+            upstream_queue_timeout_ms: 2000,
+            upstream_max_requests_per_second: 0,
+            upstream_http2: false,
+            upstream_h2c_hosts: "".to_string(),
+            upstream_content_type_overrides: "".to_string(),
+            playlist_live_cache_control: "no-store".to_string(),
+            playlist_vod_cache_control: "public, max-age=30".to_string(),
+            playlist_compression_level: 9,
+            segment_compression_level: 1,
+            upstream_playlist_timeout_ms: 8000,
+            upstream_segment_timeout_ms: 60000,
+            upstream_decode_retries: 2,
+            upstream_retries: 2,
+            upstream_retry_base_delay_ms: 200,
+            proxy_stale_if_error_seconds: 30,
+            proxy_live_segment_max_age_seconds: 30,
+            proxy_inflight_max_age_seconds: 90,
+            proxy_m3u8_ttl_seconds: 10,
+            proxy_segment_ttl_seconds: 300,
+            cache_ttl_jitter_percent: 10,
+            proxy_negative_ttl_seconds: 5,
+            proxy_m3u8_lock_ms: 3000,
+            proxy_l1_cache_max_bytes: 0,
+            prefetch_max_concurrent: 5,
+            prefetch_max_per_client: 2,
+            prefetch_write_batch_size: 8,
+            prefetch_write_batch_ms: 50,
+            proxy_verify_ts_sync: false,
+            client_id_strategy: "ip_ua".to_string(),
+            trusted_proxies:
+                "10.0.0.0/8,172.16.0.0/12,192.168.0.0/16,127.0.0.0/8,fc00::/7,::1/128"
+                    .to_string(),
+            enforce_user_agent_policy: false,
+            user_agent_allow_list: "".to_string(),
+            user_agent_deny_list: "".to_string(),
+            refresh_stale_after_seconds: 7200,
+            denial_status: None,
+            denial_message: None,
+            admin_token: None,
+            playlist_language_preselect: false,
+            proxy_segment_decryption: false,
+            playlist_blocking_threshold_bytes: 65536,
+            playlist_url_expiry_hours: 4,
+            buffer_pool_size: 32,
+            buffer_pool_max_buffer_bytes: 2097152,
+            ppvsu_api_base: "https://api.ppv.to".to_string(),
+            ppvsu_provider_key: "ppvsu".to_string(),
+            ppvsu_decrypt_variants: "71:1,71:0".to_string(),
+            ppvsu_stream_extensions: ".m3u8,.mpd,.ts".to_string(),
+            ppvsu_resolve_max_concurrent: 2,
+            ppvsu_resolve_interval_ms: 500,
+            ppvsu_video_link_ttl_seconds: 300,
+            ppvsu_breaker_failures: 5,
+            ppvsu_breaker_cooldown_seconds: 300,
+            games_cache_ttl_seconds: 3600,
+            games_stale_retry_seconds: 30,
+            games_refresh_enabled: false,
+            games_refresh_interval_seconds: 1800,
+            games_refresh_jitter_ms: 30000,
+            games_expiry_grace_seconds: 7200,
+            shutdown_drain_timeout_ms: 10000,
+            shutdown_hook_timeout_ms: 5000,
+            request_timeout_seconds: 60,
+            max_request_body_bytes: 1048576,
+            upstream_attempt_log_size: 200,
+            upstream_forward_mp4_ranges: false,
+            rate_limit_strategy: "fixed".to_string(),
+            rate_limit_max_requests: 500,
+            rate_limit_max_requests_per_ip: 2000,
+            rate_limit_window_seconds: 60,
+            rate_limit_max_errors: 50,
+            rate_limit_max_error_ratio: 0.2,
+            rate_limit_ratio_min_requests: 250,
+            rate_limit_error_window_seconds: 600,
+            rate_limit_error_half_life_seconds: 120,
+            rate_limit_error_weights: String::new(),
+            rate_limit_timeout_seconds: 300,
         }
     }
 }
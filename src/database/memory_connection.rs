@@ -61,6 +61,39 @@ impl InMemoryDatabase {
         Ok(())
     }
 
+    /// Set a value with a TTL in milliseconds only if the key isn't there yet (SET NX PX
+    /// equivalent), returns false when it already was
+    pub async fn set_nx_px(&self, key: &str, value: &str, ttl_ms: u64) -> anyhow::Result<bool> {
+        let mut data = self.data.write().await;
+        let now = Instant::now();
+        if data
+            .get(key)
+            .is_some_and(|(_, expiry)| expiry.is_none_or(|e| e > now))
+        {
+            return Ok(false);
+        }
+
+        data.insert(
+            key.to_string(),
+            (value.to_string(), Some(now + Duration::from_millis(ttl_ms))),
+        );
+        Ok(true)
+    }
+
+    /// Delete a key only while it still holds `value` and hasn't expired, returns whether it did
+    pub async fn del_if_eq(&self, key: &str, value: &str) -> anyhow::Result<bool> {
+        let mut data = self.data.write().await;
+        let now = Instant::now();
+        if data
+            .get(key)
+            .is_some_and(|(held, expiry)| held == value && expiry.is_none_or(|e| e > now))
+        {
+            data.remove(key);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
     /// Delete a key
     pub async fn del(&self, key: &str) -> anyhow::Result<u32> {
         let mut data = self.data.write().await;
@@ -83,6 +116,18 @@ impl InMemoryDatabase {
         Ok(count)
     }
 
+    /// Set a TTL on an existing key (EXPIRE equivalent), returns false if the key doesn't exist
+    pub async fn expire(&self, key: &str, ttl_secs: u64) -> anyhow::Result<bool> {
+        let mut data = self.data.write().await;
+        match data.get_mut(key) {
+            Some(entry) => {
+                entry.1 = Some(Instant::now() + Duration::from_secs(ttl_secs));
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
     /// Get TTL for a key (returns -1 if no expiry, -2 if not exists)
     pub async fn ttl(&self, key: &str) -> anyhow::Result<i64> {
         let data = self.data.read().await;
@@ -160,14 +205,162 @@ impl InMemoryDatabase {
         Ok(results)
     }
 
+    /// Add members to a set (SADD equivalent), returns how many were new
+    /// Sets are stored as json arrays in the main store so scan/del/ttl work on them as well
+    pub async fn sadd(&self, key: &str, members: &[String]) -> anyhow::Result<u32> {
+        let mut data = self.data.write().await;
+        let entry = data
+            .entry(key.to_string())
+            .or_insert_with(|| ("[]".to_string(), None));
+
+        let mut set: Vec<String> = serde_json::from_str(&entry.0).unwrap_or_default();
+        let mut added = 0;
+        for member in members {
+            if !set.contains(member) {
+                set.push(member.clone());
+                added += 1;
+            }
+        }
+        entry.0 = serde_json::to_string(&set)?;
+
+        Ok(added)
+    }
+
+    /// Remove members from a set (SREM equivalent), empty sets are deleted like in Redis
+    pub async fn srem(&self, key: &str, members: &[String]) -> anyhow::Result<u32> {
+        let mut data = self.data.write().await;
+        let Some(entry) = data.get_mut(key) else {
+            return Ok(0);
+        };
+
+        let mut set: Vec<String> = serde_json::from_str(&entry.0).unwrap_or_default();
+        let before = set.len();
+        set.retain(|m| !members.contains(m));
+        let removed = (before - set.len()) as u32;
+
+        if set.is_empty() {
+            data.remove(key);
+        } else {
+            entry.0 = serde_json::to_string(&set)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Get all members of a set (SMEMBERS equivalent)
+    pub async fn smembers(&self, key: &str) -> anyhow::Result<Vec<String>> {
+        let set = self
+            .get(key)
+            .await?
+            .and_then(|json| serde_json::from_str::<Vec<String>>(&json).ok())
+            .unwrap_or_default();
+        Ok(set)
+    }
+
+    // sorted sets are json arrays of (member, score) kept in score order, same idea as the sets
+    // above. an expired one reads as empty
+    fn read_zset(entry: Option<&(String, Option<Instant>)>) -> Vec<(String, i64)> {
+        entry
+            .filter(|(_, expiry)| expiry.is_none_or(|e| e > Instant::now()))
+            .and_then(|(json, _)| serde_json::from_str(json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Add a member with a score to a sorted set (ZADD equivalent), returns 1 if it was new.
+    /// An existing member just gets its score updated
+    pub async fn zadd(&self, key: &str, member: &str, score: i64) -> anyhow::Result<u32> {
+        let mut data = self.data.write().await;
+        let mut zset = Self::read_zset(data.get(key));
+
+        let before = zset.len();
+        zset.retain(|(m, _)| m != member);
+        let added = u32::from(zset.len() == before);
+
+        let at = zset.partition_point(|(_, s)| *s <= score);
+        zset.insert(at, (member.to_string(), score));
+
+        let expiry = data.get(key).and_then(|(_, expiry)| *expiry);
+        let expiry = expiry.filter(|e| *e > Instant::now());
+        data.insert(key.to_string(), (serde_json::to_string(&zset)?, expiry));
+
+        Ok(added)
+    }
+
+    /// Remove every member with a score in min..=max (ZREMRANGEBYSCORE equivalent), empty sorted
+    /// sets are deleted
+    pub async fn zremrangebyscore(&self, key: &str, min: i64, max: i64) -> anyhow::Result<u32> {
+        let mut data = self.data.write().await;
+        let mut zset = Self::read_zset(data.get(key));
+
+        let before = zset.len();
+        zset.retain(|(_, s)| *s < min || *s > max);
+        let removed = (before - zset.len()) as u32;
+
+        if zset.is_empty() {
+            data.remove(key);
+        } else if let Some(entry) = data.get_mut(key) {
+            entry.0 = serde_json::to_string(&zset)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Remove a member from a sorted set (ZREM equivalent), returns 1 if it was there. empty
+    /// sorted sets are deleted
+    pub async fn zrem(&self, key: &str, member: &str) -> anyhow::Result<u32> {
+        let mut data = self.data.write().await;
+        let mut zset = Self::read_zset(data.get(key));
+
+        let before = zset.len();
+        zset.retain(|(m, _)| m != member);
+        let removed = (before - zset.len()) as u32;
+
+        if zset.is_empty() {
+            data.remove(key);
+        } else if let Some(entry) = data.get_mut(key) {
+            entry.0 = serde_json::to_string(&zset)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Number of members in a sorted set (ZCARD equivalent)
+    pub async fn zcard(&self, key: &str) -> anyhow::Result<u32> {
+        let data = self.data.read().await;
+        Ok(Self::read_zset(data.get(key)).len() as u32)
+    }
+
+    /// Members between two indexes (inclusive) with their scores, lowest score first
+    /// (ZRANGE WITHSCORES equivalent, without negative indexes)
+    pub async fn zrange_withscores(
+        &self,
+        key: &str,
+        start: usize,
+        stop: usize,
+    ) -> anyhow::Result<Vec<(String, i64)>> {
+        let data = self.data.read().await;
+        let zset = Self::read_zset(data.get(key));
+        Ok(zset
+            .into_iter()
+            .skip(start)
+            .take(stop.saturating_sub(start).saturating_add(1))
+            .collect())
+    }
+
     /// Increment a key and set TTL if it doesn't exist
     pub async fn incr(&self, key: &str, delta: u32) -> anyhow::Result<u32> {
         let mut data = self.data.write().await;
+        let now = Instant::now();
 
         let entry = data.entry(key.to_string()).or_insert_with(|| {
-            ("0".to_string(), Some(Instant::now() + Duration::from_secs(60))) // Default TTL
+            ("0".to_string(), Some(now + Duration::from_secs(60))) // Default TTL
         });
 
+        // an expired key starts over like it was never there, same as in Redis
+        if entry.1.is_some_and(|e| e <= now) {
+            *entry = ("0".to_string(), Some(now + Duration::from_secs(60)));
+        }
+
         // Update expiry if needed (keep existing or set new)
         if entry.1.is_none() {
             entry.1 = Some(Instant::now() + Duration::from_secs(60));
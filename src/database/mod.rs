@@ -44,8 +44,16 @@ impl Database {
         }
     }
 
+    /// reconnects in a row since redis last answered, always 0 in memory
+    pub fn reconnect_attempts(&self) -> u32 {
+        match self {
+            Database::Redis(db) => db.reconnect_attempts(),
+            Database::Memory(_) => 0,
+        }
+    }
+
     /// Get internal Redis connection (panics if using memory - use with caution)
-    pub fn redis_connection(&self) -> &redis::aio::MultiplexedConnection {
+    pub fn redis_connection(&self) -> &redis::aio::ConnectionManager {
         match self {
             Database::Redis(db) => &db.connection,
             Database::Memory(_) => panic!("Requested Redis connection but using in-memory database"),
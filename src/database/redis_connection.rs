@@ -1,12 +1,33 @@
 use anyhow::Context;
 use redis::Client;
-use redis::aio::MultiplexedConnection;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::time::Instant;
-use tracing::info;
+use tracing::{info, warn};
 
-#[derive(Debug, Clone)]
+// reconnect backoff, 200ms, 400ms, 800ms... capped at 5s, 6 tries before a request gives up
+const RECONNECT_EXPONENT_BASE: u64 = 2;
+const RECONNECT_FACTOR_MS: u64 = 100;
+const RECONNECT_MAX_DELAY_MS: u64 = 5000;
+const RECONNECT_RETRIES: usize = 6;
+
+/// the connection manager is a multiplexed connection that reconnects (with backoff) on its own
+/// when upstash drops it, cloning it is cheap and every clone shares the reconnect
+#[derive(Clone)]
 pub struct RedisDatabase {
-    pub connection: MultiplexedConnection,
+    pub connection: ConnectionManager,
+    /// health checks that failed in a row, each one had the manager reconnect
+    failed_checks: Arc<AtomicU32>,
+}
+
+impl fmt::Debug for RedisDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisDatabase")
+            .field("reconnect_attempts", &self.reconnect_attempts())
+            .finish_non_exhaustive()
+    }
 }
 
 // this one is so much simpler than postgres oh my god
@@ -15,14 +36,22 @@ impl RedisDatabase {
     pub async fn connect(connection_string: &str) -> anyhow::Result<Self> {
         let client = Client::open(connection_string).context("Failed to create Redis client")?;
 
-        let connection = client
-            .get_multiplexed_tokio_connection()
+        let config = ConnectionManagerConfig::new()
+            .set_exponent_base(RECONNECT_EXPONENT_BASE)
+            .set_factor(RECONNECT_FACTOR_MS)
+            .set_max_delay(RECONNECT_MAX_DELAY_MS)
+            .set_number_of_retries(RECONNECT_RETRIES);
+
+        let connection = ConnectionManager::new_with_config(client, config)
             .await
             .context("Failed to connect to Redis database")?;
 
         info!("Redis connection established");
 
-        Ok(Self { connection })
+        Ok(Self {
+            connection,
+            failed_checks: Arc::new(AtomicU32::new(0)),
+        })
     }
 
     /// does a ping health check, not needed but it's here and is nice
@@ -30,12 +59,27 @@ impl RedisDatabase {
         let start = Instant::now();
 
         let mut conn = self.connection.clone();
-        let _: String = redis::cmd("PING")
-            .query_async(&mut conn)
-            .await
-            .context("Redis health check failed")?;
+        let result: redis::RedisResult<String> = redis::cmd("PING").query_async(&mut conn).await;
+
+        if let Err(e) = result {
+            let failed = self.failed_checks.fetch_add(1, Ordering::SeqCst) + 1;
+            warn!(
+                "Redis ping failed ({} in a row), reconnecting: {}",
+                failed, e
+            );
+            return Err(e).context("Redis health check failed");
+        }
+
+        if self.failed_checks.swap(0, Ordering::SeqCst) > 0 {
+            info!("Redis connection recovered");
+        }
 
         let elapsed = start.elapsed();
         Ok(elapsed.as_secs_f64() * 1000.0) // milliseconds
     }
+
+    /// reconnects the failed health checks kicked off since the last good one, 0 when healthy
+    pub fn reconnect_attempts(&self) -> u32 {
+        self.failed_checks.load(Ordering::SeqCst)
+    }
 }
@@ -28,6 +28,18 @@ pub struct Game {
     pub category: String,
 }
 
+impl Game {
+    /// live means it has started and hasn't ended yet
+    pub fn is_live(&self, now: i64) -> bool {
+        self.start_time <= now && now <= self.end_time
+    }
+
+    /// ended more than `grace_seconds` before `now`. games without an end time never expire
+    pub fn has_expired(&self, now: i64, grace_seconds: u64) -> bool {
+        self.end_time > 0 && now - self.end_time > grace_seconds as i64
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct PpvsuApiResponse {
     pub success: bool,
@@ -81,7 +93,27 @@ pub trait StreamsRepository {
     async fn store_game(&self, provider: &str, game: &Game) -> Result<()>;
     async fn get_game(&self, provider: &str, game_id: i64) -> Result<Option<Game>>;
     async fn get_games(&self, provider: &str) -> Result<Vec<Game>>;
+    // a page of games ordered by start time, only reads the games on the page
+    async fn get_games_paginated(
+        &self,
+        provider: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Game>>;
+    // index backed lookups, the indexes are kept up to date by store_game/delete_game
+    async fn get_games_by_category(&self, provider: &str, category: &str) -> Result<Vec<Game>>;
+    async fn get_live_games(&self, provider: &str) -> Result<Vec<Game>>;
     async fn delete_game(&self, provider: &str, game_id: i64) -> Result<()>;
+    // deletes the games that ended more than grace_seconds ago, returns how many
+    async fn delete_expired_games(
+        &self,
+        provider: &str,
+        now: i64,
+        grace_seconds: u64,
+    ) -> Result<usize>;
+    // swaps the live index for the games live at `now`, store_game only sees a game start or end
+    // when it's stored again. returns how many are live
+    async fn rebuild_live_index(&self, provider: &str, now: i64) -> Result<usize>;
     async fn clear_cache(&self, provider: &str) -> Result<()>;
     async fn set_last_fetch_time(&self, provider: &str, timestamp: i64) -> Result<()>;
     async fn get_last_fetch_time(&self, provider: &str) -> Result<Option<i64>>;
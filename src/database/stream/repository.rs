@@ -5,6 +5,70 @@ use crate::database::Database;
 
 use super::{Game, Stream, StreamsRepository};
 
+// secondary indexes live under the provider prefix so clear_cache drops them with the games
+fn category_index_key(provider: &str, category: &str) -> String {
+    format!("{}:category:{}", provider, category)
+}
+
+fn live_index_key(provider: &str) -> String {
+    format!("{}:live", provider)
+}
+
+// every game id scored by start time, what pagination walks
+fn games_index_key(provider: &str) -> String {
+    format!("{}:all", provider)
+}
+
+impl Database {
+    // resolves an index set of game ids into the games themselves
+    async fn get_indexed_games(
+        &self,
+        provider: &str,
+        index_key: &str,
+    ) -> anyhow::Result<Vec<Game>> {
+        let ids: Vec<String> = match self {
+            #[allow(unused_imports)]
+            Database::Redis(db) => {
+                use redis::AsyncCommands;
+                let mut conn = db.connection.clone();
+                conn.smembers(index_key).await?
+            }
+            Database::Memory(db) => db.store.smembers(index_key).await?,
+        };
+
+        self.get_games_by_ids(provider, &ids).await
+    }
+
+    // MGET on the games behind a list of ids, keeps the order of the ids
+    async fn get_games_by_ids(&self, provider: &str, ids: &[String]) -> anyhow::Result<Vec<Game>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let keys: Vec<String> = ids
+            .iter()
+            .map(|id| format!("{}:{}", provider, id))
+            .collect();
+
+        let values: Vec<Option<String>> = match self {
+            Database::Redis(db) => {
+                let mut conn = db.connection.clone();
+                redis::cmd("MGET").arg(&keys).query_async(&mut conn).await?
+            }
+            Database::Memory(db) => db.store.mget(&keys).await?,
+        };
+
+        // ids whose game is gone are just skipped
+        let games = values
+            .into_iter()
+            .flatten()
+            .filter_map(|json| serde_json::from_str::<Game>(&json).ok())
+            .collect();
+
+        Ok(games)
+    }
+}
+
 #[async_trait]
 impl StreamsRepository for Database {
     // gets all streams from a provider
@@ -130,22 +194,63 @@ impl StreamsRepository for Database {
         }
     }
 
-    // store a game with provider and id
+    // store a game with provider and id, also keeps the category and live indexes in sync
     async fn store_game(&self, provider: &str, game: &Game) -> anyhow::Result<()> {
+        let now = Utc::now().timestamp();
+        let key = format!("{}:{}", provider, game.id);
+        let value = serde_json::to_string(game)?;
+
+        // a game can move category between fetches, drop it from the old index
+        let previous_category = self
+            .get_game(provider, game.id)
+            .await?
+            .map(|previous| previous.category)
+            .filter(|category| *category != game.category);
+
         match self {
             #[allow(unused_imports)]
             Database::Redis(db) => {
                 use redis::AsyncCommands;
                 let mut conn = db.connection.clone();
-                let key = format!("{}:{}", provider, game.id);
-                let value = serde_json::to_string(game)?;
-                let _: () = conn.set(&key, value).await?;
+
+                let mut pipe = redis::pipe();
+                pipe.atomic().set(&key, value).ignore();
+                if let Some(previous_category) = previous_category {
+                    pipe.srem(category_index_key(provider, &previous_category), game.id)
+                        .ignore();
+                }
+                pipe.sadd(category_index_key(provider, &game.category), game.id)
+                    .ignore();
+                pipe.zadd(games_index_key(provider), game.id, game.start_time)
+                    .ignore();
+                if game.is_live(now) {
+                    pipe.sadd(live_index_key(provider), game.id).ignore();
+                } else {
+                    pipe.srem(live_index_key(provider), game.id).ignore();
+                }
+
+                let _: () = pipe.query_async(&mut conn).await?;
                 Ok(())
             }
             Database::Memory(db) => {
-                let key = format!("{}:{}", provider, game.id);
-                let value = serde_json::to_string(game)?;
+                let id = [game.id.to_string()];
                 db.store.set(&key, &value).await?;
+                if let Some(previous_category) = previous_category {
+                    db.store
+                        .srem(&category_index_key(provider, &previous_category), &id)
+                        .await?;
+                }
+                db.store
+                    .sadd(&category_index_key(provider, &game.category), &id)
+                    .await?;
+                db.store
+                    .zadd(&games_index_key(provider), &id[0], game.start_time)
+                    .await?;
+                if game.is_live(now) {
+                    db.store.sadd(&live_index_key(provider), &id).await?;
+                } else {
+                    db.store.srem(&live_index_key(provider), &id).await?;
+                }
                 Ok(())
             }
         }
@@ -233,25 +338,153 @@ impl StreamsRepository for Database {
         }
     }
 
-    // flush it from storage
+    // goes through delete_game so the indexes lose the games too
+    async fn delete_expired_games(
+        &self,
+        provider: &str,
+        now: i64,
+        grace_seconds: u64,
+    ) -> anyhow::Result<usize> {
+        let expired: Vec<i64> = self
+            .get_games(provider)
+            .await?
+            .into_iter()
+            .filter(|game| game.has_expired(now, grace_seconds))
+            .map(|game| game.id)
+            .collect();
+
+        for game_id in &expired {
+            self.delete_game(provider, *game_id).await?;
+        }
+
+        Ok(expired.len())
+    }
+
+    // walks the start time index instead of scanning every game. games stored before the index
+    // existed show up once the next refresh stores them again
+    async fn get_games_paginated(
+        &self,
+        provider: &str,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Game>> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let key = games_index_key(provider);
+        let last = offset.saturating_add(limit - 1);
+
+        let ids: Vec<String> = match self {
+            #[allow(unused_imports)]
+            Database::Redis(db) => {
+                use redis::AsyncCommands;
+                let mut conn = db.connection.clone();
+                let start = offset.min(isize::MAX as usize) as isize;
+                let stop = last.min(isize::MAX as usize) as isize;
+                conn.zrange(&key, start, stop).await?
+            }
+            Database::Memory(db) => db
+                .store
+                .zrange_withscores(&key, offset, last)
+                .await?
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect(),
+        };
+
+        self.get_games_by_ids(provider, &ids).await
+    }
+
+    // flush it from storage along with its index entries
     async fn delete_game(&self, provider: &str, game_id: i64) -> anyhow::Result<()> {
+        let key = format!("{}:{}", provider, game_id);
+        let category = self
+            .get_game(provider, game_id)
+            .await?
+            .map(|game| game.category);
+
         match self {
             #[allow(unused_imports)]
             Database::Redis(db) => {
                 use redis::AsyncCommands;
                 let mut conn = db.connection.clone();
-                let key = format!("{}:{}", provider, game_id);
-                let _: () = conn.del(&key).await?;
+
+                let mut pipe = redis::pipe();
+                pipe.atomic().del(&key).ignore();
+                if let Some(category) = category {
+                    pipe.srem(category_index_key(provider, &category), game_id)
+                        .ignore();
+                }
+                pipe.srem(live_index_key(provider), game_id).ignore();
+                pipe.zrem(games_index_key(provider), game_id).ignore();
+
+                let _: () = pipe.query_async(&mut conn).await?;
                 Ok(())
             }
             Database::Memory(db) => {
-                let key = format!("{}:{}", provider, game_id);
+                let id = [game_id.to_string()];
                 let _ = db.store.del(&key).await?;
+                if let Some(category) = category {
+                    db.store
+                        .srem(&category_index_key(provider, &category), &id)
+                        .await?;
+                }
+                db.store.srem(&live_index_key(provider), &id).await?;
+                db.store.zrem(&games_index_key(provider), &id[0]).await?;
                 Ok(())
             }
         }
     }
 
+    // games in a category, only reads the games listed in the category index
+    async fn get_games_by_category(
+        &self,
+        provider: &str,
+        category: &str,
+    ) -> anyhow::Result<Vec<Game>> {
+        self.get_indexed_games(provider, &category_index_key(provider, category))
+            .await
+    }
+
+    // games that were live when they were last stored
+    async fn get_live_games(&self, provider: &str) -> anyhow::Result<Vec<Game>> {
+        self.get_indexed_games(provider, &live_index_key(provider))
+            .await
+    }
+
+    async fn rebuild_live_index(&self, provider: &str, now: i64) -> anyhow::Result<usize> {
+        let live: Vec<String> = self
+            .get_games(provider)
+            .await?
+            .into_iter()
+            .filter(|game| game.is_live(now))
+            .map(|game| game.id.to_string())
+            .collect();
+        let key = live_index_key(provider);
+
+        match self {
+            Database::Redis(db) => {
+                let mut conn = db.connection.clone();
+
+                // swapped in one go so a reader never sees an empty index in between
+                let mut pipe = redis::pipe();
+                pipe.atomic().del(&key).ignore();
+                if !live.is_empty() {
+                    pipe.sadd(&key, &live).ignore();
+                }
+                let _: () = pipe.query_async(&mut conn).await?;
+            }
+            Database::Memory(db) => {
+                db.store.del(&key).await?;
+                if !live.is_empty() {
+                    db.store.sadd(&key, &live).await?;
+                }
+            }
+        }
+
+        Ok(live.len())
+    }
+
     // used mainly for debugging
     async fn clear_cache(&self, provider: &str) -> anyhow::Result<()> {
         match self {
@@ -1,10 +1,13 @@
 /* Logger initialization */
 use std::{panic, thread};
 
-use tracing::{error, level_filters::LevelFilter};
+use tracing::{Subscriber, error, level_filters::LevelFilter};
 use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::Layer;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::LookupSpan;
 
-use crate::CargoEnv;
+use crate::{CargoEnv, LogFormat};
 
 pub struct LoggerGuards {
     pub _tracing_guard: WorkerGuard,
@@ -15,7 +18,11 @@ pub struct LoggerGuards {
 pub struct Logger {}
 
 impl Logger {
-    pub fn init(cargo_env: CargoEnv, sentry_dsn: Option<String>) -> LoggerGuards {
+    pub fn init(
+        cargo_env: CargoEnv,
+        log_format: LogFormat,
+        sentry_dsn: Option<String>,
+    ) -> LoggerGuards {
         let file_logger = tracing_appender::rolling::daily("logs", "daily.log");
         let console_logger = std::io::stdout();
 
@@ -53,7 +60,7 @@ impl Logger {
         use tracing_subscriber::layer::SubscriberExt;
         use tracing_subscriber::util::SubscriberInitExt;
 
-        let fmt_layer = tracing_subscriber::fmt::layer().with_writer(non_blocking);
+        let fmt_layer = Self::fmt_layer(log_format, non_blocking);
 
         let registry = tracing_subscriber::registry()
             .with(max_level)
@@ -80,48 +87,38 @@ impl Logger {
                 },
             };
 
-            let backtrace = backtrace::Backtrace::new();
-
-            match info.location() {
-                Some(location) => {
-                    // we have no trace so just do the weird panic
-                    if msg.starts_with("notrace - ") {
-                        error!(
-                            target: "panic", "thread '{}' panicked at '{}': {}:{}",
-                            thread,
-                            msg.replace("notrace - ", ""),
-                            location.file(),
-                            location.line()
-                        );
-                    }
-                    // we have a trace so we do full panic
-                    else {
-                        error!(
-                            target: "panic", "thread '{}' panicked at '{}': {}:{}\n{:?}",
-                            thread,
-                            msg,
-                            location.file(),
-                            location.line(),
-                            backtrace
-                        );
-                    }
+            // what even happens to not have a location
+            let location = info
+                .location()
+                .map(|location| format!("{}:{}", location.file(), location.line()))
+                .unwrap_or_else(|| "unknown".to_string());
+
+            // thread, location and backtrace are fields of their own so json logs don't get them
+            // crammed into the message
+            match msg.strip_prefix("notrace - ") {
+                // we have no trace so just do the weird panic
+                Some(msg) => {
+                    error!(
+                        target: "panic",
+                        thread,
+                        location = %location,
+                        "thread '{}' panicked at '{}'",
+                        thread,
+                        msg
+                    );
                 }
-                // what even happens to get here
+                // we have a trace so we do full panic
                 None => {
-                    if msg.starts_with("notrace - ") {
-                        error!(
-                            target: "panic", "thread '{}' panicked at '{}'",
-                            thread,
-                            msg.replace("notrace - ", ""),
-                        );
-                    } else {
-                        error!(
-                            target: "panic", "thread '{}' panicked at '{}'\n{:?}",
-                            thread,
-                            msg,
-                            backtrace
-                        );
-                    }
+                    let backtrace = backtrace::Backtrace::new();
+                    error!(
+                        target: "panic",
+                        thread,
+                        location = %location,
+                        backtrace = ?backtrace,
+                        "thread '{}' panicked at '{}'",
+                        thread,
+                        msg
+                    );
                 }
             }
         }));
@@ -132,4 +129,21 @@ impl Logger {
             _sentry_guard: sentry_guard,
         }
     }
+
+    /// the stdout/file layer, `writer` is where the lines go
+    pub fn fmt_layer<S, W>(log_format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a> + 'static,
+        W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    {
+        match log_format {
+            LogFormat::Text => tracing_subscriber::fmt::layer().with_writer(writer).boxed(),
+            LogFormat::Json => tracing_subscriber::fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(false)
+                .with_writer(writer)
+                .boxed(),
+        }
+    }
 }
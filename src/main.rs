@@ -1,7 +1,6 @@
 use std::sync::Arc;
 
 use anyhow::Context;
-use clap::Parser;
 use dotenvy::dotenv;
 
 use tracing::info;
@@ -12,10 +11,15 @@ use api::{AppConfig, Database, EdgeApplicationServer, Logger};
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
-    let config = Arc::new(AppConfig::parse());
+    // flags/env first, then the cargo_env specific defaults for anything that wasn't set
+    let config = Arc::new(AppConfig::load());
 
     // init logger and sentry, guards are kept alive to flush logs and maintain sentry connection
-    let _guards = Logger::init(config.cargo_env, config.sentry_dsn.clone());
+    let _guards = Logger::init(
+        config.cargo_env,
+        config.log_format,
+        config.sentry_dsn.clone(),
+    );
 
     // logging is up to you, I like to use info! for general information on what to do
     info!("logger and env prepped (edge mode - no database)...");
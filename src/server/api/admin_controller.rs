@@ -0,0 +1,221 @@
+use axum::Router;
+use axum::extract::{Json, Path, Query};
+use axum::routing::{delete, get, post};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::server::error::{AppResult, Error};
+use crate::server::extractors::AdminAuthentication;
+use crate::server::services::edge_services::EdgeServices;
+use crate::server::services::link_resolver_services::ResolveProgress;
+use crate::server::services::rate_limit_services::{RateLimitSnapshot, is_valid_client_id};
+use crate::server::services::upstream_attempt_services::UpstreamAttempt;
+use crate::server::utils::m3u8_utils;
+
+pub struct AdminController;
+
+#[derive(Serialize)]
+pub struct ImportResponse {
+    pub imported: usize,
+}
+
+/// everything the limiter has on one client
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientRateLimitResponse {
+    pub client_id: String,
+    pub exempt: bool,
+    pub timed_out: bool,
+    pub timeout_reason: Option<String>,
+    /// seconds until the timeout is lifted, only set while timed out
+    pub retry_after: Option<u64>,
+    pub error_score: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClearTimeoutResponse {
+    pub client_id: String,
+    /// false when the client wasn't timed out to begin with
+    pub cleared: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExemptionRequest {
+    pub exempt: bool,
+}
+
+#[derive(Deserialize)]
+pub struct AttemptsQuery {
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct AttemptsResponse {
+    pub attempts: Vec<UpstreamAttempt>,
+}
+
+#[derive(Serialize)]
+pub struct InvalidateResponse {
+    pub stream: String,
+    pub invalidated: usize,
+}
+
+impl AdminController {
+    pub fn app() -> Router {
+        Router::new()
+            .route("/ratelimit/export", get(Self::export_rate_limits_endpoint))
+            .route("/ratelimit/import", post(Self::import_rate_limits_endpoint))
+            .route(
+                "/ratelimit/{client_id}",
+                get(Self::client_rate_limit_endpoint)
+                    .delete(Self::clear_client_timeout_endpoint)
+                    .post(Self::set_client_exemption_endpoint),
+            )
+            .route("/upstream/attempts", get(Self::upstream_attempts_endpoint))
+            .route(
+                "/ppvsu/resolve",
+                get(Self::resolve_progress_endpoint).post(Self::resolve_live_links_endpoint),
+            )
+            .route(
+                "/cache/streams/{stream}",
+                delete(Self::invalidate_stream_cache_endpoint),
+            )
+    }
+
+    /// dump timeouts, exemptions and error scores for migrating redis or auditing bans
+    pub async fn export_rate_limits_endpoint(
+        AdminAuthentication(services): AdminAuthentication,
+    ) -> AppResult<Json<RateLimitSnapshot>> {
+        info!("recieved request to export rate limit state");
+
+        let snapshot = services.rate_limit.export_state().await?;
+
+        Ok(Json(snapshot))
+    }
+
+    /// load a previous export (or a hand written list of bans), nothing is written if any entry
+    /// is invalid
+    pub async fn import_rate_limits_endpoint(
+        AdminAuthentication(services): AdminAuthentication,
+        Json(snapshot): Json<RateLimitSnapshot>,
+    ) -> AppResult<Json<ImportResponse>> {
+        info!("recieved request to import rate limit state");
+
+        let imported = services.rate_limit.import_state(snapshot).await?;
+
+        Ok(Json(ImportResponse { imported }))
+    }
+
+    pub async fn client_rate_limit_endpoint(
+        AdminAuthentication(services): AdminAuthentication,
+        Path(client_id): Path<String>,
+    ) -> AppResult<Json<ClientRateLimitResponse>> {
+        validate_client_id(&client_id)?;
+
+        Ok(Json(client_status(&services, client_id).await))
+    }
+
+    /// lifts a client's timeout and forgets its errors, otherwise the next error would put it
+    /// straight back
+    pub async fn clear_client_timeout_endpoint(
+        AdminAuthentication(services): AdminAuthentication,
+        Path(client_id): Path<String>,
+    ) -> AppResult<Json<ClearTimeoutResponse>> {
+        info!("recieved request to clear timeout for client {}", client_id);
+        validate_client_id(&client_id)?;
+
+        let cleared = services.rate_limit.clear_timeout(&client_id).await;
+        services.rate_limit.clear_error_score(&client_id).await;
+
+        Ok(Json(ClearTimeoutResponse { client_id, cleared }))
+    }
+
+    /// `{"exempt": true}` to stop limiting a client (our own backends, partners), false to undo it
+    pub async fn set_client_exemption_endpoint(
+        AdminAuthentication(services): AdminAuthentication,
+        Path(client_id): Path<String>,
+        Json(request): Json<ExemptionRequest>,
+    ) -> AppResult<Json<ClientRateLimitResponse>> {
+        info!(
+            "recieved request to set exemption for client {} to {}",
+            client_id, request.exempt
+        );
+        validate_client_id(&client_id)?;
+
+        services
+            .rate_limit
+            .set_exempt(&client_id, request.exempt)
+            .await;
+
+        Ok(Json(client_status(&services, client_id).await))
+    }
+
+    /// drop every cached playlist and segment of a stream, e.g. `ppvsu-42` once the game is over
+    pub async fn invalidate_stream_cache_endpoint(
+        AdminAuthentication(services): AdminAuthentication,
+        Path(stream): Path<String>,
+    ) -> AppResult<Json<InvalidateResponse>> {
+        info!("recieved request to invalidate cache for stream {}", stream);
+
+        if !m3u8_utils::is_valid_stream_id(&stream) {
+            return Err(Error::BadRequest("Invalid stream id".to_string()));
+        }
+
+        let invalidated = services.proxy_cache.invalidate_stream(&stream).await?;
+
+        Ok(Json(InvalidateResponse {
+            stream,
+            invalidated,
+        }))
+    }
+
+    /// starts resolving the direct link of every live ppvsu game in the background, 409 if a run
+    /// is already going
+    pub async fn resolve_live_links_endpoint(
+        AdminAuthentication(services): AdminAuthentication,
+    ) -> AppResult<Json<ResolveProgress>> {
+        info!("recieved request to resolve all live ppvsu links");
+
+        let progress = services.link_resolver.start()?;
+
+        Ok(Json(progress))
+    }
+
+    pub async fn resolve_progress_endpoint(
+        AdminAuthentication(services): AdminAuthentication,
+    ) -> AppResult<Json<ResolveProgress>> {
+        Ok(Json(services.link_resolver.progress()))
+    }
+
+    /// the most recent upstream requests, oldest first. defaults to everything that's kept
+    pub async fn upstream_attempts_endpoint(
+        AdminAuthentication(services): AdminAuthentication,
+        Query(query): Query<AttemptsQuery>,
+    ) -> AppResult<Json<AttemptsResponse>> {
+        let attempts = services
+            .upstream_attempts
+            .recent(query.limit.unwrap_or(usize::MAX));
+
+        Ok(Json(AttemptsResponse { attempts }))
+    }
+}
+
+fn validate_client_id(client_id: &str) -> AppResult<()> {
+    if !is_valid_client_id(client_id) {
+        return Err(Error::BadRequest("Invalid client id".to_string()));
+    }
+    Ok(())
+}
+
+async fn client_status(services: &EdgeServices, client_id: String) -> ClientRateLimitResponse {
+    let rate_limit = &services.rate_limit;
+    let timeout = rate_limit.is_user_timed_out(&client_id).await;
+
+    ClientRateLimitResponse {
+        exempt: rate_limit.is_exempt(&client_id).await,
+        timed_out: timeout.is_some(),
+        retry_after: timeout.as_ref().map(|(_, retry_after)| *retry_after),
+        timeout_reason: timeout.map(|(reason, _)| reason),
+        error_score: rate_limit.get_error_score(&client_id).await,
+        client_id,
+    }
+}
@@ -2,12 +2,15 @@ use axum::Extension;
 use axum::Json;
 use axum::http::StatusCode;
 use chrono::Utc;
+use std::sync::atomic::Ordering;
 use std::time::Instant;
 use tracing::{debug, error};
 
 use crate::server::dtos::health_dto::{
-    DatabaseHealth, HealthResponse, HealthStatus, RedisHealth, ServiceHealthDetails,
+    DatabaseHealth, HealthResponse, HealthStatus, ProbeResponse, RedisHealth, RefreshHealth,
+    ServiceHealthDetails,
 };
+use crate::server::services::circuit_breaker_services::{BreakerState, BreakerStatus};
 use crate::server::services::edge_services::EdgeServices;
 use crate::server::{get_app_version, get_uptime_seconds};
 
@@ -24,30 +27,36 @@ pub async fn health_endpoint(
 ) -> (StatusCode, Json<HealthResponse>) {
     let start = Instant::now();
     
-    // Try Redis health check but don't let it block indefinitely
-    // This prevents health check failures when Redis is slow but not dead
-    let redis_health = tokio::time::timeout(
-        std::time::Duration::from_millis(1500),
-        check_redis_health(&services)
-    ).await.unwrap_or_else(|_| {
-        debug!("Redis health check timed out");
-        RedisHealth {
-            status: HealthStatus::Degraded,
-            response_time_ms: HEALTH_CHECK_TIMEOUT_MS as f64,
-        }
-    });
+    let redis_health = timed_redis_health(&services).await;
 
-    let db_health = DatabaseHealth {
-        status: HealthStatus::Healthy, // N/A for edge mode
-        response_time_ms: 0.0,
-        pool_active: 0,
-        pool_max: 0,
-    };
+    // no database in edge mode, overall status below is driven by redis only
+    let db_health = DatabaseHealth::disabled();
+
+    let refresh_health = RefreshHealth::evaluate(
+        &services.refresh_tracker.snapshot(),
+        Utc::now().timestamp(),
+        services.config.refresh_stale_after_seconds,
+    );
+
+    let circuit_breakers = timed_circuit_breakers(&services).await;
+    let breaker_open = circuit_breakers
+        .iter()
+        .any(|b| b.state != BreakerState::Closed);
 
     // Determine overall status - degraded is still OK for Fly.io
-    let overall_status = match redis_health.status {
-        HealthStatus::Unhealthy => HealthStatus::Degraded, // Don't report unhealthy for transient issues
-        other => other,
+    let overall_status = match (redis_health.status, refresh_health.status) {
+        (HealthStatus::Unhealthy, _) => HealthStatus::Degraded, // Don't report unhealthy for transient issues
+        // stale games data means something upstream is wrong even if redis is fine
+        (_, HealthStatus::Degraded) => HealthStatus::Degraded,
+        // so does an upstream we stopped calling, games are being served stale
+        (HealthStatus::Healthy, _) if breaker_open => HealthStatus::Degraded,
+        (other, _) => other,
+    };
+    // draining beats everything else, the load balancer has to take this node out
+    let overall_status = if is_accepting_traffic(&services) {
+        overall_status
+    } else {
+        HealthStatus::Draining
     };
 
     let elapsed_ms = start.elapsed().as_secs_f64() * 1000.0;
@@ -62,24 +71,109 @@ pub async fn health_endpoint(
         services: ServiceHealthDetails {
             database: db_health,
             redis: redis_health,
+            refresh: refresh_health,
+            circuit_breakers,
         },
     };
 
     // Always return 200 OK for degraded/healthy to keep Fly.io happy
     // Only return 503 for truly unhealthy state
     let http_status = match overall_status {
-        HealthStatus::Unhealthy => StatusCode::SERVICE_UNAVAILABLE,
+        HealthStatus::Unhealthy | HealthStatus::Draining => StatusCode::SERVICE_UNAVAILABLE,
         _ => StatusCode::OK,
     };
 
     (http_status, Json(response))
 }
 
+/// liveness, the process is up and answering. stays 200 while draining so the node doesn't get
+/// restarted in the middle of finishing its requests
+pub async fn live_endpoint(
+    Extension(_services): Extension<EdgeServices>,
+) -> (StatusCode, Json<ProbeResponse>) {
+    (
+        StatusCode::OK,
+        Json(ProbeResponse {
+            status: HealthStatus::Healthy,
+            uptime_seconds: get_uptime_seconds(),
+            redis: None,
+        }),
+    )
+}
+
+/// readiness, whether this node should get new traffic: 503 once shutdown started or when redis
+/// isn't answering properly
+pub async fn ready_endpoint(
+    Extension(services): Extension<EdgeServices>,
+) -> (StatusCode, Json<ProbeResponse>) {
+    if !is_accepting_traffic(&services) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ProbeResponse {
+                status: HealthStatus::Draining,
+                uptime_seconds: get_uptime_seconds(),
+                redis: None,
+            }),
+        );
+    }
+
+    let redis_health = timed_redis_health(&services).await;
+    let (http_status, status) = match redis_health.status {
+        HealthStatus::Healthy => (StatusCode::OK, HealthStatus::Healthy),
+        _ => (StatusCode::SERVICE_UNAVAILABLE, HealthStatus::Unhealthy),
+    };
+
+    (
+        http_status,
+        Json(ProbeResponse {
+            status,
+            uptime_seconds: get_uptime_seconds(),
+            redis: Some(redis_health),
+        }),
+    )
+}
+
+fn is_accepting_traffic(services: &EdgeServices) -> bool {
+    services.accepting_traffic.load(Ordering::SeqCst)
+}
+
+// Try Redis health check but don't let it block indefinitely
+// This prevents health check failures when Redis is slow but not dead
+async fn timed_redis_health(services: &EdgeServices) -> RedisHealth {
+    tokio::time::timeout(
+        std::time::Duration::from_millis(1500),
+        check_redis_health(services),
+    )
+    .await
+    .unwrap_or_else(|_| {
+        debug!("Redis health check timed out");
+        RedisHealth {
+            status: HealthStatus::Degraded,
+            response_time_ms: HEALTH_CHECK_TIMEOUT_MS as f64,
+            reconnect_attempts: services.db.reconnect_attempts(),
+        }
+    })
+}
+
+// the breakers live in redis too, same deal as above. nothing to show beats a hanging health check
+async fn timed_circuit_breakers(services: &EdgeServices) -> Vec<BreakerStatus> {
+    tokio::time::timeout(
+        std::time::Duration::from_millis(500),
+        services.circuit_breaker.statuses(),
+    )
+    .await
+    .unwrap_or_else(|_| {
+        debug!("Circuit breaker statuses timed out");
+        Vec::new()
+    })
+}
+
 async fn check_redis_health(services: &EdgeServices) -> RedisHealth {
     match services.db.health_check().await {
         Ok(response_time) => RedisHealth {
             status: HealthStatus::Healthy,
             response_time_ms: response_time,
+            reconnect_attempts: services.db.reconnect_attempts(),
         },
         Err(e) => {
             error!("Redis health check failed: {}", e);
@@ -87,6 +181,7 @@ async fn check_redis_health(services: &EdgeServices) -> RedisHealth {
             RedisHealth {
                 status: HealthStatus::Degraded,
                 response_time_ms: 0.0,
+                reconnect_attempts: services.db.reconnect_attempts(),
             }
         }
     }
@@ -0,0 +1,15 @@
+use std::future::ready;
+
+use axum::Router;
+use axum::routing::get;
+use metrics_exporter_prometheus::PrometheusHandle;
+
+/// prometheus scrape endpoint. the counters and histograms themselves are recorded where things
+/// happen (proxy cache lookups, upstream requests, rate limit checks, the http middleware)
+pub struct MetricsController;
+
+impl MetricsController {
+    pub fn app(handle: PrometheusHandle) -> Router {
+        Router::new().route("/metrics", get(move || ready(handle.render())))
+    }
+}
@@ -1,3 +1,7 @@
+pub mod admin_controller;
 pub mod health_controller;
+pub mod metrics_controller;
 pub mod proxy_controller;
+pub mod rate_limit_controller;
+pub mod sign_controller;
 pub mod stream_controller;
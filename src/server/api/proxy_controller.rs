@@ -2,88 +2,41 @@
 // as a service due to how independent they are
 use axum::{
     Router,
-    extract::Query,
-    http::{HeaderMap, StatusCode, header},
+    extract::OriginalUri,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
     response::{IntoResponse, Response},
     routing::get,
 };
-use std::io::{Read, Write};
 
 use base64::{Engine as _, engine::general_purpose::URL_SAFE};
-use flate2::{Compression, read::GzDecoder, write::GzEncoder};
-use serde::Deserialize;
-use tracing::{debug, error, info};
-
-/// Supported compression encodings
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum ContentEncoding {
-    Zstd,
-    Gzip,
-    None,
-}
-
-impl ContentEncoding {
-    /// determine the best encoding based on Accept-Encoding header
-    /// apple HLS player sends "gzip, deflate" or "identity" - IT MUST BE RESPECTED (i think)
-    ///
-    /// this is a work in progress. Current issues arise from content-length missing? HAR files
-    /// show that the client doesn't recieve them and doesn't query for any more m3u8s for some
-    /// reason. Not sure what the issue is, please help me on this if you read it before I remove
-    /// this comment LMAO
-    fn from_accept_encoding(accept_encoding: Option<&str>) -> Self {
-        match accept_encoding {
-            Some(v) => {
-                // don't compress if client explicitly requests identity-only
-                if v == "identity" || v.starts_with("identity,") {
-                    return Self::None;
-                }
-                // Prefer zstd if supported (better compression), fallback to gzip
-                if v.contains("zstd") {
-                    Self::Zstd
-                } else if v.contains("gzip") {
-                    Self::Gzip
-                } else {
-                    Self::None
-                }
-            }
-            None => Self::None,
-        }
-    }
-
-    fn as_header_value(&self) -> Option<&'static str> {
-        match self {
-            Self::Zstd => Some("zstd"),
-            Self::Gzip => Some("gzip"),
-            Self::None => None,
-        }
-    }
-
-    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
-        match self {
-            Self::Zstd => zstd::encode_all(data, 3),
-            Self::Gzip => {
-                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-                encoder.write_all(data)?;
-                encoder.finish()
-            }
-            Self::None => Ok(data.to_vec()),
-        }
-    }
-}
+use http_body::Body as _;
+use tracing::{debug, error, warn};
 
 use crate::server::{
+    dtos::proxy_dto::ProxyQuery,
     error::{AppResult, Error},
-    extractors::EdgeAuthentication,
-    services::{cookie_services::CookieService, edge_services::EdgeServices},
-    utils::signature_utils::SignatureUtil,
+    extractors::{
+        CheckedRateLimit, CheckedUserAgent, EdgeAuthentication, SchemaExtractor, ValidatedQuery,
+    },
+    services::{
+        cookie_services::CookieService,
+        edge_services::EdgeServices,
+        proxy_cache_services::{M3u8Fill, M3u8FillLock, ProxyCacheService},
+        rate_limit_services::UpstreamFailure,
+    },
+    utils::{
+        access_log_utils::{AccessLogEntry, CacheOutcome},
+        buffer_pool_utils::PooledBuffer,
+        decode_utils,
+        encoding_utils::ContentEncoding,
+        etag_utils,
+        m3u8_utils::{self, PlaylistOptions},
+        range_utils::{self, ByteRange, MultipartRanges, UpstreamRangeReply},
+        segment_decrypt_utils,
+        upstream_utils::{self, BodyKind, Schema, UpstreamConnection},
+    },
 };
 
-#[derive(Deserialize)]
-struct ProxyQuery {
-    url: String,
-    schema: Option<String>,
-}
-
 pub struct ProxyController;
 
 impl ProxyController {
@@ -94,7 +47,11 @@ impl ProxyController {
     }
 
     /// build m3u8 response with proper headers and optional compression
-    fn build_m3u8_response(processed_body: &str, headers: &HeaderMap) -> AppResult<Response> {
+    fn build_m3u8_response(
+        processed_body: &str,
+        headers: &HeaderMap,
+        services: &EdgeServices,
+    ) -> AppResult<Response> {
         // determine client's preferred encoding (apple hls likes gzip, not zstd)
         let encoding = ContentEncoding::from_accept_encoding(
             headers
@@ -109,18 +66,28 @@ impl ProxyController {
                 .parse()
                 .expect("Static header value should parse"),
         );
+        // live edges must never sit in an intermediary cache, vod playlists don't change
+        let cache_control = m3u8_utils::playlist_cache_control(
+            processed_body,
+            &services.config.playlist_live_cache_control,
+            &services.config.playlist_vod_cache_control,
+        );
         response_headers.insert(
             header::CACHE_CONTROL,
-            "no-store"
-                .parse()
-                .expect("Static header value should parse"),
+            HeaderValue::from_str(cache_control)
+                .unwrap_or_else(|_| HeaderValue::from_static("no-store")),
         );
 
         let response_body: Vec<u8> = if encoding != ContentEncoding::None {
-            let compressed_body = encoding.compress(processed_body.as_bytes()).map_err(|e| {
-                error!("Failed to compress response with {:?}: {}", encoding, e);
-                Error::InternalServerErrorWithContext("Failed to compress response".to_string())
-            })?;
+            let compressed_body = encoding
+                .compress_with_level(
+                    processed_body.as_bytes(),
+                    services.config.playlist_compression_level,
+                )
+                .map_err(|e| {
+                    error!("Failed to compress response with {:?}: {}", encoding, e);
+                    Error::InternalServerErrorWithContext("Failed to compress response".to_string())
+                })?;
             debug!(
                 "Compressed M3U8 with {:?} from {} to {} bytes",
                 encoding,
@@ -156,33 +123,148 @@ impl ProxyController {
         Ok((StatusCode::OK, response_headers, response_body).into_response())
     }
 
+    /// stale-if-error for playlists, if upstream failed but we cached this playlist recently it
+    /// gets re-signed and served with a short max-age instead of stalling the player
+    async fn stale_m3u8_response(
+        target_url: &str,
+        client_id: &str,
+        services: &EdgeServices,
+        schema: &str,
+        playlist_options: &PlaylistOptions,
+        headers: &HeaderMap,
+        access_log: &mut AccessLogEntry,
+    ) -> Option<Response> {
+        if schema != "sports" {
+            return None;
+        }
+
+        let raw_m3u8 = services.proxy_cache.get_stale_m3u8(target_url).await?;
+        let processed_body = Self::process_m3u8_by_schema_with_retry(
+            &raw_m3u8,
+            target_url,
+            client_id,
+            services,
+            schema,
+            playlist_options,
+        )
+        .await
+        .ok()?;
+        let mut response = Self::build_m3u8_response(&processed_body, headers, services).ok()?;
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            "max-age=2"
+                .parse()
+                .expect("Static header value should parse"),
+        );
+
+        warn!("Upstream failed, serving stale playlist for {}", target_url);
+        access_log.cache = CacheOutcome::Stale;
+        Some(response)
+    }
+
     async fn proxy_get(
         EdgeAuthentication(client_id, services): EdgeAuthentication,
-        Query(params): Query<ProxyQuery>,
+        _: CheckedUserAgent,
+        _: CheckedRateLimit,
+        SchemaExtractor(schema): SchemaExtractor,
+        ValidatedQuery(params): ValidatedQuery<ProxyQuery>,
+        OriginalUri(uri): OriginalUri,
         headers: HeaderMap,
+    ) -> Response {
+        let access_log_enabled = services.config.access_log;
+        let mut access_log = AccessLogEntry::new("GET", &uri.to_string(), &client_id);
+
+        // every exit of the proxy goes through here so there's exactly one access log line
+        let response = Self::proxy(
+            client_id,
+            services,
+            schema,
+            params,
+            headers,
+            &mut access_log,
+        )
+        .await
+        .into_response();
+
+        if access_log_enabled {
+            access_log.finish(
+                response.status().as_u16(),
+                response.body().size_hint().exact(),
+            );
+            access_log.emit();
+        }
+
+        response
+    }
+
+    async fn proxy(
+        client_id: String,
+        services: EdgeServices,
+        schema: Schema,
+        params: ProxyQuery,
+        headers: HeaderMap,
+        access_log: &mut AccessLogEntry,
     ) -> AppResult<Response> {
         let target_url = Self::decode_url(&params.url)?;
+        upstream_utils::validate_target(&target_url, &services.allowed_hosts)?;
+
+        access_log.upstream_host = CookieService::extract_domain(&target_url);
+
+        let schema = schema.as_str();
+        let playlist_options = PlaylistOptions {
+            depth: params.depth.unwrap_or(0),
+            accept_language: services
+                .config
+                .playlist_language_preselect
+                .then(|| headers.get(header::ACCEPT_LANGUAGE))
+                .flatten()
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.to_string()),
+            stream: params
+                .stream
+                .clone()
+                .filter(|stream| m3u8_utils::is_valid_stream_id(stream)),
+            playlist_expiry_hours: Some(services.config.playlist_url_expiry_hours),
+            decrypt: services.config.proxy_segment_decryption && params.decrypt.unwrap_or(false),
+        };
+        let is_playlist_url = params.kind.as_deref() == Some("playlist");
+        debug!("Proxying (schema={}): {}", schema, target_url);
 
-        if !target_url.starts_with("http://") && !target_url.starts_with("https://") {
-            return Err(Error::BadRequest("Invalid URL format".to_string()));
+        // a segment of a playlist rewritten with decrypt on
+        if params.key.is_some() && services.config.proxy_segment_decryption {
+            return Self::proxy_decrypted(
+                &target_url,
+                &params,
+                &services,
+                schema,
+                &headers,
+                access_log,
+            )
+            .await;
         }
 
-        let schema = params.schema.as_deref().unwrap_or("sports");
-        debug!("Proxying (schema={}): {}", schema, target_url);
-
+        // set when this request took the playlist's fill lock. it's let go when dropped, after the
+        // playlist is cached or on whichever other way this returns
+        let mut m3u8_lock = None;
         if schema == "sports" {
-            let (cached_m3u8, cached_segment) = services.proxy_cache.get_cached(&target_url).await;
+            let (cached_m3u8, cached_segment) = services
+                .proxy_cache
+                .get_cached(&target_url, params.live.unwrap_or(false))
+                .await;
 
             if let Some(raw_m3u8) = cached_m3u8 {
                 debug!("Cache HIT (m3u8) for {}", target_url);
+                access_log.cache = CacheOutcome::Hit;
                 let processed_body = Self::process_m3u8_by_schema_with_retry(
                     &raw_m3u8,
                     &target_url,
                     &client_id,
                     &services,
                     schema,
-                )?;
-                return Self::build_m3u8_response(&processed_body, &headers);
+                    &playlist_options,
+                )
+                .await?;
+                return Self::build_m3u8_response(&processed_body, &headers, &services);
             }
 
             if let Some(cached_bytes) = cached_segment {
@@ -191,10 +273,25 @@ impl ProxyController {
                     cached_bytes.len(),
                     target_url
                 );
-                return Self::build_segment_response(&cached_bytes, &headers, schema, false);
+                access_log.cache = CacheOutcome::Hit;
+                return Self::build_cached_segment_response(
+                    &cached_bytes,
+                    &target_url,
+                    &headers,
+                    schema,
+                    &services,
+                );
+            }
+
+            // upstream 404'd this segment moments ago, asking again won't change that
+            if let Some(status) = services.proxy_cache.get_missing(&target_url).await {
+                debug!("Cache NEGATIVE HIT ({}) for {}", status, target_url);
+                access_log.cache = CacheOutcome::Negative;
+                return Err(Error::NotFound("Upstream segment not found".to_string()));
             }
 
             debug!("Cache MISS for {}", target_url);
+            access_log.cache = CacheOutcome::Miss;
 
             // Check if a prefetch is in-flight for this URL; if so, wait for it
             if let Some(cached_bytes) = services.proxy_cache.wait_for_inflight(&target_url).await {
@@ -203,22 +300,111 @@ impl ProxyController {
                     cached_bytes.len(),
                     target_url
                 );
-                return Self::build_segment_response(&cached_bytes, &headers, schema, false);
+                access_log.cache = CacheOutcome::Inflight;
+                return Self::build_cached_segment_response(
+                    &cached_bytes,
+                    &target_url,
+                    &headers,
+                    schema,
+                    &services,
+                );
+            }
+
+            // an expired playlist is only refetched by one request, the rest get its result
+            let filled_m3u8 = services
+                .proxy_cache
+                .wait_for_m3u8_fill(&target_url, is_playlist_url)
+                .await;
+            let raw_m3u8 = match filled_m3u8 {
+                M3u8Fill::Filled(text) => Some(text),
+                M3u8Fill::Fetch(token) => {
+                    m3u8_lock = token.map(|token| {
+                        M3u8FillLock::new(services.proxy_cache.clone(), &target_url, token)
+                    });
+                    None
+                }
+            };
+            if let Some(raw_m3u8) = raw_m3u8 {
+                debug!(
+                    "Got playlist from another request's refetch for {}",
+                    target_url
+                );
+                access_log.cache = CacheOutcome::Inflight;
+                let processed_body = Self::process_m3u8_by_schema_with_retry(
+                    &raw_m3u8,
+                    &target_url,
+                    &client_id,
+                    &services,
+                    schema,
+                    &playlist_options,
+                )
+                .await?;
+                return Self::build_m3u8_response(&processed_body, &headers, &services);
             }
         }
 
         // extract domain for cookie handling
         let domain = CookieService::extract_domain(&target_url);
 
-        // load any stored cookies for this domain
+        // host failed to connect recently, don't make this request wait out the connect timeout too
+        if let Some(remaining) = domain
+            .as_deref()
+            .and_then(|d| services.host_health.unreachable_for(d))
+        {
+            debug!(
+                "Upstream host {:?} marked unreachable, failing fast",
+                domain
+            );
+            return Err(Error::ServiceUnavailable {
+                message: "Upstream host is unreachable".to_string(),
+                retry_after: remaining.as_secs().max(1),
+            });
+        }
+
+        // waits for a slot if the host is at its connection cap, held until the body is read
+        let host_permit = services
+            .upstream_limiter
+            .acquire(domain.as_deref().unwrap_or_default())
+            .await?;
+
+        // load any stored cookies for this domain that apply to the request path
         let stored_cookies = if let Some(ref d) = domain {
-            services.cookies.get_cookies(d).await
+            let path = url::Url::parse(&target_url)
+                .map(|u| u.path().to_string())
+                .unwrap_or_else(|_| "/".to_string());
+            services.cookies.get_cookies(d, &path).await
         } else {
             None
         };
 
-        let mut request_builder =
-            Self::apply_schema_headers(services.http.get(&target_url), schema, &target_url, &headers);
+        let connection = UpstreamConnection::for_schema(
+            schema,
+            &services.config.upstream_close_connection_schemas,
+        );
+
+        let mut request_builder = upstream_utils::apply_schema_headers(
+            services.upstream_timeouts.apply(
+                connection.apply(services.http.get(&target_url)),
+                &target_url,
+            ),
+            schema,
+            &target_url,
+        );
+
+        // big mp4 files get the range from upstream instead of being fetched whole and sliced
+        // here, set before the forwarded headers so an allowlisted range isn't sent twice
+        let forwarded_range = ByteRange::from_headers(&headers).filter(|_| {
+            services.config.upstream_forward_mp4_ranges
+                && services.content_type_overrides.kind_from_url(&target_url) == Some(BodyKind::Mp4)
+        });
+        if let Some(range) = forwarded_range {
+            debug!("Forwarding range {} upstream", range.header_value());
+            request_builder = request_builder.header(header::RANGE, range.header_value());
+        }
+
+        request_builder = services
+            .forwarded_headers
+            .apply(request_builder, schema, &headers);
 
         // add cookies to request
         if let Some(cookies) = stored_cookies {
@@ -231,24 +417,56 @@ impl ProxyController {
             request_builder
         );
 
-        let target_response = request_builder.send().await.map_err(|e| {
-            error!("Request failed: {}", e);
-            // record error for rate limiting - spawn to not block the response
-            let rate_limit = services.rate_limit.clone();
-            let uid = client_id.clone();
+        // kept so a cut off body can be fetched again
+        let retry_request = request_builder.try_clone();
+
+        // connection errors, timeouts and 502/503/504 get a few more tries with backoff, every
+        // try waits for the global requests per second cap and shows up in the attempt log
+        let upstream = &services;
+        let target_response = match services
+            .upstream_retry
+            .send(request_builder, move |request| async move {
+                upstream.acquire_upstream_permit().await;
+                upstream.upstream_attempts.send("proxy", request).await
+            })
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Request failed: {}", e);
+                if let Some(ref d) = domain {
+                    services.host_health.record_send_error(d, &e);
+                }
+                // record error for rate limiting. no response at all is rarely the client's
+                // fault so request_failed weighs nothing by default
+                Self::record_error_later(&services, &client_id, UpstreamFailure::Network);
 
-            // spawn a new thread to handle this, it's not relevant to this
-            tokio::spawn(async move {
-                rate_limit.record_error(&uid, "proxy_request_failed").await;
-            });
-            Error::InternalServerErrorWithContext(format!("Request failed: {}", e))
-        })?;
+                if let Some(stale) = Self::stale_m3u8_response(
+                    &target_url,
+                    &client_id,
+                    &services,
+                    schema,
+                    &playlist_options,
+                    &headers,
+                    access_log,
+                )
+                .await
+                {
+                    return Ok(stale);
+                }
+                return Err(Error::BadGateway(format!("Request failed: {}", e)));
+            }
+        };
 
         debug!(
             "Received response with status: {}",
             target_response.status()
         );
 
+        if let Some(ref d) = domain {
+            services.host_health.mark_reachable(d);
+        }
+
         // store cookies
         if let Some(ref d) = domain {
             let set_cookies: Vec<String> = target_response
@@ -270,6 +488,29 @@ impl ProxyController {
             }
         }
 
+        // a 206 (or 416) for a forwarded range goes back as is, a 200 means upstream ignored the
+        // range and it gets sliced below like any other full body
+        let range_reply = match forwarded_range {
+            Some(_) => UpstreamRangeReply::from_response(
+                target_response.status(),
+                target_response.headers(),
+            ),
+            None => UpstreamRangeReply::Ignored,
+        };
+        if let UpstreamRangeReply::Relay {
+            status,
+            content_range,
+        } = range_reply
+        {
+            return Ok(Self::relay_range(
+                target_response,
+                status,
+                content_range,
+                schema,
+                host_permit,
+            ));
+        }
+
         // this line WILL get hit at some point.
         let response_status = target_response.status();
         if !response_status.is_success() {
@@ -287,18 +528,36 @@ impl ProxyController {
                 "User: {}, Response from target not successful: {}",
                 client_id, response_status
             );
-            // Record error for rate limiting - these upstream errors count against the user
-            // only if they're client-induced (4xx) not server errors (5xx)
-            if response_status.is_client_error() {
-                let rate_limit = services.rate_limit.clone();
-                let uid = client_id.clone();
-                tokio::spawn(async move {
-                    rate_limit
-                        .record_error(&uid, "proxy_upstream_client_error")
-                        .await;
-                });
+            // Record error for rate limiting - by default these upstream errors only count
+            // against the user if they're client-induced (a 403, not a 429/451 or a 5xx)
+            Self::record_error_later(
+                &services,
+                &client_id,
+                UpstreamFailure::from_status(response_status.as_u16()),
+            );
+
+            // a missing segment is remembered for a few seconds so retries don't all come here
+            if schema == "sports" {
+                services
+                    .proxy_cache
+                    .cache_missing(&target_url, response_status.as_u16())
+                    .await;
             }
-            return Err(Error::BadRequest(
+
+            if let Some(stale) = Self::stale_m3u8_response(
+                &target_url,
+                &client_id,
+                &services,
+                schema,
+                &playlist_options,
+                &headers,
+                access_log,
+            )
+            .await
+            {
+                return Ok(stale);
+            }
+            return Err(Error::BadGateway(
                 "Api returned an invalid response".to_string(),
             ));
 
@@ -335,60 +594,74 @@ impl ProxyController {
             .and_then(|v| v.to_str().ok())
             .map(|s| s.to_string());
 
-        let is_mp4 = content_type.contains("video/mp4");
         debug!(
-            "Content-Type: {}, Encoding: {:?}, Is MP4: {}",
-            content_type, content_encoding, is_mp4
+            "Content-Type: {}, Encoding: {:?}",
+            content_type, content_encoding
         );
 
+        // plain segments go straight through, the prefetch fills the cache for them so there's
+        // no foreground cache write on this path. urls we marked as playlists never are
+        let header_kind = if is_playlist_url {
+            Some(BodyKind::M3u8)
+        } else {
+            services
+                .content_type_overrides
+                .kind_from_headers(&target_url, &content_type)
+        };
+        if Self::can_stream_passthrough(header_kind, content_encoding.as_deref(), &headers) {
+            let is_mp4 = header_kind == Some(BodyKind::Mp4);
+            return Ok(Self::stream_passthrough(
+                target_response,
+                schema,
+                is_mp4,
+                host_permit,
+            ));
+        }
+
         debug!("Reading response bytes");
-        let bytes = target_response.bytes().await.map_err(|e| {
-            error!("Failed to read response: {}", e);
-            Error::InternalServerErrorWithContext(format!("Failed to read response: {}", e))
-        })?;
-        debug!("Read {} bytes", bytes.len());
-
-        let decompressed: Vec<u8> = match content_encoding.as_deref() {
-            Some("zstd") => {
-                debug!("Decompressing zstd-encoded response");
-                zstd::decode_all(&bytes[..]).map_err(|e| {
-                    error!("Failed to decompress zstd: {}", e);
-                    Error::InternalServerErrorWithContext(
-                        "Failed to decompress response".to_string(),
-                    )
-                })?
-            }
-            Some("gzip") => {
-                debug!("Decompressing gzip-encoded response");
-                let mut decoder = GzDecoder::new(&bytes[..]);
-                let mut decomp: Vec<u8> = Vec::new();
-                decoder.read_to_end(&mut decomp).map_err(|e| {
-                    error!("Failed to decompress gzip response: {}", e);
-                    Error::InternalServerErrorWithContext(
-                        "Failed to decompress response".to_string(),
-                    )
-                })?;
-                decomp
+        // cut off gzip/zstd bodies get fetched again instead of turning into a 500
+        let decompressed = match decode_utils::read_decoded_body_pooled(
+            target_response,
+            retry_request,
+            services.config.upstream_decode_retries,
+            &services.body_buffers,
+            |request| async move {
+                upstream.acquire_upstream_permit().await;
+                upstream.upstream_attempts.send("proxy", request).await
+            },
+        )
+        .await
+        {
+            Ok(decompressed) => decompressed,
+            Err(e) => {
+                // a body that keeps getting cut off is the connection, not the client
+                if decode_utils::is_malformed_body(&e) {
+                    Self::record_error_later(&services, &client_id, UpstreamFailure::Decode);
+                }
+                return Err(e);
             }
-            _ => bytes.to_vec(),
         };
 
         debug!("Decompressed size: {} bytes", decompressed.len());
 
-        // check if content starts with #EXT to detect M3U8, or default to M3U8 unless MP4
-        let is_m3u8 = if is_mp4 {
-            false
+        // hosts with a configured body kind skip the detection, otherwise check if content
+        // starts with #EXT to detect M3U8 unless it's MP4
+        let body_kind = if is_playlist_url {
+            BodyKind::M3u8
         } else {
-            decompressed.starts_with(b"#EXT")
-                || content_type.contains("mpegurl")
-                || content_type.contains("m3u8")
+            services
+                .content_type_overrides
+                .classify(&target_url, &content_type, &decompressed)
         };
+        let is_mp4 = body_kind == BodyKind::Mp4;
+        let is_m3u8 = body_kind == BodyKind::M3u8;
         debug!("Detected as M3U8: {}, MP4: {}", is_m3u8, is_mp4);
 
         if is_m3u8 {
             debug!("Processing as M3U8 playlist");
-            let text = String::from_utf8(decompressed).map_err(|e| {
+            let text = std::str::from_utf8(&decompressed).map_err(|e| {
                 error!("Failed to parse m3u8 as UTF-8: {}", e);
+                Self::record_error_later(&services, &client_id, UpstreamFailure::Decode);
                 Error::InternalServerErrorWithContext("Invalid m3u8 encoding".to_string())
             })?;
             debug!("M3U8 text length: {} chars", text.len());
@@ -397,49 +670,192 @@ impl ProxyController {
             if schema == "sports" {
                 let cache = services.proxy_cache.clone();
                 let url_clone = target_url.clone();
-                let text_clone = text.clone();
+                let text_clone = text.to_string();
+                let m3u8_lock = m3u8_lock.take();
                 tokio::spawn(async move {
                     cache.cache_m3u8(&url_clone, &text_clone).await;
+                    // only now, so the waiters find the playlist once the lock is gone
+                    drop(m3u8_lock);
                 });
 
                 // Extract segment URLs and spawn background prefetch for all segments.
                 // The first segment is included so the client can get a cache hit or
                 // wait on the inflight prefetch instead of doing a cold upstream fetch.
-                let segment_urls = Self::extract_segment_urls(&text, &target_url);
+                let segment_urls = Self::extract_segment_urls(text, &target_url);
+
+                if let Some(stream) = playlist_options.stream.clone() {
+                    let cache = services.proxy_cache.clone();
+                    let mut urls = segment_urls.clone();
+                    urls.push(target_url.clone());
+                    tokio::spawn(async move {
+                        cache.index_stream(&stream, &urls).await;
+                    });
+                }
+
                 if !segment_urls.is_empty() {
                     let prefetch_cache = services.proxy_cache.clone();
+                    let prefetch_client = client_id.clone();
+                    let prefetch_schema = schema.to_string();
                     tokio::spawn(async move {
-                        prefetch_cache.prefetch_segments(segment_urls).await;
+                        prefetch_cache
+                            .prefetch_segments(&prefetch_client, &prefetch_schema, segment_urls)
+                            .await;
                     });
                 }
             }
 
             let processed_body = Self::process_m3u8_by_schema_with_retry(
-                &text,
+                text,
                 &target_url,
                 &client_id,
                 &services,
                 schema,
-            )?;
+                &playlist_options,
+            )
+            .await?;
             debug!(
                 "Processed M3U8, response length: {} bytes",
                 processed_body.len()
             );
 
-            Ok(Self::build_m3u8_response(&processed_body, &headers)?)
+            Ok(Self::build_m3u8_response(&processed_body, &headers, &services)?)
         } else {
             // Cache decompressed segment bytes for sports schema (fire-and-forget)
             if schema == "sports" {
                 let cache = services.proxy_cache.clone();
                 let url_clone = target_url.clone();
-                let bytes_clone = decompressed.clone();
+                let bytes_clone = decompressed.to_vec();
+                let stream = playlist_options.stream.clone();
                 tokio::spawn(async move {
                     cache.cache_segment(&url_clone, &bytes_clone).await;
+                    if let Some(stream) = stream {
+                        cache.index_stream(&stream, &[url_clone]).await;
+                    }
                 });
             }
 
-            Self::build_segment_response(&decompressed, &headers, schema, is_mp4)
+            Self::build_segment_response(
+                &decompressed,
+                &headers,
+                schema,
+                is_mp4,
+                services.config.segment_compression_level,
+            )
+        }
+    }
+
+    /// fetches the segment still encrypted (or takes it from the cache the prefetch filled) and
+    /// hands it out decrypted. the plaintext is cached under its own key so a player that did get
+    /// the key never gets served it
+    async fn proxy_decrypted(
+        target_url: &str,
+        params: &ProxyQuery,
+        services: &EdgeServices,
+        schema: &str,
+        headers: &HeaderMap,
+        access_log: &mut AccessLogEntry,
+    ) -> AppResult<Response> {
+        let key_url = Self::decode_url(params.key.as_deref().unwrap_or_default())?;
+        upstream_utils::validate_target(&key_url, &services.allowed_hosts)?;
+        let iv = params
+            .iv
+            .as_deref()
+            .and_then(segment_decrypt_utils::parse_iv)
+            .ok_or_else(|| Error::BadRequest("Missing or invalid iv".to_string()))?;
+        let live = params.live.unwrap_or(false);
+
+        // a different key or iv decrypts to different bytes, they can't share an entry
+        let decrypted_url = format!("{}#aes-128:{}:{}", target_url, key_url, hex::encode(iv));
+        if let (_, Some(cached_bytes)) = services.proxy_cache.get_cached(&decrypted_url, live).await
+        {
+            debug!("Cache HIT (decrypted segment) for {}", target_url);
+            access_log.cache = CacheOutcome::Hit;
+            return Self::build_cached_segment_response(
+                &cached_bytes,
+                target_url,
+                headers,
+                schema,
+                services,
+            );
+        }
+        access_log.cache = CacheOutcome::Miss;
+
+        let key = match services.segment_keys.get(&key_url) {
+            Some((key, _)) => key,
+            None => {
+                let key = Self::fetch_for_decryption(&key_url, services, schema).await?;
+                // an error page instead of a key shouldn't stick around
+                if key.len() != segment_decrypt_utils::AES_BLOCK_LEN {
+                    error!("Key from {} is {} bytes", key_url, key.len());
+                    return Err(Error::BadGateway("Api returned an invalid key".to_string()));
+                }
+                let ttl = std::time::Duration::from_secs(services.config.proxy_segment_ttl_seconds);
+                let now = services.clock.now_millis();
+                services.segment_keys.insert(&key_url, &key, now, ttl);
+                key
+            }
+        };
+        let encrypted = match services.proxy_cache.get_cached(target_url, live).await {
+            (_, Some(cached_bytes)) => cached_bytes,
+            _ => Self::fetch_for_decryption(target_url, services, schema).await?,
+        };
+        let decrypted = segment_decrypt_utils::decrypt_segment(&encrypted, &key, &iv)?;
+
+        let cache = services.proxy_cache.clone();
+        let bytes_clone = decrypted.clone();
+        tokio::spawn(async move {
+            cache.cache_segment(&decrypted_url, &bytes_clone).await;
+        });
+
+        Self::build_segment_response(
+            &decrypted,
+            headers,
+            schema,
+            false,
+            services.config.segment_compression_level,
+        )
+    }
+
+    // a key or a still encrypted segment, whole and decompressed
+    async fn fetch_for_decryption(
+        url: &str,
+        services: &EdgeServices,
+        schema: &str,
+    ) -> AppResult<Vec<u8>> {
+        let request_builder = upstream_utils::apply_schema_headers(
+            services
+                .upstream_timeouts
+                .apply(services.http.get(url), url),
+            schema,
+            url,
+        );
+
+        services.acquire_upstream_permit().await;
+        let response = services
+            .upstream_attempts
+            .send("decrypt", request_builder)
+            .await
+            .map_err(|e| {
+                error!("Request failed: {}", e);
+                Error::BadGateway(format!("Request failed: {}", e))
+            })?;
+
+        if !response.status().is_success() {
+            error!("Upstream returned {} for {}", response.status(), url);
+            return Err(Error::BadGateway(
+                "Api returned an invalid response".to_string(),
+            ));
         }
+
+        let body = decode_utils::read_decoded_body_pooled(
+            response,
+            None,
+            0,
+            &services.body_buffers,
+            |request| request.send(),
+        )
+        .await?;
+        Ok(body.to_vec())
     }
 
     async fn proxy_options() -> impl IntoResponse {
@@ -451,53 +867,128 @@ impl ProxyController {
         full_bytes: &[u8],
         headers: &HeaderMap,
     ) -> (Vec<u8>, StatusCode, Option<String>) {
-        let total_len = full_bytes.len();
-        if let Some(range_value) = headers.get(header::RANGE) {
-            if let Ok(range_str) = range_value.to_str() {
-                if let Some(range_part) = range_str.strip_prefix("bytes=") {
-                    let parts: Vec<&str> = range_part.split('-').collect();
-                    if parts.len() == 2 {
-                        let start: usize = parts[0].parse().unwrap_or(0);
-                        let end: usize = if parts[1].is_empty() {
-                            total_len.saturating_sub(1)
-                        } else {
-                            parts[1].parse().unwrap_or(total_len.saturating_sub(1))
-                        };
-                        let end = end.min(total_len.saturating_sub(1));
-
-                        if start < total_len && start <= end {
-                            let sliced = full_bytes[start..=end].to_vec();
-                            let content_range = format!("bytes {}-{}/{}", start, end, total_len);
-                            debug!("Serving range {}-{} of {} bytes", start, end, total_len);
-                            return (sliced, StatusCode::PARTIAL_CONTENT, Some(content_range));
-                        }
-                    }
-                }
-            }
+        let total_len = full_bytes.len() as u64;
+        let resolved = ByteRange::from_headers(headers).and_then(|r| r.resolve(total_len));
+
+        if let Some((start, end)) = resolved {
+            let sliced = full_bytes[start as usize..=end as usize].to_vec();
+            let content_range = range_utils::content_range(start, end, total_len);
+            debug!("Serving range {}-{} of {} bytes", start, end, total_len);
+            return (sliced, StatusCode::PARTIAL_CONTENT, Some(content_range));
         }
         (full_bytes.to_vec(), StatusCode::OK, None)
     }
 
-    /// Build a complete segment (TS/MP4) response with range handling, compression, and cache headers.
-    fn build_segment_response(
+    /// Build a `multipart/byteranges` response when the client asked for several ranges at once,
+    /// None for a single range or none at all. Ranges that are out of bounds are left out,
+    /// overlapping ones are merged, and when none are left or there are too many the full body
+    /// goes out like it does for a single bad range.
+    fn build_multi_range_response(
         full_bytes: &[u8],
         headers: &HeaderMap,
         schema: &str,
         is_mp4: bool,
-    ) -> AppResult<Response> {
-        let (response_bytes, status_code, range_header) = Self::apply_range(full_bytes, headers);
+    ) -> Option<Response> {
+        let ranges = headers
+            .get(header::RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(ByteRange::parse_list)
+            .filter(|ranges| ranges.len() > 1)?;
 
-        let encoding = ContentEncoding::from_accept_encoding(
-            headers
-                .get(header::ACCEPT_ENCODING)
-                .and_then(|v| v.to_str().ok()),
+        let total_len = full_bytes.len() as u64;
+        let resolved: Vec<(u64, u64)> = ranges
+            .iter()
+            .filter_map(|range| range.resolve(total_len))
+            .collect();
+        let resolved = range_utils::coalesce_ranges(&resolved, total_len)?;
+        if resolved.is_empty() {
+            return None;
+        }
+
+        let multipart = MultipartRanges::build(
+            full_bytes,
+            &resolved,
+            Self::segment_content_type(is_mp4),
+            &MultipartRanges::new_boundary(),
+        );
+        debug!(
+            "Serving {} ranges of {} bytes as multipart",
+            resolved.len(),
+            total_len
         );
 
+        // parts are never compressed, same as single ranges
+        let mut response_headers = Self::segment_headers(schema, is_mp4);
+        response_headers.insert(
+            header::CONTENT_TYPE,
+            multipart
+                .content_type()
+                .parse()
+                .expect("Multipart content type should parse"),
+        );
+        response_headers.insert(
+            header::CONTENT_LENGTH,
+            multipart
+                .body
+                .len()
+                .to_string()
+                .parse()
+                .expect("Content length should parse"),
+        );
+
+        let body = multipart.body;
+        Some((StatusCode::PARTIAL_CONTENT, response_headers, body).into_response())
+    }
+
+    /// Relay the upstream's answer to a forwarded range (206 or 416) without touching the body.
+    fn relay_range<T: Send + 'static>(
+        target_response: reqwest::Response,
+        status: StatusCode,
+        content_range: Option<String>,
+        schema: &str,
+        hold: T,
+    ) -> Response {
+        let mut response_headers = Self::segment_headers(schema, true);
+
+        if let Some(content_range) = content_range.and_then(|v| v.parse().ok()) {
+            response_headers.insert(header::CONTENT_RANGE, content_range);
+        }
+        // the range is over whatever encoding upstream used, so that has to go along with it
+        if let Some(encoding) = target_response.headers().get(header::CONTENT_ENCODING) {
+            response_headers.insert(header::CONTENT_ENCODING, encoding.clone());
+        }
+        if let Some(length) = target_response.content_length() {
+            response_headers.insert(
+                header::CONTENT_LENGTH,
+                length
+                    .to_string()
+                    .parse()
+                    .expect("Content length should parse"),
+            );
+        }
+
+        debug!(
+            "Relaying upstream {} for range {:?}",
+            status,
+            response_headers.get(header::CONTENT_RANGE)
+        );
+        let body = upstream_utils::passthrough_body(target_response, hold);
+
+        (status, response_headers, body).into_response()
+    }
+
+    /// Content-Type of a segment, MP4 files keep theirs.
+    fn segment_content_type(is_mp4: bool) -> &'static str {
+        if is_mp4 { "video/mp4" } else { "video/mp2t" }
+    }
+
+    /// Headers every segment (TS/MP4) response gets, streamed or buffered.
+    fn segment_headers(schema: &str, is_mp4: bool) -> HeaderMap {
         let mut response_headers = HeaderMap::new();
 
         response_headers.insert(
             header::CONTENT_TYPE,
-            "video/mp2t"
+            Self::segment_content_type(is_mp4)
                 .parse()
                 .expect("Static header value should parse"),
         );
@@ -524,6 +1015,118 @@ impl ProxyController {
             "bytes".parse().expect("Static header value should parse"),
         );
 
+        response_headers
+    }
+
+    /// Whether a segment can go straight through without being buffered. Ranges get sliced and
+    /// compression needs the whole body, so only plain full segments the client doesn't want
+    /// compressed qualify, and the upstream body has to be uncompressed too.
+    fn can_stream_passthrough(
+        body_kind: Option<BodyKind>,
+        upstream_encoding: Option<&str>,
+        headers: &HeaderMap,
+    ) -> bool {
+        let is_segment = matches!(body_kind, Some(BodyKind::Ts) | Some(BodyKind::Mp4));
+        let upstream_plain =
+            upstream_encoding.is_none_or(|e| e.trim().eq_ignore_ascii_case("identity"));
+        let client_encoding = ContentEncoding::from_accept_encoding(
+            headers
+                .get(header::ACCEPT_ENCODING)
+                .and_then(|v| v.to_str().ok()),
+        );
+
+        is_segment
+            && upstream_plain
+            && !headers.contains_key(header::RANGE)
+            && client_encoding == ContentEncoding::None
+    }
+
+    /// Stream a segment body to the client as it arrives from upstream. `hold` (the host's
+    /// connection permit) is kept until the body is done.
+    fn stream_passthrough<T: Send + 'static>(
+        target_response: reqwest::Response,
+        schema: &str,
+        is_mp4: bool,
+        hold: T,
+    ) -> Response {
+        let mut response_headers = Self::segment_headers(schema, is_mp4);
+
+        if let Some(length) = target_response.content_length() {
+            response_headers.insert(
+                header::CONTENT_LENGTH,
+                length
+                    .to_string()
+                    .parse()
+                    .expect("Content length should parse"),
+            );
+        }
+
+        debug!(
+            "Streaming segment through (length: {:?})",
+            target_response.content_length()
+        );
+        let body = upstream_utils::passthrough_body(target_response, hold);
+
+        (StatusCode::OK, response_headers, body).into_response()
+    }
+
+    /// A segment from the proxy cache, tagged with an ETag. A client that already has it (its
+    /// If-None-Match matches) gets an empty 304 instead. The cache only has the bytes, so mp4 is
+    /// told apart by the host's content type override or the url's extension.
+    fn build_cached_segment_response(
+        cached_bytes: &[u8],
+        target_url: &str,
+        headers: &HeaderMap,
+        schema: &str,
+        services: &EdgeServices,
+    ) -> AppResult<Response> {
+        let is_mp4 =
+            services.content_type_overrides.kind_from_url(target_url) == Some(BodyKind::Mp4);
+        let etag = ProxyCacheService::segment_etag(target_url, cached_bytes.len());
+        if etag_utils::if_none_match(headers, &etag) {
+            debug!("Segment not modified ({}) for {}", etag, target_url);
+            return Ok(etag_utils::not_modified(
+                &etag,
+                &Self::segment_headers(schema, is_mp4),
+            ));
+        }
+
+        let mut response = Self::build_segment_response(
+            cached_bytes,
+            headers,
+            schema,
+            is_mp4,
+            services.config.segment_compression_level,
+        )?;
+        etag_utils::insert_etag(response.headers_mut(), &etag);
+        Ok(response)
+    }
+
+    /// Build a complete segment (TS/MP4) response with range handling, compression, and cache headers.
+    fn build_segment_response(
+        full_bytes: &[u8],
+        headers: &HeaderMap,
+        schema: &str,
+        is_mp4: bool,
+        compression_level: i32,
+    ) -> AppResult<Response> {
+        // more than one range gets a multipart body, a single range stays on the path below
+        if let Some(response) =
+            Self::build_multi_range_response(full_bytes, headers, schema, is_mp4)
+        {
+            return Ok(response);
+        }
+
+        let (response_bytes, status_code, range_header) = Self::apply_range(full_bytes, headers);
+
+        let encoding = ContentEncoding::from_accept_encoding(
+            headers
+                .get(header::ACCEPT_ENCODING)
+                .and_then(|v| v.to_str().ok()),
+        );
+
+        let mut response_headers = Self::segment_headers(schema, is_mp4);
+
         if let Some(range_val) = range_header {
             response_headers.insert(
                 header::CONTENT_RANGE,
@@ -532,38 +1135,41 @@ impl ProxyController {
         }
 
         // Only compress full responses (not partial content - Safari expects raw bytes for ranges)
-        let final_bytes =
-            if encoding != ContentEncoding::None && status_code != StatusCode::PARTIAL_CONTENT {
-                let compressed_bytes = encoding.compress(&response_bytes).map_err(|e| {
+        let final_bytes = if encoding != ContentEncoding::None
+            && status_code != StatusCode::PARTIAL_CONTENT
+        {
+            let compressed_bytes = encoding
+                .compress_with_level(&response_bytes, compression_level)
+                .map_err(|e| {
                     error!(
                         "Failed to compress binary response with {:?}: {}",
                         encoding, e
                     );
                     Error::InternalServerErrorWithContext("Failed to compress response".to_string())
                 })?;
-                debug!(
-                    "Compressed binary with {:?} from {} to {} bytes",
-                    encoding,
-                    response_bytes.len(),
-                    compressed_bytes.len()
-                );
-                if let Some(enc_header) = encoding.as_header_value() {
-                    response_headers.insert(
-                        header::CONTENT_ENCODING,
-                        enc_header
-                            .parse()
-                            .expect("Static header value should parse"),
-                    );
-                }
-                compressed_bytes
-            } else {
-                debug!(
-                    "Sending uncompressed {} bytes (partial: {})",
-                    response_bytes.len(),
-                    status_code == StatusCode::PARTIAL_CONTENT
+            debug!(
+                "Compressed binary with {:?} from {} to {} bytes",
+                encoding,
+                response_bytes.len(),
+                compressed_bytes.len()
+            );
+            if let Some(enc_header) = encoding.as_header_value() {
+                response_headers.insert(
+                    header::CONTENT_ENCODING,
+                    enc_header
+                        .parse()
+                        .expect("Static header value should parse"),
                 );
-                response_bytes
-            };
+            }
+            compressed_bytes
+        } else {
+            debug!(
+                "Sending uncompressed {} bytes (partial: {})",
+                response_bytes.len(),
+                status_code == StatusCode::PARTIAL_CONTENT
+            );
+            response_bytes
+        };
 
         response_headers.insert(
             header::CONTENT_LENGTH,
@@ -664,6 +1270,15 @@ impl ProxyController {
     //     Ok((StatusCode::OK, response_headers, bytes).into_response())
     // }
 
+    // counts a failure against the client in the background so it doesn't hold up the response
+    fn record_error_later(services: &EdgeServices, client_id: &str, failure: UpstreamFailure) {
+        let rate_limit = services.rate_limit.clone();
+        let uid = client_id.to_string();
+        tokio::spawn(async move {
+            rate_limit.record_error(&uid, failure).await;
+        });
+    }
+
     // decode my url encoding
     fn decode_url(url_param: &str) -> AppResult<String> {
         if url_param.starts_with("http://") || url_param.starts_with("https://") {
@@ -694,142 +1309,32 @@ impl ProxyController {
         }
     }
 
-    // this should always be sports but I'll keep it here incase you want to switch sources to
-    // streamed.pk or something and want to send their headers
-    fn apply_schema_headers(
-        mut request_builder: reqwest::RequestBuilder,
-        schema: &str,
-        target_url: &str,
-        _headers: &HeaderMap,
-    ) -> reqwest::RequestBuilder {
-        match schema {
-            // not needed for this case but it's here as another example
-            // "movie" => {
-            //     request_builder
-            //         .header(header::HOST, "storm.vodvidl.site")
-            //         .header(header::ORIGIN, "https://vidlink.pro")
-            //         .header(header::REFERER, "https://vidlink.pro/")
-            //         .header(
-            //             header::USER_AGENT,
-            //             "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:145.0) Gecko/20100101 Firefox/145.0",
-            //         )
-            //         .header(header::ACCEPT, "*/*")
-            //         .header(header::TE, "trailers")
-            // }
-            "sports" => {
-                // Always request compressed content from upstream - we handle decompression ourselves
-                // and will respect the client's Accept-Encoding when sending the response back
-                let accept_encoding = "gzip, deflate, br, zstd";
-
-                if target_url.contains("poocloud.in") {
-                    request_builder = request_builder
-                        .header(header::ORIGIN, "https://ppvs.su")
-                        .header(header::ACCEPT, "*/*")
-                        .header(header::ACCEPT_LANGUAGE, "en-US,en;q=0.9")
-                        .header(header::ACCEPT_ENCODING, accept_encoding)
-                        .header(header::REFERER, "https://modistreams.org/")
-                        .header(
-                            header::USER_AGENT,
-                            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
-                        )
-                        .header("Sec-GPC", "1")
-                        .header("Sec-Fetch-Dest", "empty")
-                        .header("Sec-Fetch-Mode", "cors")
-                        .header("Sec-Fetch-Site", "cross-site")
-                        .header(header::CONNECTION, "keep-alive")
-                        .header("Priority", "u=4")
-                        .header(header::PRAGMA, "no-cache")
-                        .header(header::CACHE_CONTROL, "no-cache")
-                }
-                if target_url.contains("modifiles.fans") {
-                    request_builder = request_builder
-                        .header(header::ORIGIN, "https://pooembed.eu")
-                        .header(header::ACCEPT, "*/*")
-                        .header(header::ACCEPT_LANGUAGE, "en-US,en;q=0.9")
-                        .header(header::ACCEPT_ENCODING, accept_encoding)
-                        .header(header::REFERER, "https://pooembed.eu/")
-                        .header(
-                            header::USER_AGENT,
-                            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
-                        )
-                        .header("Sec-GPC", "1")
-                        .header("Sec-Fetch-Dest", "empty")
-                        .header("Sec-Fetch-Mode", "cors")
-                        .header("Sec-Fetch-Site", "cross-site")
-                        .header(header::CONNECTION, "keep-alive")
-                        .header("Priority", "u=4")
-
-                }
-                else {
-                    request_builder = request_builder
-                        .header(header::REFERER, "https://api.ppv.to/api/streams/")
-                        .header(header::ORIGIN, "https://api.ppv.to/api/streams")
-                        .header(
-                            header::USER_AGENT,
-                            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
-                        )
-                        .header(header::ACCEPT_ENCODING, accept_encoding)
-                        .header(header::ACCEPT, "*/*");
-                }
-
-                // forward Range headers - we fetch full content, decompress, then serve the range ourselves
-                request_builder
-            }
-            "captions" => {
-                request_builder
-                    .header(
-                        header::USER_AGENT,
-                        "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:145.0) Gecko/20100101 Firefox/145.0",
-                    )
-                    .header(header::ACCEPT, "*/*")
-            }
-            _ => {
-                // default to sports if anything, but this ideally shouldn't happen and I should
-                // probably just send an error because it's malicious most times but whatever it's
-                // authenticated for right now.
-                info!("Unknown schema, falling back to sports headers");
-
-                // Always request compressed content from upstream
-                let accept_encoding = "gzip, deflate, br, zstd";
-
-                request_builder = request_builder
-                    .header(header::REFERER, "https://api.ppv.to/api/streams/")
-                    .header(header::ORIGIN, "https://api.ppv.to/api/streams")
-                    .header(
-                        header::USER_AGENT,
-                        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
-                    )
-                    .header(header::ACCEPT_ENCODING, accept_encoding)
-                    .header(header::ACCEPT, "*/*");
-
-                // Don't forward Range headers - we fetch full content, decompress, then serve the range ourselves
-                request_builder
-            }
-        }
-    }
-
-    fn process_m3u8_by_schema(
+    async fn process_m3u8_by_schema(
         text: &str,
         target_url: &str,
         client_id: &str,
         services: &EdgeServices,
         _schema: &str,
-    ) -> AppResult<String> {
+        options: &PlaylistOptions,
+    ) -> AppResult<PooledBuffer<String>> {
         // matcher for later if needed
         {
             debug!("Processing with sports schema");
-            Self::process_m3u8(text, target_url, client_id, services)
+            Self::process_m3u8(text, target_url, client_id, services, options).await
         }
     }
 
-    fn process_m3u8_by_schema_with_retry(
+    async fn process_m3u8_by_schema_with_retry(
         text: &str,
         target_url: &str,
         client_id: &str,
         services: &EdgeServices,
         schema: &str,
-    ) -> AppResult<String> {
-        let result = Self::process_m3u8_by_schema(text, target_url, client_id, services, schema);
+        options: &PlaylistOptions,
+    ) -> AppResult<PooledBuffer<String>> {
+        let result =
+            Self::process_m3u8_by_schema(text, target_url, client_id, services, schema, options)
+                .await;
 
         match &result {
             Err(Error::InternalServerError | Error::InternalServerErrorWithContext(_)) => {
@@ -839,77 +1344,30 @@ impl ProxyController {
                 //
                 // I don't recall ever seeing the above error! ever triggering though so I'm not
                 // sure when this would happen
-                Self::process_m3u8_by_schema(text, target_url, client_id, services, schema)
+                Self::process_m3u8_by_schema(text, target_url, client_id, services, schema, options)
+                    .await
             }
             _ => result,
         }
     }
 
-    fn process_m3u8(
+    async fn process_m3u8(
         text: &str,
         target_url: &str,
         client_id: &str,
         services: &EdgeServices,
-    ) -> AppResult<String> {
-        let base_url = url::Url::parse(target_url).map_err(|e| {
-            error!("Failed to parse base URL: {}", e);
-            Error::InternalServerErrorWithContext(format!("Invalid base URL: {}", e))
-        })?;
-
-        let base_path = format!(
-            "{}://{}{}",
-            base_url.scheme(),
-            base_url.host_str().unwrap_or(""),
-            &base_url.path()[..base_url.path().rfind('/').unwrap_or(0) + 1]
-        );
-
-        // trim comment lines that start with ## because it's some stupid fucking smiley face that
-        // says processed by indians in a hamster wheel LMAO
-        let lines: Vec<String> = text
-            .lines()
-            .filter(|line| !line.trim().starts_with("##"))
-            .map(|line| {
-                let trimmed = line.trim();
-
-                if trimmed.is_empty() || trimmed.starts_with('#') {
-                    return line.to_string();
-                }
-
-                let full_url = if trimmed.starts_with("http://") || trimmed.starts_with("https://")
-                {
-                    trimmed.to_string()
-                } else {
-                    match url::Url::parse(&base_path).and_then(|base| base.join(trimmed)) {
-                        Ok(resolved) => resolved.to_string(),
-                        Err(e) => {
-                            error!("Failed to resolve: {} - {}", trimmed, e);
-                            return line.to_string();
-                        }
-                    }
-                };
-
-                let encoded = URL_SAFE
-                    .encode(full_url.as_bytes())
-                    .trim_end_matches('=')
-                    .to_string();
-
-                let expiry = SignatureUtil::generate_expiry(12); // 12 hours
-                // sign just the encoded URL to avoid path mismatch issues
-                let signature = services
-                    .signature_util
-                    .generate_signature(client_id, expiry, &encoded);
-
-                format!(
-                    "/api/v1/proxy?url={}&schema=sports&sig={}&exp={}&client={}",
-                    encoded,
-                    signature,
-                    expiry,
-                    urlencoding::encode(client_id)
-                )
-            })
-            .collect();
-
-        Ok(lines.join("\n"))
+        options: &PlaylistOptions,
+    ) -> AppResult<PooledBuffer<String>> {
+        m3u8_utils::rewrite_playlist_offloaded(
+            text,
+            target_url,
+            client_id,
+            &services.signature_util,
+            options,
+            services.config.playlist_blocking_threshold_bytes,
+            services.playlist_buffers.take(),
+        )
+        .await
     }
 
     // movie processing not needed, but it's another example
@@ -1014,7 +1472,8 @@ impl ProxyController {
             }
 
             if prev_was_extinf && !trimmed.is_empty() && !trimmed.starts_with('#') {
-                let resolved = if trimmed.starts_with("http://") || trimmed.starts_with("https://") {
+                let resolved = if trimmed.starts_with("http://") || trimmed.starts_with("https://")
+                {
                     Some(trimmed.to_string())
                 } else {
                     url::Url::parse(&base_path)
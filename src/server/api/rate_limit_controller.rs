@@ -0,0 +1,46 @@
+use axum::Router;
+use axum::extract::Json;
+use axum::routing::get;
+use tracing::debug;
+
+use crate::server::dtos::rate_limit_dto::RateLimitStatusResponse;
+use crate::server::error::AppResult;
+use crate::server::extractors::EdgeAuthentication;
+
+pub struct RateLimitController;
+
+impl RateLimitController {
+    pub fn app() -> Router {
+        Router::new().route("/status", get(Self::get_status_endpoint))
+    }
+
+    /// lets a client see where it stands before it starts getting 429s. this doesn't count
+    /// against the quota
+    pub async fn get_status_endpoint(
+        EdgeAuthentication(client_id, services): EdgeAuthentication,
+    ) -> AppResult<Json<RateLimitStatusResponse>> {
+        debug!(
+            "recieved rate limit status request for client {}",
+            client_id
+        );
+
+        let status = services.rate_limit.peek_rate_limit(&client_id).await;
+        let timeout = services.rate_limit.is_user_timed_out(&client_id).await;
+        let error_score = services.rate_limit.get_error_score(&client_id).await;
+
+        let (timeout_reason, retry_after) = match timeout {
+            Some((reason, retry_after)) => (Some(reason), Some(retry_after)),
+            None => (None, None),
+        };
+
+        Ok(Json(RateLimitStatusResponse {
+            limit: status.limit,
+            remaining: status.remaining,
+            reset_at: status.reset_at,
+            timed_out: timeout_reason.is_some(),
+            timeout_reason,
+            retry_after,
+            error_score,
+        }))
+    }
+}
@@ -0,0 +1,46 @@
+use axum::Router;
+use axum::extract::Json;
+use axum::routing::post;
+use tracing::info;
+
+use crate::server::dtos::sign_dto::{SignUrlsRequest, SignUrlsResponse};
+use crate::server::error::AppResult;
+use crate::server::extractors::{AdminAuthentication, EdgeAuthentication};
+use crate::server::utils::sign_utils;
+
+pub struct SignController;
+
+impl SignController {
+    pub fn app() -> Router {
+        Router::new().route("/", post(Self::sign_urls_endpoint))
+    }
+
+    /// signs a batch of upstream urls for the caller so a front end can prefetch streams without
+    /// going through a playlist rewrite for each one. it hands out signatures for any allowed
+    /// host, so only our own back ends get it, with the admin token. the urls are bound to the
+    /// client id the request comes in with, `X-Client-Id` with the header strategy
+    pub async fn sign_urls_endpoint(
+        AdminAuthentication(_): AdminAuthentication,
+        EdgeAuthentication(client_id, services): EdgeAuthentication,
+        Json(request): Json<SignUrlsRequest>,
+    ) -> AppResult<Json<SignUrlsResponse>> {
+        info!(
+            "recieved request to sign {} urls for client {}",
+            request.urls.len(),
+            client_id
+        );
+
+        let expiry = services
+            .signature_util
+            .expiry_in(services.config.signed_url_expiry_hours);
+        let response = sign_utils::sign_batch(
+            &request,
+            &client_id,
+            &services.allowed_hosts,
+            &services.signature_util,
+            expiry,
+        )?;
+
+        Ok(Json(response))
+    }
+}
@@ -1,15 +1,16 @@
 use axum::Router;
-use axum::extract::{Json, Path};
+use axum::extract::{Json, Path, Query};
 use axum::routing::{delete, get};
-use base64::{Engine as _, engine::general_purpose::URL_SAFE};
 use serde::Serialize;
 use tracing::debug;
 use tracing::info;
 
-use crate::server::dtos::stream_dto::{GameDto, GameListResponse, ResponseStreamDto, SportsurgeEventDto, SportsurgeEventListResponse, SportsurgeStreamResponse};
+use crate::server::dtos::stream_dto::{GameDto, GameFilter, GameListResponse, ResponseStreamDto, SportsurgeEventDto, SportsurgeEventListResponse, SportsurgeStreamResponse};
 use crate::server::error::AppResult;
 use crate::server::extractors::EdgeAuthentication;
-use crate::server::utils::signature_utils::SignatureUtil;
+use crate::server::services::edge_services::EdgeServices;
+use crate::server::utils::m3u8_utils::sign_proxy_url_with_params;
+use crate::server::utils::upstream_utils::UpstreamTimeouts;
 
 pub struct StreamController;
 
@@ -32,6 +33,7 @@ impl StreamController {
                 get(Self::get_ppvsu_decoded_game_endpoint),
             )
             .route("/ppvsu/{id}/signed-url", get(Self::get_signed_url_endpoint))
+            .route("/ppvsu/{id}/resolve", get(Self::resolve_game_endpoint))
             // sportsurge routes
             .route("/sportsurge", get(Self::get_sportsurge_events_endpoint))
             .route("/sportsurge/{id}/embed", get(Self::get_sportsurge_embed_endpoint))
@@ -41,12 +43,14 @@ impl StreamController {
             .route("/{provider}", get(Self::get_stream_endpoint))
     }
 
+    /// `?category=` and `?only_live=true` narrow the list down
     pub async fn get_all_streams_endpoint(
         EdgeAuthentication(_client_id, services): EdgeAuthentication,
+        Query(filter): Query<GameFilter>,
     ) -> AppResult<Json<GameListResponse>> {
         info!("recieved request to retrieve all games with auto-fetch");
 
-        let categories = services.streams.get_all_games().await?;
+        let categories = services.streams.get_all_games(filter).await?;
 
         Ok(Json(GameListResponse { categories }))
     }
@@ -107,37 +111,55 @@ impl StreamController {
     ) -> AppResult<Json<SignedUrlResponse>> {
         info!("received request to generate signed URL for game {}", id);
 
+        Ok(Json(Self::resolve_game(&services, &client_id, id).await?))
+    }
+
+    /// the game's playable m3u8 as a signed proxy url, ready to hand to a player
+    pub async fn resolve_game_endpoint(
+        EdgeAuthentication(client_id, services): EdgeAuthentication,
+        Path(id): Path<i64>,
+    ) -> AppResult<Json<SignedUrlResponse>> {
+        info!("received request to resolve game {}", id);
+
+        Ok(Json(Self::resolve_game(&services, &client_id, id).await?))
+    }
+
+    async fn resolve_game(
+        services: &EdgeServices,
+        client_id: &str,
+        id: i64,
+    ) -> AppResult<SignedUrlResponse> {
         let game = services.ppvsu.get_game_by_id(id).await?;
-        let link = services.ppvsu.fetch_video_link(&game.video_link).await?;
 
-        let encoded_url = URL_SAFE
-            .encode(link.as_bytes())
-            .trim_end_matches('=')
-            .to_string();
+        // some games come with the playlist itself instead of an iframe, nothing to decrypt then.
+        // iframe links are cached by fetch_video_link
+        let link = if UpstreamTimeouts::is_playlist_url(&game.video_link) {
+            debug!("game {} already links straight to a playlist", id);
+            game.video_link
+        } else {
+            services.ppvsu.fetch_video_link(&game.video_link).await?
+        };
 
         // gen expiry (12 hours from now)
-        let expiry = SignatureUtil::generate_expiry(12);
-
-        // For edge, we sign with the client_id (IP + User-Agent hash) instead of user_id
-        let signature =
-            services
-                .signature_util
-                .generate_signature(&client_id, expiry, &encoded_url);
-
-        let signed_url = format!(
-            "/api/v1/proxy?url={}&schema=sports&sig={}&exp={}&client={}",
-            encoded_url,
-            signature,
+        let expiry = services.signature_util.expiry_in(12);
+
+        // For edge, we sign with the client_id (IP + User-Agent hash) instead of user_id, and
+        // tag it with the game so everything cached for it can be invalidated in one go
+        let signed_url = sign_proxy_url_with_params(
+            &link,
+            "sports",
+            client_id,
             expiry,
-            urlencoding::encode(&client_id)
+            &services.signature_util,
+            &[("stream", format!("ppvsu-{}", id))],
         );
 
         info!("generated signed URL for game {} (expires: {})", id, expiry);
 
-        Ok(Json(SignedUrlResponse {
+        Ok(SignedUrlResponse {
             signed_url,
             expires_at: expiry,
-        }))
+        })
     }
 
     // ===================================================================
@@ -1,12 +1,19 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::server::services::circuit_breaker_services::BreakerStatus;
+use crate::server::services::refresh_health_services::RefreshState;
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum HealthStatus {
     Healthy,
     Degraded,
     Unhealthy,
+    /// the service isn't used in this deployment (e.g. the database in edge mode)
+    Disabled,
+    /// shutting down, still finishing requests but shouldn't get new ones
+    Draining,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,10 +26,24 @@ pub struct HealthResponse {
     pub services: ServiceHealthDetails,
 }
 
+/// what `/health/live` and `/health/ready` answer, small enough for a probe every few seconds
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProbeResponse {
+    pub status: HealthStatus,
+    pub uptime_seconds: u64,
+    /// only on the readiness probe
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redis: Option<RedisHealth>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServiceHealthDetails {
     pub database: DatabaseHealth,
     pub redis: RedisHealth,
+    pub refresh: RefreshHealth,
+    /// upstream hosts this node has called and their circuit breakers
+    #[serde(default)]
+    pub circuit_breakers: Vec<BreakerStatus>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -33,8 +54,54 @@ pub struct DatabaseHealth {
     pub pool_max: u32,
 }
 
+impl DatabaseHealth {
+    /// edge mode has no database, report that instead of a fake healthy one
+    pub fn disabled() -> Self {
+        Self {
+            status: HealthStatus::Disabled,
+            response_time_ms: 0.0,
+            pool_active: 0,
+            pool_max: 0,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RedisHealth {
     pub status: HealthStatus,
     pub response_time_ms: f64,
+    /// reconnects in a row since redis last answered a ping
+    #[serde(default)]
+    pub reconnect_attempts: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshHealth {
+    pub status: HealthStatus,
+    pub last_success: Option<i64>,
+    /// seconds since the last successful refresh
+    pub age_seconds: Option<i64>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<i64>,
+}
+
+impl RefreshHealth {
+    /// nothing has run yet -> disabled, never succeeded or too old -> degraded
+    pub fn evaluate(state: &RefreshState, now: i64, stale_after_seconds: i64) -> Self {
+        let age_seconds = state.last_success.map(|at| now - at);
+        let status = match (age_seconds, &state.last_error) {
+            (None, None) => HealthStatus::Disabled,
+            (None, Some(_)) => HealthStatus::Degraded,
+            (Some(age), _) if age > stale_after_seconds => HealthStatus::Degraded,
+            (Some(_), _) => HealthStatus::Healthy,
+        };
+
+        Self {
+            status,
+            last_success: state.last_success,
+            age_seconds,
+            last_error: state.last_error.clone(),
+            last_error_at: state.last_error_at,
+        }
+    }
 }
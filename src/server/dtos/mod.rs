@@ -1,2 +1,5 @@
 pub mod health_dto;
+pub mod proxy_dto;
+pub mod rate_limit_dto;
+pub mod sign_dto;
 pub mod stream_dto;
@@ -0,0 +1,43 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::Deserialize;
+use validator::Validate;
+
+/// longest `url` the proxy takes, real upstream urls are nowhere near this even encoded
+pub const MAX_PROXY_URL_LEN: u64 = 8192;
+
+// the names signing accepts, whether it's one the proxy knows is up to the schema extractor
+static SCHEMA_NAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[A-Za-z0-9]{1,32}$").expect("Static regex should compile"));
+
+static HEX_IV: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[0-9a-fA-F]{32}$").expect("Static regex should compile"));
+
+/// query params of `/api/v1/proxy`, the signature params are read by the edge authentication
+#[derive(Deserialize, Debug, Validate)]
+pub struct ProxyQuery {
+    #[validate(length(min = 1, max = MAX_PROXY_URL_LEN, message = "url is empty or too long"))]
+    pub url: String,
+    #[validate(regex(path = *SCHEMA_NAME, message = "schema must be 1 to 32 letters or digits"))]
+    pub schema: Option<String>,
+    /// how many master playlists deep this request is, only set on variants of a master
+    pub depth: Option<u32>,
+    /// set on segments of a live playlist, cached ones are only served while they're fresh
+    pub live: Option<bool>,
+    /// which stream this url belongs to, cached entries are indexed under it for invalidation
+    pub stream: Option<String>,
+    /// `playlist` on variants/renditions of a master, the response is always handled as a
+    /// playlist
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    /// set on playlists the client wants AES-128 decrypted, see `PlaylistOptions::decrypt`
+    pub decrypt: Option<bool>,
+    /// encoded url of the AES-128 key the segment is encrypted with, only on segments of a
+    /// playlist rewritten with `decrypt`
+    #[validate(length(min = 1, max = MAX_PROXY_URL_LEN, message = "key is empty or too long"))]
+    pub key: Option<String>,
+    /// the iv that goes with `key`
+    #[validate(regex(path = *HEX_IV, message = "iv must be 32 hex digits"))]
+    pub iv: Option<String>,
+}
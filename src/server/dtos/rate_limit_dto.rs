@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RateLimitStatusResponse {
+    pub limit: u32,
+    pub remaining: u32,
+    /// unix timestamp the current window resets at
+    pub reset_at: i64,
+    pub timed_out: bool,
+    pub timeout_reason: Option<String>,
+    /// seconds until the timeout is lifted, only set while timed out
+    pub retry_after: Option<u64>,
+    /// decays over time, the client gets timed out once it reaches the configured max
+    pub error_score: f64,
+}
@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignUrlsRequest {
+    /// upstream urls, signed in the same order
+    pub urls: Vec<String>,
+    /// defaults to sports
+    pub schema: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedUrlResult {
+    pub url: String,
+    /// the `/api/v1/proxy` url, missing when this entry was rejected
+    pub signed_url: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignUrlsResponse {
+    pub urls: Vec<SignedUrlResult>,
+    pub expires_at: i64,
+}
@@ -61,6 +61,25 @@ pub struct GameListResponse {
     pub categories: Vec<CategoryDto>,
 }
 
+/// query params for the game list, `?category=Football&only_live=true`
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GameFilter {
+    /// matched case insensitively
+    pub category: Option<String>,
+    /// only games that have started and haven't ended yet
+    #[serde(default)]
+    pub only_live: bool,
+}
+
+impl GameFilter {
+    pub fn matches(&self, game: &Game, now: i64) -> bool {
+        self.category
+            .as_deref()
+            .is_none_or(|category| game.category.eq_ignore_ascii_case(category))
+            && (!self.only_live || game.is_live(now))
+    }
+}
+
 // Sportsurge-specific DTOs - simplified
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SportsurgeEventDto {
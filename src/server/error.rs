@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::sync::OnceLock;
 use std::{collections::HashMap, fmt::Debug};
 
 use axum::extract::rejection::JsonRejection;
@@ -7,19 +8,47 @@ use axum::{Json, http::StatusCode, response::IntoResponse};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use thiserror::Error;
-use tracing::info;
+use tracing::{info, warn};
 use validator::{ValidationErrors, ValidationErrorsKind};
 
+use crate::server::utils::decode_utils::DecodeError;
+use crate::server::utils::request_id_utils;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ApiError {
     pub errors: HashMap<String, Vec<String>>,
+    pub error: ErrorEnvelope,
 }
 
 impl ApiError {
-    pub fn new(error: String) -> Self {
+    pub fn new(code: &str, error: String) -> Self {
+        let envelope = ErrorEnvelope::new(code, &error);
         let mut error_map: HashMap<String, Vec<String>> = HashMap::new();
         error_map.insert("message".to_owned(), vec![error]);
-        Self { errors: error_map }
+        Self {
+            errors: error_map,
+            error: envelope,
+        }
+    }
+}
+
+/// the `error` part of every error body. `code` never changes for a kind of error so clients can
+/// branch on it instead of the message, `errors` is still sent next to it for older clients
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ErrorEnvelope {
+    pub code: String,
+    pub message: String,
+    /// same as the x-request-id header, None outside of a request
+    pub request_id: Option<String>,
+}
+
+impl ErrorEnvelope {
+    pub fn new(code: &str, message: &str) -> Self {
+        Self {
+            code: code.to_string(),
+            message: message.to_string(),
+            request_id: request_id_utils::current(),
+        }
     }
 }
 
@@ -29,6 +58,45 @@ pub type AppResult<T> = Result<T, Error>;
 
 pub type ErrorMap = HashMap<Cow<'static, str>, Vec<Cow<'static, str>>>;
 
+/// what gets sent back for denied requests (unauthorized/forbidden) when the operator wants one
+/// generic answer, so clients can't tell if it was the signature, an allowlist or anything else.
+/// the real reason is still logged
+#[derive(Debug, Clone)]
+pub struct DenialResponse {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+static DENIAL_RESPONSE: OnceLock<DenialResponse> = OnceLock::new();
+
+impl DenialResponse {
+    /// None when neither is configured, which keeps the normal per error responses
+    pub fn from_config(status: Option<u16>, message: Option<String>) -> Option<Self> {
+        if status.is_none() && message.is_none() {
+            return None;
+        }
+
+        let status = match status.map(StatusCode::from_u16) {
+            Some(Ok(status)) => status,
+            Some(Err(_)) => {
+                warn!("Invalid denial status configured, falling back to 403");
+                StatusCode::FORBIDDEN
+            }
+            None => StatusCode::FORBIDDEN,
+        };
+
+        Some(Self {
+            status,
+            message: message.unwrap_or_else(|| "Access denied".to_string()),
+        })
+    }
+
+    /// set once at startup, every denial after that uses it
+    pub fn install(self) {
+        let _ = DENIAL_RESPONSE.set(self);
+    }
+}
+
 // add any errors that are needed here
 #[derive(Error, Debug)]
 pub enum Error {
@@ -54,6 +122,14 @@ pub enum Error {
     UnprocessableEntity { errors: ErrorMap },
     #[error("{message}")]
     TooManyRequests { message: String, retry_after: u64 },
+    #[error("{message}")]
+    ServiceUnavailable { message: String, retry_after: u64 },
+    #[error("{0}")]
+    BadGateway(String),
+    #[error("{0}")]
+    UpstreamBody(DecodeError),
+    #[error("{0}")]
+    GatewayTimeout(String),
     #[error(transparent)]
     ValidationError(#[from] ValidationErrors),
     #[error(transparent)]
@@ -63,6 +139,29 @@ pub enum Error {
 }
 
 impl Error {
+    /// machine readable code for the body, one per kind of error
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Unauthorized => "unauthorized",
+            Self::InvalidLoginAttmpt => "invalid_login",
+            Self::Forbidden => "forbidden",
+            Self::NotFound(_) => "not_found",
+            Self::BadRequest(_) => "bad_request",
+            Self::ObjectConflict(_) => "conflict",
+            Self::UnprocessableEntity { .. } => "unprocessable_entity",
+            Self::TooManyRequests { .. } => "rate_limited",
+            Self::ServiceUnavailable { .. } => "service_unavailable",
+            Self::BadGateway(_) | Self::UpstreamBody(_) => "bad_gateway",
+            Self::GatewayTimeout(_) => "gateway_timeout",
+            Self::ValidationError(_) => "validation_failed",
+            Self::AxumJsonRejection(_) => "invalid_json",
+            Self::ApplicationStartup(_)
+            | Self::InternalServerError
+            | Self::InternalServerErrorWithContext(_)
+            | Self::AnyhowError(_) => "internal_error",
+        }
+    }
+
     pub fn unprocessable_entity(errors: ValidationErrors) -> Response {
         let mut validation_errors = ErrorMap::new();
 
@@ -113,6 +212,7 @@ impl Error {
 
         let body = Json(json!({
             "errors": validation_errors,
+            "error": ErrorEnvelope::new("validation_failed", "Request validation failed"),
         }));
 
         (StatusCode::BAD_REQUEST, body).into_response()
@@ -122,24 +222,68 @@ impl Error {
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
         info!("{:#?}", self);
+        let code = self.code();
         if let Self::ValidationError(e) = self {
             return Self::unprocessable_entity(e);
         }
 
+        if let (Self::Unauthorized | Self::Forbidden, Some(denial)) = (&self, DENIAL_RESPONSE.get())
+        {
+            // one code for every denial too, a different one would give the reason away
+            let body = Json(ApiError::new("access_denied", denial.message.clone()));
+            return (denial.status, body).into_response();
+        }
+
         // handle TooManyRequests separately to include Retry-After header
         if let Self::TooManyRequests {
             message,
             retry_after,
         } = self
         {
+            let envelope = ErrorEnvelope::new(code, &message);
             let body = Json(json!({
                 "errors": {
                     "message": [message]
                 },
+                "error": envelope,
                 "retry_after": retry_after
             }));
+            // nothing left until the window resets, reset is a unix timestamp like `reset_at`
+            let reset_at = chrono::Utc::now().timestamp() + retry_after as i64;
             return (
                 StatusCode::TOO_MANY_REQUESTS,
+                [
+                    (axum::http::header::RETRY_AFTER, retry_after.to_string()),
+                    (
+                        axum::http::HeaderName::from_static("x-ratelimit-remaining"),
+                        "0".to_string(),
+                    ),
+                    (
+                        axum::http::HeaderName::from_static("x-ratelimit-reset"),
+                        reset_at.to_string(),
+                    ),
+                ],
+                body,
+            )
+                .into_response();
+        }
+
+        // upstream backpressure, also tells the client when to come back
+        if let Self::ServiceUnavailable {
+            message,
+            retry_after,
+        } = self
+        {
+            let envelope = ErrorEnvelope::new(code, &message);
+            let body = Json(json!({
+                "errors": {
+                    "message": [message]
+                },
+                "error": envelope,
+                "retry_after": retry_after
+            }));
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
                 [(axum::http::header::RETRY_AFTER, retry_after.to_string())],
                 body,
             )
@@ -149,7 +293,11 @@ impl IntoResponse for Error {
         let (status, error_message) = match self {
             Self::InternalServerErrorWithContext(err) => (StatusCode::INTERNAL_SERVER_ERROR, err),
             Self::NotFound(err) => (StatusCode::NOT_FOUND, err),
+            Self::BadRequest(err) => (StatusCode::BAD_REQUEST, err),
             Self::ObjectConflict(err) => (StatusCode::CONFLICT, err),
+            Self::BadGateway(err) => (StatusCode::BAD_GATEWAY, err),
+            Self::UpstreamBody(err) => (StatusCode::BAD_GATEWAY, err.to_string()),
+            Self::GatewayTimeout(err) => (StatusCode::GATEWAY_TIMEOUT, err),
             Self::InvalidLoginAttmpt => (
                 StatusCode::BAD_REQUEST,
                 Self::InvalidLoginAttmpt.to_string(),
@@ -163,7 +311,7 @@ impl IntoResponse for Error {
             ),
         };
 
-        let body = Json(ApiError::new(error_message));
+        let body = Json(ApiError::new(code, error_message));
 
         (status, body).into_response()
     }
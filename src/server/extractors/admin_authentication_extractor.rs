@@ -0,0 +1,56 @@
+use axum::Extension;
+use axum::extract::FromRequestParts;
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use tracing::warn;
+
+use crate::server::error::Error;
+use crate::server::services::edge_services::EdgeServices;
+
+pub struct AdminAuthentication(pub EdgeServices);
+
+/// admin routes take `Authorization: Bearer <admin_token>`, with no admin_token configured every
+/// admin request is rejected
+impl<S> FromRequestParts<S> for AdminAuthentication
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(services): Extension<EdgeServices> =
+            Extension::from_request_parts(parts, state)
+                .await
+                .map_err(|err| Error::InternalServerErrorWithContext(err.to_string()))?;
+
+        let Some(admin_token) = services.config.admin_token.as_deref() else {
+            warn!("admin request rejected, no admin token configured");
+            return Err(Error::Forbidden);
+        };
+
+        let provided = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .ok_or(Error::Unauthorized)?;
+
+        if !tokens_match(provided, admin_token) {
+            warn!("admin request rejected, bad token");
+            return Err(Error::Unauthorized);
+        }
+
+        Ok(AdminAuthentication(services))
+    }
+}
+
+// constant time so the token can't be guessed a byte at a time
+fn tokens_match(provided: &str, expected: &str) -> bool {
+    provided.len() == expected.len()
+        && provided
+            .as_bytes()
+            .iter()
+            .zip(expected.as_bytes().iter())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
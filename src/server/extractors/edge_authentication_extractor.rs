@@ -2,30 +2,251 @@ use axum::Extension;
 use axum::extract::{ConnectInfo, FromRequestParts, Query};
 use axum::http::header::USER_AGENT;
 use axum::http::request::Parts;
+use hmac::{Hmac, Mac};
 use serde::Deserialize;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-use std::net::SocketAddr;
-use tracing::{debug, error};
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use tracing::{debug, error, warn};
 
 use crate::server::error::Error;
 use crate::server::services::edge_services::EdgeServices;
+use crate::server::utils::signature_utils::signed_payload;
 
 #[derive(Deserialize)]
 struct SignedUrlQuery {
     sig: Option<String>,
     exp: Option<String>,
     client: Option<String>, // client identifier (hashed IP + user-agent)
+    kid: Option<String>,    // which signing key was used, urls from before key ids have none
 }
 
 pub struct EdgeAuthentication(pub String, pub EdgeServices);
 
-/// generates a client identifier from IP address and user-agent
+/// header partners send their own client id in when the header strategy is on
+pub const CLIENT_ID_HEADER: &str = "x-client-id";
+
+/// which request signals the client id is derived from
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ClientIdStrategy {
+    Ip,
+    #[default]
+    IpUserAgent,
+    /// the `X-Client-Id` header when it's there, IP + user-agent when it isn't. only turn this
+    /// on behind something that sets/strips the header, anyone can send it otherwise
+    Header,
+}
+
+impl ClientIdStrategy {
+    /// `ip`, `ip_ua` or `header`, anything else falls back to `ip_ua`
+    pub fn parse(strategy: &str) -> Self {
+        match strategy.trim().to_ascii_lowercase().as_str() {
+            "ip" => Self::Ip,
+            "ip_ua" => Self::IpUserAgent,
+            "header" => Self::Header,
+            other => {
+                warn!("Unknown client id strategy {}, using ip_ua", other);
+                Self::IpUserAgent
+            }
+        }
+    }
+}
+
+/// proxies/load balancers whose X-Forwarded-For and X-Real-IP headers are believed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrustedProxies {
+    ranges: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// comma seperated cidrs (`10.0.0.0/8`, `fc00::/7`) or plain ips, entries that don't parse
+    /// are skipped
+    pub fn parse_list(list: &str) -> Self {
+        let ranges = list
+            .split(',')
+            .map(|entry| entry.trim())
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| {
+                let range = Self::parse_range(entry);
+                if range.is_none() {
+                    warn!("Ignoring trusted proxy {}, not an ip or cidr", entry);
+                }
+                range
+            })
+            .collect();
+        Self { ranges }
+    }
+
+    fn parse_range(entry: &str) -> Option<(IpAddr, u8)> {
+        let (ip, prefix) = match entry.split_once('/') {
+            Some((ip, prefix)) => (ip.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+            None => (entry.parse::<IpAddr>().ok()?, None),
+        };
+        let bits = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(bits);
+        (prefix <= bits).then_some((ip, prefix))
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // v4 peers on a dual stack socket show up as ::ffff:a.b.c.d
+        let ip = ip.to_canonical();
+        self.ranges.iter().any(|(network, prefix)| {
+            let (network, ip, bits) = match (network, ip) {
+                (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                    (u32::from(*network) as u128, u32::from(ip) as u128, 32)
+                }
+                (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                    (u128::from(*network), u128::from(ip), 128)
+                }
+                _ => return false,
+            };
+            // a /0 would shift the whole value out
+            let shift = bits - *prefix as u32;
+            *prefix == 0 || network >> shift == ip >> shift
+        })
+    }
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+// keyed so a client that knows its own ip and user-agent can't work out its id (or anyone
+// else's) without the secret. sha-256 won't change between builds the way DefaultHasher can
+fn hash_client_id(secret: &str, parts: &[&str]) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    for part in parts {
+        // length prefixed so ("ab", "c") and ("a", "bc") don't hash the same
+        mac.update(&(part.len() as u64).to_be_bytes());
+        mac.update(part.as_bytes());
+    }
+    // 128 bits is plenty for an id and keeps the signed urls shorter
+    hex::encode(&mac.finalize().into_bytes()[..16])
+}
+
+/// generates a client identifier from IP address and user-agent, without a secret
 pub fn generate_client_id(ip: Option<&str>, user_agent: Option<&str>) -> String {
-    let mut hasher = DefaultHasher::new();
-    ip.unwrap_or("unknown").hash(&mut hasher);
-    user_agent.unwrap_or("unknown").hash(&mut hasher);
-    format!("{:x}", hasher.finish())
+    generate_keyed_client_id("", ip, user_agent)
+}
+
+/// `generate_client_id` keyed with a secret, what requests actually get. changing the secret
+/// changes every id
+pub fn generate_keyed_client_id(
+    secret: &str,
+    ip: Option<&str>,
+    user_agent: Option<&str>,
+) -> String {
+    hash_client_id(
+        secret,
+        &[
+            "ip_ua",
+            ip.unwrap_or("unknown"),
+            user_agent.unwrap_or("unknown"),
+        ],
+    )
+}
+
+/// client id for the configured strategy. a partner header that's empty, too long or has odd
+/// characters is ignored rather than trusted
+pub fn derive_client_id(
+    strategy: ClientIdStrategy,
+    ip: Option<&str>,
+    user_agent: Option<&str>,
+    client_header: Option<&str>,
+) -> String {
+    derive_keyed_client_id("", strategy, ip, user_agent, client_header)
+}
+
+/// `derive_client_id` keyed with a secret
+pub fn derive_keyed_client_id(
+    secret: &str,
+    strategy: ClientIdStrategy,
+    ip: Option<&str>,
+    user_agent: Option<&str>,
+    client_header: Option<&str>,
+) -> String {
+    match strategy {
+        ClientIdStrategy::Ip => hash_client_id(secret, &["ip", ip.unwrap_or("unknown")]),
+        ClientIdStrategy::IpUserAgent => generate_keyed_client_id(secret, ip, user_agent),
+        ClientIdStrategy::Header => match client_header.map(|h| h.trim()).filter(|h| {
+            !h.is_empty()
+                && h.len() <= 128
+                && h.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        }) {
+            Some(header) => hash_client_id(secret, &["header", header]),
+            None => generate_keyed_client_id(secret, ip, user_agent),
+        },
+    }
+}
+
+/// the client's ip, what client ids and the per ip rate limit are worked out from.
+/// X-Forwarded-For is walked from the right (the hop closest to us) skipping trusted proxies,
+/// the first hop that isn't one is the client, anything left of it was written by the client
+/// and can say whatever it wants. the headers only count when the peer itself is a trusted
+/// proxy, otherwise the peer is the client. requests without connection info (in process,
+/// like the tests) are treated as coming from a trusted proxy
+pub fn client_ip(parts: &Parts, trusted_proxies: &TrustedProxies) -> Option<String> {
+    let peer = parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ci| ci.0.ip().to_canonical());
+    if let Some(peer) = peer.filter(|peer| !trusted_proxies.contains(*peer)) {
+        return Some(peer.to_string());
+    }
+
+    let hops: Vec<&str> = parts
+        .headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(|hop| hop.trim())
+        .filter(|hop| !hop.is_empty())
+        .collect();
+    let client = hops.iter().rev().find(|hop| {
+        !hop.parse::<IpAddr>()
+            .is_ok_and(|ip| trusted_proxies.contains(ip))
+    });
+
+    client
+        // every hop was one of ours, the request started on the inside
+        .or(hops.first())
+        .map(|hop| hop.to_string())
+        .or_else(|| {
+            parts
+                .headers
+                .get("x-real-ip")
+                .and_then(|h| h.to_str().ok())
+                .map(|s| s.trim().to_string())
+        })
+        .or_else(|| peer.map(|peer| peer.to_string()))
+}
+
+/// the client id and ip for a request, the way the configured strategy works them out
+pub fn request_client_id(parts: &Parts, services: &EdgeServices) -> (String, Option<String>) {
+    let user_agent = parts.headers.get(USER_AGENT).and_then(|h| h.to_str().ok());
+
+    let client_ip = client_ip(parts, &services.trusted_proxies);
+
+    let client_header = parts
+        .headers
+        .get(CLIENT_ID_HEADER)
+        .and_then(|h| h.to_str().ok());
+
+    let client_id = derive_keyed_client_id(
+        &services.config.access_token_secret,
+        services.client_id_strategy,
+        client_ip.as_deref(),
+        user_agent,
+        client_header,
+    );
+    (client_id, client_ip)
+}
+
+// a query param as it was sent, still encoded the way it was signed
+fn raw_query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
 }
 
 /// edge authentication extractor - no database required
@@ -42,34 +263,7 @@ where
                 .await
                 .map_err(|err| Error::InternalServerErrorWithContext(err.to_string()))?;
 
-        let user_agent = parts
-            .headers
-            .get(USER_AGENT)
-            .and_then(|h| h.to_str().ok())
-            .map(|s| s.to_string());
-
-        // try to get client IP from X-Forwarded-For, X-Real-IP, or connection info
-        let client_ip = parts
-            .headers
-            .get("x-forwarded-for")
-            .and_then(|h| h.to_str().ok())
-            .and_then(|s| s.split(',').next())
-            .map(|s| s.trim().to_string())
-            .or_else(|| {
-                parts
-                    .headers
-                    .get("x-real-ip")
-                    .and_then(|h| h.to_str().ok())
-                    .map(|s| s.to_string())
-            })
-            .or_else(|| {
-                parts
-                    .extensions
-                    .get::<ConnectInfo<SocketAddr>>()
-                    .map(|ci| ci.0.ip().to_string())
-            });
-
-        let client_id = generate_client_id(client_ip.as_deref(), user_agent.as_deref());
+        let (client_id, client_ip) = request_client_id(parts, &services);
         debug!(
             "Generated client_id: {} from IP: {:?}",
             client_id, client_ip
@@ -82,6 +276,7 @@ where
                 sig: None,
                 exp: None,
                 client: None,
+                kid: None,
             }));
 
         // verify
@@ -91,28 +286,32 @@ where
                 Error::Unauthorized
             })?;
 
-            let url_param = parts
-                .uri
-                .query()
-                .and_then(|q| {
-                    q.split('&')
-                        .find(|param| param.starts_with("url="))
-                        .and_then(|param| param.strip_prefix("url="))
-                })
-                .ok_or_else(|| {
-                    error!("missing url parameter in signed URL");
-                    Error::Unauthorized
-                })?;
+            let raw_query = parts.uri.query().unwrap_or_default();
+            let url_param = raw_query_param(raw_query, "url").ok_or_else(|| {
+                error!("missing url parameter in signed URL");
+                Error::Unauthorized
+            })?;
+            let payload = signed_payload(url_param, |name| raw_query_param(raw_query, name));
 
             // use the client_id from the query (what was used to generate the signature)
             // or fall back to the current client_id
             let signature_client_id = query.client.as_deref().unwrap_or(&client_id);
 
-            if !services.signature_util.verify_signature(
+            // in strict mode a url shared with someone on another ip/user-agent stops working
+            if services.config.strict_client_binding && signature_client_id != client_id {
+                error!(
+                    "Signed url used by another client - signed for: {}, used by: {}",
+                    signature_client_id, client_id
+                );
+                return Err(Error::Unauthorized);
+            }
+
+            if !services.signature_util.verify_signature_with_kid(
                 signature_client_id,
                 expiry,
-                url_param,
+                &payload,
                 sig,
+                query.kid.as_deref(),
             ) {
                 error!(
                     "Signature invalid - url: {}, client: {}, expiry: {}",
@@ -122,6 +321,18 @@ where
             }
 
             debug!("Signature verified for client: {}", signature_client_id);
+        } else if services.config.strict_client_binding
+            && raw_query_param(parts.uri.query().unwrap_or_default(), "url").is_some()
+        {
+            // strict mode is pointless if the signature can just be left off. only proxy
+            // requests carry a url, the other routes are never signed
+            error!("Unsigned proxy request rejected in strict mode");
+            return Err(Error::Unauthorized);
+        } else if raw_query_param(parts.uri.query().unwrap_or_default(), "stream").is_some() {
+            // cached entries are indexed (and invalidated) by stream, only a signed url gets to
+            // say which one it belongs to
+            error!("Unsigned request naming a stream rejected");
+            return Err(Error::Unauthorized);
         }
 
         // allow requests through without strict auth
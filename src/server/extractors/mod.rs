@@ -1,7 +1,11 @@
+mod admin_authentication_extractor;
 mod edge_authentication_extractor;
+mod rate_limit_extractor;
 mod user_agent_extractor;
 mod validation_extractor;
 
+pub use admin_authentication_extractor::*;
 pub use edge_authentication_extractor::*;
+pub use rate_limit_extractor::*;
 pub use user_agent_extractor::*;
 pub use validation_extractor::*;
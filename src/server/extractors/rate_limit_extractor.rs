@@ -0,0 +1,57 @@
+use axum::Extension;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use tracing::warn;
+
+use crate::server::error::Error;
+use crate::server::extractors::request_client_id;
+use crate::server::services::edge_services::EdgeServices;
+use crate::server::services::rate_limit_services::RateLimitResult;
+
+/// counts the request against the client's (and its ip's) rate limit. clients over the limit or
+/// timed out for too many errors get a 429, what's left of the window comes back otherwise
+pub struct CheckedRateLimit(pub RateLimitResult);
+
+impl<S> FromRequestParts<S> for CheckedRateLimit
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(services): Extension<EdgeServices> =
+            Extension::from_request_parts(parts, state)
+                .await
+                .map_err(|err| Error::InternalServerErrorWithContext(err.to_string()))?;
+
+        let (client_id, client_ip) = request_client_id(parts, &services);
+
+        match services
+            .rate_limit
+            .check_rate_limit(&client_id, client_ip.as_deref())
+            .await
+        {
+            RateLimitResult::RateLimited { retry_after } => {
+                warn!("Rate limited client {} ({:?})", client_id, client_ip);
+                Err(Error::TooManyRequests {
+                    message: "Too many requests, slow down".to_string(),
+                    retry_after,
+                })
+            }
+            RateLimitResult::TimedOut {
+                reason,
+                retry_after,
+            } => {
+                warn!(
+                    "Timed out client {} ({:?}): {}",
+                    client_id, client_ip, reason
+                );
+                Err(Error::TooManyRequests {
+                    message: format!("Timed out: {}", reason),
+                    retry_after,
+                })
+            }
+            allowed => Ok(CheckedRateLimit(allowed)),
+        }
+    }
+}
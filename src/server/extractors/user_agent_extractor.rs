@@ -1,8 +1,11 @@
+use axum::Extension;
 use axum::extract::FromRequestParts;
 use axum::http::header::USER_AGENT;
 use axum::http::request::Parts;
+use tracing::warn;
 
 use crate::server::error::Error;
+use crate::server::services::edge_services::EdgeServices;
 
 pub struct UserAgentExtractor(pub Option<String>);
 
@@ -21,3 +24,139 @@ where
         }
     }
 }
+
+// native hls players, matched case insensitively anywhere in the user agent
+const PLAYER_AGENTS: &[&str] = &[
+    "applecoremedia",
+    "appletv",
+    "exoplayer",
+    "stagefright",
+    "vlc",
+    "kodi",
+    "lavf",
+    "mpv",
+    "roku",
+];
+
+// crawlers and http libraries. "bot/" rather than "bot" so phones like the cubot don't count
+const BOT_AGENTS: &[&str] = &[
+    "bot/",
+    "crawler",
+    "spider",
+    "+http",
+    "facebookexternalhit",
+    "curl/",
+    "wget/",
+    "python-requests",
+    "python-urllib",
+    "go-http-client",
+    "scrapy",
+    "headlesschrome",
+    "phantomjs",
+];
+
+/// what kind of client a user agent belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserAgentClass {
+    /// no user agent or a blank one
+    Missing,
+    /// a native hls player
+    Player,
+    /// a crawler, scraper or http library
+    Bot,
+    /// anything else, mostly browsers playing through hls.js
+    Other,
+}
+
+impl UserAgentClass {
+    /// players win over bots, some players send their http library's name along with their own
+    pub fn classify(user_agent: Option<&str>) -> Self {
+        let Some(user_agent) = user_agent.map(|ua| ua.trim()).filter(|ua| !ua.is_empty()) else {
+            return Self::Missing;
+        };
+        let user_agent = user_agent.to_ascii_lowercase();
+
+        if PLAYER_AGENTS.iter().any(|agent| user_agent.contains(agent)) {
+            Self::Player
+        } else if BOT_AGENTS.iter().any(|agent| user_agent.contains(agent)) {
+            Self::Bot
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// which user agents the proxy serves. off lets everything through, on refuses missing and bot
+/// user agents. the allow list is checked first and the deny list refuses on top of the bots
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserAgentPolicy {
+    enforce: bool,
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl UserAgentPolicy {
+    /// `allow` and `deny` are the comma seperated lists from the config, matched case
+    /// insensitively anywhere in the user agent
+    pub fn new(enforce: bool, allow: &str, deny: &str) -> Self {
+        Self {
+            enforce,
+            allow: Self::parse_list(allow),
+            deny: Self::parse_list(deny),
+        }
+    }
+
+    fn parse_list(list: &str) -> Vec<String> {
+        list.split(',')
+            .map(|entry| entry.trim().to_ascii_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .collect()
+    }
+
+    pub fn allows(&self, user_agent: Option<&str>) -> bool {
+        if !self.enforce {
+            return true;
+        }
+
+        let lowered = user_agent.unwrap_or("").to_ascii_lowercase();
+        if self.allow.iter().any(|entry| lowered.contains(entry)) {
+            return true;
+        }
+        if self.deny.iter().any(|entry| lowered.contains(entry)) {
+            return false;
+        }
+
+        !matches!(
+            UserAgentClass::classify(user_agent),
+            UserAgentClass::Missing | UserAgentClass::Bot
+        )
+    }
+}
+
+/// the user agent's class, once it got past the configured user agent policy. a refused one is
+/// `Unauthorized`
+pub struct CheckedUserAgent(pub UserAgentClass);
+
+impl<S> FromRequestParts<S> for CheckedUserAgent
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(services): Extension<EdgeServices> =
+            Extension::from_request_parts(parts, state)
+                .await
+                .map_err(|err| Error::InternalServerErrorWithContext(err.to_string()))?;
+
+        // a header that isn't valid text is as good as none
+        let user_agent = parts.headers.get(USER_AGENT).and_then(|h| h.to_str().ok());
+
+        if !services.user_agent_policy.allows(user_agent) {
+            warn!("Refusing user agent {:?}", user_agent);
+            return Err(Error::Unauthorized);
+        }
+
+        Ok(CheckedUserAgent(UserAgentClass::classify(user_agent)))
+    }
+}
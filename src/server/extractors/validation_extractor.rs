@@ -1,13 +1,18 @@
 use axum::http::Request;
+use axum::http::request::Parts;
 use axum::{
-    Json,
+    Extension, Json,
     body::Body,
-    extract::{FromRequest, rejection::JsonRejection},
+    extract::{FromRequest, FromRequestParts, Query, rejection::JsonRejection},
 };
+use serde::Deserialize;
 use serde::de::DeserializeOwned;
+use tracing::warn;
 use validator::Validate;
 
 use crate::server::error::Error;
+use crate::server::services::edge_services::EdgeServices;
+use crate::server::utils::upstream_utils::Schema;
 
 pub struct ValidationExtractor<T>(pub T);
 
@@ -25,3 +30,67 @@ where
         Ok(ValidationExtractor(value))
     }
 }
+
+/// query params deserialized and run through their `Validate` rules, a query that doesn't
+/// deserialize is a 400 and failed rules are a 400 with the messages per field
+pub struct ValidatedQuery<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| Error::BadRequest(rejection.body_text()))?;
+        value.validate()?;
+        Ok(ValidatedQuery(value))
+    }
+}
+
+#[derive(Deserialize)]
+struct SchemaQuery {
+    schema: Option<String>,
+}
+
+/// the `schema` query param. a missing one is sports, an unknown one is a 400 with
+/// `strict_schema` on and falls back to sports otherwise
+pub struct SchemaExtractor(pub Schema);
+
+impl<S> FromRequestParts<S> for SchemaExtractor
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(services): Extension<EdgeServices> =
+            Extension::from_request_parts(parts, state)
+                .await
+                .map_err(|err| Error::InternalServerErrorWithContext(err.to_string()))?;
+
+        // a query that doesn't parse is the proxy's problem to report, not this one's
+        let schema = Query::<SchemaQuery>::from_request_parts(parts, state)
+            .await
+            .ok()
+            .and_then(|Query(query)| query.schema);
+        let Some(schema) = schema else {
+            return Ok(SchemaExtractor(Schema::default()));
+        };
+
+        match schema.parse() {
+            Ok(schema) => Ok(SchemaExtractor(schema)),
+            Err(e) if services.config.strict_schema => {
+                warn!("Rejecting unknown schema {:?}", schema);
+                Err(e)
+            }
+            Err(_) => {
+                warn!("Unknown schema {:?}, falling back to sports", schema);
+                Ok(SchemaExtractor(Schema::default()))
+            }
+        }
+    }
+}
@@ -1,45 +1,44 @@
 // Edge server module - no database, only Redis
-mod api;
+pub mod api;
 pub mod dtos;
 pub mod error;
 pub mod extractors;
 pub mod services;
 pub mod utils;
 
-use std::future::ready;
+use std::future::{Future, IntoFuture};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, OnceLock};
 use std::time::{Duration, Instant};
 
 use anyhow::Context;
 use axum::Extension;
-use axum::extract::MatchedPath;
+use axum::extract::{MatchedPath, State};
+use axum::http::Request;
 use axum::http::header::{self, ACCEPT, AUTHORIZATION, CONTENT_TYPE};
 use axum::http::method;
-use axum::http::request::Parts as RequestParts;
-use axum::http::{HeaderValue, Request};
 use axum::middleware::{self, Next};
 use axum::response::IntoResponse;
 use axum::routing::get;
-use axum::{BoxError, Json, Router, error_handling::HandleErrorLayer, http::StatusCode};
+use axum::{BoxError, Router, error_handling::HandleErrorLayer};
 use lazy_static::lazy_static;
 use method::Method;
 use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
-use serde_json::json;
 use tower::{ServiceBuilder, buffer::BufferLayer, limit::RateLimitLayer};
-use tower_http::{
-    cors::{AllowOrigin, CorsLayer},
-    trace::TraceLayer,
-};
-use tracing::{debug, info};
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::trace::TraceLayer;
+use tracing::{debug, info, warn};
 
 use crate::config::AppConfig;
 use crate::database::Database;
+use crate::server::error::{DenialResponse, Error};
 use crate::server::services::edge_services::EdgeServices;
+use crate::server::services::shutdown_services::{InFlightRequests, ShutdownHooks};
+use crate::server::utils::cors_utils::CorsOrigins;
+use crate::server::utils::request_id_utils;
 
 lazy_static! {
-    // 60 second timeout for video streaming (large segments)
-    // Must be longer than Fly.io health check timeout (10s)
-    static ref HTTP_TIMEOUT: u64 = 60;
     static ref EXPONENTIAL_SECONDS: &'static [f64] = &[
         0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 25.0, 50.0,
     ];
@@ -58,48 +57,6 @@ pub fn get_app_version() -> &'static str {
     env!("CARGO_PKG_VERSION")
 }
 
-macro_rules! cors_builder {
-    (
-        origins: $origins:expr,
-        preview_origins: $preview_origins:expr,
-        methods: $methods:expr,
-        headers: $headers:expr
-    ) => {{
-        let origins: Vec<String> = $origins;
-        let preview_origins: Vec<String> = $preview_origins;
-
-        CorsLayer::new()
-            .allow_origin(AllowOrigin::predicate(
-                move |origin: &HeaderValue, _request_parts: &RequestParts| {
-                    let origin_str = origin.to_str().unwrap_or("");
-                    if origins.iter().any(|s| s == "*") {
-                        return true;
-                    }
-                    if let Some(host) = origin_str
-                        .strip_prefix("https://")
-                        .or_else(|| origin_str.strip_prefix("http://"))
-                    {
-                        if origins.iter().any(|s| host.ends_with(s.as_str())) {
-                            return true;
-                        }
-                        if preview_origins.iter().any(|s| host.ends_with(s.as_str())) {
-                            return true;
-                        }
-                    }
-                    if origins.iter().any(|s| s.ends_with(origin_str))
-                        || preview_origins.iter().any(|s| s.ends_with(origin_str))
-                    {
-                        return true;
-                    }
-                    false
-                },
-            ))
-            .allow_methods($methods)
-            .allow_headers($headers)
-            .allow_credentials(true)
-    }};
-}
-
 pub struct EdgeApplicationServer;
 
 impl EdgeApplicationServer {
@@ -114,56 +71,74 @@ impl EdgeApplicationServer {
                 *EXPONENTIAL_SECONDS,
             )
             .context("could not setup metric buckets")?
+            .set_buckets_for_metric(
+                Matcher::Full(String::from("upstream_request_duration_seconds")),
+                *EXPONENTIAL_SECONDS,
+            )
+            .context("could not setup metric buckets")?
             .install_recorder()
             .context("can't run the metric recorder")?;
 
         let services = EdgeServices::new(db, config.clone());
+        let shutdown_hooks = services.shutdown_hooks.clone();
+        let accepting_traffic = services.accepting_traffic.clone();
+
+        // stopped by its shutdown hook
+        if let Some(games_refresh) = services.games_refresh.clone() {
+            games_refresh.spawn();
+        }
+
+        if let Some(denial) =
+            DenialResponse::from_config(config.denial_status, config.denial_message.clone())
+        {
+            info!("using configured denial response ({})", denial.status);
+            denial.install();
+        }
 
         // CORS configuration
-        let cors_origins: Vec<String> = config
-            .cors_origin
-            .split(",")
-            .map(|s| {
-                s.trim_start_matches("https://")
-                    .trim_start_matches("http://")
-                    .to_string()
-            })
-            .collect();
-        let preview_cors_origins: Vec<String> = config
-            .preview_cors_origin
-            .split(",")
-            .map(|s| {
-                s.trim_start_matches("https://")
-                    .trim_start_matches("http://")
-                    .to_string()
-            })
-            .collect();
-
-        let cors = cors_builder!(
-            origins: cors_origins.clone(),
-            preview_origins: preview_cors_origins.clone(),
-            methods: vec![
+        let cors_origins = CorsOrigins::parse(&config.cors_origin, &config.preview_cors_origin);
+
+        let cors = cors_origins.clone().layer(
+            vec![
                 Method::GET,
                 Method::POST,
                 Method::PUT,
                 Method::DELETE,
                 Method::OPTIONS,
             ],
-            headers: vec![AUTHORIZATION, CONTENT_TYPE, ACCEPT]
+            vec![AUTHORIZATION, CONTENT_TYPE, ACCEPT],
         );
 
-        let proxy_cors = cors_builder!(
-            origins: cors_origins,
-            preview_origins: preview_cors_origins,
-            methods: vec![Method::GET, Method::OPTIONS],
-            headers: vec![AUTHORIZATION, CONTENT_TYPE, header::RANGE]
-        )
-        .expose_headers([header::CONTENT_LENGTH, header::CONTENT_RANGE]);
+        // what hls/video players send, Range for seeking and partial segments
+        let proxy_cors = cors_origins
+            .layer(
+                vec![Method::GET, Method::OPTIONS],
+                vec![
+                    AUTHORIZATION,
+                    CONTENT_TYPE,
+                    header::RANGE,
+                    header::ACCEPT_ENCODING,
+                ],
+            )
+            .expose_headers([
+                header::CONTENT_LENGTH,
+                header::CONTENT_RANGE,
+                header::ACCEPT_RANGES,
+                request_id_utils::REQUEST_ID_HEADER,
+            ]);
 
-        // edge routes: streams, proxy, health (with CORS)
+        // edge routes: streams, proxy, health, rate limit status, admin, url signing (with CORS)
         let api_routes = Router::new()
             .nest("/streams", api::stream_controller::StreamController::app())
             .route("/health", get(api::health_controller::health_endpoint))
+            .route("/health/live", get(api::health_controller::live_endpoint))
+            .route("/health/ready", get(api::health_controller::ready_endpoint))
+            .nest(
+                "/ratelimit",
+                api::rate_limit_controller::RateLimitController::app(),
+            )
+            .nest("/admin", api::admin_controller::AdminController::app())
+            .nest("/sign", api::sign_controller::SignController::app())
             .layer(cors);
 
         let proxy_routes = Router::new()
@@ -173,18 +148,19 @@ impl EdgeApplicationServer {
         // Main API router
         let api_router = Router::new()
             .route("/", get(api::health_controller::health_endpoint))
-            .route("/metrics", get(move || ready(recorder_handle.render())))
+            .merge(api::metrics_controller::MetricsController::app(
+                recorder_handle,
+            ))
             .nest("/api/v1", api_routes.merge(proxy_routes))
-            .layer(Extension(services))
-            .layer(
-                ServiceBuilder::new()
-                    .layer(TraceLayer::new_for_http())
-                    .layer(HandleErrorLayer::new(Self::handle_timeout_error))
-                    .timeout(Duration::from_secs(*HTTP_TIMEOUT))
-                    .layer(BufferLayer::new(2048))
-                    .layer(RateLimitLayer::new(50, Duration::from_secs(1))),
-            )
-            .route_layer(middleware::from_fn(Self::track_metrics));
+            .layer(Extension(services));
+        let api_router = Self::with_request_limits(
+            api_router,
+            Duration::from_secs(config.request_timeout_seconds),
+            config.max_request_body_bytes,
+        )
+        .layer(middleware::from_fn(request_id_utils::track_request_id))
+        .layer(TraceLayer::new_for_http())
+        .route_layer(middleware::from_fn(Self::track_metrics));
 
         let router = api_router.fallback(Self::handle_404);
 
@@ -197,34 +173,111 @@ impl EdgeApplicationServer {
             &port
         );
 
-        axum::serve(addr, router)
-            .with_graceful_shutdown(Self::shutdown_signal())
-            .await
-            .context("axum serving failed")?;
+        Self::serve_until(
+            addr,
+            router,
+            Self::draining_on(Self::shutdown_signal(), accepting_traffic),
+            shutdown_hooks,
+            Duration::from_millis(config.shutdown_drain_timeout_ms),
+            Duration::from_millis(config.shutdown_hook_timeout_ms),
+        )
+        .await
+    }
+
+    /// serves until `signal` resolves, gives the in-flight requests up to `drain_timeout` to
+    /// finish (whatever is left after that is cut off) and then gives the shutdown hooks up to
+    /// `hook_timeout` to clean up
+    pub async fn serve_until<F>(
+        listener: tokio::net::TcpListener,
+        router: Router,
+        signal: F,
+        shutdown_hooks: ShutdownHooks,
+        drain_timeout: Duration,
+        hook_timeout: Duration,
+    ) -> anyhow::Result<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let in_flight = InFlightRequests::new();
+        let router = router.layer(middleware::from_fn_with_state(
+            in_flight.clone(),
+            Self::track_in_flight,
+        ));
+
+        // what was in flight when the signal came in, the drain clock starts then too
+        let at_signal = Arc::new(AtomicUsize::new(0));
+        let (signalled, drain_started) = tokio::sync::oneshot::channel::<()>();
+        let signal = {
+            let in_flight = in_flight.clone();
+            let at_signal = at_signal.clone();
+            async move {
+                signal.await;
+                let count = in_flight.count();
+                at_signal.store(count, Ordering::SeqCst);
+                info!("shutting down, draining {} in-flight requests", count);
+                let _ = signalled.send(());
+            }
+        };
+        let drain_deadline = async move {
+            match drain_started.await {
+                Ok(()) => tokio::time::sleep(drain_timeout).await,
+                // the server stopped on its own, the other branch has it
+                Err(_) => std::future::pending().await,
+            }
+        };
+
+        // the peer address decides whether forwarded headers are believed, see `client_ip`
+        let app = router.into_make_service_with_connect_info::<SocketAddr>();
+        tokio::select! {
+            result = axum::serve(listener, app).with_graceful_shutdown(signal).into_future() => {
+                result.context("axum serving failed")?;
+                info!("drained {} in-flight requests", at_signal.load(Ordering::SeqCst));
+            }
+            _ = drain_deadline => {
+                let total = at_signal.load(Ordering::SeqCst);
+                let left = in_flight.count();
+                warn!(
+                    "drained {} of {} in-flight requests within {:?}, cutting off the rest",
+                    total.saturating_sub(left),
+                    total,
+                    drain_timeout
+                );
+            }
+        }
+
+        shutdown_hooks.run(hook_timeout).await;
 
         Ok(())
     }
 
+    /// every request gets `request_timeout` to come up with a response (a 504 after that) and
+    /// can send at most `max_body_bytes` of body (a 413 after that)
+    pub fn with_request_limits(
+        router: Router,
+        request_timeout: Duration,
+        max_body_bytes: usize,
+    ) -> Router {
+        router.layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(move |err: BoxError| {
+                    Self::handle_timeout_error(err, request_timeout)
+                }))
+                .timeout(request_timeout)
+                .layer(BufferLayer::new(2048))
+                .layer(RateLimitLayer::new(50, Duration::from_secs(1)))
+                .layer(RequestBodyLimitLayer::new(max_body_bytes)),
+        )
+    }
+
     // custom timeout layer
-    async fn handle_timeout_error(err: BoxError) -> (StatusCode, Json<serde_json::Value>) {
+    async fn handle_timeout_error(err: BoxError, request_timeout: Duration) -> Error {
         if err.is::<tower::timeout::error::Elapsed>() {
-            (
-                StatusCode::REQUEST_TIMEOUT,
-                Json(json!({
-                    "error":
-                        format!(
-                            "request took longer than the configured {} second timeout",
-                            *HTTP_TIMEOUT
-                        )
-                })),
-            )
+            Error::GatewayTimeout(format!(
+                "request took longer than the configured {} second timeout",
+                request_timeout.as_secs_f64()
+            ))
         } else {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({
-                    "error": format!("unhandled internal error: {}", err)
-                })),
-            )
+            Error::InternalServerErrorWithContext(format!("unhandled internal error: {}", err))
         }
     }
 
@@ -247,20 +300,51 @@ impl EdgeApplicationServer {
         response
     }
 
+    async fn track_in_flight(
+        State(in_flight): State<InFlightRequests>,
+        request: Request<axum::body::Body>,
+        next: Next,
+    ) -> impl IntoResponse {
+        let _guard = in_flight.track();
+        next.run(request).await
+    }
+
+    // `signal`, but the node stops reporting ready as soon as it fires
+    async fn draining_on<F>(signal: F, accepting_traffic: Arc<AtomicBool>)
+    where
+        F: Future<Output = ()>,
+    {
+        signal.await;
+        accepting_traffic.store(false, Ordering::SeqCst);
+    }
+
+    // SIGINT locally, SIGTERM is what fly sends on a deploy
     async fn shutdown_signal() {
-        tokio::signal::ctrl_c()
-            .await
-            .expect("how did we crash listening for SIGINT");
+        let ctrl_c = async {
+            tokio::signal::ctrl_c()
+                .await
+                .expect("how did we crash listening for SIGINT");
+        };
+
+        #[cfg(unix)]
+        let terminate = async {
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("how did we crash listening for SIGTERM")
+                .recv()
+                .await;
+        };
+        #[cfg(not(unix))]
+        let terminate = std::future::pending::<()>();
+
+        tokio::select! {
+            _ = ctrl_c => {}
+            _ = terminate => {}
+        }
         println!("signal shutdown");
     }
 
-    async fn handle_404() -> impl IntoResponse {
-        (
-            StatusCode::NOT_FOUND,
-            axum::response::Json(serde_json::json!({
-            "errors":{
-            "message": vec!(String::from("This resource doesn't exist.")),}
-            })),
-        )
+    /// unknown routes get the same error body as every other error
+    pub async fn handle_404() -> Error {
+        Error::NotFound("This resource doesn't exist.".to_string())
     }
 }
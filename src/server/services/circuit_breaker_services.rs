@@ -0,0 +1,353 @@
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::database::Database;
+use crate::server::utils::clock_utils::{DynClock, SystemClock};
+
+// defaults for when a host's breaker opens and how long it stays open
+const FAILURE_THRESHOLD: u32 = 5;
+const COOLDOWN_SECONDS: u64 = 300;
+/// how long the half-open probe has before another request may probe instead, the ppvs.su
+/// client times out well before this
+const PROBE_LOCK_MS: u64 = 60_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// requests go through
+    Closed,
+    /// too many failures in a row, nothing goes through until the cooldown is over
+    Open,
+    /// cooldown is over, the next request is let through as a probe. it closes the breaker
+    /// again on success and opens it for another cooldown on failure
+    HalfOpen,
+}
+
+/// a host's breaker as the health endpoint shows it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BreakerStatus {
+    pub host: String,
+    pub state: BreakerState,
+    /// failures in a row so far
+    pub failures: u32,
+    /// seconds until an open breaker lets a probe through
+    pub retry_after_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreakerConfig {
+    /// failures in a row that open the breaker, 0 turns it off
+    pub failure_threshold: u32,
+    /// how long an open breaker stops requests before it probes. failures further apart than
+    /// this don't count as in a row
+    pub cooldown_seconds: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: FAILURE_THRESHOLD,
+            cooldown_seconds: COOLDOWN_SECONDS,
+        }
+    }
+}
+
+/// stops calling an upstream host that keeps failing (usually a cloudflare ban on ppvs.su, where
+/// every extra request makes the ban worse) for a cooldown. the state is in the store, so every
+/// node backs off as soon as one of them opened the breaker and only one of them probes
+pub struct CircuitBreaker {
+    db: Arc<Database>,
+    config: CircuitBreakerConfig,
+    clock: DynClock,
+    /// hosts this node has asked about, what the health endpoint reports on
+    hosts: Mutex<BTreeSet<String>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(db: Arc<Database>, config: CircuitBreakerConfig) -> Self {
+        Self {
+            db,
+            config,
+            clock: SystemClock::shared(),
+            hosts: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// what the cooldown is measured with
+    pub fn with_clock(mut self, clock: DynClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// the answers that count against a host. a 404 is upstream working fine
+    pub fn is_failure_status(status: u16) -> bool {
+        status == 403 || status == 429 || status >= 500
+    }
+
+    fn failures_key(host: &str) -> String {
+        format!("breaker:{}:failures", host)
+    }
+
+    fn open_until_key(host: &str) -> String {
+        format!("breaker:{}:open_until", host)
+    }
+
+    fn probe_key(host: &str) -> String {
+        format!("breaker:{}:probe", host)
+    }
+
+    fn remember_host(&self, host: &str) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if !hosts.contains(host) {
+            hosts.insert(host.to_string());
+        }
+    }
+
+    /// Ok when a request to `host` may go out, otherwise Err with the seconds until it's worth
+    /// trying again. in half-open only the request that gets the probe lock goes through
+    pub async fn allow(&self, host: &str) -> Result<(), u64> {
+        if self.config.failure_threshold == 0 {
+            return Ok(());
+        }
+        self.remember_host(host);
+
+        let Some(open_until) = self.open_until(host).await else {
+            return Ok(());
+        };
+
+        let now = self.clock.now();
+        if now < open_until {
+            return Err((open_until - now) as u64);
+        }
+
+        if self.try_probe(host).await {
+            info!("circuit breaker for {} is half-open, probing", host);
+            Ok(())
+        } else {
+            // someone else's probe is still out
+            Err(1)
+        }
+    }
+
+    /// closes the breaker again
+    pub async fn record_success(&self, host: &str) {
+        if self.config.failure_threshold == 0 {
+            return;
+        }
+        self.remember_host(host);
+
+        let was_open = self.open_until(host).await.is_some();
+        let keys = [
+            Self::failures_key(host),
+            Self::open_until_key(host),
+            Self::probe_key(host),
+        ];
+
+        let result = match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(redis) => {
+                use redis::AsyncCommands;
+                let mut conn = redis.connection.clone();
+                let result: Result<(), redis::RedisError> = conn.del(&keys[..]).await;
+                result.map_err(|e| e.to_string())
+            }
+            Database::Memory(mem) => mem
+                .store
+                .del_multiple(&keys)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        };
+
+        match result {
+            Ok(()) if was_open => info!("circuit breaker for {} closed again", host),
+            Ok(()) => {}
+            Err(e) => error!("Failed to reset circuit breaker for {}: {}", host, e),
+        }
+    }
+
+    /// counts a failure, opens the breaker once there are enough in a row or when it was the
+    /// half-open probe that failed
+    pub async fn record_failure(&self, host: &str) {
+        if self.config.failure_threshold == 0 {
+            return;
+        }
+        self.remember_host(host);
+
+        let failures = self.add_failure(host).await;
+        let probing = self.open_until(host).await.is_some();
+        if !probing && failures < self.config.failure_threshold {
+            return;
+        }
+
+        let open_until = self.clock.now() + self.config.cooldown_seconds as i64;
+        warn!(
+            "circuit breaker for {} open for {}s after {} failures in a row",
+            host, self.config.cooldown_seconds, failures
+        );
+        metrics::counter!("circuit_breaker_opened_total", "host" => host.to_string()).increment(1);
+
+        let open_key = Self::open_until_key(host);
+        let probe_key = Self::probe_key(host);
+        let result = match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(redis) => {
+                use redis::AsyncCommands;
+                let mut conn = redis.connection.clone();
+                let result: Result<(), redis::RedisError> = redis::pipe()
+                    .set(&open_key, open_until)
+                    .ignore()
+                    .del(&probe_key)
+                    .ignore()
+                    .query_async(&mut conn)
+                    .await;
+                result.map_err(|e| e.to_string())
+            }
+            Database::Memory(mem) => {
+                let result = mem.store.set(&open_key, &open_until.to_string()).await;
+                let _ = mem.store.del(&probe_key).await;
+                result.map_err(|e| e.to_string())
+            }
+        };
+
+        if let Err(e) = result {
+            error!("Failed to open circuit breaker for {}: {}", host, e);
+        }
+    }
+
+    pub async fn status(&self, host: &str) -> BreakerStatus {
+        let failures = self.failures(host).await;
+        let (state, retry_after_seconds) = match self.open_until(host).await {
+            None => (BreakerState::Closed, None),
+            Some(open_until) => {
+                let now = self.clock.now();
+                if now < open_until {
+                    (BreakerState::Open, Some((open_until - now) as u64))
+                } else {
+                    (BreakerState::HalfOpen, None)
+                }
+            }
+        };
+
+        BreakerStatus {
+            host: host.to_string(),
+            state,
+            failures,
+            retry_after_seconds,
+        }
+    }
+
+    /// every host this node has sent requests to through the breaker
+    pub async fn statuses(&self) -> Vec<BreakerStatus> {
+        let hosts: Vec<String> = self.hosts.lock().unwrap().iter().cloned().collect();
+        let mut statuses = Vec::with_capacity(hosts.len());
+        for host in hosts {
+            statuses.push(self.status(&host).await);
+        }
+        statuses
+    }
+
+    /// a store error counts as closed, the breaker shouldn't take ppvs.su down with redis
+    async fn open_until(&self, host: &str) -> Option<i64> {
+        let key = Self::open_until_key(host);
+        match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(redis) => {
+                use redis::AsyncCommands;
+                let mut conn = redis.connection.clone();
+                let result: Result<Option<i64>, redis::RedisError> = conn.get(&key).await;
+                result.ok().flatten()
+            }
+            Database::Memory(mem) => mem
+                .store
+                .get(&key)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|v| v.parse().ok()),
+        }
+    }
+
+    async fn failures(&self, host: &str) -> u32 {
+        let key = Self::failures_key(host);
+        match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(redis) => {
+                use redis::AsyncCommands;
+                let mut conn = redis.connection.clone();
+                let result: Result<Option<u32>, redis::RedisError> = conn.get(&key).await;
+                result.ok().flatten().unwrap_or(0)
+            }
+            Database::Memory(mem) => mem
+                .store
+                .get(&key)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+        }
+    }
+
+    /// INCR on the failure count, which runs out one cooldown after the last failure
+    async fn add_failure(&self, host: &str) -> u32 {
+        let key = Self::failures_key(host);
+        let ttl = self.config.cooldown_seconds.max(1);
+
+        let result = match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(redis) => {
+                use redis::AsyncCommands;
+                let mut conn = redis.connection.clone();
+                let result: Result<(u32,), redis::RedisError> = redis::pipe()
+                    .incr(&key, 1)
+                    .expire(&key, ttl as i64)
+                    .ignore()
+                    .query_async(&mut conn)
+                    .await;
+                result
+                    .map(|(failures,)| failures)
+                    .map_err(|e| e.to_string())
+            }
+            Database::Memory(mem) => match mem.store.incr(&key, 1).await {
+                Ok(failures) => {
+                    let _ = mem.store.expire(&key, ttl).await;
+                    Ok(failures)
+                }
+                Err(e) => Err(e.to_string()),
+            },
+        };
+
+        result.unwrap_or_else(|e| {
+            error!("Failed to count failure for {}: {}", host, e);
+            0
+        })
+    }
+
+    /// SET NX PX on the host's probe lock, a store error counts as getting it
+    async fn try_probe(&self, host: &str) -> bool {
+        let key = Self::probe_key(host);
+        match self.db.as_ref() {
+            Database::Redis(redis) => {
+                let mut conn = redis.connection.clone();
+                let result: Result<Option<String>, redis::RedisError> = redis::cmd("SET")
+                    .arg(&key)
+                    .arg(1)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(PROBE_LOCK_MS)
+                    .query_async(&mut conn)
+                    .await;
+                result.map(|set| set.is_some()).unwrap_or(true)
+            }
+            Database::Memory(mem) => mem
+                .store
+                .set_nx_px(&key, "1", PROBE_LOCK_MS)
+                .await
+                .unwrap_or(true),
+        }
+    }
+}
@@ -1,28 +1,137 @@
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
 use tracing::{debug, error};
 
 use crate::database::Database;
+use crate::server::utils::clock_utils::{DynClock, SystemClock};
 
-/// ttl of 24hrs
+/// ttl of 24hrs, cookies with their own expiry are dropped before this when they run out
 const COOKIE_TTL_SECONDS: u64 = 86400;
 
 pub type DynCookieService = Arc<dyn CookieServiceTrait + Send + Sync>;
 
 #[async_trait::async_trait]
 pub trait CookieServiceTrait {
-    async fn get_cookies(&self, domain: &str) -> Option<String>;
+    /// Cookie header value for a request to `path` on `domain`, None when nothing applies
+    async fn get_cookies(&self, domain: &str, path: &str) -> Option<String>;
 
     async fn store_cookies(&self, domain: &str, cookies: &[String]);
 }
 
+/// one cookie from a Set-Cookie header with the attributes that decide where it's sent back
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredCookie {
+    pub name: String,
+    pub value: String,
+    pub path: String,
+    pub domain: Option<String>,
+    pub secure: bool,
+    /// unix seconds, None for session cookies which live as long as the stored entry does
+    pub expires_at: Option<i64>,
+}
+
+impl StoredCookie {
+    /// `name=value; Path=/; Max-Age=60; ...`, None when there's no name=value pair. Max-Age wins
+    /// over Expires, unknown attributes are ignored
+    pub fn parse(set_cookie: &str, now: i64) -> Option<Self> {
+        let mut attributes = set_cookie.split(';');
+        let (name, value) = attributes.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = Self {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+            path: "/".to_string(),
+            domain: None,
+            secure: false,
+            expires_at: None,
+        };
+        let mut max_age = None;
+
+        for attribute in attributes {
+            let (key, val) = attribute
+                .split_once('=')
+                .map(|(k, v)| (k.trim(), v.trim()))
+                .unwrap_or((attribute.trim(), ""));
+
+            match key.to_ascii_lowercase().as_str() {
+                // anything not starting with / gets the default path
+                "path" if val.starts_with('/') => cookie.path = val.to_string(),
+                "domain" if !val.is_empty() => {
+                    cookie.domain = Some(val.trim_start_matches('.').to_ascii_lowercase())
+                }
+                "secure" => cookie.secure = true,
+                "max-age" => max_age = val.parse::<i64>().ok(),
+                "expires" => {
+                    if let Some(at) = parse_cookie_date(val) {
+                        cookie.expires_at = Some(at);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(max_age) = max_age {
+            // zero or less means delete it now
+            cookie.expires_at = Some(now + max_age.max(0));
+        }
+
+        Some(cookie)
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// the cookie path matching from RFC 6265, `/live` matches `/live` and `/live/x` but not
+    /// `/lively`
+    pub fn matches_path(&self, request_path: &str) -> bool {
+        let request_path = if request_path.is_empty() {
+            "/"
+        } else {
+            request_path
+        };
+
+        request_path == self.path
+            || (request_path.starts_with(&self.path)
+                && (self.path.ends_with('/')
+                    || request_path.as_bytes().get(self.path.len()) == Some(&b'/')))
+    }
+}
+
+// Expires is an http date, some servers still send the old dashed form
+fn parse_cookie_date(value: &str) -> Option<i64> {
+    chrono::DateTime::parse_from_rfc2822(value)
+        .map(|d| d.timestamp())
+        .ok()
+        .or_else(|| {
+            chrono::NaiveDateTime::parse_from_str(value, "%a, %d-%b-%Y %H:%M:%S GMT")
+                .ok()
+                .map(|d| d.and_utc().timestamp())
+        })
+}
+
 pub struct CookieService {
     db: Arc<Database>,
+    clock: DynClock,
 }
 
 impl CookieService {
     pub fn new(db: Arc<Database>) -> Self {
-        Self { db }
+        Self {
+            db,
+            clock: SystemClock::shared(),
+        }
+    }
+
+    /// what cookie expiry is checked against
+    pub fn with_clock(mut self, clock: DynClock) -> Self {
+        self.clock = clock;
+        self
     }
 
     fn cookie_key(&self, domain: &str) -> String {
@@ -34,131 +143,125 @@ impl CookieService {
             .ok()
             .and_then(|u| u.host_str().map(|h| h.to_string()))
     }
-}
 
-// this stuff should probably be in the database repository type of files
-#[async_trait::async_trait]
-impl CookieServiceTrait for CookieService {
-    async fn get_cookies(&self, domain: &str) -> Option<String> {
+    /// everything stored for the domain, expired or not. entries from before cookies were stored
+    /// with their attributes don't parse and are treated as empty
+    async fn load(&self, domain: &str) -> Vec<StoredCookie> {
         let key = self.cookie_key(domain);
 
-        match self.db.as_ref() {
+        let result = match self.db.as_ref() {
             #[allow(unused_imports)]
             Database::Redis(db) => {
                 use redis::AsyncCommands;
                 let mut conn = db.connection.clone();
                 let result: Result<Option<String>, redis::RedisError> = conn.get(&key).await;
-
-                match result {
-                    Ok(Some(cookies)) => {
-                        debug!(
-                            "Loaded cookies for domain {}: {} bytes",
-                            domain,
-                            cookies.len()
-                        );
-                        Some(cookies)
-                    }
-                    Ok(None) => None,
-                    Err(e) => {
-                        error!("Failed to get cookies for domain {}: {}", domain, e);
-                        None
-                    }
-                }
+                result.map_err(|e| e.to_string())
             }
-            Database::Memory(db) => {
-                let result = db.store.get(&key).await;
-
-                match result {
-                    Ok(Some(cookies)) => {
-                        debug!(
-                            "Loaded cookies for domain {}: {} bytes",
-                            domain,
-                            cookies.len()
-                        );
-                        Some(cookies)
-                    }
-                    Ok(None) => None,
-                    Err(e) => {
-                        error!("Failed to get cookies for domain {}: {}", domain, e);
-                        None
-                    }
-                }
+            Database::Memory(db) => db.store.get(&key).await.map_err(|e| e.to_string()),
+        };
+
+        match result {
+            Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                debug!("Ignoring unreadable cookies for domain {}: {}", domain, e);
+                Vec::new()
+            }),
+            Ok(None) => Vec::new(),
+            Err(e) => {
+                error!("Failed to get cookies for domain {}: {}", domain, e);
+                Vec::new()
             }
         }
     }
 
-    async fn store_cookies(&self, domain: &str, cookies: &[String]) {
-        if cookies.is_empty() {
-            return;
-        }
-
+    async fn save(&self, domain: &str, cookies: &[StoredCookie]) {
         let key = self.cookie_key(domain);
-
-        let mut cookie_map: std::collections::HashMap<String, String> =
-            std::collections::HashMap::new();
-
-        if let Some(existing) = self.get_cookies(domain).await {
-            for cookie_str in existing.split("; ") {
-                if let Some((name, _)) = cookie_str.split_once('=') {
-                    cookie_map.insert(name.to_string(), cookie_str.to_string());
-                }
+        let json = match serde_json::to_string(cookies) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Failed to serialize cookies for domain {}: {}", domain, e);
+                return;
             }
-        }
-
-        // parse new cookies and merge (new values override old)
-        for cookie in cookies {
-            // Set-Cookie format: name=value; attr1; attr2...
-            // only want the name=value part
-            let Some(cookie_value) = cookie.split(';').next() else {
-                continue;
-            };
-            let Some((name, _)) = cookie_value.split_once('=') else {
-                continue;
-            };
-            cookie_map.insert(name.trim().to_string(), cookie_value.trim().to_string());
-        }
+        };
 
-        // join all cookies into a single Cookie header value
-        let cookie_header: String = cookie_map.values().cloned().collect::<Vec<_>>().join("; ");
-
-        match self.db.as_ref() {
+        let result = match self.db.as_ref() {
             Database::Redis(db) => {
                 use redis::AsyncCommands;
                 let mut conn = db.connection.clone();
                 let result: Result<(), redis::RedisError> =
-                    conn.set_ex(&key, &cookie_header, COOKIE_TTL_SECONDS).await;
-
-                match result {
-                    Ok(_) => {
-                        debug!(
-                            "Stored {} cookies for domain {} (TTL: {}s)",
-                            cookie_map.len(),
-                            domain,
-                            COOKIE_TTL_SECONDS
-                        );
-                    }
-                    Err(e) => {
-                        error!("Failed to store cookies for domain {}: {}", domain, e);
-                    }
-                }
+                    conn.set_ex(&key, &json, COOKIE_TTL_SECONDS).await;
+                result.map_err(|e| e.to_string())
             }
-            Database::Memory(db) => {
-                let result = db.store.set_ex(&key, &cookie_header, COOKIE_TTL_SECONDS).await;
-
-                match result {
-                    Ok(_) => {
-                        debug!(
-                            "Stored {} cookies for domain {} (TTL: {}s)",
-                            cookie_map.len(),
-                            domain,
-                            COOKIE_TTL_SECONDS
-                        );
-                    }
-                    Err(e) => {
-                        error!("Failed to store cookies for domain {}: {}", domain, e);
-                    }
-                }
+            Database::Memory(db) => db
+                .store
+                .set_ex(&key, &json, COOKIE_TTL_SECONDS)
+                .await
+                .map_err(|e| e.to_string()),
+        };
+
+        match result {
+            Ok(_) => {
+                debug!(
+                    "Stored {} cookies for domain {} (TTL: {}s)",
+                    cookies.len(),
+                    domain,
+                    COOKIE_TTL_SECONDS
+                );
+            }
+            Err(e) => {
+                error!("Failed to store cookies for domain {}: {}", domain, e);
             }
         }
     }
 }
+
+// this stuff should probably be in the database repository type of files
+#[async_trait::async_trait]
+impl CookieServiceTrait for CookieService {
+    async fn get_cookies(&self, domain: &str, path: &str) -> Option<String> {
+        let now = self.clock.now();
+        let mut cookies: Vec<StoredCookie> = self
+            .load(domain)
+            .await
+            .into_iter()
+            .filter(|c| !c.is_expired(now) && c.matches_path(path))
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+
+        // more specific paths first, like a browser sends them
+        cookies.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+        let header = cookies
+            .iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        debug!(
+            "Loaded {} cookies for domain {} path {}",
+            cookies.len(),
+            domain,
+            path
+        );
+        Some(header)
+    }
+
+    async fn store_cookies(&self, domain: &str, cookies: &[String]) {
+        if cookies.is_empty() {
+            return;
+        }
+
+        let now = self.clock.now();
+        let mut stored = self.load(domain).await;
+
+        // merge, a cookie with the same name and path replaces the old one (new values override
+        // old) and an already expired one just deletes it
+        for cookie in cookies.iter().filter_map(|c| StoredCookie::parse(c, now)) {
+            stored.retain(|c| !(c.name == cookie.name && c.path == cookie.path));
+            stored.push(cookie);
+        }
+        stored.retain(|c| !c.is_expired(now));
+
+        self.save(domain, &stored).await;
+    }
+}
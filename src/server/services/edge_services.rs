@@ -1,17 +1,43 @@
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
 use tracing::info;
 
 use crate::{
     config::AppConfig,
     database::Database,
+    server::extractors::{ClientIdStrategy, TrustedProxies, UserAgentPolicy},
     server::services::{
+        circuit_breaker_services::{CircuitBreaker, CircuitBreakerConfig},
         cookie_services::CookieService,
+        games_refresh_services::GamesRefreshTask,
+        host_health_services::HostHealthService,
+        link_resolver_services::LinkResolver,
         ppvsu_services::PpvsuService,
+        proxy_cache_services::{
+            CacheBypassPattern, PrefetchScheduler, ProxyCacheConfig, SegmentMemoryCache,
+        },
+        rate_limit_services::{
+            EdgeRateLimitService, ErrorWeights, RateLimitConfig, RateLimitStrategy,
+        },
+        refresh_health_services::{RefreshRetry, RefreshTracker},
+        shutdown_services::ShutdownHooks,
         sportsurge_scraper::SportsurgeScraper,
         stream_services::StreamsService,
+        upstream_attempt_services::UpstreamAttemptLog,
+        upstream_limit_services::{UpstreamLimiter, UpstreamRateLimit},
+    },
+    server::utils::{
+        buffer_pool_utils::BufferPool,
+        clock_utils::{DynClock, SystemClock},
+        signature_utils::SignatureUtil,
+        stream_decrypt_utils::{DecryptVariant, Ppvsu2024Decryptor, parse_stream_extensions},
+        ttl_utils::TtlJitter,
+        upstream_utils::{
+            ContentTypeOverrides, ForwardedHeaders, HostAllowlist, UpstreamConnection,
+            UpstreamHttp, UpstreamRetry, UpstreamTimeouts,
+        },
     },
-    server::utils::signature_utils::SignatureUtil,
 };
 
 use super::{
@@ -23,6 +49,9 @@ use super::{
     stream_services::DynStreamsService,
 };
 
+// a key is 16 bytes, this holds thousands
+const SEGMENT_KEY_CACHE_BYTES: usize = 64 * 1024;
+
 /// edge services without database dependencies
 /// only uses Redis (or valkey goated) for caching and rate limiting
 #[derive(Clone)]
@@ -34,7 +63,38 @@ pub struct EdgeServices {
     pub rate_limit: DynRateLimitService,
     pub cookies: DynCookieService,
     pub proxy_cache: DynProxyCacheService,
-    pub http: reqwest::Client,
+    pub host_health: Arc<HostHealthService>,
+    /// per host breakers in front of ppvs.su
+    pub circuit_breaker: Arc<CircuitBreaker>,
+    pub upstream_limiter: Arc<UpstreamLimiter>,
+    /// requests per second cap on everything sent upstream
+    pub upstream_rate: Arc<UpstreamRateLimit>,
+    pub upstream_attempts: Arc<UpstreamAttemptLog>,
+    pub upstream_timeouts: UpstreamTimeouts,
+    pub upstream_retry: UpstreamRetry,
+    pub forwarded_headers: ForwardedHeaders,
+    pub allowed_hosts: HostAllowlist,
+    pub content_type_overrides: ContentTypeOverrides,
+    /// decompressed upstream bodies
+    pub body_buffers: BufferPool<Vec<u8>>,
+    /// rewritten playlists
+    pub playlist_buffers: BufferPool<String>,
+    pub client_id_strategy: ClientIdStrategy,
+    pub trusted_proxies: TrustedProxies,
+    pub user_agent_policy: UserAgentPolicy,
+    /// AES-128 keys of decrypted segments, every segment under a key would fetch it otherwise
+    pub segment_keys: Arc<SegmentMemoryCache>,
+    pub refresh_tracker: Arc<RefreshTracker>,
+    pub link_resolver: Arc<LinkResolver>,
+    /// background games refresh, None unless it's turned on. `serve` starts it
+    pub games_refresh: Option<Arc<GamesRefreshTask>>,
+    pub shutdown_hooks: ShutdownHooks,
+    /// flipped to false once a graceful shutdown starts, the readiness check fails from then on
+    /// so the load balancer stops sending new traffic here
+    pub accepting_traffic: Arc<AtomicBool>,
+    /// what the handlers take "now" from, tests swap it
+    pub clock: DynClock,
+    pub http: UpstreamHttp,
     pub db: Arc<Database>,
     pub config: Arc<AppConfig>,
 }
@@ -43,46 +103,170 @@ impl EdgeServices {
     pub fn new(db: Database, config: Arc<AppConfig>) -> Self {
         info!("starting edge services (no database)...");
 
-        let signature_util = Arc::new(SignatureUtil::new(config.access_token_secret.clone()));
+        let fallback_secrets = config
+            .access_token_fallback_secrets
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let signature_util = Arc::new(
+            SignatureUtil::with_fallback_secrets(
+                config.access_token_secret.clone(),
+                fallback_secrets,
+            )
+            .with_expiry_grace(config.signature_expiry_grace_seconds),
+        );
 
         info!("signature util ok, starting remaining services...");
         let db_arc = Arc::new(db);
         
-        // High-performance HTTP client for 1000+ concurrent connections
-        // Tuned for video streaming with connection pooling and keep-alive
-        let http = reqwest::Client::builder()
-            // Pool size: enough for 1000+ concurrent upstream connections
-            .pool_max_idle_per_host(200)
-            // Connection timeout for establishing new connections
-            .connect_timeout(std::time::Duration::from_secs(10))
-            // Overall request timeout - must be longer than health checks
-            .timeout(std::time::Duration::from_secs(60))
-            // Idle connections live longer for streaming workloads
-            .pool_idle_timeout(std::time::Duration::from_secs(120))
-            // TCP keep-alive to prevent connection drops
-            .tcp_keepalive(std::time::Duration::from_secs(60))
-            .build()
+        let http = UpstreamHttp::new(config.upstream_http2, &config.upstream_h2c_hosts)
             .expect("Failed to build HTTP client");
 
-        let ppvsu = Arc::new(PpvsuService::new(db_arc.clone())) as DynPpvsuService;
-        let streams = Arc::new(StreamsService::new(db_arc.clone(), ppvsu.clone()))
-            as DynStreamsService;
+        let upstream_rate = Arc::new(UpstreamRateLimit::new(
+            config.upstream_max_requests_per_second,
+        ));
+
+        let refresh_tracker = Arc::new(RefreshTracker::new());
+        let refresh_retry = Arc::new(RefreshRetry::new(std::time::Duration::from_secs(
+            config.games_stale_retry_seconds,
+        )));
+        let ttl_jitter = TtlJitter::new(config.cache_ttl_jitter_percent);
+        let circuit_breaker = Arc::new(CircuitBreaker::new(
+            db_arc.clone(),
+            CircuitBreakerConfig {
+                failure_threshold: config.ppvsu_breaker_failures,
+                cooldown_seconds: config.ppvsu_breaker_cooldown_seconds,
+            },
+        ));
+        let upstream_attempts = Arc::new(UpstreamAttemptLog::new(
+            config.upstream_attempt_log_size,
+            UpstreamAttemptLog::proxy_from_env(),
+        ));
+        let ppvsu = Arc::new(
+            PpvsuService::new(db_arc.clone())
+                .with_api_base(config.ppvsu_api_base.clone())
+                .with_provider_key(config.ppvsu_provider_key.clone())
+                .with_decryptor(Arc::new(
+                    Ppvsu2024Decryptor::new(DecryptVariant::parse_list(
+                        &config.ppvsu_decrypt_variants,
+                    ))
+                    .with_extensions(parse_stream_extensions(&config.ppvsu_stream_extensions)),
+                ))
+                .with_refresh_tracker(refresh_tracker.clone())
+                .with_refresh_retry(refresh_retry.clone())
+                .with_attempt_log(upstream_attempts.clone())
+                .with_upstream_rate(upstream_rate.clone())
+                .with_circuit_breaker(circuit_breaker.clone())
+                .with_video_link_ttl(config.ppvsu_video_link_ttl_seconds)
+                .with_games_ttl(config.games_cache_ttl_seconds, ttl_jitter),
+        ) as DynPpvsuService;
+        let streams = Arc::new(
+            StreamsService::new(db_arc.clone(), ppvsu.clone())
+                .with_provider_key(config.ppvsu_provider_key.clone())
+                .with_games_ttl(config.games_cache_ttl_seconds, ttl_jitter)
+                .with_refresh_retry(refresh_retry),
+        ) as DynStreamsService;
+        let link_resolver = Arc::new(LinkResolver::new(
+            ppvsu.clone(),
+            config.ppvsu_resolve_max_concurrent,
+            std::time::Duration::from_millis(config.ppvsu_resolve_interval_ms),
+        ));
         
         // Sportsurge scraper - scrapes sportsurge.ws homepage
         let sportsurge = Arc::new(SportsurgeScraper::new(db_arc.clone())) as DynSportsurgeScraper;
 
-        let rate_limit = Arc::new(super::rate_limit_services::EdgeRateLimitService::new(
+        let rate_limit = Arc::new(EdgeRateLimitService::with_config(
             db_arc.clone(),
+            RateLimitConfig {
+                strategy: RateLimitStrategy::parse(&config.rate_limit_strategy),
+                max_requests_per_window: config.rate_limit_max_requests,
+                max_requests_per_ip_window: config.rate_limit_max_requests_per_ip,
+                window_seconds: config.rate_limit_window_seconds,
+                max_errors_before_timeout: config.rate_limit_max_errors,
+                max_error_ratio: config.rate_limit_max_error_ratio,
+                ratio_min_requests: config.rate_limit_ratio_min_requests,
+                error_window_seconds: config.rate_limit_error_window_seconds,
+                error_half_life_seconds: config.rate_limit_error_half_life_seconds,
+                error_weights: ErrorWeights::parse_list(&config.rate_limit_error_weights),
+                timeout_duration_seconds: config.rate_limit_timeout_seconds,
+            },
         )) as DynRateLimitService;
 
         let cookies = Arc::new(CookieService::new(db_arc.clone())) as DynCookieService;
 
-        // Passed http.clone() here to satisfy the 2-argument requirement
+        let upstream_limiter = Arc::new(UpstreamLimiter::new(
+            config.upstream_max_connections_per_host,
+            std::time::Duration::from_millis(config.upstream_queue_timeout_ms),
+        ));
+
+        let upstream_timeouts = UpstreamTimeouts::new(
+            std::time::Duration::from_millis(config.upstream_playlist_timeout_ms),
+            std::time::Duration::from_millis(config.upstream_segment_timeout_ms),
+        );
+
+        // prefetches still running when the server stops are cancelled by its shutdown hook
+        let shutdown_hooks = ShutdownHooks::new();
+        let prefetch_scheduler = Arc::new(PrefetchScheduler::new(
+            config.prefetch_max_concurrent,
+            config.prefetch_max_per_client,
+        ));
+        shutdown_hooks.register(prefetch_scheduler.clone());
+
+        let games_refresh = config.games_refresh_enabled.then(|| {
+            let task = Arc::new(
+                GamesRefreshTask::new(
+                    ppvsu.clone(),
+                    db_arc.clone(),
+                    config.ppvsu_provider_key.clone(),
+                    std::time::Duration::from_secs(config.games_refresh_interval_seconds),
+                    std::time::Duration::from_millis(config.games_refresh_jitter_ms),
+                )
+                .with_refresh_tracker(refresh_tracker.clone())
+                .with_expiry_grace(std::time::Duration::from_secs(
+                    config.games_expiry_grace_seconds,
+                )),
+            );
+            shutdown_hooks.register(task.clone());
+            task
+        });
+
+        let proxy_cache_config = ProxyCacheConfig {
+            bypass_patterns: CacheBypassPattern::parse_list(&config.proxy_cache_bypass_patterns),
+            upstream_connection: UpstreamConnection::for_schema(
+                "sports",
+                &config.upstream_close_connection_schemas,
+            ),
+            upstream_limiter: upstream_limiter.clone(),
+            upstream_rate: upstream_rate.clone(),
+            upstream_timeouts,
+            stale_if_error_seconds: config.proxy_stale_if_error_seconds,
+            live_segment_max_age_seconds: config.proxy_live_segment_max_age_seconds,
+            prefetch_scheduler,
+            verify_ts_sync: config.proxy_verify_ts_sync,
+            attempt_log: upstream_attempts.clone(),
+            inflight_max_age_seconds: config.proxy_inflight_max_age_seconds,
+            m3u8_ttl_seconds: config.proxy_m3u8_ttl_seconds,
+            segment_ttl_seconds: config.proxy_segment_ttl_seconds,
+            ttl_jitter,
+            negative_ttl_seconds: config.proxy_negative_ttl_seconds,
+            m3u8_lock_ms: config.proxy_m3u8_lock_ms,
+            l1_max_bytes: config.proxy_l1_cache_max_bytes,
+            prefetch_write_batch_size: config.prefetch_write_batch_size,
+            prefetch_write_batch_ms: config.prefetch_write_batch_ms,
+            allowed_hosts: HostAllowlist::parse_list(&config.allowed_proxy_hosts),
+        };
+
         let proxy_cache = Arc::new(super::proxy_cache_services::ProxyCacheService::new(
             db_arc.clone(),
             http.clone(),
+            proxy_cache_config,
         )) as DynProxyCacheService;
 
+        let host_health = Arc::new(HostHealthService::new(std::time::Duration::from_secs(
+            config.upstream_unreachable_cooldown_seconds,
+        )));
+
 
 
         Self {
@@ -93,9 +277,52 @@ impl EdgeServices {
             rate_limit,
             cookies,
             proxy_cache,
+            host_health,
+            circuit_breaker,
+            upstream_limiter,
+            upstream_rate,
+            upstream_attempts,
+            upstream_timeouts,
+            upstream_retry: UpstreamRetry::new(
+                config.upstream_retries,
+                std::time::Duration::from_millis(config.upstream_retry_base_delay_ms),
+            ),
+            forwarded_headers: ForwardedHeaders::parse_list(&config.forward_client_headers),
+            allowed_hosts: HostAllowlist::parse_list(&config.allowed_proxy_hosts),
+            content_type_overrides: ContentTypeOverrides::parse_list(
+                &config.upstream_content_type_overrides,
+            ),
+            client_id_strategy: ClientIdStrategy::parse(&config.client_id_strategy),
+            trusted_proxies: TrustedProxies::parse_list(&config.trusted_proxies),
+            user_agent_policy: UserAgentPolicy::new(
+                config.enforce_user_agent_policy,
+                &config.user_agent_allow_list,
+                &config.user_agent_deny_list,
+            ),
+            body_buffers: BufferPool::new(
+                config.buffer_pool_size,
+                config.buffer_pool_max_buffer_bytes,
+            ),
+            playlist_buffers: BufferPool::new(
+                config.buffer_pool_size,
+                config.buffer_pool_max_buffer_bytes,
+            ),
+            segment_keys: Arc::new(SegmentMemoryCache::new(SEGMENT_KEY_CACHE_BYTES)),
+            refresh_tracker,
+            link_resolver,
+            games_refresh,
+            shutdown_hooks,
+            accepting_traffic: Arc::new(AtomicBool::new(true)),
+            clock: SystemClock::shared(),
             http,
             db: db_arc,
             config,
         }
     }
+
+    /// waits until another upstream request fits under the requests per second cap, call it
+    /// right before sending
+    pub async fn acquire_upstream_permit(&self) {
+        self.upstream_rate.acquire().await;
+    }
 }
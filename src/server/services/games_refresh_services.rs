@@ -0,0 +1,212 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rand::Rng;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::{
+    database::{Database, stream::StreamsRepository},
+    server::{
+        services::ppvsu_services::DynPpvsuService,
+        services::refresh_health_services::RefreshTracker,
+        services::shutdown_services::ShutdownHook,
+        utils::clock_utils::{DynClock, SystemClock},
+    },
+};
+
+/// how long after a game ended the refresh deletes it, unless told otherwise
+pub const GAMES_EXPIRY_GRACE: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// refetches the games in the background every `interval` (plus up to `jitter`), so a request
+/// never has to wait on ppvs.su for a stale cache. with several nodes on the same redis only the
+/// one that gets the lock for a round refetches, the others skip it
+pub struct GamesRefreshTask {
+    ppvsu: DynPpvsuService,
+    db: Arc<Database>,
+    provider: String,
+    interval: Duration,
+    jitter: Duration,
+    expiry_grace: Duration,
+    refresh_tracker: Arc<RefreshTracker>,
+    clock: DynClock,
+    stopped: watch::Sender<bool>,
+}
+
+impl GamesRefreshTask {
+    pub fn new(
+        ppvsu: DynPpvsuService,
+        db: Arc<Database>,
+        provider: impl Into<String>,
+        interval: Duration,
+        jitter: Duration,
+    ) -> Self {
+        Self {
+            ppvsu,
+            db,
+            provider: provider.into(),
+            // a 0 interval would spin on ppvs.su, which is exactly what gets us banned
+            interval: if interval.is_zero() {
+                Duration::from_secs(1)
+            } else {
+                interval
+            },
+            jitter,
+            expiry_grace: GAMES_EXPIRY_GRACE,
+            refresh_tracker: Arc::new(RefreshTracker::new()),
+            clock: SystemClock::shared(),
+            stopped: watch::Sender::new(false),
+        }
+    }
+
+    /// shares the refresh state with the health endpoint
+    pub fn with_refresh_tracker(mut self, tracker: Arc<RefreshTracker>) -> Self {
+        self.refresh_tracker = tracker;
+        self
+    }
+
+    /// how long after a game ended the refresh deletes it
+    pub fn with_expiry_grace(mut self, grace: Duration) -> Self {
+        self.expiry_grace = grace;
+        self
+    }
+
+    /// what the refresh and fetch times are recorded with
+    pub fn with_clock(mut self, clock: DynClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn lock_key(&self) -> String {
+        format!("games:refresh_lock:{}", self.provider)
+    }
+
+    /// runs the refresh loop until `stop` is called, the first round happens after one interval
+    /// since startup requests fill the cache on their own anyway
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut stopped = self.stopped.subscribe();
+            info!(
+                "refreshing games every {}s (+ up to {}ms jitter)",
+                self.interval.as_secs(),
+                self.jitter.as_millis()
+            );
+
+            loop {
+                tokio::select! {
+                    _ = stopped.wait_for(|stopped| *stopped) => break,
+                    _ = tokio::time::sleep(self.next_delay()) => {}
+                }
+                self.refresh_once().await;
+            }
+
+            info!("games refresh stopped");
+        })
+    }
+
+    pub fn stop(&self) {
+        self.stopped.send_replace(true);
+    }
+
+    fn next_delay(&self) -> Duration {
+        let jitter = self.jitter.as_millis() as u64;
+        if jitter == 0 {
+            return self.interval;
+        }
+        self.interval + Duration::from_millis(rand::rng().random_range(0..=jitter))
+    }
+
+    /// one round, true when this node did the refetch. false when another node holds the round's
+    /// lock or the refetch failed
+    pub async fn refresh_once(&self) -> bool {
+        if !self.try_lock().await {
+            info!("another node is refreshing the games, skipping this round");
+            return false;
+        }
+
+        let now = self.clock.now();
+        // games that dropped off upstream are cleared by the fetch, a failed one keeps them
+        let refreshed = match self.ppvsu.fetch_and_cache_games().await {
+            Ok(games) => {
+                self.refresh_tracker.record_success(now);
+                if let Err(e) = self.db.set_last_fetch_time(&self.provider, now).await {
+                    error!("failed to store the games fetch time: {}", e);
+                }
+                info!("background refresh cached {} games", games.len());
+                true
+            }
+            Err(e) => {
+                error!("background games refresh failed: {}", e);
+                self.refresh_tracker.record_failure(now, &e.to_string());
+                false
+            }
+        };
+
+        // upstream keeps listing games for a while after they ended, and a failed fetch keeps
+        // them too. either way nothing else ever removes them
+        self.delete_expired_games(now).await;
+        // games that started or ended since they were last stored
+        if let Err(e) = self.db.rebuild_live_index(&self.provider, now).await {
+            error!("failed to rebuild the live games index: {}", e);
+        }
+        refreshed
+    }
+
+    async fn delete_expired_games(&self, now: i64) {
+        let grace = self.expiry_grace.as_secs();
+        let deleted = self
+            .db
+            .delete_expired_games(&self.provider, now, grace)
+            .await;
+        match deleted {
+            Ok(0) => {}
+            Ok(deleted) => info!("deleted {} games that ended over {}s ago", deleted, grace),
+            Err(e) => error!("failed to delete expired games: {}", e),
+        }
+    }
+
+    /// SET NX PX on the round's lock. it isn't released after the refetch, it expires after one
+    /// interval so the other nodes skip the rest of the round. a store error counts as getting
+    /// it, refetching twice beats not refetching
+    async fn try_lock(&self) -> bool {
+        let lock_key = self.lock_key();
+        let ttl_ms = self.interval.as_millis() as u64;
+
+        match self.db.as_ref() {
+            Database::Redis(redis) => {
+                let mut conn = redis.connection.clone();
+                let result: Result<Option<String>, redis::RedisError> = redis::cmd("SET")
+                    .arg(&lock_key)
+                    .arg(1)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(ttl_ms)
+                    .query_async(&mut conn)
+                    .await;
+                result.map(|set| set.is_some()).unwrap_or_else(|e| {
+                    error!("Failed to take games refresh lock: {}", e);
+                    true
+                })
+            }
+            Database::Memory(mem) => mem
+                .store
+                .set_nx_px(&lock_key, "1", ttl_ms)
+                .await
+                .unwrap_or(true),
+        }
+    }
+}
+
+#[async_trait]
+impl ShutdownHook for GamesRefreshTask {
+    fn name(&self) -> &str {
+        "games_refresh"
+    }
+
+    async fn on_shutdown(&self) -> anyhow::Result<()> {
+        self.stop();
+        Ok(())
+    }
+}
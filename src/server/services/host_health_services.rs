@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+/// remembers upstream hosts that couldn't be connected to (dns failure, connection refused,
+/// connect timeout) so requests for them fail fast for a short cooldown instead of each one
+/// sitting through the full connect timeout. this is in process only, every edge learns on its own
+pub struct HostHealthService {
+    cooldown: Duration,
+    unreachable: Mutex<HashMap<String, Instant>>,
+}
+
+impl HostHealthService {
+    pub fn new(cooldown: Duration) -> Self {
+        Self {
+            cooldown,
+            unreachable: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// only connection level failures count, an upstream that answers with an error status is
+    /// reachable and goes through the normal error handling
+    pub fn is_connection_failure(err: &reqwest::Error) -> bool {
+        err.is_connect()
+    }
+
+    /// how much longer the host is considered unreachable, None if it's fine to try
+    pub fn unreachable_for(&self, host: &str) -> Option<Duration> {
+        let mut lock = self.unreachable.lock().unwrap();
+        let until = *lock.get(host)?;
+
+        let now = Instant::now();
+        if until <= now {
+            lock.remove(host);
+            return None;
+        }
+
+        Some(until - now)
+    }
+
+    pub fn mark_unreachable(&self, host: &str) {
+        warn!(
+            "Marking upstream host {} unreachable for {}s",
+            host,
+            self.cooldown.as_secs()
+        );
+        self.unreachable
+            .lock()
+            .unwrap()
+            .insert(host.to_string(), Instant::now() + self.cooldown);
+    }
+
+    /// a successful response from the host clears it early
+    pub fn mark_reachable(&self, host: &str) {
+        if self.unreachable.lock().unwrap().remove(host).is_some() {
+            debug!("Upstream host {} reachable again", host);
+        }
+    }
+
+    /// look at a failed send and mark the host if it never connected, returns true if it did
+    pub fn record_send_error(&self, host: &str, err: &reqwest::Error) -> bool {
+        if !Self::is_connection_failure(err) {
+            return false;
+        }
+
+        self.mark_unreachable(host);
+        true
+    }
+}
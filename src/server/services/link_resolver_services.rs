@@ -0,0 +1,171 @@
+// on demand eager resolution of live ppvsu links. this is the old "decode everything on refresh"
+// path, except it only runs when an admin asks for it and it's slow on purpose so the ip doesn't
+// get banned
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{error, info};
+
+use crate::server::error::{AppResult, Error};
+use crate::server::services::ppvsu_services::DynPpvsuService;
+
+/// where the current (or last) resolve run is at
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ResolveProgress {
+    pub running: bool,
+    /// live games picked up by the run
+    pub total: usize,
+    pub completed: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+}
+
+/// resolves the direct link of every live game through `fetch_video_link`, which caches them with
+/// the normal video link TTL. only one run at a time, at most `max_concurrent` fetches in flight
+/// and a new fetch is started at most once every `interval`
+pub struct LinkResolver {
+    ppvsu: DynPpvsuService,
+    max_concurrent: usize,
+    interval: Duration,
+    // doubles as the single flight lock, a run can only start while `running` is false
+    progress: Mutex<ResolveProgress>,
+}
+
+impl LinkResolver {
+    pub fn new(ppvsu: DynPpvsuService, max_concurrent: usize, interval: Duration) -> Self {
+        Self {
+            ppvsu,
+            max_concurrent: max_concurrent.max(1),
+            interval,
+            progress: Mutex::new(ResolveProgress::default()),
+        }
+    }
+
+    pub fn progress(&self) -> ResolveProgress {
+        self.progress.lock().unwrap().clone()
+    }
+
+    fn claim(&self) -> AppResult<()> {
+        let mut progress = self.progress.lock().unwrap();
+        if progress.running {
+            return Err(Error::ObjectConflict(
+                "a resolve is already running".to_string(),
+            ));
+        }
+
+        *progress = ResolveProgress {
+            running: true,
+            started_at: Some(chrono::Utc::now().timestamp()),
+            ..Default::default()
+        };
+        Ok(())
+    }
+
+    /// kicks off a run in the background and returns straight away, poll `progress` for how it's
+    /// going
+    pub fn start(self: &Arc<Self>) -> AppResult<ResolveProgress> {
+        self.claim()?;
+
+        let resolver = self.clone();
+        tokio::spawn(async move {
+            resolver.run().await;
+        });
+
+        Ok(self.progress())
+    }
+
+    /// runs to completion and returns the final counts
+    pub async fn resolve_live(&self) -> AppResult<ResolveProgress> {
+        self.claim()?;
+        Ok(self.run().await)
+    }
+
+    async fn run(&self) -> ResolveProgress {
+        let now = chrono::Utc::now().timestamp();
+        let games = match self.ppvsu.get_games_with_refresh().await {
+            Ok(games) => games,
+            Err(e) => {
+                error!("failed to load games for link resolve: {}", e);
+                return self.finish();
+            }
+        };
+        let live: Vec<_> = games.into_iter().filter(|g| g.is_live(now)).collect();
+
+        info!(
+            "resolving links for {} live games ({} at a time)",
+            live.len(),
+            self.max_concurrent
+        );
+        self.progress.lock().unwrap().total = live.len();
+
+        let semaphore = Arc::new(Semaphore::new(self.max_concurrent));
+        let mut join_set = JoinSet::new();
+
+        for (i, game) in live.into_iter().enumerate() {
+            if i > 0 && !self.interval.is_zero() {
+                tokio::time::sleep(self.interval).await;
+            }
+
+            let permit = semaphore
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("semaphore closed");
+            let ppvsu = self.ppvsu.clone();
+            join_set.spawn(async move {
+                let _permit = permit;
+                let result = ppvsu.fetch_video_link(&game.video_link).await;
+                (game.id, result)
+            });
+
+            // count whatever already finished so progress moves while we're still launching
+            while let Some(done) = join_set.try_join_next() {
+                self.record(done);
+            }
+        }
+
+        while let Some(done) = join_set.join_next().await {
+            self.record(done);
+        }
+
+        self.finish()
+    }
+
+    fn record(&self, done: Result<(i64, AppResult<String>), tokio::task::JoinError>) {
+        let succeeded = match done {
+            Ok((_, Ok(_))) => true,
+            Ok((id, Err(e))) => {
+                error!("failed to resolve link for game {}: {}", id, e);
+                false
+            }
+            Err(e) => {
+                error!("link resolve task panicked: {}", e);
+                false
+            }
+        };
+
+        let mut progress = self.progress.lock().unwrap();
+        progress.completed += 1;
+        if succeeded {
+            progress.succeeded += 1;
+        } else {
+            progress.failed += 1;
+        }
+    }
+
+    fn finish(&self) -> ResolveProgress {
+        let mut progress = self.progress.lock().unwrap();
+        progress.running = false;
+        progress.finished_at = Some(chrono::Utc::now().timestamp());
+        info!(
+            "link resolve done, {} succeeded, {} failed",
+            progress.succeeded, progress.failed
+        );
+        progress.clone()
+    }
+}
@@ -1,10 +1,18 @@
+pub mod circuit_breaker_services;
 pub mod cookie_services;
 pub mod edge_services;
+pub mod games_refresh_services;
+pub mod host_health_services;
+pub mod link_resolver_services;
 pub mod ppvsu_services;
 pub mod proxy_cache_services;
 pub mod rate_limit_services;
+pub mod refresh_health_services;
+pub mod shutdown_services;
 pub mod sportsurge_scraper;
 pub mod stream_services;
+pub mod upstream_attempt_services;
+pub mod upstream_limit_services;
 
 pub use cookie_services::DynCookieService;
 pub use ppvsu_services::DynPpvsuService;
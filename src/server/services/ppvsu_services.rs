@@ -1,40 +1,33 @@
 // all the stream related functions, im not commenting on all of them, they're pretty readable
 use async_trait::async_trait;
-use base64::Engine;
-use chacha20::ChaCha20;
-use chacha20::cipher::{KeyIvInit, StreamCipher};
 use flate2::read::GzDecoder;
 use mockall::automock;
 use std::io::Read;
 use std::sync::Arc;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::{
     database::{
         Database,
         stream::{DynStreamsRepository, Game, PpvsuApiResponse, PpvsuStreamDetailResponse},
     },
-    server::error::{AppResult, Error},
+    server::{
+        error::{AppResult, Error},
+        services::circuit_breaker_services::{CircuitBreaker, CircuitBreakerConfig},
+        services::refresh_health_services::{RefreshRetry, RefreshTracker},
+        services::upstream_attempt_services::UpstreamAttemptLog,
+        services::upstream_limit_services::UpstreamRateLimit,
+        utils::clock_utils::{DynClock, SystemClock},
+        utils::stream_decrypt_utils::{DecryptVariant, DynStreamDecryptor, Ppvsu2024Decryptor},
+        utils::ttl_utils::{DEFAULT_TTL_JITTER_PERCENT, TtlJitter},
+    },
 };
 
 pub type DynPpvsuService = Arc<dyn PpvsuServiceTrait + Send + Sync>;
 
-/// ROT-71 cipher - rotates ASCII characters by 71 positions
-/// This transforms the custom charset to valid standard base64
-/// Range: 33 ('!') to 126 ('~') = 94 characters
-fn rot71_decode(input: &str) -> String {
-    input
-        .chars()
-        .map(|c| {
-            let code = c as u32;
-            if (33..=126).contains(&code) {
-                char::from_u32(33 + ((code - 33) + 71) % 94).unwrap_or(c)
-            } else {
-                c
-            }
-        })
-        .collect()
-}
+pub const DEFAULT_API_BASE: &str = "https://api.ppv.to";
+/// what games and fetch times are stored under
+pub const DEFAULT_PROVIDER_KEY: &str = "ppvsu";
 
 fn encode_variant(mut n: usize, out: &mut Vec<u8>) {
     while n >= 0x80 {
@@ -44,132 +37,6 @@ fn encode_variant(mut n: usize, out: &mut Vec<u8>) {
     out.push(n as u8);
 }
 
-/// Parse protobuf message with 2 length-delimited fields
-/// 1 (0x0a): Custom charset encoded ciphertext (requires ROT-71 → base64 → ChaCha20)
-/// 2 (0x12): stream name
-fn parse_protobuf(buffer: &[u8]) -> AppResult<(String, Option<String>)> {
-    let mut offset = 0;
-    let mut field1: Option<String> = None;
-    let mut field2: Option<String> = None;
-
-    while offset < buffer.len() {
-        let tag = buffer[offset];
-        offset += 1;
-
-        let mut length: usize = 0;
-        let mut shift = 0;
-        loop {
-            if offset >= buffer.len() {
-                break;
-            }
-            let byte = buffer[offset];
-            offset += 1;
-            length |= ((byte & 0x7F) as usize) << shift;
-            shift += 7;
-            if byte & 0x80 == 0 {
-                break;
-            }
-        }
-
-        if offset + length > buffer.len() {
-            break;
-        }
-
-        let field_data = &buffer[offset..offset + length];
-        offset += length;
-
-        match tag {
-            0x0a => {
-                field1 = Some(String::from_utf8_lossy(field_data).to_string());
-            }
-            0x12 => {
-                field2 = Some(String::from_utf8_lossy(field_data).to_string());
-            }
-            _ => {}
-        }
-    }
-
-    field1.map(|f1| (f1, field2)).ok_or_else(|| {
-        Error::InternalServerErrorWithContext("failed to extract field1 from protobuf".to_string())
-    })
-}
-
-/// ChaCha20 decryption with counter=1
-/// Key: full `island` header (32 bytes UTF-8)
-/// Nonce: first 12 bytes of decoded ciphertext
-/// Counter starts at 1, not 0 (critical for correct decryption)
-fn chacha20_decrypt(decoded_data: &[u8], key: &str) -> AppResult<String> {
-    use chacha20::cipher::StreamCipherSeek;
-
-    if decoded_data.len() < 12 {
-        return Err(Error::InternalServerErrorWithContext(
-            "decoded data too short to contain nonce".to_string(),
-        ));
-    }
-
-    let key_bytes = key.as_bytes();
-    if key_bytes.len() != 32 {
-        return Err(Error::InternalServerErrorWithContext(format!(
-            "key must be 32 bytes, got {}",
-            key_bytes.len()
-        )));
-    }
-
-    // First 12 bytes are the nonce, rest is ciphertext
-    let nonce = &decoded_data[..12];
-    let ciphertext = &decoded_data[12..];
-
-    // Create cipher with 32-byte key and 12-byte nonce
-    let mut cipher = ChaCha20::new(key_bytes.into(), nonce.into());
-
-    // Seek to block 1 (64 bytes) - counter starts at 1, not 0
-    cipher.seek(64u64);
-
-    let mut buffer = ciphertext.to_vec();
-    cipher.apply_keystream(&mut buffer);
-
-    // Extract URL (ends with .m3u8, may have trailing garbage)
-    let plaintext = String::from_utf8_lossy(&buffer);
-    if let Some(end_idx) = plaintext.find(".m3u8") {
-        Ok(plaintext[..end_idx + 5].to_string())
-    } else {
-        // Fallback: return up to first null byte or non-printable char
-        let clean: String = plaintext
-            .chars()
-            .take_while(|c| c.is_ascii() && !c.is_ascii_control())
-            .collect();
-        Ok(clean)
-    }
-}
-
-/// New decryption pipeline (2024 update)
-/// Parse protobuf → field1 (custom charset encoded)
-/// ROT-71 decode field1 → standard base64
-/// Base64 decode → [nonce (12 bytes) || ciphertext]
-/// ChaCha20 decrypt with island header as key, counter=1
-fn decrypt_stream_url(encrypted_blob: &[u8], island_header: &str) -> AppResult<String> {
-    // Step 1: Parse protobuf to extract field1 (encoded ciphertext)
-    let (encoded_ciphertext, _stream_name) = parse_protobuf(encrypted_blob)?;
-
-    // Step 2: ROT-71 transform to get valid standard base64
-    let base64_ciphertext = rot71_decode(&encoded_ciphertext);
-
-    // Step 3: Base64 decode to get binary [nonce || ciphertext]
-    let decoded_data = base64::engine::general_purpose::STANDARD
-        .decode(&base64_ciphertext)
-        .map_err(|e| {
-            Error::InternalServerErrorWithContext(format!(
-                "failed to base64 decode after ROT-71: {}",
-                e
-            ))
-        })?;
-
-    // Step 4: ChaCha20 decrypt (nonce is first 12 bytes, counter=1)
-    let decrypted_url = chacha20_decrypt(&decoded_data, island_header)?;
-
-    Ok(decrypted_url)
-}
-
 #[automock]
 #[async_trait]
 pub trait PpvsuServiceTrait {
@@ -182,10 +49,42 @@ pub trait PpvsuServiceTrait {
     async fn is_cache_stale(&self, cache_time: i64, current_time: i64) -> bool;
 }
 
+/// a 404 means the id is really gone (NotFound), anything else non-2xx is upstream having a bad
+/// time and the cached copy is still good to serve
+pub fn check_refetch_status(game_id: i64, status: reqwest::StatusCode) -> AppResult<()> {
+    if status == reqwest::StatusCode::NOT_FOUND {
+        info!("ppvs.su API says game {} no longer exists", game_id);
+        return Err(Error::NotFound(format!(
+            "game {} not found upstream",
+            game_id
+        )));
+    }
+    if !status.is_success() {
+        error!("ppvs.su API returned {} for game {}", status, game_id);
+        return Err(Error::InternalServerErrorWithContext(format!(
+            "ppvs.su API returned {}",
+            status
+        )));
+    }
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct PpvsuService {
     repository: DynStreamsRepository,
     http_client: reqwest::Client,
+    decryptor: DynStreamDecryptor,
+    api_base: String,
+    provider: String,
+    refresh_tracker: Arc<RefreshTracker>,
+    refresh_retry: Arc<RefreshRetry>,
+    attempt_log: Arc<UpstreamAttemptLog>,
+    upstream_rate: Arc<UpstreamRateLimit>,
+    circuit_breaker: Arc<CircuitBreaker>,
+    clock: DynClock,
+    video_link_ttl_seconds: u64,
+    games_ttl_seconds: u64,
+    ttl_jitter: TtlJitter,
 }
 
 impl PpvsuService {
@@ -197,31 +96,203 @@ impl PpvsuService {
             .build()
             .unwrap_or_else(|_| reqwest::Client::new());
 
+        let circuit_breaker = Arc::new(CircuitBreaker::new(
+            db.clone(),
+            CircuitBreakerConfig::default(),
+        ));
+
         Self {
             repository: db,
             http_client,
+            decryptor: Arc::new(Ppvsu2024Decryptor::default()),
+            api_base: DEFAULT_API_BASE.to_string(),
+            provider: DEFAULT_PROVIDER_KEY.to_string(),
+            refresh_tracker: Arc::new(RefreshTracker::new()),
+            refresh_retry: Arc::default(),
+            attempt_log: Arc::new(UpstreamAttemptLog::default()),
+            upstream_rate: Arc::default(),
+            circuit_breaker,
+            clock: SystemClock::shared(),
+            video_link_ttl_seconds: VIDEO_LINK_CACHE_TTL_SECS,
+            games_ttl_seconds: GAMES_CACHE_TTL_SECS,
+            ttl_jitter: TtlJitter::new(DEFAULT_TTL_JITTER_PERCENT),
+        }
+    }
+
+    /// base url of the ppvs.su api, a mirror or a local server in tests
+    pub fn with_api_base(mut self, api_base: impl Into<String>) -> Self {
+        self.api_base = api_base.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// key the games are stored under, so a mirror or a second source doesn't mix its games
+    /// with ours
+    pub fn with_provider_key(mut self, provider: impl Into<String>) -> Self {
+        self.provider = provider.into();
+        self
+    }
+
+    /// decrypt pipelines to try in order when a video link is fetched
+    pub fn with_decrypt_variants(self, variants: Vec<DecryptVariant>) -> Self {
+        self.with_decryptor(Arc::new(Ppvsu2024Decryptor::new(variants)))
+    }
+
+    /// swaps the whole decrypt pipeline, for when upstream changes the scheme entirely
+    pub fn with_decryptor(mut self, decryptor: DynStreamDecryptor) -> Self {
+        self.decryptor = decryptor;
+        self
+    }
+
+    /// shares the refresh state with the health endpoint
+    pub fn with_refresh_tracker(mut self, tracker: Arc<RefreshTracker>) -> Self {
+        self.refresh_tracker = tracker;
+        self
+    }
+
+    /// shares the background retry of a failed refresh with the streams service
+    pub fn with_refresh_retry(mut self, refresh_retry: Arc<RefreshRetry>) -> Self {
+        self.refresh_retry = refresh_retry;
+        self
+    }
+
+    /// records every request to ppvs.su alongside the proxy's upstream attempts
+    pub fn with_attempt_log(mut self, attempt_log: Arc<UpstreamAttemptLog>) -> Self {
+        self.attempt_log = attempt_log;
+        self
+    }
+
+    /// shares the requests per second cap on upstream requests with the proxy
+    pub fn with_upstream_rate(mut self, upstream_rate: Arc<UpstreamRateLimit>) -> Self {
+        self.upstream_rate = upstream_rate;
+        self
+    }
+
+    /// shares the per host circuit breakers with the health endpoint
+    pub fn with_circuit_breaker(mut self, circuit_breaker: Arc<CircuitBreaker>) -> Self {
+        self.circuit_breaker = circuit_breaker;
+        self
+    }
+
+    /// what cache times and game staleness are measured with
+    pub fn with_clock(mut self, clock: DynClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// how long a decrypted video link is cached before it's fetched again
+    pub fn with_video_link_ttl(mut self, seconds: u64) -> Self {
+        self.video_link_ttl_seconds = seconds;
+        self
+    }
+
+    /// how long fetched games count as fresh, each fetch gets its own jittered window
+    pub fn with_games_ttl(mut self, seconds: u64, jitter: TtlJitter) -> Self {
+        self.games_ttl_seconds = seconds;
+        self.ttl_jitter = jitter;
+        self
+    }
+
+    // every request to ppvs.su goes through here. it's turned away while the host's circuit
+    // breaker is open, otherwise waits for the rate cap, logs the attempt and tells the breaker
+    // how it went. `context` is what a failed send is reported as
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+        context: &str,
+    ) -> AppResult<reqwest::Response> {
+        let (client, request) = request.build_split();
+        let request = request.map_err(|e| {
+            error!("{}: {}", context, e);
+            Error::InternalServerErrorWithContext(format!("{}: {}", context, e))
+        })?;
+        let host = request.url().host_str().unwrap_or_default().to_string();
+
+        if let Err(retry_after) = self.circuit_breaker.allow(&host).await {
+            warn!(
+                "not calling {}, its circuit breaker is open for {}s",
+                host, retry_after
+            );
+            return Err(Error::ServiceUnavailable {
+                message: format!("{} is unavailable, try again later", host),
+                retry_after,
+            });
+        }
+
+        self.upstream_rate.acquire().await;
+        let result = self
+            .attempt_log
+            .send(
+                &self.provider,
+                reqwest::RequestBuilder::from_parts(client, request),
+            )
+            .await;
+
+        match &result {
+            Ok(response) if CircuitBreaker::is_failure_status(response.status().as_u16()) => {
+                self.circuit_breaker.record_failure(&host).await
+            }
+            Ok(_) => self.circuit_breaker.record_success(&host).await,
+            Err(_) => self.circuit_breaker.record_failure(&host).await,
+        }
+
+        result.map_err(|e| {
+            error!("{}: {}", context, e);
+            Error::InternalServerErrorWithContext(format!("{}: {}", context, e))
+        })
+    }
+
+    /// fetches all games and marks the cache fresh as of `current_time`, the refresh tracker
+    /// hears about it either way
+    async fn refresh_games(&self, current_time: i64) -> AppResult<Vec<Game>> {
+        let games = match self.fetch_and_cache_games().await {
+            Ok(games) => games,
+            Err(e) => {
+                self.refresh_tracker
+                    .record_failure(current_time, &e.to_string());
+                return Err(e);
+            }
+        };
+
+        self.refresh_tracker.record_success(current_time);
+        self.repository
+            .set_last_fetch_time(&self.provider, current_time)
+            .await?;
+        Ok(games)
+    }
+
+    /// tries the refresh again in the background after serving stale games
+    fn schedule_refresh_retry(&self) {
+        let service = self.clone();
+        let scheduled = self.refresh_retry.schedule(async move {
+            let current_time = service.clock.now();
+            match service.refresh_games(current_time).await {
+                Ok(games) => info!("background retry refreshed {} games", games.len()),
+                Err(e) => warn!("background retry of the games refresh failed: {}", e),
+            }
+        });
+        if scheduled {
+            info!("scheduled a background retry of the games refresh");
         }
     }
 
     async fn refetch_game(&self, game_id: i64) -> AppResult<Game> {
         info!("refetching game {} from ppvs.su API", game_id);
 
-        let response = self
+        let request = self
             .http_client
-            .get(format!("https://api.ppv.to/api/streams/{}", game_id))
+            .get(format!("{}/api/streams/{}", self.api_base, game_id))
             .header("Accept", "application/json, text/plain, */*")
             .header("Accept-Language", "en-US,en;q=0.9")
-            .header("Referer", "https://api.ppv.to/api/streams/")
-            .header("Origin", "https://api.ppv.to/api/streams")
+            .header("Referer", format!("{}/api/streams/", self.api_base))
+            .header("Origin", format!("{}/api/streams", self.api_base))
             .header("Sec-Fetch-Dest", "empty")
             .header("Sec-Fetch-Mode", "cors")
-            .header("Sec-Fetch-Site", "same-origin")
-            .send()
-            .await
-            .map_err(|e| {
-                error!("failed to fetch game {}: {}", game_id, e);
-                Error::InternalServerErrorWithContext(format!("failed to fetch game: {}", e))
-            })?;
+            .header("Sec-Fetch-Site", "same-origin");
+        let response = self
+            .send(request, &format!("failed to fetch game {}", game_id))
+            .await?;
+
+        check_refetch_status(game_id, response.status())?;
 
         let detail_response: PpvsuStreamDetailResponse = response.json().await.map_err(|e| {
             error!("failed to parse game response: {}", e);
@@ -247,12 +318,7 @@ impl PpvsuService {
         //
         // let video_link = self.fetch_video_link(&iframe).await?;
 
-        let cache_time = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map_err(|_| {
-                Error::InternalServerErrorWithContext("System time before UNIX epoch".to_string())
-            })?
-            .as_secs() as i64;
+        let cache_time = self.clock.now();
 
         let game = Game {
             id: data.id,
@@ -265,13 +331,16 @@ impl PpvsuService {
             category: data.category_name.unwrap_or_else(|| "Unknown".to_string()),
         };
 
-        self.repository.store_game("ppvsu", &game).await?;
+        self.repository.store_game(&self.provider, &game).await?;
 
         Ok(game)
     }
 }
 
+// default for how long decrypted video links are cached
 const VIDEO_LINK_CACHE_TTL_SECS: u64 = 300;
+// and how long fetched games count as fresh
+pub const GAMES_CACHE_TTL_SECS: u64 = 3600;
 
 #[async_trait]
 impl PpvsuServiceTrait for PpvsuService {
@@ -312,7 +381,7 @@ impl PpvsuServiceTrait for PpvsuService {
         protobuf_header.extend_from_slice(path_bytes);
 
         // POST to /fetch endpoint to get the encrypted blob
-        let response = self
+        let request = self
             .http_client
             .post(format!("{}/fetch", base_url))
             .header("Accept", "*/*")
@@ -323,13 +392,8 @@ impl PpvsuServiceTrait for PpvsuService {
             .header("Accept-Language", "en-US,en;q=0.9")
             .header("Origin", &base_url)
             .header("Referer", iframe_url)
-            .body(protobuf_header)
-            .send()
-            .await
-            .map_err(|e| {
-                error!("fetch endpoint request failed: {}", e);
-                Error::InternalServerErrorWithContext(format!("fetch endpoint request failed: {}", e))
-            })?;
+            .body(protobuf_header);
+        let response = self.send(request, "fetch endpoint request failed").await?;
 
         if !response.status().is_success() {
             error!("fetch endpoint returned status: {}", response.status());
@@ -359,14 +423,14 @@ impl PpvsuServiceTrait for PpvsuService {
         })?;
         info!("received encrypted blob ({} chars)", encrypted_blob.len());
 
-        // Protobuf parse → ROT-71 decode → Base64 decode → ChaCha20 decrypt
-        let video_link = decrypt_stream_url(&encrypted_blob, &island_header)?;
+        // Protobuf parse → ROT-n decode → Base64 decode → ChaCha20 decrypt
+        let video_link = self.decryptor.decrypt(&encrypted_blob, &island_header)?;
         info!("decrypted video link: {}", video_link);
 
         // Cache the decrypted video link
         if let Err(e) = self
             .repository
-            .set_video_link(stream_path, &video_link, VIDEO_LINK_CACHE_TTL_SECS)
+            .set_video_link(stream_path, &video_link, self.video_link_ttl_seconds)
             .await
         {
             error!("failed to cache video link: {}", e);
@@ -383,7 +447,7 @@ impl PpvsuServiceTrait for PpvsuService {
         //
         // also just going to drop the future here because there is no point for me to actually
         // check it
-        let _ = self.http_client.get("https://api.ppv.to/api/ping")
+        let _ = self.http_client.get(format!("{}/api/ping", self.api_base))
             .header("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:146.0) Gecko/20100101 Firefox/146.0")
             .header("Accept", "application/json")
             .header("Accept-Language", "en-US,en;q=0.5")
@@ -392,24 +456,19 @@ impl PpvsuServiceTrait for PpvsuService {
             .header("Origin", "https://ppv.to")
             .header("Sec-GPC", "1")
             .send();
-        let response = self
+        let request = self
             .http_client
-            .get("https://api.ppv.to/api/streams")
+            .get(format!("{}/api/streams", self.api_base))
             .header("Accept", "application/json, text/plain, */*")
             .header("Accept-Language", "en-US,en;q=0.9")
             .header("Accept-Encoding", "gzip, deflate, br")
-            .header("Referer", "https://api.ppv.to/api/streams/")
-            .header("Origin", "https://api.ppv.to/api/streams")
+            .header("Referer", format!("{}/api/streams/", self.api_base))
+            .header("Origin", format!("{}/api/streams", self.api_base))
             .header("DNT", "1")
             .header("Sec-Fetch-Dest", "empty")
             .header("Sec-Fetch-Mode", "cors")
-            .header("Sec-Fetch-Site", "same-origin")
-            .send()
-            .await
-            .map_err(|e| {
-                error!("failed to fetch ppvs.su API: {}", e);
-                Error::InternalServerErrorWithContext(format!("failed to fetch ppvs.su API: {}", e))
-            })?;
+            .header("Sec-Fetch-Site", "same-origin");
+        let response = self.send(request, "failed to fetch ppvs.su API").await?;
 
         info!(
             "received response from ppvs.su with status: {}",
@@ -460,12 +519,11 @@ impl PpvsuServiceTrait for PpvsuService {
             ));
         }
 
-        let cache_time = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map_err(|_| {
-                Error::InternalServerErrorWithContext("System time before UNIX epoch".to_string())
-            })?
-            .as_secs() as i64;
+        // the old games only go once there are new ones, a failed fetch leaves them to be served
+        // stale
+        self.repository.clear_cache(&self.provider).await?;
+
+        let cache_time = self.clock.now();
 
         let mut games: Vec<Game> = Vec::new();
         let mut game_mem: Game;
@@ -484,7 +542,9 @@ impl PpvsuServiceTrait for PpvsuService {
                     };
                     games.push(game_mem.clone());
 
-                    self.repository.store_game("ppvsu", &game_mem).await?;
+                    self.repository
+                        .store_game(&self.provider, &game_mem)
+                        .await?;
                 }
             }
         }
@@ -575,7 +635,7 @@ impl PpvsuServiceTrait for PpvsuService {
     async fn get_games_with_refresh(&self) -> AppResult<Vec<Game>> {
         info!("retrieving games with refresh logic");
 
-        let cache_time = self.repository.get_last_fetch_time("ppvsu").await?;
+        let cache_time = self.repository.get_last_fetch_time(&self.provider).await?;
         let current_time = self.get_current_timestamp().await?;
 
         match cache_time {
@@ -585,13 +645,16 @@ impl PpvsuServiceTrait for PpvsuService {
                     "overall cache is fresh (last fetch {} seconds ago)",
                     cache_age
                 );
-                self.repository.get_games("ppvsu").await.map_err(|e| {
-                    error!("failed to get games from cache: {}", e);
-                    Error::InternalServerErrorWithContext(format!(
-                        "failed to get games from cache: {}",
-                        e
-                    ))
-                })
+                self.repository
+                    .get_games(&self.provider)
+                    .await
+                    .map_err(|e| {
+                        error!("failed to get games from cache: {}", e);
+                        Error::InternalServerErrorWithContext(format!(
+                            "failed to get games from cache: {}",
+                            e
+                        ))
+                    })
             }
             _ => {
                 if let Some(last_fetch) = cache_time {
@@ -604,12 +667,26 @@ impl PpvsuServiceTrait for PpvsuService {
                     info!("no cache found, fetching all games");
                 }
 
-                self.repository.clear_cache("ppvsu").await?;
-                let games = self.fetch_and_cache_games().await?;
-                self.repository
-                    .set_last_fetch_time("ppvsu", current_time)
-                    .await?;
-                Ok(games)
+                match self.refresh_games(current_time).await {
+                    Ok(games) => Ok(games),
+                    Err(e) => {
+                        // the failed fetch left the old games alone, better stale than nothing
+                        let stale = self.repository.get_games(&self.provider).await?;
+                        if stale.is_empty() {
+                            return Err(e);
+                        }
+                        warn!(
+                            "refetch of all games failed, serving {} stale ones ({}): {}",
+                            stale.len(),
+                            cache_time
+                                .map(|at| format!("last fetched {}s ago", current_time - at))
+                                .unwrap_or_else(|| "never fetched".to_string()),
+                            e
+                        );
+                        self.schedule_refresh_retry();
+                        Ok(stale)
+                    }
+                }
             }
         }
 
@@ -694,25 +771,23 @@ impl PpvsuServiceTrait for PpvsuService {
     async fn get_game_by_id(&self, game_id: i64) -> AppResult<Game> {
         info!("fetching game {} from cache or API", game_id);
 
-        if let Some(cached_game) = self.repository.get_game("ppvsu", game_id).await? {
-            let current_time = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .map_err(|_| {
-                    Error::InternalServerErrorWithContext(
-                        "System time before UNIX epoch".to_string(),
-                    )
-                })?
-                .as_secs() as i64;
+        let cached = self.repository.get_game(&self.provider, game_id).await?;
+
+        if let Some(cached_game) = &cached {
+            let current_time = self.clock.now();
 
             let cache_age = current_time - cached_game.cache_time;
-            let one_hour = 3600;
 
-            if cache_age <= one_hour {
+            if !self.ttl_jitter.is_stale(
+                cached_game.cache_time,
+                current_time,
+                self.games_ttl_seconds,
+            ) {
                 info!(
                     "returning cached game {} (age: {} seconds)",
                     game_id, cache_age
                 );
-                return Ok(cached_game);
+                return Ok(cached_game.clone());
             }
 
             info!(
@@ -723,37 +798,56 @@ impl PpvsuServiceTrait for PpvsuService {
             info!("game {} not in cache, fetching from API", game_id);
         }
 
-        let game = self
-            .refetch_game(game_id)
-            .await
-            .map_err(|e| Error::NotFound(format!("game {} not found: {}", game_id, e)))?;
-
-        Ok(game)
+        match self.refetch_game(game_id).await {
+            Ok(game) => Ok(game),
+            Err(Error::NotFound(message)) => {
+                // upstream dropped it, so don't keep handing out the old copy
+                if cached.is_some() {
+                    info!("removing game {} from cache", game_id);
+                    self.repository.delete_game(&self.provider, game_id).await?;
+                }
+                Err(Error::NotFound(format!(
+                    "game {} not found: {}",
+                    game_id, message
+                )))
+            }
+            Err(e) => match cached {
+                Some(stale_game) => {
+                    warn!(
+                        "refetch of game {} failed, serving stale copy: {}",
+                        game_id, e
+                    );
+                    Ok(stale_game)
+                }
+                None => Err(Error::NotFound(format!(
+                    "game {} not found: {}",
+                    game_id, e
+                ))),
+            },
+        }
     }
 
     async fn clear_cache(&self) -> AppResult<()> {
         info!("clearing ppvsu cache");
 
-        self.repository.clear_cache("ppvsu").await.map_err(|e| {
-            error!("failed to clear ppvsu cache: {}", e);
-            Error::InternalServerErrorWithContext(format!("failed to clear cache: {}", e))
-        })?;
+        self.repository
+            .clear_cache(&self.provider)
+            .await
+            .map_err(|e| {
+                error!("failed to clear ppvsu cache: {}", e);
+                Error::InternalServerErrorWithContext(format!("failed to clear cache: {}", e))
+            })?;
 
         info!("ppvsu cache cleared successfully");
         Ok(())
     }
 
     async fn get_current_timestamp(&self) -> AppResult<i64> {
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_secs() as i64)
-            .map_err(|_| {
-                Error::InternalServerErrorWithContext("System time before UNIX epoch".to_string())
-            })
+        Ok(self.clock.now())
     }
 
     async fn is_cache_stale(&self, cache_time: i64, current_time: i64) -> bool {
-        const ONE_HOUR: i64 = 3600;
-        current_time - cache_time > ONE_HOUR
+        self.ttl_jitter
+            .is_stale(cache_time, current_time, self.games_ttl_seconds)
     }
 }
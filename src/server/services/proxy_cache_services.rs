@@ -1,16 +1,551 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use sha2::{Digest, Sha256};
-use tokio::sync::{Notify, Semaphore};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore, watch};
 use tokio::task::JoinSet;
 use tracing::{debug, error, info, warn};
 
 use base64::Engine;
+use regex::Regex;
 use crate::database::Database;
+use crate::server::services::cookie_services::CookieService;
+use crate::server::services::shutdown_services::ShutdownHook;
+use crate::server::services::upstream_attempt_services::UpstreamAttemptLog;
+use crate::server::services::upstream_limit_services::{UpstreamLimiter, UpstreamRateLimit};
+use crate::server::utils::decode_utils;
+use crate::server::utils::etag_utils;
+use crate::server::utils::segment_utils::check_segment;
+use crate::server::utils::ttl_utils::{DEFAULT_TTL_JITTER_PERCENT, TtlJitter};
+use crate::server::utils::upstream_utils::{
+    HostAllowlist, UpstreamConnection, UpstreamHttp, UpstreamTimeouts, apply_schema_headers,
+    validate_target,
+};
 
+// defaults for how long playlists and segments stay cached
 const M3U8_TTL_SECONDS: u64 = 10;
 const SEGMENT_TTL_SECONDS: u64 = 300;
+// and how long a segment upstream said doesn't exist is remembered
+const NEGATIVE_TTL_SECONDS: u64 = 5;
+
+/// how often a request waiting on another one's playlist refetch checks the cache
+const M3U8_FILL_POLL: Duration = Duration::from_millis(50);
+
+/// how long a url that was cached as a playlist keeps getting the fill lock, even when nothing
+/// in the url says it's a playlist (like the entry urls handed out by the stream endpoints)
+const M3U8_SEEN_TTL_SECONDS: u64 = 3600;
+
+// defaults for how prefetched segments are batched into one store write
+const PREFETCH_WRITE_BATCH_SIZE: usize = 8;
+const PREFETCH_WRITE_BATCH_MS: u64 = 50;
+
+// deletes the fill lock only while it still holds the caller's token, once the lock ran out and
+// another request took it, it's theirs
+const RELEASE_M3U8_LOCK_SCRIPT: &str = "if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) else return 0 end";
+
+/// what `wait_for_m3u8_fill` came back with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum M3u8Fill {
+    /// another request refetched the playlist while this one waited
+    Filled(String),
+    /// this request should fetch the playlist itself. the token is set when it got the fill lock,
+    /// hand it to `release_m3u8_lock` once the playlist is cached or the fetch failed
+    Fetch(Option<String>),
+}
+
+/// the fill lock a request took in `wait_for_m3u8_fill`, let go when it's dropped. every way out
+/// of the refetch hands it back that way, a failed fetch included, so the waiters don't sit out
+/// the whole lock and then all go upstream at once
+pub struct M3u8FillLock {
+    cache: DynProxyCacheService,
+    url: String,
+    token: String,
+}
+
+impl M3u8FillLock {
+    pub fn new(cache: DynProxyCacheService, url: &str, token: String) -> Self {
+        Self {
+            cache,
+            url: url.to_string(),
+            token,
+        }
+    }
+}
+
+impl Drop for M3u8FillLock {
+    fn drop(&mut self) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            // no runtime to release it on, it runs out on its own
+            return;
+        };
+        let cache = self.cache.clone();
+        let url = std::mem::take(&mut self.url);
+        let token = std::mem::take(&mut self.token);
+        runtime.spawn(async move {
+            cache.release_m3u8_lock(&url, &token).await;
+        });
+    }
+}
+
+/// a url pattern that skips the proxy cache entirely (no lookup, no store)
+#[derive(Debug, Clone)]
+pub enum CacheBypassPattern {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl CacheBypassPattern {
+    /// parses a comma separated list, plain entries are substring matches and entries prefixed
+    /// with `re:` are regexes. bad regexes get logged and skipped so a typo can't take the proxy down
+    pub fn parse_list(patterns: &str) -> Vec<Self> {
+        patterns
+            .split(',')
+            .map(|p| p.trim())
+            .filter(|p| !p.is_empty())
+            .filter_map(|p| match p.strip_prefix("re:") {
+                Some(re) => match Regex::new(re) {
+                    Ok(re) => Some(Self::Regex(re)),
+                    Err(e) => {
+                        warn!("Ignoring invalid cache bypass regex {}: {}", re, e);
+                        None
+                    }
+                },
+                None => Some(Self::Substring(p.to_string())),
+            })
+            .collect()
+    }
+
+    pub fn matches(&self, url: &str) -> bool {
+        match self {
+            Self::Substring(s) => url.contains(s.as_str()),
+            Self::Regex(re) => re.is_match(url),
+        }
+    }
+}
+
+/// shares the prefetch pool between clients. every fetch needs a slot from its client's sub-limit
+/// and then one from the global pool, both are fifo so one client's giant playlist only ever holds
+/// `max_per_client` of the global slots and everyone else's fetches get in between. on shutdown
+/// it cancels every prefetch that's still queued or running
+#[derive(Debug)]
+pub struct PrefetchScheduler {
+    global: Arc<Semaphore>,
+    max_per_client: usize,
+    clients: Mutex<HashMap<String, Arc<Semaphore>>>,
+    cancelled: watch::Sender<bool>,
+}
+
+impl Default for PrefetchScheduler {
+    fn default() -> Self {
+        Self::new(5, 2)
+    }
+}
+
+impl PrefetchScheduler {
+    /// `max_per_client` is clamped to `max_concurrent`, the global cap is always the upper bound
+    pub fn new(max_concurrent: usize, max_per_client: usize) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        Self {
+            global: Arc::new(Semaphore::new(max_concurrent)),
+            max_per_client: max_per_client.clamp(1, max_concurrent),
+            clients: Mutex::new(HashMap::new()),
+            cancelled: watch::Sender::new(false),
+        }
+    }
+
+    /// stops every running prefetch and makes new ones return straight away
+    pub fn cancel(&self) {
+        self.cancelled.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.borrow()
+    }
+
+    /// resolves once `cancel` was called, right away if it already was
+    pub async fn cancelled(&self) {
+        let mut cancelled = self.cancelled.subscribe();
+        // the sender lives in self, so this can't fail while we wait
+        let _ = cancelled.wait_for(|cancelled| *cancelled).await;
+    }
+
+    fn client_semaphore(&self, client_id: &str) -> Arc<Semaphore> {
+        let mut clients = self.clients.lock().unwrap();
+        if !clients.contains_key(client_id) {
+            // drop clients that have nothing queued or running so the map doesn't grow forever
+            clients.retain(|_, s| Arc::strong_count(s) > 1);
+        }
+        clients
+            .entry(client_id.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_client)))
+            .clone()
+    }
+
+    /// waits for a slot, hold both permits until the fetch is done
+    pub async fn acquire(&self, client_id: &str) -> (OwnedSemaphorePermit, OwnedSemaphorePermit) {
+        let client = self
+            .client_semaphore(client_id)
+            .acquire_owned()
+            .await
+            .expect("semaphore closed");
+        let global = self
+            .global
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore closed");
+        (client, global)
+    }
+}
+
+// half written prefetches are worse than none, they're all dropped once the server stopped
+#[async_trait::async_trait]
+impl ShutdownHook for PrefetchScheduler {
+    fn name(&self) -> &str {
+        "prefetch"
+    }
+
+    async fn on_shutdown(&self) -> anyhow::Result<()> {
+        self.cancel();
+        Ok(())
+    }
+}
+
+struct InflightEntry {
+    notify: Arc<Notify>,
+    registered_at: Instant,
+}
+
+fn record_inflight(count: usize) {
+    metrics::gauge!("proxy_prefetch_inflight").set(count as f64);
+}
+
+/// segment urls a prefetch is currently fetching, so a request for one can wait on the prefetch
+/// instead of going upstream itself. entries are removed by the `InflightGuard` the fetch holds,
+/// which also happens when the fetch panics or gets cancelled. anything older than `max_age` is
+/// treated as orphaned and reaped
+#[derive(Clone)]
+pub struct InflightRegistry {
+    entries: Arc<Mutex<HashMap<String, InflightEntry>>>,
+    max_age: Duration,
+}
+
+impl Default for InflightRegistry {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(90))
+    }
+}
+
+impl InflightRegistry {
+    /// a `max_age` of 0 never reaps, entries only go away through their guard
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            max_age,
+        }
+    }
+
+    /// marks the url as in flight until the returned guard is dropped. a url that's already in
+    /// flight shares the existing notifier, whichever fetch finishes first wakes the waiters
+    pub fn register(&self, url: &str) -> InflightGuard {
+        let mut entries = self.entries.lock().unwrap();
+        let notify = entries
+            .entry(url.to_string())
+            .or_insert_with(|| InflightEntry {
+                notify: Arc::new(Notify::new()),
+                registered_at: Instant::now(),
+            })
+            .notify
+            .clone();
+        record_inflight(entries.len());
+        drop(entries);
+
+        InflightGuard {
+            entries: self.entries.clone(),
+            url: url.to_string(),
+            notify,
+        }
+    }
+
+    /// the notifier to wait on when the url is in flight
+    pub fn waiter(&self, url: &str) -> Option<Arc<Notify>> {
+        self.reap();
+        self.entries
+            .lock()
+            .unwrap()
+            .get(url)
+            .map(|entry| entry.notify.clone())
+    }
+
+    /// drops entries older than `max_age` and wakes anyone waiting on them, returns how many
+    pub fn reap(&self) -> usize {
+        if self.max_age.is_zero() {
+            return 0;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|url, entry| {
+            let orphaned = entry.registered_at.elapsed() > self.max_age;
+            if orphaned {
+                warn!("Reaping orphaned inflight prefetch: {}", url);
+                entry.notify.notify_waiters();
+            }
+            !orphaned
+        });
+        record_inflight(entries.len());
+        before - entries.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// keeps a url registered as in flight, dropping it removes the entry and wakes the waiters
+pub struct InflightGuard {
+    entries: Arc<Mutex<HashMap<String, InflightEntry>>>,
+    url: String,
+    notify: Arc<Notify>,
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            // only our own entry, a reaped and re-registered url belongs to someone else now
+            if entries
+                .get(&self.url)
+                .is_some_and(|entry| Arc::ptr_eq(&entry.notify, &self.notify))
+            {
+                entries.remove(&self.url);
+            }
+            record_inflight(entries.len());
+        }
+        self.notify.notify_waiters();
+    }
+}
+
+/// a prefetched segment waiting for its batch to be written, the guard keeps it in flight until
+/// then so nobody reads the store before it's there
+struct PrefetchedSegment {
+    url: String,
+    bytes: Vec<u8>,
+    _guard: InflightGuard,
+}
+
+struct L1Entry {
+    bytes: Vec<u8>,
+    /// unix millis, same as the stored segment's time key
+    cached_at: i64,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct L1State {
+    entries: HashMap<String, L1Entry>,
+    /// entries by when they were last used, the first one is what gets evicted
+    by_use: BTreeMap<u64, String>,
+    bytes: usize,
+    tick: u64,
+}
+
+impl L1State {
+    fn remove(&mut self, key: &str) -> Option<L1Entry> {
+        let entry = self.entries.remove(key)?;
+        self.by_use.remove(&entry.last_used);
+        self.bytes -= entry.bytes.len();
+        Some(entry)
+    }
+}
+
+/// hot segments kept in process in front of the store, so a hit doesn't cost a Redis round trip.
+/// bounded by total bytes, the least recently used segment goes first when it's full. entries
+/// run out with the same TTL as the stored segment. a `max_bytes` of 0 keeps nothing
+pub struct SegmentMemoryCache {
+    max_bytes: usize,
+    state: Mutex<L1State>,
+}
+
+impl SegmentMemoryCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            state: Mutex::new(L1State::default()),
+        }
+    }
+
+    /// the bytes and when they were cached (unix millis)
+    pub fn get(&self, key: &str) -> Option<(Vec<u8>, i64)> {
+        if self.max_bytes == 0 {
+            return None;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state
+            .entries
+            .get(key)
+            .is_some_and(|entry| entry.expires_at <= Instant::now())
+        {
+            state.remove(key);
+            return None;
+        }
+
+        state.tick += 1;
+        let tick = state.tick;
+        let entry = state.entries.get_mut(key)?;
+        let previous = std::mem::replace(&mut entry.last_used, tick);
+        let found = (entry.bytes.clone(), entry.cached_at);
+        state.by_use.remove(&previous);
+        state.by_use.insert(tick, key.to_string());
+        Some(found)
+    }
+
+    /// a segment bigger than the whole cache is skipped
+    pub fn insert(&self, key: &str, bytes: &[u8], cached_at: i64, ttl: Duration) {
+        if self.max_bytes == 0 || bytes.len() > self.max_bytes || ttl.is_zero() {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.remove(key);
+
+        // expired ones go before anything still good gets evicted
+        let now = Instant::now();
+        if state.bytes + bytes.len() > self.max_bytes {
+            let expired: Vec<String> = state
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.expires_at <= now)
+                .map(|(expired, _)| expired.clone())
+                .collect();
+            for expired in expired {
+                state.remove(&expired);
+            }
+        }
+
+        while state.bytes + bytes.len() > self.max_bytes {
+            let Some((_, oldest)) = state.by_use.pop_first() else {
+                break;
+            };
+            if let Some(entry) = state.entries.remove(&oldest) {
+                state.bytes -= entry.bytes.len();
+            }
+        }
+
+        state.tick += 1;
+        let tick = state.tick;
+        state.bytes += bytes.len();
+        state.by_use.insert(tick, key.to_string());
+        state.entries.insert(
+            key.to_string(),
+            L1Entry {
+                bytes: bytes.to_vec(),
+                cached_at,
+                expires_at: now + ttl,
+                last_used: tick,
+            },
+        );
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.state.lock().unwrap().remove(key);
+    }
+
+    /// total bytes held
+    pub fn size_bytes(&self) -> usize {
+        self.state.lock().unwrap().bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProxyCacheConfig {
+    /// urls matching any of these are never looked up or stored
+    pub bypass_patterns: Vec<CacheBypassPattern>,
+    /// connection reuse for prefetch requests
+    pub upstream_connection: UpstreamConnection,
+    /// per host cap on active upstream requests, shared with the proxy controller
+    pub upstream_limiter: Arc<UpstreamLimiter>,
+    /// global requests per second cap, shared with the proxy controller and ppvsu
+    pub upstream_rate: Arc<UpstreamRateLimit>,
+    /// prefetches are always segments so they get the segment budget
+    pub upstream_timeouts: UpstreamTimeouts,
+    /// how long past its normal TTL a playlist can still be served when upstream fails, 0 is off
+    pub stale_if_error_seconds: u64,
+    /// global and per client caps on concurrent prefetches
+    pub prefetch_scheduler: Arc<PrefetchScheduler>,
+    /// check every packet of a .ts segment for the sync byte before caching it
+    pub verify_ts_sync: bool,
+    /// cached segments of a live playlist older than this are a miss, vod ones keep the full TTL.
+    /// 0 is off
+    pub live_segment_max_age_seconds: u64,
+    /// prefetches show up in the upstream attempt log too
+    pub attempt_log: Arc<UpstreamAttemptLog>,
+    /// inflight prefetch entries older than this are considered orphaned and reaped, 0 is off
+    pub inflight_max_age_seconds: u64,
+    /// how long a raw playlist stays cached
+    pub m3u8_ttl_seconds: u64,
+    /// how long a segment stays cached
+    pub segment_ttl_seconds: u64,
+    /// random spread on the playlist and segment ttls, each entry gets its own
+    pub ttl_jitter: TtlJitter,
+    /// how long an upstream 404/410 for a segment is remembered, requests for it in that time get
+    /// a 404 without going upstream. 0 is off
+    pub negative_ttl_seconds: u64,
+    /// how long the request refetching an expired playlist holds the fill lock, everyone else
+    /// waits at most this long for it. 0 is off
+    pub m3u8_lock_ms: u64,
+    /// byte cap of the in process segment cache in front of the store, 0 is off
+    pub l1_max_bytes: usize,
+    /// prefetched segments written to the store in one round trip, 1 writes each one as it lands
+    pub prefetch_write_batch_size: usize,
+    /// longest a prefetched segment waits for the rest of its batch before it's written anyway
+    pub prefetch_write_batch_ms: u64,
+    /// hosts prefetches may go to, the same list the proxy checks. segment urls come out of an
+    /// upstream playlist so they aren't covered by the signature
+    pub allowed_hosts: HostAllowlist,
+}
+
+impl Default for ProxyCacheConfig {
+    fn default() -> Self {
+        Self {
+            bypass_patterns: Vec::new(),
+            upstream_connection: UpstreamConnection::default(),
+            upstream_limiter: Arc::default(),
+            upstream_rate: Arc::default(),
+            upstream_timeouts: UpstreamTimeouts::default(),
+            stale_if_error_seconds: 0,
+            prefetch_scheduler: Arc::default(),
+            verify_ts_sync: false,
+            live_segment_max_age_seconds: 0,
+            attempt_log: Arc::default(),
+            inflight_max_age_seconds: 0,
+            m3u8_ttl_seconds: M3U8_TTL_SECONDS,
+            segment_ttl_seconds: SEGMENT_TTL_SECONDS,
+            ttl_jitter: TtlJitter::new(DEFAULT_TTL_JITTER_PERCENT),
+            negative_ttl_seconds: NEGATIVE_TTL_SECONDS,
+            m3u8_lock_ms: 0,
+            l1_max_bytes: 0,
+            prefetch_write_batch_size: PREFETCH_WRITE_BATCH_SIZE,
+            prefetch_write_batch_ms: PREFETCH_WRITE_BATCH_MS,
+            allowed_hosts: HostAllowlist::default(),
+        }
+    }
+}
 
 pub type DynProxyCacheService = Arc<dyn ProxyCacheServiceTrait + Send + Sync>;
 
@@ -18,99 +553,364 @@ pub type DynProxyCacheService = Arc<dyn ProxyCacheServiceTrait + Send + Sync>;
 pub trait ProxyCacheServiceTrait {
     /// Pipeline check Redis for both m3u8 and segment caches in one round trip.
     /// Returns (Option<m3u8_text>, Option<segment_bytes>).
-    async fn get_cached(&self, url: &str) -> (Option<String>, Option<Vec<u8>>);
+    /// `live` is set when the segment came from a live playlist, those are only served while
+    /// younger than live_segment_max_age_seconds.
+    async fn get_cached(&self, url: &str, live: bool) -> (Option<String>, Option<Vec<u8>>);
 
-    /// Cache raw m3u8 text (before URL rewriting) with short TTL.
+    /// Cache raw m3u8 text (before URL rewriting) with short TTL. Leaves the fill lock alone.
     async fn cache_m3u8(&self, url: &str, text: &str);
 
+    /// Waits for another request that's already refetching this expired playlist. `Fetch` means
+    /// this request should fetch it itself, either because it got the fill lock or because whoever
+    /// held it never cached anything. `is_playlist` is what the url says about itself, urls that
+    /// don't say still get the lock once they've been cached as a playlist.
+    async fn wait_for_m3u8_fill(&self, url: &str, is_playlist: bool) -> M3u8Fill;
+
+    /// Lets go of the fill lock taken by `wait_for_m3u8_fill`, only while it's still held with
+    /// `token`, so a lock that ran out and was taken by another request stays theirs.
+    async fn release_m3u8_lock(&self, url: &str, token: &str);
+
+    /// Last good copy of a playlist, kept around for stale_if_error_seconds past the normal TTL.
+    /// Only meant for when the upstream fetch failed.
+    async fn get_stale_m3u8(&self, url: &str) -> Option<String>;
+
     /// Cache segment bytes with longer TTL.
     async fn cache_segment(&self, url: &str, bytes: &[u8]);
 
+    /// Remember that upstream answered `status` for a segment. Only 404/410 are kept, for the
+    /// negative TTL, so clients retrying a segment missing in an ad break don't all go upstream.
+    async fn cache_missing(&self, url: &str, status: u16);
+
+    /// The status upstream gave for a segment it recently didn't have, None when it's not known
+    /// to be missing.
+    async fn get_missing(&self, url: &str) -> Option<u16>;
+
     /// Wait for an in-flight prefetch of the given URL.
     /// Returns `Some(bytes)` if the prefetch completes and the segment is in cache,
     /// or `None` if no prefetch is in-flight or the wait times out.
     async fn wait_for_inflight(&self, url: &str) -> Option<Vec<u8>>;
 
     /// Pre-fetch a list of segment URLs in the background, caching each in Redis.
-    /// Skips URLs already cached. Caps concurrent upstream fetches at 5.
-    async fn prefetch_segments(&self, urls: Vec<String>);
+    /// Skips URLs already cached or known missing. Concurrent upstream fetches are capped globally and per client
+    /// by the prefetch scheduler.
+    async fn prefetch_segments(&self, client_id: &str, schema: &str, urls: Vec<String>);
+
+    /// Remember that these URLs belong to a stream (e.g. `ppvsu-42`) so they can be dropped
+    /// together later. The index expires along with the longest lived entry.
+    async fn index_stream(&self, stream: &str, urls: &[String]);
+
+    /// Drop every cached playlist and segment indexed under the stream.
+    /// Returns how many URLs were invalidated.
+    async fn invalidate_stream(&self, stream: &str) -> anyhow::Result<usize>;
 }
 
 pub struct ProxyCacheService {
     db: Arc<Database>,
-    http: reqwest::Client,
-    inflight: Mutex<HashMap<String, Arc<Notify>>>,
+    http: UpstreamHttp,
+    config: ProxyCacheConfig,
+    inflight: InflightRegistry,
+    l1: Arc<SegmentMemoryCache>,
 }
 
 impl ProxyCacheService {
-    pub fn new(db: Arc<Database>, http: reqwest::Client) -> Self {
+    pub fn new(db: Arc<Database>, http: impl Into<UpstreamHttp>, config: ProxyCacheConfig) -> Self {
         Self {
             db,
-            http,
-            inflight: Mutex::new(HashMap::new()),
+            http: http.into(),
+            inflight: InflightRegistry::new(Duration::from_secs(config.inflight_max_age_seconds)),
+            l1: Arc::new(SegmentMemoryCache::new(config.l1_max_bytes)),
+            config,
         }
     }
 
+    /// true if the url matches one of the configured bypass patterns
+    pub fn should_bypass(&self, url: &str) -> bool {
+        self.config.bypass_patterns.iter().any(|p| p.matches(url))
+    }
+
     fn hash_url(url: &str) -> String {
         let mut hasher = Sha256::new();
         hasher.update(url.as_bytes());
         hex::encode(hasher.finalize())
     }
 
+    // hit ratios per kind, a lookup that found nothing at all is one miss
+    fn record_lookup(m3u8_hit: bool, segment_hit: bool) {
+        if m3u8_hit {
+            metrics::counter!("proxy_cache_hits_total", "kind" => "m3u8").increment(1);
+        }
+        if segment_hit {
+            metrics::counter!("proxy_cache_hits_total", "kind" => "segment").increment(1);
+        }
+        if !m3u8_hit && !segment_hit {
+            metrics::counter!("proxy_cache_misses_total").increment(1);
+        }
+    }
+
+    /// etag for the segment cached under `url`, built from the same hash as its cache key
+    pub fn segment_etag(url: &str, len: usize) -> String {
+        etag_utils::segment_etag(&Self::hash_url(url), len)
+    }
+
     fn m3u8_key(url: &str) -> String {
         format!("pcache:m3u8:{}", Self::hash_url(url))
     }
 
+    fn m3u8_stale_key(url: &str) -> String {
+        format!("pcache:m3u8:stale:{}", Self::hash_url(url))
+    }
+
+    fn m3u8_lock_key(url: &str) -> String {
+        format!("pcache:m3u8:lock:{}", Self::hash_url(url))
+    }
+
+    /// set whenever the url is cached as a playlist, outlives the playlist itself
+    fn m3u8_seen_key(url: &str) -> String {
+        format!("pcache:playlist:{}", Self::hash_url(url))
+    }
+
     fn segment_key(url: &str) -> String {
         format!("pcache:seg:{}", Self::hash_url(url))
     }
 
-    /// Fetch a single segment from upstream with sports-style headers, decompress, and cache it.
-    async fn fetch_and_cache_segment(
-        http: &reqwest::Client,
-        db: &Arc<Database>,
+    fn negative_key(url: &str) -> String {
+        format!("pcache:neg:{}", Self::hash_url(url))
+    }
+
+    fn stream_index_key(stream: &str) -> String {
+        format!("pcache:stream:{}", stream)
+    }
+
+    /// every key cached for a url hash, playlists and segments alike
+    fn entry_keys(hash: &str) -> Vec<String> {
+        vec![
+            format!("pcache:m3u8:{}", hash),
+            format!("pcache:m3u8:stale:{}", hash),
+            format!("pcache:playlist:{}", hash),
+            format!("pcache:seg:{}", hash),
+            format!("pcache:seg:at:{}", hash),
+            format!("pcache:neg:{}", hash),
+        ]
+    }
+
+    /// when the segment was stored (unix millis), expires with the segment
+    fn segment_time_key(url: &str) -> String {
+        format!("pcache:seg:at:{}", Self::hash_url(url))
+    }
+
+    /// live segments only count while they're younger than the live max-age. one without a
+    /// timestamp is treated as too old, there's no telling how long it's been sitting there
+    fn segment_is_fresh(&self, live: bool, cached_at: Option<i64>) -> bool {
+        let max_age = self.config.live_segment_max_age_seconds;
+        if !live || max_age == 0 {
+            return true;
+        }
+
+        let now = chrono::Utc::now().timestamp_millis();
+        cached_at.is_some_and(|at| now - at <= max_age as i64 * 1000)
+    }
+
+    /// SET NX PX of a fresh token on the playlist's fill lock, the token when this request got
+    /// it. a store error counts as getting it so requests go back to fetching on their own
+    async fn try_lock_m3u8(&self, lock_key: &str) -> Option<String> {
+        let ttl_ms = self.config.m3u8_lock_ms;
+        let token = nanoid::nanoid!();
+
+        match self.db.as_ref() {
+            Database::Redis(redis) => {
+                let mut conn = redis.connection.clone();
+                let result: Result<Option<String>, redis::RedisError> = redis::cmd("SET")
+                    .arg(lock_key)
+                    .arg(&token)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(ttl_ms)
+                    .query_async(&mut conn)
+                    .await;
+                let locked = result.map(|set| set.is_some()).unwrap_or_else(|e| {
+                    error!("Failed to take m3u8 fill lock: {}", e);
+                    true
+                });
+                locked.then_some(token)
+            }
+            Database::Memory(mem) => {
+                let locked = mem
+                    .store
+                    .set_nx_px(lock_key, &token, ttl_ms)
+                    .await
+                    .unwrap_or(true);
+                locked.then_some(token)
+            }
+        }
+    }
+
+    /// whether the url was cached as a playlist in the last hour. a store error counts as no, the
+    /// request just fetches on its own like before there was a lock
+    async fn seen_as_playlist(&self, url: &str) -> bool {
+        let key = Self::m3u8_seen_key(url);
+        match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(redis) => {
+                use redis::AsyncCommands;
+                let mut conn = redis.connection.clone();
+                let result: Result<bool, redis::RedisError> = conn.exists(&key).await;
+                result.unwrap_or_else(|e| {
+                    error!("Failed to check if url is a playlist: {}", e);
+                    false
+                })
+            }
+            Database::Memory(mem) => mem.store.get(&key).await.ok().flatten().is_some(),
+        }
+    }
+
+    /// the cached playlist and whether its fill lock is still held, in one round trip
+    async fn poll_m3u8_fill(&self, key: &str, lock_key: &str) -> (Option<String>, bool) {
+        match self.db.as_ref() {
+            Database::Redis(redis) => {
+                let mut conn = redis.connection.clone();
+                let result: Result<(Option<String>, bool), redis::RedisError> = redis::pipe()
+                    .get(key)
+                    .exists(lock_key)
+                    .query_async(&mut conn)
+                    .await;
+                result.unwrap_or_else(|e| {
+                    error!("Failed to check m3u8 fill: {}", e);
+                    (None, false)
+                })
+            }
+            Database::Memory(mem) => (
+                mem.store.get(key).await.ok().flatten(),
+                mem.store.get(lock_key).await.ok().flatten().is_some(),
+            ),
+        }
+    }
+
+    /// keeps a segment that was just stored in memory too, for the rest of its `ttl_seconds`
+    fn remember_segment(l1: &SegmentMemoryCache, url: &str, bytes: &[u8], ttl_seconds: u64) {
+        l1.insert(
+            &Self::segment_key(url),
+            bytes,
+            chrono::Utc::now().timestamp_millis(),
+            Duration::from_secs(ttl_seconds),
+        );
+    }
+
+    /// upstream answers worth remembering a segment as missing for, a 5xx might be gone on the
+    /// next try
+    pub fn is_negative_status(status: u16) -> bool {
+        matches!(status, 404 | 410)
+    }
+
+    /// Store the status upstream gave for a missing segment with the negative TTL.
+    async fn store_missing(
+        db: &Database,
         url: &str,
+        status: u16,
+        ttl: u64,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let accept_encoding = "gzip, deflate, br, zstd";
-
-        let mut request_builder = http.get(url);
-
-        if url.contains("strm.poocloud.in") {
-            request_builder = request_builder
-                .header(reqwest::header::ORIGIN, "https://ppvs.su")
-                .header(reqwest::header::ACCEPT, "*/*")
-                .header(reqwest::header::ACCEPT_ENCODING, accept_encoding)
-                .header(reqwest::header::REFERER, "https://modistreams.org/")
-                .header(
-                    reqwest::header::USER_AGENT,
-                    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
-                );
-        }
-        if url.contains("modifiles.fans") {
-            request_builder = request_builder
-                .header(reqwest::header::ORIGIN, "https://pooembed.eu")
-                .header(reqwest::header::ACCEPT, "*/*")
-                .header(reqwest::header::ACCEPT_ENCODING, accept_encoding)
-                .header(reqwest::header::REFERER, "https://pooembed.eu/")
-                .header(
-                    reqwest::header::USER_AGENT,
-                    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
-                );
-        } else {
-            request_builder = request_builder
-                .header(reqwest::header::REFERER, "https://api.ppv.to/api/streams/")
-                .header(reqwest::header::ORIGIN, "https://api.ppv.to/api/streams")
-                .header(
-                    reqwest::header::USER_AGENT,
-                    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
-                )
-                .header(reqwest::header::ACCEPT_ENCODING, accept_encoding)
-                .header(reqwest::header::ACCEPT, "*/*");
-        }
-
-        let response = request_builder.send().await?;
+        if ttl == 0 || !Self::is_negative_status(status) || UpstreamTimeouts::is_playlist_url(url) {
+            return Ok(());
+        }
+
+        let key = Self::negative_key(url);
+        match db {
+            Database::Redis(redis) => {
+                use redis::AsyncCommands;
+                let mut conn = redis.connection.clone();
+                let _: () = conn.set_ex(&key, status, ttl).await?;
+            }
+            Database::Memory(mem) => {
+                mem.store.set_ex(&key, &status.to_string(), ttl).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Store segment bytes along with the time they were cached, both with the segment TTL.
+    async fn store_segment(
+        db: &Database,
+        url: &str,
+        bytes: &[u8],
+        ttl: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Self::store_segments(db, &[(url, bytes, ttl)]).await
+    }
+
+    /// Same as `store_segment` for a bunch of segments (each with its own TTL), one pipeline (one
+    /// round trip) on redis. atomic so a segment never lands without its timestamp
+    async fn store_segments(
+        db: &Database,
+        segments: &[(&str, &[u8], u64)],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let now = chrono::Utc::now().timestamp_millis();
+
+        match db {
+            #[allow(unused_imports)]
+            Database::Redis(redis) => {
+                use redis::AsyncCommands;
+                let mut conn = redis.connection.clone();
+                let mut pipe = redis::pipe();
+                pipe.atomic();
+                for (url, bytes, ttl) in segments {
+                    pipe.set_ex(Self::segment_key(url), *bytes, *ttl)
+                        .ignore()
+                        .set_ex(Self::segment_time_key(url), now, *ttl)
+                        .ignore();
+                }
+                let result: Result<(), redis::RedisError> = pipe.query_async(&mut conn).await;
+                result?;
+            }
+            Database::Memory(mem) => {
+                for (url, bytes, ttl) in segments {
+                    // Store binary data as base64 string for in-memory
+                    let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+                    mem.store
+                        .set_ex(&Self::segment_key(url), &encoded, *ttl)
+                        .await?;
+                    mem.store
+                        .set_ex(&Self::segment_time_key(url), &now.to_string(), *ttl)
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fetch a single segment from upstream with the schema's headers and decompress it, caching
+    /// is left to the batched write in `prefetch_segments`.
+    async fn fetch_segment(
+        http: &UpstreamHttp,
+        db: &Database,
+        url: &str,
+        schema: &str,
+        config: &ProxyCacheConfig,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+        // held until the body is read below
+        let host = CookieService::extract_domain(url).unwrap_or_default();
+        let _host_permit = config.upstream_limiter.acquire(&host).await?;
+        config.upstream_rate.acquire().await;
+
+        // same headers as the foreground fetch, origins 403 requests that look different
+        let request_builder = apply_schema_headers(
+            config
+                .upstream_connection
+                .apply(http.get(url))
+                .timeout(config.upstream_timeouts.segment),
+            schema,
+            url,
+        );
+
+        let response = config.attempt_log.send("prefetch", request_builder).await?;
 
         if !response.status().is_success() {
+            // the foreground request for it gets the 404 straight away instead of trying again
+            let status = response.status().as_u16();
+            if let Err(e) = Self::store_missing(db, url, status, config.negative_ttl_seconds).await
+            {
+                error!("Failed to remember missing segment: {}", e);
+            }
             return Err(format!("Upstream returned {}", response.status()).into());
         }
 
@@ -122,50 +922,71 @@ impl ProxyCacheService {
 
         let bytes = response.bytes().await?;
 
-        let decompressed: Vec<u8> = match content_encoding.as_deref() {
-            Some("zstd") => zstd::decode_all(&bytes[..])?,
-            Some("gzip") => {
-                use std::io::Read;
-                let mut decoder = flate2::read::GzDecoder::new(&bytes[..]);
-                let mut decomp = Vec::new();
-                decoder.read_to_end(&mut decomp)?;
-                decomp
-            }
-            _ => bytes.to_vec(),
-        };
+        let decompressed = decode_utils::decompress(content_encoding.as_deref(), &bytes)
+            .map_err(|e| e.to_string())?;
 
-        // Cache the segment
-        let key = Self::segment_key(url);
-        
-        match db.as_ref() {
-            Database::Redis(redis) => {
-                use redis::AsyncCommands;
-                let mut conn = redis.connection.clone();
-                let _: Result<(), redis::RedisError> = conn
-                    .set_ex(&key, &decompressed[..], SEGMENT_TTL_SECONDS)
-                    .await;
-            }
-            Database::Memory(mem) => {
-                // Store binary data as base64 string for in-memory
-                let encoded = base64::engine::general_purpose::STANDARD.encode(&decompressed);
-                let _ = mem.store.set_ex(&key, &encoded, SEGMENT_TTL_SECONDS).await;
+        check_segment(url, &decompressed, config.verify_ts_sync)
+            .map_err(|problem| format!("Not caching segment, {}", problem))?;
+
+        Ok(decompressed)
+    }
+
+    /// writes the batch in one go, then empties it which drops the inflight guards and lets the
+    /// requests waiting on those segments read them
+    async fn flush_prefetched(&self, batch: &mut Vec<PrefetchedSegment>) {
+        if batch.is_empty() {
+            return;
+        }
+
+        let segments: Vec<(&str, &[u8], u64)> = batch
+            .iter()
+            .map(|segment| {
+                let ttl = self
+                    .config
+                    .ttl_jitter
+                    .apply(self.config.segment_ttl_seconds);
+                (segment.url.as_str(), segment.bytes.as_slice(), ttl)
+            })
+            .collect();
+
+        match Self::store_segments(&self.db, &segments).await {
+            Ok(()) => {
+                for (url, bytes, ttl) in &segments {
+                    Self::remember_segment(&self.l1, url, bytes, *ttl);
+                }
+                debug!("Prefetched and cached {} segments", batch.len());
             }
+            Err(e) => error!("Failed to cache {} prefetched segments: {}", batch.len(), e),
         }
 
-        debug!(
-            "Prefetched and cached segment ({} bytes): {}",
-            decompressed.len(),
-            url
-        );
-        Ok(())
+        batch.clear();
     }
 }
 
 #[async_trait::async_trait]
 impl ProxyCacheServiceTrait for ProxyCacheService {
-    async fn get_cached(&self, url: &str) -> (Option<String>, Option<Vec<u8>>) {
+    async fn get_cached(&self, url: &str, live: bool) -> (Option<String>, Option<Vec<u8>>) {
+        if self.should_bypass(url) {
+            debug!("Proxy cache BYPASS for {}", url);
+            metrics::counter!("proxy_cache_bypasses_total").increment(1);
+            return (None, None);
+        }
+
         let m3u8_key = Self::m3u8_key(url);
         let seg_key = Self::segment_key(url);
+        let seg_time_key = Self::segment_time_key(url);
+
+        // hot segments come straight out of memory, no round trip
+        if let Some(seg) = self
+            .l1
+            .get(&seg_key)
+            .filter(|(_, cached_at)| self.segment_is_fresh(live, Some(*cached_at)))
+            .map(|(seg, _)| seg)
+        {
+            debug!("Proxy cache HIT (segment, memory) for {}", url);
+            Self::record_lookup(false, true);
+            return (None, Some(seg));
+        }
 
         match self.db.as_ref() {
             #[allow(unused_imports)]
@@ -173,25 +994,43 @@ impl ProxyCacheServiceTrait for ProxyCacheService {
                 use redis::AsyncCommands;
                 let mut conn = redis.connection.clone();
 
-                // Pipeline both GETs into a single round trip
-                let result: Result<(Option<String>, Option<Vec<u8>>), redis::RedisError> = redis::pipe()
+                // Pipeline all the GETs into a single round trip
+                let result: Result<
+                    (Option<String>, Option<Vec<u8>>, Option<i64>),
+                    redis::RedisError,
+                > = redis::pipe()
                     .get(&m3u8_key)
                     .get(&seg_key)
+                    .get(&seg_time_key)
                     .query_async(&mut conn)
                     .await;
 
                 match result {
-                    Ok((m3u8, seg)) => {
+                    Ok((m3u8, seg, cached_at)) => {
+                        let seg = seg.filter(|_| self.segment_is_fresh(live, cached_at));
                         if m3u8.is_some() {
                             debug!("Proxy cache HIT (m3u8) for {}", url);
                         }
-                        if seg.is_some() {
+                        if let Some(bytes) = &seg {
                             debug!("Proxy cache HIT (segment) for {}", url);
+                            // kept in memory for whatever is left of its TTL, the next hit on
+                            // this instance skips Redis
+                            let age = chrono::Utc::now().timestamp_millis()
+                                - cached_at.unwrap_or_default();
+                            let left = (self.config.segment_ttl_seconds as i64 * 1000 - age).max(0);
+                            self.l1.insert(
+                                &seg_key,
+                                bytes,
+                                cached_at.unwrap_or_default(),
+                                Duration::from_millis(left as u64),
+                            );
                         }
+                        Self::record_lookup(m3u8.is_some(), seg.is_some());
                         (m3u8, seg)
                     }
                     Err(e) => {
                         error!("Proxy cache GET failed: {}", e);
+                        Self::record_lookup(false, false);
                         (None, None)
                     }
                 }
@@ -202,6 +1041,14 @@ impl ProxyCacheServiceTrait for ProxyCacheService {
                     Ok(Some(encoded)) => base64::engine::general_purpose::STANDARD.decode(&encoded).ok(),
                     _ => None,
                 };
+                let cached_at = mem
+                    .store
+                    .get(&seg_time_key)
+                    .await
+                    .ok()
+                    .flatten()
+                    .and_then(|at| at.parse::<i64>().ok());
+                let seg = seg.filter(|_| self.segment_is_fresh(live, cached_at));
 
                 if m3u8.is_some() {
                     debug!("Proxy cache HIT (m3u8) for {}", url);
@@ -209,86 +1056,206 @@ impl ProxyCacheServiceTrait for ProxyCacheService {
                 if seg.is_some() {
                     debug!("Proxy cache HIT (segment) for {}", url);
                 }
+                Self::record_lookup(m3u8.is_some(), seg.is_some());
                 (m3u8, seg)
             }
         }
     }
 
     async fn cache_m3u8(&self, url: &str, text: &str) {
+        if self.should_bypass(url) {
+            return;
+        }
+
         let key = Self::m3u8_key(url);
+        let stale_key = Self::m3u8_stale_key(url);
+        let ttl = self.config.ttl_jitter.apply(self.config.m3u8_ttl_seconds);
+        let stale_ttl = ttl + self.config.stale_if_error_seconds;
+        let seen_key = Self::m3u8_seen_key(url);
 
         match self.db.as_ref() {
             #[allow(unused_imports)]
             Database::Redis(redis) => {
                 use redis::AsyncCommands;
                 let mut conn = redis.connection.clone();
-                let result: Result<(), redis::RedisError> = conn.set_ex(&key, text, M3U8_TTL_SECONDS).await;
+                let mut pipe = redis::pipe();
+                pipe.set_ex(&key, text, ttl).ignore();
+                pipe.set_ex(&seen_key, 1, M3U8_SEEN_TTL_SECONDS).ignore();
+                if self.config.stale_if_error_seconds > 0 {
+                    pipe.set_ex(&stale_key, text, stale_ttl).ignore();
+                }
+                let result: Result<(), redis::RedisError> = pipe.query_async(&mut conn).await;
 
                 match result {
-                    Ok(_) => debug!(
-                        "Cached m3u8 ({} bytes, TTL {}s)",
-                        text.len(),
-                        M3U8_TTL_SECONDS
-                    ),
+                    Ok(_) => debug!("Cached m3u8 ({} bytes, TTL {}s)", text.len(), ttl),
                     Err(e) => error!("Failed to cache m3u8: {}", e),
                 }
             }
             Database::Memory(mem) => {
-                let result = mem.store.set_ex(&key, text, M3U8_TTL_SECONDS).await;
+                if self.config.stale_if_error_seconds > 0 {
+                    let _ = mem.store.set_ex(&stale_key, text, stale_ttl).await;
+                }
+                let _ = mem
+                    .store
+                    .set_ex(&seen_key, "1", M3U8_SEEN_TTL_SECONDS)
+                    .await;
+                let result = mem.store.set_ex(&key, text, ttl).await;
                 match result {
-                    Ok(_) => debug!(
-                        "Cached m3u8 ({} bytes, TTL {}s)",
-                        text.len(),
-                        M3U8_TTL_SECONDS
-                    ),
+                    Ok(_) => debug!("Cached m3u8 ({} bytes, TTL {}s)", text.len(), ttl),
                     Err(e) => error!("Failed to cache m3u8: {}", e),
                 }
             }
         }
     }
 
-    async fn cache_segment(&self, url: &str, bytes: &[u8]) {
-        let key = Self::segment_key(url);
+    async fn wait_for_m3u8_fill(&self, url: &str, is_playlist: bool) -> M3u8Fill {
+        if self.config.m3u8_lock_ms == 0 || self.should_bypass(url) {
+            return M3u8Fill::Fetch(None);
+        }
+        if !is_playlist && !self.seen_as_playlist(url).await {
+            return M3u8Fill::Fetch(None);
+        }
+
+        let key = Self::m3u8_key(url);
+        let lock_key = Self::m3u8_lock_key(url);
+        if let Some(token) = self.try_lock_m3u8(&lock_key).await {
+            return M3u8Fill::Fetch(Some(token));
+        }
+
+        debug!("Waiting for another request to refetch playlist: {}", url);
+        // the lock runs out on its own, so a holder that died only holds everyone up that long
+        loop {
+            tokio::time::sleep(M3U8_FILL_POLL).await;
+            match self.poll_m3u8_fill(&key, &lock_key).await {
+                (Some(text), _) => return M3u8Fill::Filled(text),
+                (None, true) => continue,
+                (None, false) => {
+                    debug!("Playlist fill lock gone without a playlist: {}", url);
+                    return M3u8Fill::Fetch(None);
+                }
+            }
+        }
+    }
+
+    async fn release_m3u8_lock(&self, url: &str, token: &str) {
+        let lock_key = Self::m3u8_lock_key(url);
+        let result = match self.db.as_ref() {
+            Database::Redis(redis) => {
+                let mut conn = redis.connection.clone();
+                let result: Result<i64, redis::RedisError> =
+                    redis::Script::new(RELEASE_M3U8_LOCK_SCRIPT)
+                        .key(&lock_key)
+                        .arg(token)
+                        .invoke_async(&mut conn)
+                        .await;
+                result.map(|_| ()).map_err(|e| e.to_string())
+            }
+            Database::Memory(mem) => mem
+                .store
+                .del_if_eq(&lock_key, token)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        };
+
+        if let Err(e) = result {
+            error!("Failed to release m3u8 fill lock: {}", e);
+        }
+    }
+
+    async fn get_stale_m3u8(&self, url: &str) -> Option<String> {
+        if self.config.stale_if_error_seconds == 0 || self.should_bypass(url) {
+            return None;
+        }
+
+        let key = Self::m3u8_stale_key(url);
 
         match self.db.as_ref() {
             #[allow(unused_imports)]
             Database::Redis(redis) => {
                 use redis::AsyncCommands;
                 let mut conn = redis.connection.clone();
-                let result: Result<(), redis::RedisError> =
-                    conn.set_ex(&key, bytes, SEGMENT_TTL_SECONDS).await;
-
-                match result {
-                    Ok(_) => debug!(
-                        "Cached segment ({} bytes, TTL {}s)",
-                        bytes.len(),
-                        SEGMENT_TTL_SECONDS
-                    ),
-                    Err(e) => error!("Failed to cache segment: {}", e),
-                }
+                let result: Result<Option<String>, redis::RedisError> = conn.get(&key).await;
+                result
+                    .map_err(|e| error!("Proxy cache stale GET failed: {}", e))
+                    .ok()
+                    .flatten()
             }
-            Database::Memory(mem) => {
-                let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
-                let result = mem.store.set_ex(&key, &encoded, SEGMENT_TTL_SECONDS).await;
-                match result {
-                    Ok(_) => debug!(
-                        "Cached segment ({} bytes, TTL {}s)",
-                        bytes.len(),
-                        SEGMENT_TTL_SECONDS
-                    ),
-                    Err(e) => error!("Failed to cache segment: {}", e),
-                }
+            Database::Memory(mem) => mem.store.get(&key).await.ok().flatten(),
+        }
+    }
+
+    async fn cache_segment(&self, url: &str, bytes: &[u8]) {
+        if self.should_bypass(url) {
+            return;
+        }
+
+        if let Err(problem) = check_segment(url, bytes, self.config.verify_ts_sync) {
+            warn!("Not caching segment {}, {}", url, problem);
+            return;
+        }
+
+        let ttl = self
+            .config
+            .ttl_jitter
+            .apply(self.config.segment_ttl_seconds);
+        match Self::store_segment(&self.db, url, bytes, ttl).await {
+            Ok(_) => {
+                Self::remember_segment(&self.l1, url, bytes, ttl);
+                debug!("Cached segment ({} bytes, TTL {}s)", bytes.len(), ttl);
             }
+            Err(e) => error!("Failed to cache segment: {}", e),
         }
     }
 
-    async fn wait_for_inflight(&self, url: &str) -> Option<Vec<u8>> {
-        let notify = {
-            let lock = self.inflight.lock().unwrap();
-            lock.get(url).cloned()
+    async fn cache_missing(&self, url: &str, status: u16) {
+        if self.should_bypass(url) {
+            return;
+        }
+
+        let ttl = self.config.negative_ttl_seconds;
+        if let Err(e) = Self::store_missing(&self.db, url, status, ttl).await {
+            error!("Failed to remember missing segment: {}", e);
+        }
+    }
+
+    async fn get_missing(&self, url: &str) -> Option<u16> {
+        if self.config.negative_ttl_seconds == 0
+            || self.should_bypass(url)
+            || UpstreamTimeouts::is_playlist_url(url)
+        {
+            return None;
+        }
+
+        let key = Self::negative_key(url);
+        let status = match self.db.as_ref() {
+            Database::Redis(redis) => {
+                use redis::AsyncCommands;
+                let mut conn = redis.connection.clone();
+                let result: Result<Option<u16>, redis::RedisError> = conn.get(&key).await;
+                result.unwrap_or_else(|e| {
+                    error!("Proxy cache negative GET failed: {}", e);
+                    None
+                })
+            }
+            Database::Memory(mem) => mem
+                .store
+                .get(&key)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|status| status.parse().ok()),
         };
 
-        let notify = notify?;
+        if status.is_some() {
+            debug!("Proxy cache NEGATIVE HIT for {}", url);
+            metrics::counter!("proxy_cache_negative_hits_total").increment(1);
+        }
+        status
+    }
+
+    async fn wait_for_inflight(&self, url: &str) -> Option<Vec<u8>> {
+        let notify = self.inflight.waiter(url)?;
 
         debug!("Waiting for inflight prefetch: {}", url);
 
@@ -302,6 +1269,9 @@ impl ProxyCacheServiceTrait for ProxyCacheService {
 
         // Prefetch completed, check cache for the cached segment
         let seg_key = Self::segment_key(url);
+        if let Some((bytes, _)) = self.l1.get(&seg_key) {
+            return Some(bytes);
+        }
         
         match self.db.as_ref() {
             Database::Redis(redis) => {
@@ -366,12 +1336,20 @@ impl ProxyCacheServiceTrait for ProxyCacheService {
         }
     }
 
-    async fn prefetch_segments(&self, urls: Vec<String>) {
-        if urls.is_empty() {
+    async fn prefetch_segments(&self, client_id: &str, schema: &str, urls: Vec<String>) {
+        // bypassed urls would never be stored so there's no point fetching them early, and a
+        // playlist pointing somewhere we don't proxy to doesn't get fetched on its behalf
+        let urls: Vec<String> = urls
+            .into_iter()
+            .filter(|url| !self.should_bypass(url))
+            .filter(|url| validate_target(url, &self.config.allowed_hosts).is_ok())
+            .collect();
+
+        if urls.is_empty() || self.config.prefetch_scheduler.is_cancelled() {
             return;
         }
 
-        // Check which URLs are already cached
+        // Check which URLs are already cached, ones upstream just said are missing are skipped too
         let uncached: Vec<String> = match self.db.as_ref() {
             #[allow(unused_imports)]
             Database::Redis(redis) => {
@@ -380,6 +1358,7 @@ impl ProxyCacheServiceTrait for ProxyCacheService {
                 let mut pipe = redis::pipe();
                 for url in &urls {
                     pipe.exists(Self::segment_key(url));
+                    pipe.exists(Self::negative_key(url));
                 }
 
                 let exists_results: Vec<bool> = match pipe.query_async(&mut conn).await {
@@ -391,8 +1370,8 @@ impl ProxyCacheServiceTrait for ProxyCacheService {
                 };
 
                 urls.into_iter()
-                    .zip(exists_results.into_iter())
-                    .filter(|(_, exists)| !exists)
+                    .zip(exists_results.chunks(2))
+                    .filter(|(_, exists)| !exists.contains(&true))
                     .map(|(url, _)| url)
                     .collect()
             }
@@ -400,9 +1379,13 @@ impl ProxyCacheServiceTrait for ProxyCacheService {
                 let mut uncached = Vec::new();
                 for url in &urls {
                     let key = Self::segment_key(url);
-                    match mem.store.get(&key).await {
-                        Ok(None) | Err(_) => uncached.push(url.clone()),
-                        Ok(Some(_)) => {} // Already cached
+                    let negative_key = Self::negative_key(url);
+                    match (
+                        mem.store.get(&key).await,
+                        mem.store.get(&negative_key).await,
+                    ) {
+                        (Ok(None) | Err(_), Ok(None) | Err(_)) => uncached.push(url.clone()),
+                        _ => {} // Already cached or known missing
                     }
                 }
                 uncached
@@ -416,48 +1399,164 @@ impl ProxyCacheServiceTrait for ProxyCacheService {
 
         info!("Prefetching {} segments", uncached.len());
 
-        // Register inflight notifiers for each uncached URL
-        {
-            let mut lock = self.inflight.lock().unwrap();
-            for url in &uncached {
-                lock.entry(url.clone())
-                    .or_insert_with(|| Arc::new(Notify::new()));
-            }
-        }
+        // Register inflight notifiers for each uncached URL, the guard moves into the fetch task
+        // so the entry goes away however the task ends
+        self.inflight.reap();
+        let guards: Vec<InflightGuard> = uncached
+            .iter()
+            .map(|url| self.inflight.register(url))
+            .collect();
 
-        let semaphore = Arc::new(Semaphore::new(5));
         let mut join_set = JoinSet::new();
 
         // Spawn a task for each fetch — all go in-flight immediately,
-        // semaphore gates the actual upstream requests to 5 concurrent
-        for url in uncached {
+        // the scheduler gates the actual upstream requests, fairly across clients
+        let config = Arc::new(self.config.clone());
+        for (url, guard) in uncached.into_iter().zip(guards) {
             let http = self.http.clone();
             let db = self.db.clone();
-            let sem = semaphore.clone();
+            let config = config.clone();
+            let client_id = client_id.to_string();
+            let schema = schema.to_string();
             join_set.spawn(async move {
-                let _permit = sem.acquire().await.expect("semaphore closed");
-                let result = Self::fetch_and_cache_segment(&http, &db, &url).await;
-                (url, result)
+                let _permits = config.prefetch_scheduler.acquire(&client_id).await;
+                let result = Self::fetch_segment(&http, &db, &url, &schema, &config).await;
+                (url, guard, result)
             });
         }
 
-        // Pop completed results as they land and handle inflight notifications
-        while let Some(completed) = join_set.join_next().await {
-            match completed {
-                Ok((url, result)) => {
-                    let notify = {
-                        let mut lock = self.inflight.lock().unwrap();
-                        lock.remove(&url)
-                    };
-                    if let Some(notify) = notify {
-                        notify.notify_waiters();
+        // Pop completed results as they land. fetched segments are written in batches, every
+        // `prefetch_write_batch_size` of them or `prefetch_write_batch_ms` after the first one,
+        // their inflight guards are only dropped once that write is done. failed fetches drop
+        // theirs straight away. shutting down aborts whatever is left, unwritten segments included
+        let batch_size = self.config.prefetch_write_batch_size.max(1);
+        let batch_window = Duration::from_millis(self.config.prefetch_write_batch_ms);
+        let mut batch: Vec<PrefetchedSegment> = Vec::with_capacity(batch_size);
+        let mut flush_at: Option<tokio::time::Instant> = None;
+
+        let cancelled = self.config.prefetch_scheduler.cancelled();
+        tokio::pin!(cancelled);
+        loop {
+            let flush_due = async {
+                match flush_at {
+                    Some(at) => tokio::time::sleep_until(at).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                completed = join_set.join_next() => match completed {
+                    Some(Ok((url, guard, Ok(bytes)))) => {
+                        flush_at.get_or_insert_with(|| tokio::time::Instant::now() + batch_window);
+                        batch.push(PrefetchedSegment { url, bytes, _guard: guard });
+                        if batch.len() >= batch_size {
+                            self.flush_prefetched(&mut batch).await;
+                            flush_at = None;
+                        }
                     }
-                    if let Err(e) = result {
-                        error!("Prefetch failed for {}: {}", url, e);
+                    Some(Ok((url, _, Err(e)))) => error!("Prefetch failed for {}: {}", url, e),
+                    Some(Err(e)) if e.is_cancelled() => {}
+                    Some(Err(e)) => error!("Prefetch task panicked: {}", e),
+                    None => {
+                        self.flush_prefetched(&mut batch).await;
+                        break;
                     }
+                },
+                _ = flush_due => {
+                    self.flush_prefetched(&mut batch).await;
+                    flush_at = None;
+                }
+                _ = &mut cancelled => {
+                    info!("Shutting down, cancelling {} prefetches", join_set.len());
+                    join_set.abort_all();
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn index_stream(&self, stream: &str, urls: &[String]) {
+        let hashes: Vec<String> = urls
+            .iter()
+            .filter(|url| !self.should_bypass(url))
+            .map(|url| Self::hash_url(url))
+            .collect();
+        if hashes.is_empty() {
+            return;
+        }
+
+        let key = Self::stream_index_key(stream);
+        // long enough to outlive whichever entry it points at, even one that got the longest ttl
+        let jitter = self.config.ttl_jitter;
+        let (_, segment_ttl) = jitter.bounds(self.config.segment_ttl_seconds);
+        let (_, m3u8_ttl) = jitter.bounds(self.config.m3u8_ttl_seconds);
+        let ttl = segment_ttl.max(m3u8_ttl + self.config.stale_if_error_seconds);
+
+        match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(redis) => {
+                use redis::AsyncCommands;
+                let mut conn = redis.connection.clone();
+                let result: Result<(), redis::RedisError> = redis::pipe()
+                    .sadd(&key, &hashes)
+                    .ignore()
+                    .expire(&key, ttl as i64)
+                    .ignore()
+                    .query_async(&mut conn)
+                    .await;
+                if let Err(e) = result {
+                    error!("Failed to index stream {}: {}", stream, e);
+                }
+            }
+            Database::Memory(mem) => {
+                let result = match mem.store.sadd(&key, &hashes).await {
+                    Ok(_) => mem.store.expire(&key, ttl).await.map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    error!("Failed to index stream {}: {}", stream, e);
                 }
-                Err(e) => error!("Prefetch task panicked: {}", e),
             }
         }
     }
+
+    async fn invalidate_stream(&self, stream: &str) -> anyhow::Result<usize> {
+        let key = Self::stream_index_key(stream);
+
+        let hashes = match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(redis) => {
+                use redis::AsyncCommands;
+                let mut conn = redis.connection.clone();
+                let hashes: Vec<String> = conn.smembers(&key).await?;
+
+                let mut keys: Vec<String> =
+                    hashes.iter().flat_map(|h| Self::entry_keys(h)).collect();
+                keys.push(key);
+                let _: () = conn.del(&keys).await?;
+                hashes
+            }
+            Database::Memory(mem) => {
+                let hashes = mem.store.smembers(&key).await?;
+
+                let mut keys: Vec<String> =
+                    hashes.iter().flat_map(|h| Self::entry_keys(h)).collect();
+                keys.push(key);
+                mem.store.del_multiple(&keys).await?;
+                hashes
+            }
+        };
+
+        // only this instance's memory copies, other instances keep theirs until they run out
+        for hash in &hashes {
+            self.l1.remove(&format!("pcache:seg:{}", hash));
+        }
+        let invalidated = hashes.len();
+
+        info!(
+            "Invalidated {} cached urls for stream {}",
+            invalidated, stream
+        );
+        Ok(invalidated)
+    }
 }
@@ -1,19 +1,74 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info, warn};
 
-use crate::database::Database;
+use crate::database::{Database, MemoryDatabase};
+use crate::server::error::{AppResult, Error};
+use crate::server::utils::clock_utils::{DynClock, SystemClock};
+
+// clients that are never limited, our own backends mostly
+const EXEMPT_SET_KEY: &str = "edge_ratelimit_exempt";
+// longest ttl accepted on import, anything longer is almost certainly a typo
+const MAX_IMPORT_TTL_SECONDS: u64 = 30 * 24 * 60 * 60;
+// the same failure over and over doubles its weight each time, up to 2^this
+const MAX_REPEAT_DOUBLINGS: u32 = 2;
+
+/// how requests get counted against `max_requests_per_window`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RateLimitStrategy {
+    /// a counter that resets when the window runs out. cheap, but a client can get up to twice
+    /// the limit through around a reset
+    #[default]
+    FixedWindow,
+    /// a log of request times over the last window, no bursts past the limit but it keeps one
+    /// sorted set entry per request
+    SlidingWindow,
+}
+
+impl RateLimitStrategy {
+    /// `fixed` or `sliding`, anything else falls back to `fixed`
+    pub fn parse(strategy: &str) -> Self {
+        match strategy.trim().to_ascii_lowercase().as_str() {
+            "fixed" => Self::FixedWindow,
+            "sliding" => Self::SlidingWindow,
+            other => {
+                warn!("Unknown rate limit strategy {}, using fixed", other);
+                Self::FixedWindow
+            }
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct RateLimitConfig {
+    /// how requests in a window are counted
+    pub strategy: RateLimitStrategy,
     /// maximum requests per window for general API calls
     pub max_requests_per_window: u32,
+    /// maximum requests per window from one ip, whatever client ids they're spread over. a
+    /// client id is just a hash of the ip and user agent, so rotating the user agent gets a
+    /// fresh client bucket every time. 0 turns it off
+    pub max_requests_per_ip_window: u32,
     /// window duration in seconds for rate limiting
     pub window_seconds: u64,
-    /// maximum errors before a user gets timed out
+    /// error score a user can reach before getting timed out, once a client is past
+    /// `ratio_min_requests` this only applies if their error ratio is also too high. one plain
+    /// client fault is worth 1, see `UpstreamFailure::weight`
     pub max_errors_before_timeout: u32,
-    /// error tracking window in seconds
+    /// fraction of requests in the error window that can fail before a high volume client gets
+    /// timed out
+    pub max_error_ratio: f64,
+    /// requests in the error window before the ratio is used instead of just the error count
+    pub ratio_min_requests: u32,
+    /// error tracking window in seconds, the requests for the ratio are counted over this and a
+    /// score nothing was added to for this long is dropped
     pub error_window_seconds: u64,
+    /// the error score halves every this many seconds without new errors
+    pub error_half_life_seconds: u64,
+    /// what each kind of failure adds to the error score
+    pub error_weights: ErrorWeights,
     /// timeout duration in seconds when error threshold is exceeded
     pub timeout_duration_seconds: u64,
 }
@@ -23,11 +78,66 @@ impl Default for RateLimitConfig {
         Self {
             // these should all be changed as you see fit
             max_requests_per_window: 500, // 500 requests per window (very generous)
+            max_requests_per_ip_window: 2000, // a few viewers behind one nat
             window_seconds: 60,           // per minute
             max_errors_before_timeout: 50, // 50 errors triggers timeout
+            max_error_ratio: 0.2,         // busy clients need 20% of requests failing
+            ratio_min_requests: 250,      // what counts as busy
             error_window_seconds: 600,    // within 10 minutes
+            error_half_life_seconds: 120, // 49 errors are down to ~2 nine minutes later
+            error_weights: ErrorWeights::default(),
             timeout_duration_seconds: 300, // 5 minute timeout
+            strategy: RateLimitStrategy::FixedWindow,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// decide if a client should be timed out given its error score and requests in the error
+    /// window. a client watching a stream pulls a lot of segments so a flat threshold punishes
+    /// the busy ones, past `ratio_min_requests` the error ratio has to be exceeded too
+    pub fn should_timeout(&self, score: f64, requests: u32) -> bool {
+        if score < self.max_errors_before_timeout as f64 {
+            return false;
+        }
+
+        if requests < self.ratio_min_requests {
+            return true;
+        }
+
+        score / requests as f64 >= self.max_error_ratio
+    }
+}
+
+// whole seconds (at least one) until a request logged at `logged_at` ms is out of the window
+fn seconds_until_aged_out(logged_at: i64, window_ms: i64, now: i64) -> u64 {
+    ((logged_at + window_ms - now).max(1) as u64).div_ceil(1000)
+}
+
+// the stricter of a client's and its ip's results. limited by either is limited (for as
+// long as the longer one), otherwise whichever has less left
+fn stricter(client: RateLimitResult, ip: RateLimitResult) -> RateLimitResult {
+    match (client, ip) {
+        (
+            RateLimitResult::RateLimited { retry_after: a },
+            RateLimitResult::RateLimited { retry_after: b },
+        ) => RateLimitResult::RateLimited {
+            retry_after: a.max(b),
+        },
+        (limited @ RateLimitResult::RateLimited { .. }, _)
+        | (_, limited @ RateLimitResult::RateLimited { .. }) => limited,
+        (
+            client @ RateLimitResult::Allowed { remaining: a, .. },
+            ip @ RateLimitResult::Allowed { remaining: b, .. },
+        ) => {
+            if b < a {
+                ip
+            } else {
+                client
+            }
         }
+        // a timeout only ever comes from the client side, it's checked before counting
+        (client, _) => client,
     }
 }
 
@@ -41,15 +151,341 @@ pub enum RateLimitResult {
     TimedOut { reason: String, retry_after: u64 },
 }
 
+impl RateLimitResult {
+    /// short name for metrics
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Allowed { .. } => "allowed",
+            Self::RateLimited { .. } => "limited",
+            Self::TimedOut { .. } => "timed_out",
+        }
+    }
+}
+
+/// the kinds of failures the error weights are keyed by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProxyErrorKind {
+    /// a 4xx the client brought on itself
+    UpstreamClientError,
+    /// a 5xx, or a 4xx that's the origin's doing (throttling, legal)
+    UpstreamServerError,
+    /// no response at all
+    RequestFailed,
+    /// a body that wouldn't decompress or a playlist that isn't text
+    DecodeFailure,
+}
+
+impl ProxyErrorKind {
+    pub const ALL: [Self; 4] = [
+        Self::UpstreamClientError,
+        Self::UpstreamServerError,
+        Self::RequestFailed,
+        Self::DecodeFailure,
+    ];
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|k| k.label() == kind.trim().to_ascii_lowercase())
+    }
+
+    /// name in the weights config and logs
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::UpstreamClientError => "upstream_client_error",
+            Self::UpstreamServerError => "upstream_server_error",
+            Self::RequestFailed => "request_failed",
+            Self::DecodeFailure => "decode_failure",
+        }
+    }
+}
+
+/// how much each kind of failure adds to a client's error score. by default only the ones the
+/// client caused count, an origin that's down or throttling us shouldn't get viewers timed out,
+/// and bodies that keep failing to decode count the most since they point at someone feeding us
+/// garbage on purpose
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorWeights {
+    weights: HashMap<ProxyErrorKind, f64>,
+}
+
+impl Default for ErrorWeights {
+    fn default() -> Self {
+        Self {
+            weights: HashMap::from([
+                (ProxyErrorKind::UpstreamClientError, 1.0),
+                (ProxyErrorKind::UpstreamServerError, 0.0),
+                (ProxyErrorKind::RequestFailed, 0.0),
+                (ProxyErrorKind::DecodeFailure, 3.0),
+            ]),
+        }
+    }
+}
+
+impl ErrorWeights {
+    /// comma seperated `kind=weight` entries, e.g. `decode_failure=5,upstream_server_error=0.1`.
+    /// kinds that aren't listed keep their default, anything malformed or negative is logged and
+    /// skipped
+    pub fn parse_list(entries: &str) -> Self {
+        let mut weights = Self::default();
+
+        for entry in entries
+            .split(',')
+            .map(|e| e.trim())
+            .filter(|e| !e.is_empty())
+        {
+            match Self::parse_entry(entry) {
+                Some((kind, weight)) => {
+                    weights.weights.insert(kind, weight);
+                }
+                None => warn!("Ignoring invalid error weight: {}", entry),
+            }
+        }
+
+        weights
+    }
+
+    // one `kind=weight`
+    fn parse_entry(entry: &str) -> Option<(ProxyErrorKind, f64)> {
+        let (kind, weight) = entry.split_once('=')?;
+        let kind = ProxyErrorKind::parse(kind)?;
+        let weight: f64 = weight.trim().parse().ok()?;
+
+        (weight.is_finite() && weight >= 0.0).then_some((kind, weight))
+    }
+
+    pub fn get(&self, kind: ProxyErrorKind) -> f64 {
+        self.weights.get(&kind).copied().unwrap_or(0.0)
+    }
+
+    /// what one failure adds to the score, before repeats make it more
+    pub fn weight(&self, failure: UpstreamFailure) -> f64 {
+        let weight = self.get(failure.kind());
+        match failure {
+            // a segment that already fell out of the live window, players run into these on
+            // their own
+            UpstreamFailure::ClientFault(404) | UpstreamFailure::ClientFault(410) => weight * 0.5,
+            _ => weight,
+        }
+    }
+}
+
+/// why a proxied request failed, what gets counted against the client is decided by the
+/// `ErrorWeights` for its kind
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpstreamFailure {
+    /// no response at all (dns, refused, reset, timed out), our network or the origin
+    Network,
+    /// a 4xx the client brought on itself, e.g. a 403 for a token it mangled
+    ClientFault(u16),
+    /// a 4xx that isn't about the client, 429 is the origin throttling us and 451 is legal
+    NotClientFault(u16),
+    /// the origin broke
+    Server(u16),
+    /// the upstream answered but the body couldn't be decoded
+    Decode,
+}
+
+impl UpstreamFailure {
+    /// for a non-success status from upstream
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            // 408 is the origin giving up on our request
+            408 | 429 | 451 => Self::NotClientFault(status),
+            400..=499 => Self::ClientFault(status),
+            _ => Self::Server(status),
+        }
+    }
+
+    pub fn kind(&self) -> ProxyErrorKind {
+        match self {
+            Self::Network => ProxyErrorKind::RequestFailed,
+            Self::ClientFault(_) => ProxyErrorKind::UpstreamClientError,
+            Self::NotClientFault(_) | Self::Server(_) => ProxyErrorKind::UpstreamServerError,
+            Self::Decode => ProxyErrorKind::DecodeFailure,
+        }
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Self::Network | Self::Decode => None,
+            Self::ClientFault(status) | Self::NotClientFault(status) | Self::Server(status) => {
+                Some(*status)
+            }
+        }
+    }
+
+    /// short name for logs
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Network => "network",
+            Self::ClientFault(_) => "client_fault",
+            Self::NotClientFault(_) => "not_client_fault",
+            Self::Server(_) => "server",
+            Self::Decode => "decode",
+        }
+    }
+}
+
+/// a client's errors as a score that decays over time instead of a flat count, so someone who
+/// errored a lot and then behaved isn't one error away from a timeout for the rest of the window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ErrorScore {
+    /// as of `updated_at`, use `decayed` for the current value
+    pub score: f64,
+    /// unix seconds of the last error
+    pub updated_at: i64,
+    /// status of the last error and how many times in a row it came back since
+    #[serde(default)]
+    pub last_status: Option<u16>,
+    #[serde(default)]
+    pub repeats: u32,
+}
+
+impl ErrorScore {
+    /// the score at `now`, halved for every `half_life_seconds` since the last error
+    pub fn decayed(&self, now: i64, half_life_seconds: u64) -> f64 {
+        if half_life_seconds == 0 {
+            return self.score;
+        }
+
+        let elapsed = (now - self.updated_at).max(0) as f64;
+        self.score * 0.5f64.powf(elapsed / half_life_seconds as f64)
+    }
+
+    /// decays up to `now` and adds the failure with its weight. the same status again within a
+    /// half life counts double each time (capped), a client retrying the same broken request is
+    /// worse than a few unrelated misses
+    pub fn record(
+        &self,
+        failure: UpstreamFailure,
+        weights: &ErrorWeights,
+        now: i64,
+        half_life_seconds: u64,
+    ) -> Self {
+        let status = failure.status();
+        let repeated = status.is_some()
+            && status == self.last_status
+            && now - self.updated_at <= half_life_seconds as i64;
+        let repeats = if repeated {
+            self.repeats.saturating_add(1)
+        } else {
+            0
+        };
+        let weight = weights.weight(failure) * 2f64.powi(repeats.min(MAX_REPEAT_DOUBLINGS) as i32);
+
+        Self {
+            score: self.decayed(now, half_life_seconds) + weight,
+            updated_at: now,
+            last_status: status,
+            repeats,
+        }
+    }
+}
+
+/// where a client is in its current window, read without counting as a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub used: u32,
+    pub remaining: u32,
+    pub reset_at: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimeoutEntry {
+    pub client_id: String,
+    pub reason: String,
+    pub ttl_seconds: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorScoreEntry {
+    pub client_id: String,
+    pub score: ErrorScore,
+    pub ttl_seconds: u64,
+}
+
+/// everything the limiter keeps per client, for moving it between redis instances or seeding bans
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitSnapshot {
+    pub timeouts: Vec<TimeoutEntry>,
+    pub exemptions: Vec<String>,
+    #[serde(default)]
+    pub error_scores: Vec<ErrorScoreEntry>,
+}
+
+impl RateLimitSnapshot {
+    /// checks every entry before anything gets written so a bad import doesn't half apply
+    pub fn validate(&self) -> AppResult<()> {
+        let timeout_ids = self.timeouts.iter().map(|t| t.client_id.as_str());
+        let error_ids = self.error_scores.iter().map(|e| e.client_id.as_str());
+        let exempt_ids = self.exemptions.iter().map(|c| c.as_str());
+
+        if let Some(client_id) = timeout_ids
+            .chain(error_ids)
+            .chain(exempt_ids)
+            .find(|id| !is_valid_client_id(id))
+        {
+            return Err(Error::BadRequest(format!(
+                "Invalid client id in import: {:?}",
+                client_id
+            )));
+        }
+
+        let ttls = self
+            .timeouts
+            .iter()
+            .map(|t| (&t.client_id, t.ttl_seconds))
+            .chain(
+                self.error_scores
+                    .iter()
+                    .map(|e| (&e.client_id, e.ttl_seconds)),
+            );
+        for (client_id, ttl) in ttls {
+            if ttl == 0 || ttl > MAX_IMPORT_TTL_SECONDS {
+                return Err(Error::BadRequest(format!(
+                    "Invalid ttl {} for client {}",
+                    ttl, client_id
+                )));
+            }
+        }
+
+        if let Some(entry) = self.timeouts.iter().find(|t| t.reason.trim().is_empty()) {
+            return Err(Error::BadRequest(format!(
+                "Missing timeout reason for client {}",
+                entry.client_id
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// what a client id can look like when it comes from outside (imports, admin routes), generated
+/// ones are hex so they always pass
+pub fn is_valid_client_id(client_id: &str) -> bool {
+    !client_id.is_empty()
+        && client_id.len() <= 128
+        && client_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
 pub type DynRateLimitService = Arc<dyn RateLimitServiceTrait + Send + Sync>;
 
 #[async_trait::async_trait]
 pub trait RateLimitServiceTrait {
-    /// check if a request should be allowed
-    async fn check_rate_limit(&self, client_id: &str) -> RateLimitResult;
+    /// check if a request should be allowed, counted against the client and (when there is one)
+    /// the ip it came from
+    async fn check_rate_limit(&self, client_id: &str, ip: Option<&str>) -> RateLimitResult;
+
+    /// current quota for a client without incrementing it
+    async fn peek_rate_limit(&self, client_id: &str) -> RateLimitStatus;
 
-    /// record an error for a client (proxy failures, etc.)
-    async fn record_error(&self, client_id: &str, error_type: &str);
+    /// record a failed proxy request for a client, it counts toward a timeout by the weight of
+    /// its kind
+    async fn record_error(&self, client_id: &str, failure: UpstreamFailure);
 
     /// check if client is currently timed out
     async fn is_user_timed_out(&self, client_id: &str) -> Option<(String, u64)>;
@@ -60,14 +496,24 @@ pub trait RateLimitServiceTrait {
     /// clear a client's timeout
     async fn clear_timeout(&self, client_id: &str) -> bool;
 
-    /// get error count for a client
-    async fn get_error_count(&self, client_id: &str) -> u32;
+    /// forget a client's errors, returns whether it had any
+    async fn clear_error_score(&self, client_id: &str) -> bool;
+
+    /// a client's error score decayed to now, 0 when it has none
+    async fn get_error_score(&self, client_id: &str) -> f64;
 
-    /// check if client is exempt from rate limiting
+    /// check if client is exempt from rate limiting, exempt clients are never limited, timed out
+    /// or have errors counted
     async fn is_exempt(&self, client_id: &str) -> bool;
 
     /// set a client as exempt from rate limiting
     async fn set_exempt(&self, client_id: &str, exempt: bool);
+
+    /// dump current timeouts, exemptions and error scores
+    async fn export_state(&self) -> AppResult<RateLimitSnapshot>;
+
+    /// write a snapshot back, entries are validated first. returns how many entries were written
+    async fn import_state(&self, snapshot: RateLimitSnapshot) -> AppResult<usize>;
 }
 
 /// rate limiting based on client identifiers (probably not the most reliable so you can just
@@ -75,101 +521,303 @@ pub trait RateLimitServiceTrait {
 pub struct EdgeRateLimitService {
     db: Arc<Database>,
     config: RateLimitConfig,
+    clock: DynClock,
 }
 
 impl EdgeRateLimitService {
     pub fn new(db: Arc<Database>) -> Self {
+        Self::with_config(db, RateLimitConfig::default())
+    }
+
+    pub fn with_config(db: Arc<Database>, config: RateLimitConfig) -> Self {
         Self {
             db,
-            config: RateLimitConfig::default(),
+            config,
+            clock: SystemClock::shared(),
         }
     }
 
+    /// what reset times are reported against and error scores decay by
+    pub fn with_clock(mut self, clock: DynClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
     fn rate_limit_key(&self, client_id: &str) -> String {
         format!("edge_rate_limit:{}", client_id)
     }
 
-    fn error_count_key(&self, client_id: &str) -> String {
-        format!("edge_error_count:{}", client_id)
+    /// request log for the sliding window, a sorted set so it can't share the counter's key
+    fn sliding_window_key(&self, client_id: &str) -> String {
+        format!("edge_rate_log:{}", client_id)
+    }
+
+    /// requests seen over the error window, used for the error ratio
+    fn request_count_key(&self, client_id: &str) -> String {
+        format!("edge_request_count:{}", client_id)
+    }
+
+    /// the json `ErrorScore`, not the old plain counter so leftover counts just expire
+    fn error_score_key(&self, client_id: &str) -> String {
+        format!("edge_error_score:{}", client_id)
     }
 
     fn timeout_key(&self, client_id: &str) -> String {
         format!("edge_timeout:{}", client_id)
     }
-}
 
-#[async_trait::async_trait]
-impl RateLimitServiceTrait for EdgeRateLimitService {
-    async fn check_rate_limit(&self, client_id: &str) -> RateLimitResult {
-        if let Some((reason, retry_after)) = self.is_user_timed_out(client_id).await {
-            return RateLimitResult::TimedOut {
-                reason,
-                retry_after,
+    /// the ip's own bucket, next to the bucket of whichever client id it's using
+    fn ip_rate_limit_key(&self, ip: &str) -> String {
+        format!("edge_ip_rate_limit:{}", ip)
+    }
+
+    fn ip_sliding_window_key(&self, ip: &str) -> String {
+        format!("edge_ip_rate_log:{}", ip)
+    }
+
+    async fn scan_keys(&self, pattern: &str) -> anyhow::Result<Vec<String>> {
+        match self.db.as_ref() {
+            Database::Redis(db) => {
+                let mut conn = db.connection.clone();
+                let mut keys = Vec::new();
+                let mut cursor = 0u64;
+
+                loop {
+                    let (new_cursor, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+                        .arg(cursor)
+                        .arg("MATCH")
+                        .arg(pattern)
+                        .query_async(&mut conn)
+                        .await?;
+
+                    keys.extend(batch);
+                    cursor = new_cursor;
+
+                    if cursor == 0 {
+                        break;
+                    }
+                }
+
+                Ok(keys)
+            }
+            Database::Memory(db) => db.store.scan(pattern).await,
+        }
+    }
+
+    // value and remaining ttl for every key under the prefix, keyed by client id
+    async fn dump_prefix(&self, prefix: &str) -> anyhow::Result<Vec<(String, String, u64)>> {
+        let keys = self.scan_keys(&format!("{}*", prefix)).await?;
+        let mut entries = Vec::with_capacity(keys.len());
+
+        for key in keys {
+            let (value, ttl): (Option<String>, i64) = match self.db.as_ref() {
+                Database::Redis(db) => {
+                    let mut conn = db.connection.clone();
+                    redis::pipe()
+                        .get(&key)
+                        .ttl(&key)
+                        .query_async(&mut conn)
+                        .await?
+                }
+                Database::Memory(db) => (db.store.get(&key).await?, db.store.ttl(&key).await?),
+            };
+
+            // expired between the scan and the read, or never had a ttl to begin with
+            let (Some(value), true) = (value, ttl > 0) else {
+                continue;
             };
+
+            let client_id = key.trim_start_matches(prefix).to_string();
+            entries.push((client_id, value, ttl as u64));
         }
 
-        let key = self.rate_limit_key(client_id);
+        Ok(entries)
+    }
+
+    async fn load_error_score(&self, client_id: &str) -> ErrorScore {
+        let key = self.error_score_key(client_id);
+
+        let result = match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(db) => {
+                use redis::AsyncCommands;
+                let mut conn = db.connection.clone();
+                let result: Result<Option<String>, redis::RedisError> = conn.get(&key).await;
+                result.map_err(anyhow::Error::from)
+            }
+            Database::Memory(db) => db.store.get(&key).await,
+        };
+
+        match result {
+            Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                debug!(
+                    "Ignoring unreadable error score for client {}: {}",
+                    client_id, e
+                );
+                ErrorScore::default()
+            }),
+            Ok(None) => ErrorScore::default(),
+            Err(e) => {
+                error!("Failed to get error score for client {}: {}", client_id, e);
+                ErrorScore::default()
+            }
+        }
+    }
+
+    async fn store_error_score(&self, client_id: &str, score: &ErrorScore, ttl_seconds: u64) {
+        let key = self.error_score_key(client_id);
+        let json = match serde_json::to_string(score) {
+            Ok(json) => json,
+            Err(e) => {
+                error!(
+                    "Failed to serialize error score for client {}: {}",
+                    client_id, e
+                );
+                return;
+            }
+        };
+
+        let result = match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(db) => {
+                use redis::AsyncCommands;
+                let mut conn = db.connection.clone();
+                let result: Result<(), redis::RedisError> =
+                    conn.set_ex(&key, &json, ttl_seconds).await;
+                result.map_err(anyhow::Error::from)
+            }
+            Database::Memory(db) => db.store.set_ex(&key, &json, ttl_seconds).await,
+        };
+
+        if let Err(e) = result {
+            error!(
+                "Failed to store error score for client {}: {}",
+                client_id, e
+            );
+        }
+    }
+
+    // requests counted over the error window, for the error ratio
+    async fn error_window_requests(&self, client_id: &str) -> u32 {
+        let key = self.request_count_key(client_id);
 
         match self.db.as_ref() {
             #[allow(unused_imports)]
             Database::Redis(db) => {
                 use redis::AsyncCommands;
                 let mut conn = db.connection.clone();
+                let result: Result<Option<u32>, redis::RedisError> = conn.get(&key).await;
+                result.ok().flatten().unwrap_or(0)
+            }
+            Database::Memory(db) => match db.store.get(&key).await {
+                Ok(Some(requests)) => requests.parse().unwrap_or(0),
+                _ => 0,
+            },
+        }
+    }
 
-                let result: Result<(u32, i32, i64), redis::RedisError> = redis::pipe()
-                    .atomic()
-                    .incr(&key, 1u32)
-                    .expire(&key, self.config.window_seconds as i64)
-                    .ttl(&key)
-                    .query_async(&mut conn)
-                    .await;
+    async fn check_fixed_window(&self, client_id: &str) -> RateLimitResult {
+        self.count_fixed_window(
+            &format!("client {}", client_id),
+            &self.rate_limit_key(client_id),
+            Some(&self.request_count_key(client_id)),
+            self.config.max_requests_per_window,
+        )
+        .await
+    }
+
+    // the error ratio's request count only gets its TTL when it's created (EXPIRE NX, Redis 7), a
+    // client that never stops sending requests would keep pushing it back and never start over
+    fn count_request(&self, pipe: &mut redis::Pipeline, request_key: &str) {
+        pipe.incr(request_key, 1u32)
+            .ignore()
+            .cmd("EXPIRE")
+            .arg(request_key)
+            .arg(self.config.error_window_seconds)
+            .arg("NX")
+            .ignore();
+    }
+
+    async fn count_request_in_memory(&self, db: &MemoryDatabase, request_key: &str) {
+        if let Ok(1) = db.store.incr(request_key, 1).await {
+            let _ = db
+                .store
+                .expire(request_key, self.config.error_window_seconds)
+                .await;
+        }
+    }
+
+    // counts a request in the fixed window at `key`, `request_key` (the error ratio's request
+    // count) gets counted along with it when there is one. `who` is just for the logs
+    async fn count_fixed_window(
+        &self,
+        who: &str,
+        key: &str,
+        request_key: Option<&str>,
+        limit: u32,
+    ) -> RateLimitResult {
+        match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(db) => {
+                use redis::AsyncCommands;
+                let mut conn = db.connection.clone();
+
+                let mut pipe = redis::pipe();
+                pipe.atomic()
+                    .incr(key, 1u32)
+                    .expire(key, self.config.window_seconds as i64)
+                    .ttl(key);
+                if let Some(request_key) = request_key {
+                    self.count_request(&mut pipe, request_key);
+                }
+                let result: Result<(u32, i32, i64), redis::RedisError> =
+                    pipe.query_async(&mut conn).await;
 
                 match result {
                     Ok((count, _expire_result, ttl)) => {
-                        let reset_at = chrono::Utc::now().timestamp() + ttl;
+                        let reset_at = self.clock.now() + ttl;
 
-                        if count > self.config.max_requests_per_window {
-                            debug!(
-                                "Client {} rate limited: {} requests in window",
-                                client_id, count
-                            );
+                        if count > limit {
+                            debug!("{} rate limited: {} requests in window", who, count);
                             RateLimitResult::RateLimited {
                                 retry_after: ttl.max(1) as u64,
                             }
                         } else {
                             RateLimitResult::Allowed {
-                                remaining: self.config.max_requests_per_window.saturating_sub(count),
+                                remaining: limit.saturating_sub(count),
                                 reset_at,
                             }
                         }
                     }
                     Err(e) => {
-                        error!("Rate limit check failed for client {}: {}", client_id, e);
+                        error!("Rate limit check failed for {}: {}", who, e);
                         RateLimitResult::Allowed {
                             remaining: 0,
-                            reset_at: chrono::Utc::now().timestamp()
-                                + self.config.window_seconds as i64,
+                            reset_at: self.clock.now() + self.config.window_seconds as i64,
                         }
                     }
                 }
             }
             Database::Memory(db) => {
                 // For in-memory, we need to handle the increment + TTL manually
-                let count = db.store.incr(&key, 1).await.unwrap_or(1);
+                let count = db.store.incr(key, 1).await.unwrap_or(1);
+                if count == 1 {
+                    let _ = db.store.expire(key, self.config.window_seconds).await;
+                }
+                if let Some(request_key) = request_key {
+                    self.count_request_in_memory(db, request_key).await;
+                }
                 let ttl = self.config.window_seconds as i64;
-                let reset_at = chrono::Utc::now().timestamp() + ttl;
+                let reset_at = self.clock.now() + ttl;
 
-                if count > self.config.max_requests_per_window {
-                    debug!(
-                        "Client {} rate limited: {} requests in window",
-                        client_id, count
-                    );
+                if count > limit {
+                    debug!("{} rate limited: {} requests in window", who, count);
                     RateLimitResult::RateLimited {
                         retry_after: ttl.max(1) as u64,
                     }
                 } else {
                     RateLimitResult::Allowed {
-                        remaining: self.config.max_requests_per_window.saturating_sub(count),
+                        remaining: limit.saturating_sub(count),
                         reset_at,
                     }
                 }
@@ -177,74 +825,285 @@ impl RateLimitServiceTrait for EdgeRateLimitService {
         }
     }
 
-    async fn record_error(&self, client_id: &str, error_type: &str) {
-        let key = self.error_count_key(client_id);
+    // one sorted set entry per request scored by its time in ms. trimming what fell out of the
+    // window, logging this request and counting is a single round trip. rejected requests get
+    // logged too, so a client that keeps hammering stays limited until it backs off
+    async fn check_sliding_window(&self, client_id: &str) -> RateLimitResult {
+        self.log_sliding_window(
+            &format!("client {}", client_id),
+            &self.sliding_window_key(client_id),
+            Some(&self.request_count_key(client_id)),
+            self.config.max_requests_per_window,
+        )
+        .await
+    }
 
-        match self.db.as_ref() {
+    // the sliding window version of `count_fixed_window`
+    async fn log_sliding_window(
+        &self,
+        who: &str,
+        key: &str,
+        request_key: Option<&str>,
+        limit: u32,
+    ) -> RateLimitResult {
+        let now = self.clock.now_millis();
+        let window_ms = self.config.window_seconds as i64 * 1000;
+        // two requests can land on the same ms
+        let member = format!("{}-{}", now, nanoid::nanoid!(8));
+
+        let (count, oldest) = match self.db.as_ref() {
+            Database::Redis(db) => {
+                let mut conn = db.connection.clone();
+
+                let mut pipe = redis::pipe();
+                pipe.atomic()
+                    .zrembyscore(key, "-inf", now - window_ms)
+                    .ignore()
+                    .zadd(key, &member, now)
+                    .ignore()
+                    .zcard(key)
+                    .zrange_withscores(key, 0, 0)
+                    .expire(key, self.config.window_seconds as i64)
+                    .ignore();
+                if let Some(request_key) = request_key {
+                    self.count_request(&mut pipe, request_key);
+                }
+                let result: Result<(u32, Vec<(String, i64)>), redis::RedisError> =
+                    pipe.query_async(&mut conn).await;
+
+                match result {
+                    Ok((count, oldest)) => (count, oldest.first().map(|(_, score)| *score)),
+                    Err(e) => {
+                        error!("Rate limit check failed for {}: {}", who, e);
+                        return RateLimitResult::Allowed {
+                            remaining: 0,
+                            reset_at: self.clock.now() + self.config.window_seconds as i64,
+                        };
+                    }
+                }
+            }
+            Database::Memory(db) => {
+                let _ = db
+                    .store
+                    .zremrangebyscore(key, i64::MIN, now - window_ms)
+                    .await;
+                let _ = db.store.zadd(key, &member, now).await;
+                let _ = db.store.expire(key, self.config.window_seconds).await;
+                if let Some(request_key) = request_key {
+                    self.count_request_in_memory(db, request_key).await;
+                }
+
+                let count = db.store.zcard(key).await.unwrap_or(1);
+                let oldest = match db.store.zrange_withscores(key, 0, 0).await {
+                    Ok(oldest) => oldest.first().map(|(_, score)| *score),
+                    Err(_) => None,
+                };
+                (count, oldest)
+            }
+        };
+
+        // a slot frees up when the oldest request in the window ages out
+        let retry_after = seconds_until_aged_out(oldest.unwrap_or(now), window_ms, now);
+
+        if count > limit {
+            debug!("{} rate limited: {} requests in sliding window", who, count);
+            RateLimitResult::RateLimited { retry_after }
+        } else {
+            RateLimitResult::Allowed {
+                remaining: limit.saturating_sub(count),
+                reset_at: self.clock.now() + retry_after as i64,
+            }
+        }
+    }
+
+    // the ip's bucket, counted with the same strategy as the client ones
+    async fn check_ip(&self, ip: &str) -> RateLimitResult {
+        let who = format!("ip {}", ip);
+        let limit = self.config.max_requests_per_ip_window;
+        match self.config.strategy {
+            RateLimitStrategy::FixedWindow => {
+                self.count_fixed_window(&who, &self.ip_rate_limit_key(ip), None, limit)
+                    .await
+            }
+            RateLimitStrategy::SlidingWindow => {
+                self.log_sliding_window(&who, &self.ip_sliding_window_key(ip), None, limit)
+                    .await
+            }
+        }
+    }
+
+    // what's in the log over the last window, nothing gets trimmed or added
+    async fn peek_sliding_window(&self, client_id: &str) -> RateLimitStatus {
+        let key = self.sliding_window_key(client_id);
+        let now = self.clock.now_millis();
+        let window_ms = self.config.window_seconds as i64 * 1000;
+
+        let logged: Vec<(String, i64)> = match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(db) => {
+                use redis::AsyncCommands;
+                let mut conn = db.connection.clone();
+
+                let result: Result<Vec<(String, i64)>, redis::RedisError> = conn
+                    .zrangebyscore_withscores(&key, now - window_ms + 1, "+inf")
+                    .await;
+                result.unwrap_or_else(|e| {
+                    error!("Rate limit peek failed for client {}: {}", client_id, e);
+                    Vec::new()
+                })
+            }
+            Database::Memory(db) => db
+                .store
+                .zrange_withscores(&key, 0, usize::MAX)
+                .await
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|(_, score)| *score > now - window_ms)
+                .collect(),
+        };
+
+        let used = logged.len() as u32;
+        let oldest = logged.first().map(|(_, score)| *score).unwrap_or(now);
+
+        RateLimitStatus {
+            limit: self.config.max_requests_per_window,
+            used,
+            remaining: self.config.max_requests_per_window.saturating_sub(used),
+            reset_at: self.clock.now() + seconds_until_aged_out(oldest, window_ms, now) as i64,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitServiceTrait for EdgeRateLimitService {
+    async fn check_rate_limit(&self, client_id: &str, ip: Option<&str>) -> RateLimitResult {
+        if self.is_exempt(client_id).await {
+            // not counted at all, this beats a timeout too
+            return RateLimitResult::Allowed {
+                remaining: self.config.max_requests_per_window,
+                reset_at: self.clock.now() + self.config.window_seconds as i64,
+            };
+        }
+
+        let result = match self.is_user_timed_out(client_id).await {
+            Some((reason, retry_after)) => RateLimitResult::TimedOut {
+                reason,
+                retry_after,
+            },
+            None => {
+                let client = match self.config.strategy {
+                    RateLimitStrategy::FixedWindow => self.check_fixed_window(client_id).await,
+                    RateLimitStrategy::SlidingWindow => self.check_sliding_window(client_id).await,
+                };
+                // both buckets count the request, whichever is stricter answers
+                match ip.filter(|_| self.config.max_requests_per_ip_window > 0) {
+                    Some(ip) => stricter(client, self.check_ip(ip).await),
+                    None => client,
+                }
+            }
+        };
+
+        metrics::counter!("rate_limit_checks_total", "result" => result.label()).increment(1);
+        result
+    }
+
+    async fn peek_rate_limit(&self, client_id: &str) -> RateLimitStatus {
+        if self.config.strategy == RateLimitStrategy::SlidingWindow {
+            return self.peek_sliding_window(client_id).await;
+        }
+
+        let key = self.rate_limit_key(client_id);
+        let window = self.config.window_seconds as i64;
+
+        let (used, ttl) = match self.db.as_ref() {
             #[allow(unused_imports)]
             Database::Redis(db) => {
                 use redis::AsyncCommands;
                 let mut conn = db.connection.clone();
 
-                let result: Result<(u32, i32), redis::RedisError> = redis::pipe()
-                    .atomic()
-                    .incr(&key, 1u32)
-                    .expire(&key, self.config.error_window_seconds as i64)
+                let result: Result<(Option<u32>, i64), redis::RedisError> = redis::pipe()
+                    .get(&key)
+                    .ttl(&key)
                     .query_async(&mut conn)
                     .await;
 
                 match result {
-                    Ok((count, _expire_result)) => {
-                        debug!(
-                            "Client {} error recorded ({}): count now {}",
-                            client_id, error_type, count
-                        );
-
-                        if count >= self.config.max_errors_before_timeout {
-                            warn!(
-                                "Client {} exceeded error threshold ({} errors), applying timeout",
-                                client_id, count
-                            );
-                            self.timeout_user(
-                                client_id,
-                                &format!(
-                                    "Automatic timeout: {} errors in {} seconds",
-                                    count, self.config.error_window_seconds
-                                ),
-                                self.config.timeout_duration_seconds,
-                            )
-                            .await;
-                        }
-                    }
+                    Ok((used, ttl)) => (used.unwrap_or(0), ttl),
                     Err(e) => {
-                        error!("Failed to record error for client {}: {}", client_id, e);
+                        error!("Rate limit peek failed for client {}: {}", client_id, e);
+                        (0, window)
                     }
                 }
             }
             Database::Memory(db) => {
-                let count = db.store.incr(&key, 1).await.unwrap_or(1);
+                let used = match db.store.get(&key).await {
+                    Ok(Some(count)) => count.parse().unwrap_or(0),
+                    _ => 0,
+                };
+                (used, db.store.ttl(&key).await.unwrap_or(window))
+            }
+        };
 
-                debug!(
-                    "Client {} error recorded ({}): count now {}",
-                    client_id, error_type, count
-                );
+        // no window running (or no expiry on it), a fresh one would start now
+        let ttl = if ttl > 0 { ttl } else { window };
 
-                if count >= self.config.max_errors_before_timeout {
-                    warn!(
-                        "Client {} exceeded error threshold ({} errors), applying timeout",
-                        client_id, count
-                    );
-                    self.timeout_user(
-                        client_id,
-                        &format!(
-                            "Automatic timeout: {} errors in {} seconds",
-                            count, self.config.error_window_seconds
-                        ),
-                        self.config.timeout_duration_seconds,
-                    )
-                    .await;
-                }
-            }
+        RateLimitStatus {
+            limit: self.config.max_requests_per_window,
+            used,
+            remaining: self.config.max_requests_per_window.saturating_sub(used),
+            reset_at: self.clock.now() + ttl,
+        }
+    }
+
+    async fn record_error(&self, client_id: &str, failure: UpstreamFailure) {
+        if self.config.error_weights.weight(failure) <= 0.0 {
+            debug!(
+                "Client {} upstream failure not counted: {:?}",
+                client_id, failure
+            );
+            return;
+        }
+
+        if self.is_exempt(client_id).await {
+            debug!("Client {} is exempt, {:?} not counted", client_id, failure);
+            return;
+        }
+
+        let error_type = failure.label();
+        let now = self.clock.now();
+        let half_life = self.config.error_half_life_seconds;
+
+        // read, decay and write back. two errors landing at the same moment can lose one of them,
+        // which only makes the limiter a bit more lenient
+        let score = self.load_error_score(client_id).await.record(
+            failure,
+            &self.config.error_weights,
+            now,
+            half_life,
+        );
+        self.store_error_score(client_id, &score, self.config.error_window_seconds)
+            .await;
+        let requests = self.error_window_requests(client_id).await;
+
+        debug!(
+            "Client {} error recorded ({}): score now {:.2} ({} requests)",
+            client_id, error_type, score.score, requests
+        );
+
+        if self.config.should_timeout(score.score, requests) {
+            warn!(
+                "Client {} exceeded error threshold (score {:.2}, {} requests), applying timeout",
+                client_id, score.score, requests
+            );
+            self.timeout_user(
+                client_id,
+                &format!(
+                    "Automatic timeout: error score {:.1} (halves every {} seconds)",
+                    score.score, half_life
+                ),
+                self.config.timeout_duration_seconds,
+            )
+            .await;
         }
     }
 
@@ -359,43 +1218,162 @@ impl RateLimitServiceTrait for EdgeRateLimitService {
         }
     }
 
-    async fn get_error_count(&self, client_id: &str) -> u32 {
-        let key = self.error_count_key(client_id);
+    async fn clear_error_score(&self, client_id: &str) -> bool {
+        let key = self.error_score_key(client_id);
+
+        let result = match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(db) => {
+                use redis::AsyncCommands;
+                let mut conn = db.connection.clone();
+                let result: Result<u32, redis::RedisError> = conn.del(&key).await;
+                result.map_err(anyhow::Error::from)
+            }
+            Database::Memory(db) => db.store.del(&key).await,
+        };
 
+        match result {
+            Ok(deleted) => deleted > 0,
+            Err(e) => {
+                error!(
+                    "Failed to clear error score for client {}: {}",
+                    client_id, e
+                );
+                false
+            }
+        }
+    }
+
+    async fn get_error_score(&self, client_id: &str) -> f64 {
+        self.load_error_score(client_id)
+            .await
+            .decayed(self.clock.now(), self.config.error_half_life_seconds)
+    }
+
+    async fn is_exempt(&self, client_id: &str) -> bool {
         match self.db.as_ref() {
             #[allow(unused_imports)]
             Database::Redis(db) => {
                 use redis::AsyncCommands;
                 let mut conn = db.connection.clone();
 
-                let result: Result<Option<u32>, redis::RedisError> = conn.get(&key).await;
+                let result: Result<bool, redis::RedisError> =
+                    conn.sismember(EXEMPT_SET_KEY, client_id).await;
 
                 match result {
-                    Ok(Some(count)) => count,
-                    Ok(None) => 0,
+                    Ok(exempt) => exempt,
                     Err(e) => {
-                        error!("Failed to get error count for client {}: {}", client_id, e);
-                        0
+                        error!("Failed to check exemption for client {}: {}", client_id, e);
+                        false
                     }
                 }
             }
-            Database::Memory(db) => {
-                let result = db.store.get(&key).await;
+            Database::Memory(db) => match db.store.smembers(EXEMPT_SET_KEY).await {
+                Ok(members) => members.iter().any(|m| m == client_id),
+                Err(e) => {
+                    error!("Failed to check exemption for client {}: {}", client_id, e);
+                    false
+                }
+            },
+        }
+    }
 
-                match result {
-                    Ok(Some(count_str)) => count_str.parse().unwrap_or(0),
-                    _ => 0,
+    async fn set_exempt(&self, client_id: &str, exempt: bool) {
+        let members = [client_id.to_string()];
+
+        let result = match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(db) => {
+                use redis::AsyncCommands;
+                let mut conn = db.connection.clone();
+
+                let result: Result<u32, redis::RedisError> = if exempt {
+                    conn.sadd(EXEMPT_SET_KEY, client_id).await
+                } else {
+                    conn.srem(EXEMPT_SET_KEY, client_id).await
+                };
+                result.map_err(anyhow::Error::from)
+            }
+            Database::Memory(db) => {
+                if exempt {
+                    db.store.sadd(EXEMPT_SET_KEY, &members).await
+                } else {
+                    db.store.srem(EXEMPT_SET_KEY, &members).await
                 }
             }
+        };
+
+        match result {
+            Ok(_) => info!("Client {} exemption set to {}", client_id, exempt),
+            Err(e) => error!("Failed to set exemption for client {}: {}", client_id, e),
         }
     }
 
-    async fn is_exempt(&self, _client_id: &str) -> bool {
-        // no exemptions in edge mode - everyone gets rate limited equally
-        false
+    async fn export_state(&self) -> AppResult<RateLimitSnapshot> {
+        let timeouts = self
+            .dump_prefix(&self.timeout_key(""))
+            .await?
+            .into_iter()
+            .map(|(client_id, reason, ttl_seconds)| TimeoutEntry {
+                client_id,
+                reason,
+                ttl_seconds,
+            })
+            .collect();
+
+        let error_scores = self
+            .dump_prefix(&self.error_score_key(""))
+            .await?
+            .into_iter()
+            .filter_map(|(client_id, score, ttl_seconds)| {
+                Some(ErrorScoreEntry {
+                    client_id,
+                    score: serde_json::from_str(&score).ok()?,
+                    ttl_seconds,
+                })
+            })
+            .collect();
+
+        let exemptions: Vec<String> = match self.db.as_ref() {
+            #[allow(unused_imports)]
+            Database::Redis(db) => {
+                use redis::AsyncCommands;
+                let mut conn = db.connection.clone();
+                conn.smembers(EXEMPT_SET_KEY)
+                    .await
+                    .map_err(anyhow::Error::from)?
+            }
+            Database::Memory(db) => db.store.smembers(EXEMPT_SET_KEY).await?,
+        };
+
+        Ok(RateLimitSnapshot {
+            timeouts,
+            exemptions,
+            error_scores,
+        })
     }
 
-    async fn set_exempt(&self, _client_id: &str, _exempt: bool) {
-        // just noop in the edge mode
+    async fn import_state(&self, snapshot: RateLimitSnapshot) -> AppResult<usize> {
+        snapshot.validate()?;
+
+        for entry in &snapshot.timeouts {
+            self.timeout_user(&entry.client_id, &entry.reason, entry.ttl_seconds)
+                .await;
+        }
+
+        for client_id in &snapshot.exemptions {
+            self.set_exempt(client_id, true).await;
+        }
+
+        for entry in &snapshot.error_scores {
+            self.store_error_score(&entry.client_id, &entry.score, entry.ttl_seconds)
+                .await;
+        }
+
+        let imported =
+            snapshot.timeouts.len() + snapshot.exemptions.len() + snapshot.error_scores.len();
+        info!("Imported {} rate limit entries", imported);
+
+        Ok(imported)
     }
 }
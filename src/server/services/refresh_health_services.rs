@@ -0,0 +1,93 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// default for how long after serving stale games the refresh is retried in the background
+pub const REFRESH_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// what the last games refresh did, read by the health endpoint
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RefreshState {
+    /// unix timestamp of the last refresh that actually stored games
+    pub last_success: Option<i64>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<i64>,
+}
+
+/// shared between whatever refreshes the games cache and the health endpoint, so a refresh that
+/// keeps failing (usually an upstream ban) shows up even though the process itself is fine
+#[derive(Debug, Default)]
+pub struct RefreshTracker {
+    state: Mutex<RefreshState>,
+}
+
+impl RefreshTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_success(&self, at: i64) {
+        self.state.lock().unwrap().last_success = Some(at);
+        metrics::gauge!("games_refresh_last_success_timestamp").set(at as f64);
+    }
+
+    pub fn record_failure(&self, at: i64, error: &str) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.last_error = Some(error.to_string());
+            state.last_error_at = Some(at);
+        }
+        metrics::counter!("games_refresh_failures_total").increment(1);
+    }
+
+    pub fn snapshot(&self) -> RefreshState {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+/// retries a failed games refresh in the background while clients get the stale games. only one
+/// retry waits or runs at a time, so every request served stale doesn't kick off its own. a retry
+/// that fails isn't rescheduled, the next request served stale does that
+#[derive(Debug)]
+pub struct RefreshRetry {
+    delay: Duration,
+    scheduled: AtomicBool,
+}
+
+impl Default for RefreshRetry {
+    fn default() -> Self {
+        Self::new(REFRESH_RETRY_DELAY)
+    }
+}
+
+impl RefreshRetry {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            scheduled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_scheduled(&self) -> bool {
+        self.scheduled.load(Ordering::SeqCst)
+    }
+
+    /// runs `retry` after the delay, unless a retry is already waiting or running. true when this
+    /// call scheduled it
+    pub fn schedule<F>(self: &Arc<Self>, retry: F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        if self.scheduled.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(this.delay).await;
+            retry.await;
+            this.scheduled.store(false, Ordering::SeqCst);
+        });
+        true
+    }
+}
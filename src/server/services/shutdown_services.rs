@@ -0,0 +1,111 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tracing::{error, info, warn};
+
+/// async cleanup a service wants to do before the process exits (flush metrics, persist hot keys,
+/// release locks). runs after the server stopped taking requests
+#[async_trait]
+pub trait ShutdownHook: Send + Sync {
+    /// shows up in the shutdown logs
+    fn name(&self) -> &str;
+
+    async fn on_shutdown(&self) -> anyhow::Result<()>;
+}
+
+/// every registered hook, cloning shares the same list
+#[derive(Clone, Default)]
+pub struct ShutdownHooks {
+    hooks: Arc<Mutex<Vec<Arc<dyn ShutdownHook>>>>,
+}
+
+impl ShutdownHooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, hook: Arc<dyn ShutdownHook>) {
+        self.hooks.lock().unwrap().push(hook);
+    }
+
+    pub fn len(&self) -> usize {
+        self.hooks.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// runs all hooks at the same time and waits at most `timeout` for them, a hook that hangs
+    /// shouldn't keep the process from exiting. returns how many finished without an error, 0 when
+    /// they ran out of time
+    pub async fn run(&self, timeout: Duration) -> usize {
+        let hooks = self.hooks.lock().unwrap().clone();
+        if hooks.is_empty() {
+            return 0;
+        }
+
+        info!("running {} shutdown hooks", hooks.len());
+
+        let runs = hooks.iter().map(|hook| async move {
+            match hook.on_shutdown().await {
+                Ok(()) => {
+                    info!("shutdown hook {} done", hook.name());
+                    true
+                }
+                Err(e) => {
+                    error!("shutdown hook {} failed: {}", hook.name(), e);
+                    false
+                }
+            }
+        });
+
+        match tokio::time::timeout(timeout, futures::future::join_all(runs)).await {
+            Ok(results) => results.into_iter().filter(|ok| *ok).count(),
+            Err(_) => {
+                warn!(
+                    "shutdown hooks didn't finish within {:?}, exiting anyway",
+                    timeout
+                );
+                0
+            }
+        }
+    }
+}
+
+/// how many requests are being handled right now, what a graceful shutdown drains. a request
+/// counts until its handler returned the response, a body still streaming after that doesn't
+#[derive(Clone, Debug, Default)]
+pub struct InFlightRequests {
+    count: Arc<AtomicUsize>,
+}
+
+impl InFlightRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// counts one request until the guard is dropped
+    pub fn track(&self) -> InFlightGuard {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard {
+            count: self.count.clone(),
+        }
+    }
+}
+
+pub struct InFlightGuard {
+    count: Arc<AtomicUsize>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
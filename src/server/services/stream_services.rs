@@ -2,19 +2,22 @@
 use async_trait::async_trait;
 use mockall::automock;
 use std::sync::Arc;
-use tracing::info;
+use tracing::{info, warn};
 
 use std::collections::HashMap;
 
 use crate::{
-    database::stream::DynStreamsRepository,
+    database::stream::{DynStreamsRepository, Game},
     server::{
-        dtos::stream_dto::{CategoryDto, GameDto, ResponseStreamDto},
+        dtos::stream_dto::{CategoryDto, GameDto, GameFilter, ResponseStreamDto},
         error::AppResult,
+        services::refresh_health_services::RefreshRetry,
+        utils::clock_utils::{DynClock, SystemClock},
+        utils::ttl_utils::{DEFAULT_TTL_JITTER_PERCENT, TtlJitter},
     },
 };
 
-use super::ppvsu_services::DynPpvsuService;
+use super::ppvsu_services::{DEFAULT_PROVIDER_KEY, DynPpvsuService, GAMES_CACHE_TTL_SECS};
 
 pub type DynStreamsService = Arc<dyn StreamsServiceTrait + Send + Sync>;
 
@@ -23,13 +26,19 @@ pub type DynStreamsService = Arc<dyn StreamsServiceTrait + Send + Sync>;
 pub trait StreamsServiceTrait {
     async fn get_stream(&self, provider: String) -> AppResult<ResponseStreamDto>;
     async fn get_all_streams(&self) -> AppResult<Vec<ResponseStreamDto>>;
-    async fn get_all_games(&self) -> AppResult<Vec<CategoryDto>>;
+    /// games grouped by category, only the ones `filter` lets through
+    async fn get_all_games(&self, filter: GameFilter) -> AppResult<Vec<CategoryDto>>;
 }
 
 #[derive(Clone)]
 pub struct StreamsService {
     repository: DynStreamsRepository,
     ppvsu_service: DynPpvsuService,
+    provider: String,
+    clock: DynClock,
+    games_ttl_seconds: u64,
+    ttl_jitter: TtlJitter,
+    refresh_retry: Arc<RefreshRetry>,
 }
 
 impl StreamsService {
@@ -37,6 +46,60 @@ impl StreamsService {
         Self {
             repository,
             ppvsu_service,
+            provider: DEFAULT_PROVIDER_KEY.to_string(),
+            clock: SystemClock::shared(),
+            games_ttl_seconds: GAMES_CACHE_TTL_SECS,
+            ttl_jitter: TtlJitter::new(DEFAULT_TTL_JITTER_PERCENT),
+            refresh_retry: Arc::default(),
+        }
+    }
+
+    /// has to be the same key the ppvsu service stores its games under
+    pub fn with_provider_key(mut self, provider: impl Into<String>) -> Self {
+        self.provider = provider.into();
+        self
+    }
+
+    /// what cache age and live games are checked against
+    pub fn with_clock(mut self, clock: DynClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// has to match the ppvsu service's so both agree on when a fetch is stale
+    pub fn with_games_ttl(mut self, seconds: u64, jitter: TtlJitter) -> Self {
+        self.games_ttl_seconds = seconds;
+        self.ttl_jitter = jitter;
+        self
+    }
+
+    /// shares the background retry of a failed refresh with the ppvsu service
+    pub fn with_refresh_retry(mut self, refresh_retry: Arc<RefreshRetry>) -> Self {
+        self.refresh_retry = refresh_retry;
+        self
+    }
+
+    /// fetches all games and marks the cache fresh as of `current_time`
+    async fn refresh_games(&self, current_time: i64) -> AppResult<Vec<Game>> {
+        let games = self.ppvsu_service.fetch_and_cache_games().await?;
+        self.repository
+            .set_last_fetch_time(&self.provider, current_time)
+            .await?;
+        Ok(games)
+    }
+
+    /// tries the refresh again in the background after serving stale games
+    fn schedule_refresh_retry(&self) {
+        let service = self.clone();
+        let scheduled = self.refresh_retry.schedule(async move {
+            let current_time = service.clock.now();
+            match service.refresh_games(current_time).await {
+                Ok(games) => info!("background retry refreshed {} games", games.len()),
+                Err(e) => warn!("background retry of the games refresh failed: {}", e),
+            }
+        });
+        if scheduled {
+            info!("scheduled a background retry of the games refresh");
         }
     }
 }
@@ -74,17 +137,13 @@ impl StreamsServiceTrait for StreamsService {
         Ok(streams)
     }
 
-    async fn get_all_games(&self) -> AppResult<Vec<CategoryDto>> {
-        info!("retrieving all games with auto-fetch");
+    async fn get_all_games(&self, filter: GameFilter) -> AppResult<Vec<CategoryDto>> {
+        info!("retrieving all games with auto-fetch ({:?})", filter);
 
-        let last_fetch = self.repository.get_last_fetch_time("ppvsu").await?;
+        let last_fetch = self.repository.get_last_fetch_time(&self.provider).await?;
 
-        let current_time = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map_err(|_| anyhow::anyhow!("System time before UNIX epoch"))?
-            .as_secs() as i64;
+        let current_time = self.clock.now();
 
-        let one_hour = 3600;
         let should_fetch = match last_fetch {
             None => {
                 info!("no previous fetch found, fetching all games from API");
@@ -92,8 +151,11 @@ impl StreamsServiceTrait for StreamsService {
             }
             Some(last_time) => {
                 let age = current_time - last_time;
-                if age > one_hour {
-                    info!("last fetch was {} seconds ago (> 1 hour), refetching", age);
+                if self
+                    .ttl_jitter
+                    .is_stale(last_time, current_time, self.games_ttl_seconds)
+                {
+                    info!("last fetch was {} seconds ago, refetching", age);
                     true
                 } else {
                     info!("last fetch was {} seconds ago, using cache", age);
@@ -103,23 +165,39 @@ impl StreamsServiceTrait for StreamsService {
         };
 
         let games = if should_fetch {
-            // cache is old so we drop it
-            info!("Dumping cache of ppvsu:* matchers");
-            self.repository.clear_cache("ppvsu").await?;
-
+            // the old games are dropped by the fetch once it has new ones
             info!("fetching all games from ppvs.su API");
-            let games = self.ppvsu_service.fetch_and_cache_games().await?;
-            self.repository
-                .set_last_fetch_time("ppvsu", current_time)
-                .await?;
-            games
+            match self.refresh_games(current_time).await {
+                Ok(games) => games,
+                Err(e) => {
+                    // usually ppvs.su banning us (or its circuit breaker being open), the
+                    // old games are better than none
+                    let stale = self.repository.get_games(&self.provider).await?;
+                    if stale.is_empty() {
+                        return Err(e);
+                    }
+                    warn!(
+                        "refetch of all games failed, serving {} stale ones ({}): {}",
+                        stale.len(),
+                        last_fetch
+                            .map(|at| format!("last fetched {}s ago", current_time - at))
+                            .unwrap_or_else(|| "never fetched".to_string()),
+                        e
+                    );
+                    self.schedule_refresh_retry();
+                    stale
+                }
+            }
         } else {
-            self.repository.get_games("ppvsu").await?
+            self.repository.get_games(&self.provider).await?
         };
 
         let mut categories_map: HashMap<String, Vec<GameDto>> = HashMap::new();
 
-        for game in games {
+        for game in games
+            .into_iter()
+            .filter(|game| filter.matches(game, current_time))
+        {
             let category = game.category.clone();
             let game_dto = game.into_dto();
             categories_map.entry(category).or_default().push(game_dto);
@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::server::utils::request_id_utils;
+
+/// one request we made to an upstream, kept around so the pattern leading up to a ban can be
+/// looked at after the fact
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UpstreamAttempt {
+    /// unix millis when the attempt finished
+    pub at: i64,
+    /// what made the request (proxy, prefetch, ppvsu, ...)
+    pub source: String,
+    pub host: String,
+    /// None when no response came back at all
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    /// whether the request went out through an http proxy instead of directly
+    pub via_proxy: bool,
+    /// time until the response headers came back (or the request failed)
+    pub latency_ms: u64,
+}
+
+/// fixed size ring buffer of the most recent upstream attempts, the oldest ones fall off
+#[derive(Debug)]
+pub struct UpstreamAttemptLog {
+    capacity: usize,
+    via_proxy: bool,
+    attempts: Mutex<VecDeque<UpstreamAttempt>>,
+}
+
+impl Default for UpstreamAttemptLog {
+    fn default() -> Self {
+        Self::new(200, false)
+    }
+}
+
+impl UpstreamAttemptLog {
+    /// a capacity of 0 still logs every attempt, it just doesn't keep any
+    pub fn new(capacity: usize, via_proxy: bool) -> Self {
+        Self {
+            capacity,
+            via_proxy,
+            attempts: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// reqwest sends through HTTP(S)_PROXY/ALL_PROXY when they're set, so that's what decides if
+    /// attempts are marked as proxied
+    pub fn proxy_from_env() -> bool {
+        [
+            "HTTPS_PROXY",
+            "https_proxy",
+            "HTTP_PROXY",
+            "http_proxy",
+            "ALL_PROXY",
+            "all_proxy",
+        ]
+        .iter()
+        .any(|var| std::env::var(var).is_ok_and(|v| !v.trim().is_empty()))
+    }
+
+    pub fn record(
+        &self,
+        source: &str,
+        host: &str,
+        outcome: Result<u16, String>,
+        latency: Duration,
+    ) {
+        let (status, error) = match outcome {
+            Ok(status) => (Some(status), None),
+            Err(e) => (None, Some(e)),
+        };
+        let attempt = UpstreamAttempt {
+            at: chrono::Utc::now().timestamp_millis(),
+            source: source.to_string(),
+            host: host.to_string(),
+            status,
+            error,
+            via_proxy: self.via_proxy,
+            latency_ms: latency.as_millis() as u64,
+        };
+
+        info!(
+            target: "upstream_attempt",
+            source = %attempt.source,
+            host = %attempt.host,
+            status = ?attempt.status,
+            error = ?attempt.error,
+            via_proxy = attempt.via_proxy,
+            latency_ms = attempt.latency_ms,
+            "upstream attempt"
+        );
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut attempts = self.attempts.lock().unwrap();
+        if attempts.len() == self.capacity {
+            attempts.pop_front();
+        }
+        attempts.push_back(attempt);
+    }
+
+    /// sends the request (with the current request id) and records how it went, the response (or
+    /// error) is handed back as is
+    pub async fn send(
+        &self,
+        source: &str,
+        request: reqwest::RequestBuilder,
+    ) -> reqwest::Result<reqwest::Response> {
+        let (client, request) = request.build_split();
+        let mut request = request?;
+        request_id_utils::apply(&mut request);
+        let host = request.url().host_str().unwrap_or_default().to_string();
+
+        let started = Instant::now();
+        let result = client.execute(request).await;
+
+        let latency = started.elapsed();
+        let outcome = match &result {
+            Ok(response) => Ok(response.status().as_u16()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        // status is "error" when nothing came back at all
+        let status = match &outcome {
+            Ok(status) => status.to_string(),
+            Err(_) => "error".to_string(),
+        };
+        metrics::counter!("upstream_requests_total", "source" => source.to_string(), "status" => status.clone()).increment(1);
+        metrics::histogram!("upstream_request_duration_seconds", "source" => source.to_string(), "status" => status).record(latency.as_secs_f64());
+
+        self.record(source, &host, outcome, latency);
+
+        result
+    }
+
+    /// most recent attempts in the order they happened, at most `limit` of them
+    pub fn recent(&self, limit: usize) -> Vec<UpstreamAttempt> {
+        let attempts = self.attempts.lock().unwrap();
+        let skip = attempts.len().saturating_sub(limit);
+        attempts.iter().skip(skip).cloned().collect()
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::MissedTickBehavior;
+use tracing::warn;
+
+use crate::server::error::{AppResult, Error};
+
+/// caps how many requests can be active against one upstream host at a time. a popular event
+/// can otherwise open thousands of connections to one cdn which gets us reset or banned.
+/// requests over the cap queue for a bit and give up after `queue_timeout`
+#[derive(Debug)]
+pub struct UpstreamLimiter {
+    /// 0 turns the cap off
+    max_per_host: usize,
+    queue_timeout: Duration,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl Default for UpstreamLimiter {
+    fn default() -> Self {
+        Self::new(0, Duration::from_secs(2))
+    }
+}
+
+impl UpstreamLimiter {
+    pub fn new(max_per_host: usize, queue_timeout: Duration) -> Self {
+        Self {
+            max_per_host,
+            queue_timeout,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn semaphore(&self, host: &str) -> Arc<Semaphore> {
+        let mut hosts = self.hosts.lock().unwrap();
+        if !hosts.contains_key(host) {
+            // drop hosts nobody holds or waits for a slot on so the map doesn't grow forever
+            hosts.retain(|_, s| Arc::strong_count(s) > 1);
+        }
+        hosts
+            .entry(host.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_host)))
+            .clone()
+    }
+
+    /// wait for a slot on the host, Ok(None) means there's no cap. a 503 comes back if the queue
+    /// wait runs out. hold the permit until the upstream body is read
+    pub async fn acquire(&self, host: &str) -> AppResult<Option<OwnedSemaphorePermit>> {
+        if self.max_per_host == 0 {
+            return Ok(None);
+        }
+
+        let semaphore = self.semaphore(host);
+        match tokio::time::timeout(self.queue_timeout, semaphore.acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            Ok(Err(_)) | Err(_) => {
+                warn!(
+                    "Upstream host {} at its {} connection cap, gave up after {:?}",
+                    host, self.max_per_host, self.queue_timeout
+                );
+                Err(Error::ServiceUnavailable {
+                    message: "Upstream is busy, try again shortly".to_string(),
+                    retry_after: self.queue_timeout.as_secs().max(1),
+                })
+            }
+        }
+    }
+
+    /// requests currently holding a slot on the host
+    pub fn active(&self, host: &str) -> usize {
+        if self.max_per_host == 0 {
+            return 0;
+        }
+
+        self.hosts
+            .lock()
+            .unwrap()
+            .get(host)
+            .map_or(0, |s| self.max_per_host - s.available_permits())
+    }
+
+    /// hosts with a semaphore right now, idle ones go once another host shows up
+    pub fn tracked_hosts(&self) -> usize {
+        self.hosts.lock().unwrap().len()
+    }
+}
+
+/// process wide cap on how many upstream requests go out per second, across every host. the
+/// per host cap above only limits how many are open at once, a busy event can still push enough
+/// short requests through it to get the ip banned by cloudflare. a token bucket that holds a
+/// second's worth of tokens and gets one back every `1s / per_second`, anything over the rate
+/// waits for a token instead of failing
+#[derive(Debug)]
+pub struct UpstreamRateLimit {
+    /// 0 turns the cap off
+    per_second: u32,
+    tokens: Arc<Semaphore>,
+}
+
+impl Default for UpstreamRateLimit {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl UpstreamRateLimit {
+    /// starts full. the refill runs on its own task so this needs a tokio runtime unless the cap
+    /// is off, the task stops once the bucket is dropped
+    pub fn new(per_second: u32) -> Self {
+        let tokens = Arc::new(Semaphore::new(per_second as usize));
+
+        if per_second > 0 {
+            let bucket = Arc::downgrade(&tokens);
+            let capacity = per_second as usize;
+            tokio::spawn(async move {
+                let mut refill = tokio::time::interval(Duration::from_secs(1) / per_second);
+                refill.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                // the first tick is immediate and the bucket is already full
+                refill.tick().await;
+
+                loop {
+                    refill.tick().await;
+                    let Some(tokens) = bucket.upgrade() else {
+                        break;
+                    };
+                    if tokens.available_permits() < capacity {
+                        tokens.add_permits(1);
+                    }
+                }
+            });
+        }
+
+        Self { per_second, tokens }
+    }
+
+    /// take a token, waiting for the next refill when the bucket is empty
+    pub async fn acquire(&self) {
+        if self.per_second == 0 {
+            return;
+        }
+
+        // tokens aren't handed back when the request is done, the refill task does that
+        if let Ok(permit) = self.tokens.acquire().await {
+            permit.forget();
+        }
+    }
+
+    /// tokens left in the bucket right now
+    pub fn available(&self) -> usize {
+        self.tokens.available_permits()
+    }
+}
@@ -0,0 +1,102 @@
+use std::time::Instant;
+
+use tracing::info;
+
+/// query params that never make it into the access log, sig/client are credentials and the url is
+/// the upstream link which often carries its own tokens (the host is logged separately)
+const REDACTED_PARAMS: &[&str] = &["sig", "client", "url"];
+
+/// how the proxy cache took part in serving a request
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CacheOutcome {
+    /// schema isn't cached or the request failed before the cache was checked
+    #[default]
+    Skipped,
+    Hit,
+    /// served from a prefetch that was still in flight
+    Inflight,
+    Miss,
+    /// upstream failed and an expired playlist was served instead
+    Stale,
+    /// upstream just said the segment doesn't exist, answered without asking again
+    Negative,
+}
+
+impl CacheOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Skipped => "skip",
+            Self::Hit => "hit",
+            Self::Inflight => "inflight",
+            Self::Miss => "miss",
+            Self::Stale => "stale",
+            Self::Negative => "negative",
+        }
+    }
+}
+
+/// one line per request, filled in as the request goes and emitted once it's done
+#[derive(Debug)]
+pub struct AccessLogEntry {
+    pub method: String,
+    pub path: String,
+    pub client_id: String,
+    pub upstream_host: Option<String>,
+    pub status: u16,
+    pub bytes: Option<u64>,
+    pub cache: CacheOutcome,
+    started: Instant,
+}
+
+impl AccessLogEntry {
+    pub fn new(method: &str, path_and_query: &str, client_id: &str) -> Self {
+        Self {
+            method: method.to_string(),
+            path: redact_query(path_and_query),
+            client_id: client_id.to_string(),
+            upstream_host: None,
+            status: 0,
+            bytes: None,
+            cache: CacheOutcome::default(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn finish(&mut self, status: u16, bytes: Option<u64>) {
+        self.status = status;
+        self.bytes = bytes;
+    }
+
+    pub fn emit(&self) {
+        info!(
+            target: "access_log",
+            method = %self.method,
+            path = %self.path,
+            client_id = %self.client_id,
+            upstream_host = %self.upstream_host.as_deref().unwrap_or("-"),
+            status = self.status,
+            bytes = %self.bytes.map_or_else(|| "-".to_string(), |b| b.to_string()),
+            duration_ms = self.started.elapsed().as_millis() as u64,
+            cache = %self.cache.as_str(),
+            "request completed"
+        );
+    }
+}
+
+/// replaces the values of sensitive query params with `[redacted]`, everything else is kept as is
+pub fn redact_query(path_and_query: &str) -> String {
+    let Some((path, query)) = path_and_query.split_once('?') else {
+        return path_and_query.to_string();
+    };
+
+    let query = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, _)) if REDACTED_PARAMS.contains(&key) => format!("{}=[redacted]", key),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+
+    format!("{}?{}", path, query)
+}
@@ -0,0 +1,147 @@
+// reusable buffers for the proxy hot path. every proxied request decompresses a body and most
+// rewrite a playlist, under load that's a lot of big short lived allocations for the allocator to
+// churn through, so the buffers get handed back and reused instead
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// something a pool can hand out again once it's been emptied
+pub trait Reusable: Default + Send + 'static {
+    fn capacity(&self) -> usize;
+    /// empties it without giving the memory back
+    fn reset(&mut self);
+}
+
+impl Reusable for Vec<u8> {
+    fn capacity(&self) -> usize {
+        Vec::capacity(self)
+    }
+
+    fn reset(&mut self) {
+        self.clear();
+    }
+}
+
+impl Reusable for String {
+    fn capacity(&self) -> usize {
+        String::capacity(self)
+    }
+
+    fn reset(&mut self) {
+        self.clear();
+    }
+}
+
+struct PoolInner<T> {
+    idle: Mutex<Vec<T>>,
+    max_buffers: usize,
+    max_buffer_bytes: usize,
+    recycled: AtomicUsize,
+}
+
+/// keeps up to `max_buffers` emptied buffers around. buffers that grew past `max_buffer_bytes`
+/// are dropped instead of kept so one huge vod playlist doesn't pin its memory forever
+pub struct BufferPool<T: Reusable> {
+    inner: Arc<PoolInner<T>>,
+}
+
+impl<T: Reusable> Clone for BufferPool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Reusable> Default for BufferPool<T> {
+    fn default() -> Self {
+        Self::new(32, 2 * 1024 * 1024)
+    }
+}
+
+impl<T: Reusable> BufferPool<T> {
+    pub fn new(max_buffers: usize, max_buffer_bytes: usize) -> Self {
+        Self {
+            inner: Arc::new(PoolInner {
+                idle: Mutex::new(Vec::with_capacity(max_buffers)),
+                max_buffers,
+                max_buffer_bytes,
+                recycled: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// hands out fresh buffers and never keeps any
+    pub fn disabled() -> Self {
+        Self::new(0, 0)
+    }
+
+    /// an empty buffer, reused when one is idle
+    pub fn take(&self) -> PooledBuffer<T> {
+        let reused = self.inner.idle.lock().unwrap().pop();
+        if reused.is_some() {
+            self.inner.recycled.fetch_add(1, Ordering::Relaxed);
+        }
+
+        PooledBuffer {
+            buffer: Some(reused.unwrap_or_default()),
+            pool: self.inner.clone(),
+        }
+    }
+
+    /// buffers sitting in the pool right now
+    pub fn idle(&self) -> usize {
+        self.inner.idle.lock().unwrap().len()
+    }
+
+    /// how many takes got a reused buffer
+    pub fn recycled(&self) -> usize {
+        self.inner.recycled.load(Ordering::Relaxed)
+    }
+}
+
+/// a buffer on loan from a pool, it goes back (emptied) when dropped
+pub struct PooledBuffer<T: Reusable> {
+    // only None once it's been given up through `into_inner`
+    buffer: Option<T>,
+    pool: Arc<PoolInner<T>>,
+}
+
+impl<T: Reusable> PooledBuffer<T> {
+    /// keeps the buffer for good, it won't go back to the pool
+    pub fn into_inner(mut self) -> T {
+        self.buffer.take().unwrap_or_default()
+    }
+}
+
+impl<T: Reusable> Deref for PooledBuffer<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.buffer.as_ref().expect("pooled buffer already taken")
+    }
+}
+
+impl<T: Reusable> DerefMut for PooledBuffer<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.buffer.as_mut().expect("pooled buffer already taken")
+    }
+}
+
+impl<T: Reusable> Drop for PooledBuffer<T> {
+    fn drop(&mut self) {
+        let Some(mut buffer) = self.buffer.take() else {
+            return;
+        };
+        if buffer.capacity() == 0 || buffer.capacity() > self.pool.max_buffer_bytes {
+            return;
+        }
+
+        // emptied before it goes back so nothing from this request can show up in the next one
+        buffer.reset();
+        let mut idle = self.pool.idle.lock().unwrap();
+        if idle.len() < self.pool.max_buffers {
+            idle.push(buffer);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+// where the time comes from for anything that compares against "now" (signature expiry, cache
+// staleness, rate limit windows), so tests can move time instead of sleeping
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub trait Clock: Send + Sync {
+    /// unix seconds
+    fn now(&self) -> i64;
+
+    /// unix milliseconds, for things that need finer than a second (sliding rate limit windows)
+    fn now_millis(&self) -> i64 {
+        self.now() * 1000
+    }
+}
+
+pub type DynClock = Arc<dyn Clock>;
+
+/// the wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            // a clock set before 1970 is broken anyway, treat it as the epoch
+            .unwrap_or(0)
+    }
+
+    fn now_millis(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0)
+    }
+}
+
+impl SystemClock {
+    pub fn shared() -> DynClock {
+        Arc::new(Self)
+    }
+}
+
+/// a clock that only moves when it's told to
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: AtomicI64,
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+impl MockClock {
+    pub fn new(now: i64) -> Self {
+        Self {
+            now: AtomicI64::new(now),
+        }
+    }
+
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, seconds: i64) {
+        self.now.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,97 @@
+// which browser origins the api and proxy answer cors requests for, from cors_origin and
+// preview_cors_origin
+use std::sync::Arc;
+
+use axum::http::request::Parts as RequestParts;
+use axum::http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CorsOrigins {
+    /// `*` in either list, every origin gets mirrored back
+    Any,
+    List {
+        /// hosts (with a port if there is one), matches the host itself and its subdomains
+        origins: Vec<String>,
+        /// preview deploys get generated hostnames, these match any host ending in the entry
+        preview_origins: Vec<String>,
+    },
+}
+
+impl CorsOrigins {
+    /// the two comma seperated config values, entries can have the scheme on them or not
+    pub fn parse(origins: &str, preview_origins: &str) -> Self {
+        let origins = parse_hosts(origins);
+        let preview_origins = parse_hosts(preview_origins);
+
+        if origins.iter().chain(&preview_origins).any(|o| o == "*") {
+            return Self::Any;
+        }
+
+        Self::List {
+            origins,
+            preview_origins,
+        }
+    }
+
+    /// `origin` is the Origin header, like `https://app.example.com`
+    pub fn allows(&self, origin: &str) -> bool {
+        let (origins, preview_origins) = match self {
+            Self::Any => return true,
+            Self::List {
+                origins,
+                preview_origins,
+            } => (origins, preview_origins),
+        };
+
+        let Some(host) = origin
+            .strip_prefix("https://")
+            .or_else(|| origin.strip_prefix("http://"))
+            .map(|h| h.trim_end_matches('/').to_ascii_lowercase())
+            .filter(|h| !h.is_empty())
+        else {
+            return false;
+        };
+
+        origins.iter().any(|o| {
+            host == *o
+                || host
+                    .strip_suffix(o.as_str())
+                    .is_some_and(|sub| sub.ends_with('.'))
+        }) || preview_origins.iter().any(|o| host.ends_with(o.as_str()))
+    }
+
+    /// credentials are allowed, so `*` can't be sent as is and the request's origin is mirrored
+    /// instead
+    pub fn layer(self, methods: Vec<Method>, headers: Vec<HeaderName>) -> CorsLayer {
+        let allow_origin = match self {
+            Self::Any => AllowOrigin::mirror_request(),
+            list => {
+                let list = Arc::new(list);
+                AllowOrigin::predicate(move |origin: &HeaderValue, _: &RequestParts| {
+                    origin.to_str().is_ok_and(|o| list.allows(o))
+                })
+            }
+        };
+
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .allow_credentials(true)
+    }
+}
+
+fn parse_hosts(hosts: &str) -> Vec<String> {
+    hosts
+        .split(',')
+        .map(|h| {
+            h.trim()
+                .trim_start_matches("https://")
+                .trim_start_matches("http://")
+                .trim_end_matches('/')
+                .to_ascii_lowercase()
+        })
+        .filter(|h| !h.is_empty())
+        .collect()
+}
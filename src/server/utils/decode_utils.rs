@@ -0,0 +1,201 @@
+// upstream body decoding for the proxy. a connection that drops mid transfer leaves a cut off
+// gzip/zstd/brotli/deflate body, that's worth fetching again while a body that's just garbage isn't
+use std::fmt;
+use std::future::Future;
+use std::io::{ErrorKind, Read};
+
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use tracing::{debug, error, warn};
+
+use crate::server::error::{AppResult, Error};
+use crate::server::utils::buffer_pool_utils::{BufferPool, PooledBuffer};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// the body ended early, a refetch will most likely work
+    Truncated(String),
+    /// the body isn't valid for its encoding, refetching gets the same thing back
+    Malformed(String),
+}
+
+impl DecodeError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Truncated(_))
+    }
+
+    fn from_io(encoding: &str, e: std::io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::UnexpectedEof => {
+                Self::Truncated(format!("{} body cut off: {}", encoding, e))
+            }
+            _ => Self::Malformed(format!("invalid {} body: {}", encoding, e)),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated(message) | Self::Malformed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// decompresses a body by its Content-Encoding, unknown encodings are passed through as is
+pub fn decompress(encoding: Option<&str>, bytes: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let mut decomp: Vec<u8> = Vec::new();
+    decompress_into(encoding, bytes, &mut decomp)?;
+    Ok(decomp)
+}
+
+/// `decompress` into a buffer the caller already has, `out` is emptied first
+pub fn decompress_into(
+    encoding: Option<&str>,
+    bytes: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<(), DecodeError> {
+    out.clear();
+    match encoding {
+        Some("zstd") => {
+            debug!("Decompressing zstd-encoded response");
+            zstd::stream::copy_decode(bytes, &mut *out).map_err(|e| DecodeError::from_io("zstd", e))
+        }
+        Some("gzip") => {
+            debug!("Decompressing gzip-encoded response");
+            let mut decoder = GzDecoder::new(bytes);
+            decoder
+                .read_to_end(out)
+                .map(|_| ())
+                .map_err(|e| DecodeError::from_io("gzip", e))
+        }
+        Some("br") => {
+            debug!("Decompressing brotli-encoded response");
+            brotli::Decompressor::new(bytes, 4096)
+                .read_to_end(out)
+                .map(|_| ())
+                .map_err(|e| DecodeError::from_io("brotli", e))
+        }
+        Some("deflate") => {
+            debug!("Decompressing deflate-encoded response");
+            // deflate is supposed to be zlib wrapped but plenty of servers send it raw
+            let result = if is_zlib_header(bytes) {
+                ZlibDecoder::new(bytes).read_to_end(out)
+            } else {
+                DeflateDecoder::new(bytes).read_to_end(out)
+            };
+            result
+                .map(|_| ())
+                .map_err(|e| DecodeError::from_io("deflate", e))
+        }
+        _ => {
+            out.extend_from_slice(bytes);
+            Ok(())
+        }
+    }
+}
+
+// a zlib stream starts with a deflate CMF byte and a FLG byte that makes the pair a multiple of 31
+fn is_zlib_header(bytes: &[u8]) -> bool {
+    match bytes {
+        [cmf, flg, ..] => {
+            cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)).is_multiple_of(31)
+        }
+        _ => false,
+    }
+}
+
+fn content_encoding(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string())
+}
+
+/// whether `read_decoded_body` failed because the body itself is garbage, as opposed to the
+/// connection dropping
+pub fn is_malformed_body(error: &Error) -> bool {
+    matches!(error, Error::UpstreamBody(DecodeError::Malformed(_)))
+}
+
+/// reads and decompresses the body. when it comes back cut off (dropped connection, partial
+/// gzip/zstd) the request is sent again with `retry`, up to `max_retries` times. a malformed body
+/// fails straight away since a refetch would just get the same bytes
+pub async fn read_decoded_body(
+    response: reqwest::Response,
+    retry: Option<reqwest::RequestBuilder>,
+    max_retries: u32,
+) -> AppResult<Vec<u8>> {
+    read_decoded_body_pooled(
+        response,
+        retry,
+        max_retries,
+        &BufferPool::disabled(),
+        |request| request.send(),
+    )
+    .await
+    .map(PooledBuffer::into_inner)
+}
+
+/// `read_decoded_body` that decompresses into a buffer from `buffers`, it goes back to the pool
+/// once the caller is done with it. retries go out through `send` like the first request did,
+/// so they wait for the upstream rate cap and show up in the attempt log too
+pub async fn read_decoded_body_pooled<F, Fut>(
+    mut response: reqwest::Response,
+    retry: Option<reqwest::RequestBuilder>,
+    max_retries: u32,
+    buffers: &BufferPool<Vec<u8>>,
+    mut send: F,
+) -> AppResult<PooledBuffer<Vec<u8>>>
+where
+    F: FnMut(reqwest::RequestBuilder) -> Fut,
+    Fut: Future<Output = reqwest::Result<reqwest::Response>>,
+{
+    let mut attempt = 0;
+    let mut decoded_body = buffers.take();
+
+    loop {
+        let encoding = content_encoding(&response);
+        let decoded = match response.bytes().await {
+            Ok(bytes) => {
+                debug!("Read {} bytes", bytes.len());
+                decompress_into(encoding.as_deref(), &bytes, &mut decoded_body)
+            }
+            Err(e) => Err(DecodeError::Truncated(format!(
+                "failed to read body: {}",
+                e
+            ))),
+        };
+
+        let problem = match decoded {
+            Ok(()) => return Ok(decoded_body),
+            Err(problem) => problem,
+        };
+
+        let retry_request = retry.as_ref().and_then(|r| r.try_clone());
+        let Some(retry_request) =
+            retry_request.filter(|_| problem.is_retryable() && attempt < max_retries)
+        else {
+            error!("Failed to decode upstream response: {}", problem);
+            return Err(Error::UpstreamBody(problem));
+        };
+
+        attempt += 1;
+        warn!(
+            "Upstream response was cut off ({}), retrying {}/{}",
+            problem, attempt, max_retries
+        );
+
+        response = send(retry_request).await.map_err(|e| {
+            error!("Retry request failed: {}", e);
+            Error::BadGateway(format!("Request failed: {}", e))
+        })?;
+        if !response.status().is_success() {
+            error!("Retry returned {}", response.status());
+            return Err(Error::BadGateway(format!(
+                "Retry returned {}",
+                response.status()
+            )));
+        }
+    }
+}
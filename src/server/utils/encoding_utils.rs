@@ -0,0 +1,105 @@
+// response compression for the proxy, kept out of the controller so it can be tested
+use std::io::Write;
+
+use flate2::{Compression, write::GzEncoder};
+
+/// zstd's default, what a plain `compress` uses
+const ZSTD_DEFAULT_LEVEL: i32 = 3;
+/// brotli's max (11) is far too slow to run per response, 5 is about gzip's speed and still
+/// smaller
+const BROTLI_DEFAULT_QUALITY: i32 = 5;
+/// brotli window size (log2), its usual default
+const BROTLI_WINDOW: u32 = 22;
+
+/// Supported compression encodings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentEncoding {
+    Zstd,
+    Brotli,
+    Gzip,
+    None,
+}
+
+impl ContentEncoding {
+    /// determine the best encoding based on Accept-Encoding header
+    /// apple HLS player sends "gzip, deflate" or "identity" - IT MUST BE RESPECTED (i think)
+    ///
+    /// this is a work in progress. Current issues arise from content-length missing? HAR files
+    /// show that the client doesn't recieve them and doesn't query for any more m3u8s for some
+    /// reason. Not sure what the issue is, please help me on this if you read it before I remove
+    /// this comment LMAO
+    pub fn from_accept_encoding(accept_encoding: Option<&str>) -> Self {
+        match accept_encoding {
+            Some(v) => {
+                // don't compress if client explicitly requests identity-only
+                if v == "identity" || v.starts_with("identity,") {
+                    return Self::None;
+                }
+                // Prefer zstd if supported (better compression), then brotli, fallback to gzip
+                if v.contains("zstd") {
+                    Self::Zstd
+                } else if v.split(',').any(|coding| {
+                    coding
+                        .split(';')
+                        .next()
+                        .is_some_and(|name| name.trim().eq_ignore_ascii_case("br"))
+                }) {
+                    Self::Brotli
+                } else if v.contains("gzip") {
+                    Self::Gzip
+                } else {
+                    Self::None
+                }
+            }
+            None => Self::None,
+        }
+    }
+
+    pub fn as_header_value(&self) -> Option<&'static str> {
+        match self {
+            Self::Zstd => Some("zstd"),
+            Self::Brotli => Some("br"),
+            Self::Gzip => Some("gzip"),
+            Self::None => None,
+        }
+    }
+
+    /// each encoding's default level
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+        match self {
+            Self::Zstd => zstd::encode_all(data, ZSTD_DEFAULT_LEVEL),
+            Self::Brotli => Self::brotli(data, BROTLI_DEFAULT_QUALITY),
+            Self::Gzip => Self::gzip(data, Compression::default()),
+            Self::None => Ok(data.to_vec()),
+        }
+    }
+
+    /// `level` is clamped to what the encoding supports, 1-22 for zstd, 0-11 for brotli and 0-9
+    /// for gzip. higher is smaller but slower
+    pub fn compress_with_level(&self, data: &[u8], level: i32) -> Result<Vec<u8>, std::io::Error> {
+        match self {
+            Self::Zstd => zstd::encode_all(data, level.clamp(1, 22)),
+            Self::Brotli => Self::brotli(data, level),
+            Self::Gzip => Self::gzip(data, Compression::new(level.clamp(0, 9) as u32)),
+            Self::None => Ok(data.to_vec()),
+        }
+    }
+
+    fn brotli(data: &[u8], quality: i32) -> Result<Vec<u8>, std::io::Error> {
+        let mut encoder = brotli::CompressorWriter::new(
+            Vec::new(),
+            4096,
+            quality.clamp(0, 11) as u32,
+            BROTLI_WINDOW,
+        );
+        encoder.write_all(data)?;
+        encoder.flush()?;
+        Ok(encoder.into_inner())
+    }
+
+    fn gzip(data: &[u8], level: Compression) -> Result<Vec<u8>, std::io::Error> {
+        let mut encoder = GzEncoder::new(Vec::new(), level);
+        encoder.write_all(data)?;
+        encoder.finish()
+    }
+}
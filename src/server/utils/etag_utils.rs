@@ -0,0 +1,43 @@
+// conditional requests for cached segments. a segment never changes once it's cached so a client
+// asking again with the etag it got gets a 304 instead of the whole body
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+
+/// etag for a cached segment from the hash of its url (the one its cache key uses) and its length.
+/// weak because the same segment goes out compressed or not depending on the client
+pub fn segment_etag(url_hash: &str, len: usize) -> String {
+    format!("W/\"{}-{}\"", url_hash, len)
+}
+
+/// true when the client's If-None-Match has `etag` in it (or is `*`). If-None-Match always uses
+/// the weak comparison so a `W/` on either side doesn't matter
+pub fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+pub fn insert_etag(headers: &mut HeaderMap, etag: &str) {
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, value);
+    }
+}
+
+/// a 304 with no body. it keeps the Cache-Control the full response would have had so the
+/// client's copy stays fresh for as long again
+pub fn not_modified(etag: &str, segment_headers: &HeaderMap) -> Response {
+    let mut response = StatusCode::NOT_MODIFIED.into_response();
+    insert_etag(response.headers_mut(), etag);
+    if let Some(cache_control) = segment_headers.get(header::CACHE_CONTROL) {
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, cache_control.clone());
+    }
+    response
+}
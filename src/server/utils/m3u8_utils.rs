@@ -0,0 +1,653 @@
+// playlist rewriting used by the proxy controller, kept out of the controller so it can be tested
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+use base64::{Engine as _, engine::general_purpose::URL_SAFE};
+use tracing::error;
+
+use crate::server::{
+    error::{AppResult, Error},
+    utils::buffer_pool_utils::PooledBuffer,
+    utils::segment_decrypt_utils::{AES_BLOCK_LEN, KeyMethod, SegmentKey, sequence_iv},
+    utils::signature_utils::{SignatureUtil, signed_payload},
+};
+
+/// how many masters deep a playlist chain can go before we stop following it. some sources nest a
+/// master inside a master, anything past this is almost certainly a loop
+pub const MAX_PLAYLIST_DEPTH: u32 = 3;
+
+/// per request knobs for rewriting a playlist
+#[derive(Debug, Clone, Default)]
+pub struct PlaylistOptions {
+    /// how many masters deep this playlist was reached
+    pub depth: u32,
+    /// the client's Accept-Language, when set the best matching audio/subtitle rendition in a
+    /// master is made the default
+    pub accept_language: Option<String>,
+    /// the stream (e.g. `ppvsu-42`) this playlist belongs to, carried onto every rewritten url so
+    /// the proxy cache can group its entries for invalidation
+    pub stream: Option<String>,
+    /// expiry in hours for the urls of playlists the rewritten one points at (variants,
+    /// renditions), None signs them for as long as segments
+    pub playlist_expiry_hours: Option<i64>,
+    /// strip AES-128 `#EXT-X-KEY`s and have the proxy decrypt the segments instead, for players
+    /// without HLS-AES. carried onto variants so their media playlists get the same
+    pub decrypt: bool,
+}
+
+/// how long signed segment urls stay valid
+const SEGMENT_EXPIRY_HOURS: i64 = 12;
+
+/// stream ids end up in cache keys, so only short plain ones are accepted
+pub fn is_valid_stream_id(stream: &str) -> bool {
+    !stream.is_empty()
+        && stream.len() <= 64
+        && stream
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// a master playlist lists variants (other playlists) instead of media segments
+pub fn is_master_playlist(text: &str) -> bool {
+    text.lines()
+        .any(|line| line.trim_start().starts_with("#EXT-X-STREAM-INF"))
+}
+
+/// live playlists keep changing at the edge, vod ones are done and never change
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaylistKind {
+    Live,
+    Vod,
+}
+
+/// vod when it says so or has an end tag, anything else (masters included) is treated as live
+pub fn playlist_kind(text: &str) -> PlaylistKind {
+    let is_vod = text.lines().map(|line| line.trim()).any(|line| {
+        line == "#EXT-X-ENDLIST"
+            || line
+                .strip_prefix("#EXT-X-PLAYLIST-TYPE:")
+                .is_some_and(|t| t.trim().eq_ignore_ascii_case("VOD"))
+    });
+
+    if is_vod {
+        PlaylistKind::Vod
+    } else {
+        PlaylistKind::Live
+    }
+}
+
+/// picks the Cache-Control value for a playlist response
+pub fn playlist_cache_control<'a>(text: &str, live: &'a str, vod: &'a str) -> &'a str {
+    match playlist_kind(text) {
+        PlaylistKind::Live => live,
+        PlaylistKind::Vod => vod,
+    }
+}
+
+/// rewrites every uri in the playlist into a signed proxy url for the client.
+///
+/// the master/media classification is done on the fetched playlist itself, so a master that
+/// points at another master gets its variants tagged with the next depth and the chain is cut off
+/// at `MAX_PLAYLIST_DEPTH`
+pub fn rewrite_playlist(
+    text: &str,
+    target_url: &str,
+    client_id: &str,
+    signature_util: &SignatureUtil,
+    options: &PlaylistOptions,
+) -> AppResult<String> {
+    let mut output = String::new();
+    rewrite_playlist_into(
+        text,
+        target_url,
+        client_id,
+        signature_util,
+        options,
+        &mut output,
+    )?;
+    Ok(output)
+}
+
+/// `rewrite_playlist` into a buffer the caller already has (e.g. one from a pool), `output` is
+/// emptied first
+pub fn rewrite_playlist_into(
+    text: &str,
+    target_url: &str,
+    client_id: &str,
+    signature_util: &SignatureUtil,
+    options: &PlaylistOptions,
+    output: &mut String,
+) -> AppResult<()> {
+    output.clear();
+    let depth = options.depth;
+    let is_master = is_master_playlist(text);
+    if is_master && depth >= MAX_PLAYLIST_DEPTH {
+        error!(
+            "Master playlist nested {} levels deep, refusing to follow: {}",
+            depth, target_url
+        );
+        return Err(Error::BadRequest("Playlist nesting too deep".to_string()));
+    }
+
+    let preselected = options
+        .accept_language
+        .as_deref()
+        .filter(|_| is_master)
+        .and_then(|accept_language| preselect_language(text, accept_language));
+    let text = preselected.as_deref().unwrap_or(text);
+
+    // segments of a live playlist are tagged so the proxy only serves them from cache while
+    // they're fresh, a vod segment is good for as long as it's cached
+    let live_media = !is_master && playlist_kind(text) == PlaylistKind::Live;
+    // signed too, the stream index is what invalidation goes by
+    let stream = options
+        .stream
+        .as_deref()
+        .filter(|stream| is_valid_stream_id(stream));
+
+    // urls pointing at other playlists are marked so the proxy treats the response as one even
+    // when the upstream labels it wrong, and they get their own (usually shorter) expiry
+    let playlist_expiry = signature_util.expiry_in(
+        options
+            .playlist_expiry_hours
+            .unwrap_or(SEGMENT_EXPIRY_HOURS),
+    );
+    let segment_expiry = signature_util.expiry_in(SEGMENT_EXPIRY_HOURS);
+    // variants and renditions of a master get the next depth, signed so it can't be turned back.
+    // the playlist marker and decrypt are signed too, neither can be added to a segment url
+    let sign_playlist_url = |full_url: &str, depth: Option<u32>| {
+        let params: Vec<(&str, String)> = depth
+            .map(|depth| ("depth", depth.to_string()))
+            .into_iter()
+            .chain([("type", "playlist".to_string())])
+            .chain(options.decrypt.then(|| ("decrypt", "true".to_string())))
+            .chain(stream.map(|stream| ("stream", stream.to_string())))
+            .collect();
+        sign_proxy_url_with_params(
+            full_url,
+            "sports",
+            client_id,
+            playlist_expiry,
+            signature_util,
+            &params,
+        )
+    };
+    // media urls of a live playlist carry `live`, signed so a vod segment can't be served as a
+    // live one (or the other way round) by editing the url
+    let sign_media_url = |full_url: &str, key: Option<(&str, &[u8; AES_BLOCK_LEN])>, live: bool| {
+        let mut params: Vec<(&str, String)> = key
+            .map(|(key_url, iv)| key_params(key_url, iv).to_vec())
+            .unwrap_or_default();
+        if live {
+            params.push(("live", "true".to_string()));
+        }
+        if let Some(stream) = stream {
+            params.push(("stream", stream.to_string()));
+        }
+        sign_proxy_url_with_params(
+            full_url,
+            "sports",
+            client_id,
+            segment_expiry,
+            signature_util,
+            &params,
+        )
+    };
+
+    let base_url = url::Url::parse(target_url).map_err(|e| {
+        error!("Failed to parse base URL: {}", e);
+        Error::InternalServerErrorWithContext(format!("Invalid base URL: {}", e))
+    })?;
+
+    let base_path = format!(
+        "{}://{}{}",
+        base_url.scheme(),
+        base_url.host_str().unwrap_or(""),
+        &base_url.path()[..base_url.path().rfind('/').unwrap_or(0) + 1]
+    );
+
+    // trim comment lines that start with ## because it's some stupid fucking smiley face that
+    // says processed by indians in a hamster wheel LMAO
+    // rewritten lines go straight into one buffer instead of a Vec that gets joined, vod
+    // playlists can be huge. proxied urls are a lot longer than the originals so reserve extra
+    output.reserve(text.len() * 2);
+    let mut first = true;
+
+    // with decrypt on, the AES-128 key (resolved url and explicit iv) the next segments are under
+    // and where the segment sequence is at, segments without an iv use their sequence number.
+    // ll-hls parts are pieces of a segment that can't be decrypted on their own, so a playlist
+    // with them keeps its keys for the player
+    let decrypt = options.decrypt && !is_master && !has_parts(text);
+    let mut segment_key: Option<(String, Option<[u8; AES_BLOCK_LEN]>)> = None;
+    let mut sequence: u64 = 0;
+
+    for line in text.lines().filter(|line| !line.trim().starts_with("##")) {
+        let trimmed = line.trim();
+
+        if decrypt {
+            if let Some(first_sequence) = trimmed.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+                sequence = first_sequence.trim().parse().unwrap_or(0);
+            }
+            if let Some(key) = SegmentKey::parse(trimmed) {
+                let key_url = key
+                    .uri
+                    .as_deref()
+                    .and_then(|uri| resolve_uri(&base_path, uri));
+                // the player never sees a key we decrypt for, anything else stays for it to
+                // deal with
+                if let (KeyMethod::Aes128, Some(key_url)) = (&key.method, key_url) {
+                    segment_key = Some((key_url, key.iv));
+                    continue;
+                }
+                segment_key = None;
+            }
+        }
+
+        if !first {
+            output.push('\n');
+        }
+        first = false;
+
+        if trimmed.is_empty() {
+            output.push_str(line);
+            continue;
+        }
+
+        if trimmed.starts_with('#') {
+            // init segments, ll-hls parts, preload hints, renditions and keys are fetched by the
+            // client too, they have to go through the proxy
+            let rewritten = URI_TAGS
+                .iter()
+                .find(|(tag, _)| trimmed.starts_with(tag))
+                .and_then(|&(tag, kind)| {
+                    rewrite_uri_attribute(line, |uri| {
+                        resolve_uri(&base_path, uri).map(|full_url| {
+                            // an encrypted init segment always has an explicit iv
+                            let map_key = segment_key.as_ref().filter(|_| tag == "#EXT-X-MAP:");
+                            match (kind, map_key) {
+                                (UriKind::Playlist, _) => {
+                                    sign_playlist_url(&full_url, is_master.then_some(depth + 1))
+                                }
+                                (_, map_key) => sign_media_url(
+                                    &full_url,
+                                    map_key.and_then(|(key_url, iv)| {
+                                        iv.as_ref().map(|iv| (key_url.as_str(), iv))
+                                    }),
+                                    matches!(kind, UriKind::Media) && live_media,
+                                ),
+                            }
+                        })
+                    })
+                });
+            output.push_str(rewritten.as_deref().unwrap_or(line));
+            continue;
+        }
+
+        let segment_sequence = sequence;
+        sequence += 1;
+
+        let Some(full_url) = resolve_uri(&base_path, trimmed) else {
+            output.push_str(line);
+            continue;
+        };
+
+        // variants of a master are playlists themselves, carry the depth so nesting is capped
+        if is_master {
+            output.push_str(&sign_playlist_url(&full_url, Some(depth + 1)));
+        } else {
+            let iv = segment_key
+                .as_ref()
+                .map(|(_, iv)| iv.unwrap_or_else(|| sequence_iv(segment_sequence)));
+            let key = segment_key
+                .as_ref()
+                .zip(iv.as_ref())
+                .map(|((key_url, _), iv)| (key_url.as_str(), iv));
+            output.push_str(&sign_media_url(&full_url, key, live_media));
+        }
+    }
+
+    Ok(())
+}
+
+/// `rewrite_playlist` for the request path. signing every line of a big vod playlist can hold an
+/// async worker for tens of milliseconds, so playlists over `blocking_threshold` bytes are rewritten
+/// on the blocking pool instead. small ones (nearly every live playlist) stay inline since the
+/// copy and thread hop would cost more than the rewrite. a threshold of 0 keeps everything inline.
+/// the rewritten playlist ends up in `output`, which is handed back
+pub async fn rewrite_playlist_offloaded(
+    text: &str,
+    target_url: &str,
+    client_id: &str,
+    signature_util: &Arc<SignatureUtil>,
+    options: &PlaylistOptions,
+    blocking_threshold: usize,
+    mut output: PooledBuffer<String>,
+) -> AppResult<PooledBuffer<String>> {
+    if blocking_threshold == 0 || text.len() <= blocking_threshold {
+        rewrite_playlist_into(
+            text,
+            target_url,
+            client_id,
+            signature_util,
+            options,
+            &mut output,
+        )?;
+        return Ok(output);
+    }
+
+    let text = text.to_string();
+    let target_url = target_url.to_string();
+    let client_id = client_id.to_string();
+    let signature_util = signature_util.clone();
+    let options = options.clone();
+
+    tokio::task::spawn_blocking(move || {
+        rewrite_playlist_into(
+            &text,
+            &target_url,
+            &client_id,
+            &signature_util,
+            &options,
+            &mut output,
+        )
+        .map(|()| output)
+    })
+    .await
+    .map_err(|e| {
+        error!("Playlist rewrite task failed: {}", e);
+        Error::InternalServerErrorWithContext(format!("Playlist rewrite failed: {}", e))
+    })?
+}
+
+/// the params a segment url gets for the proxy to decrypt it, the key url is encoded like `url`.
+/// both are signed with the url
+fn key_params(key_url: &str, iv: &[u8; AES_BLOCK_LEN]) -> [(&'static str, String); 2] {
+    [
+        (
+            "key",
+            URL_SAFE
+                .encode(key_url.as_bytes())
+                .trim_end_matches('=')
+                .to_string(),
+        ),
+        ("iv", hex::encode(iv)),
+    ]
+}
+
+/// ll-hls playlists list parts (and hints at the next one) next to the whole segments
+fn has_parts(text: &str) -> bool {
+    text.lines()
+        .map(|line| line.trim_start())
+        .any(|line| line.starts_with("#EXT-X-PART:") || line.starts_with("#EXT-X-PRELOAD-HINT:"))
+}
+
+/// what the `URI` of a tag points at, decides which extra params its proxied url gets
+#[derive(Clone, Copy)]
+enum UriKind {
+    /// a segment or part, tagged live like the plain segment lines
+    Media,
+    /// another playlist
+    Playlist,
+    /// an encryption key, never tagged live or with a depth
+    Key,
+}
+
+/// tags that carry a `URI="..."` attribute the client will fetch. `METHOD=NONE` keys have no uri
+/// and are left as they are
+const URI_TAGS: &[(&str, UriKind)] = &[
+    // the fmp4/cmaf init segment, BYTERANGE and all the other attributes stay put
+    ("#EXT-X-MAP:", UriKind::Media),
+    ("#EXT-X-PART:", UriKind::Media),
+    ("#EXT-X-PRELOAD-HINT:", UriKind::Media),
+    ("#EXT-X-RENDITION-REPORT:", UriKind::Playlist),
+    ("#EXT-X-MEDIA:", UriKind::Playlist),
+    ("#EXT-X-KEY:", UriKind::Key),
+    ("#EXT-X-SESSION-KEY:", UriKind::Key),
+];
+
+/// resolves a playlist uri against the directory of the playlist it came from. `//host/path`
+/// gets the playlist's scheme and `/path` its host, the url join does both. anything that
+/// doesn't end up as http(s) (`skd://` fairplay keys, `data:` uris) can't be proxied, that's None
+fn resolve_uri(base_path: &str, uri: &str) -> Option<String> {
+    if uri.starts_with("http://") || uri.starts_with("https://") {
+        return Some(uri.to_string());
+    }
+
+    match url::Url::parse(base_path).and_then(|base| base.join(uri)) {
+        Ok(resolved) if matches!(resolved.scheme(), "http" | "https") => Some(resolved.to_string()),
+        Ok(_) => None,
+        Err(e) => {
+            error!("Failed to resolve: {} - {}", uri, e);
+            None
+        }
+    }
+}
+
+/// swaps the quoted `URI` value of a tag line, every other attribute is left exactly as it was
+fn rewrite_uri_attribute(
+    line: &str,
+    rewrite: impl FnOnce(&str) -> Option<String>,
+) -> Option<String> {
+    // only a whole attribute counts, so something like `X-URI=` is not picked up
+    let value_start = [":URI=\"", ",URI=\""]
+        .iter()
+        .filter_map(|needle| line.find(needle).map(|at| at + needle.len()))
+        .min()?;
+    let value_end = value_start + line[value_start..].find('"')?;
+
+    let rewritten = rewrite(&line[value_start..value_end])?;
+    Some(format!(
+        "{}{}{}",
+        &line[..value_start],
+        rewritten,
+        &line[value_end..]
+    ))
+}
+
+/// builds the signed `/api/v1/proxy` url for an upstream url
+pub fn sign_proxy_url(full_url: &str, client_id: &str, signature_util: &SignatureUtil) -> String {
+    let expiry = signature_util.expiry_in(SEGMENT_EXPIRY_HOURS);
+    sign_proxy_url_with(full_url, "sports", client_id, expiry, signature_util)
+}
+
+/// same as `sign_proxy_url` with the schema and expiry picked by the caller
+pub fn sign_proxy_url_with(
+    full_url: &str,
+    schema: &str,
+    client_id: &str,
+    expiry: i64,
+    signature_util: &SignatureUtil,
+) -> String {
+    sign_proxy_url_with_params(full_url, schema, client_id, expiry, signature_util, &[])
+}
+
+/// `sign_proxy_url_with` plus params that are signed along with the url (see `SIGNED_PARAMS`),
+/// their values have to be url safe already
+pub fn sign_proxy_url_with_params(
+    full_url: &str,
+    schema: &str,
+    client_id: &str,
+    expiry: i64,
+    signature_util: &SignatureUtil,
+    params: &[(&str, String)],
+) -> String {
+    let encoded = URL_SAFE
+        .encode(full_url.as_bytes())
+        .trim_end_matches('=')
+        .to_string();
+
+    // sign just the encoded URL (and the signed params) to avoid path mismatch issues
+    let payload = signed_payload(&encoded, |name| {
+        params
+            .iter()
+            .find(|(param, _)| *param == name)
+            .map(|(_, value)| value.as_str())
+    });
+    let signature = signature_util.generate_signature(client_id, expiry, &payload);
+
+    let mut url = format!(
+        "/api/v1/proxy?url={}&schema={}&sig={}&exp={}&client={}&kid={}",
+        encoded,
+        urlencoding::encode(schema),
+        signature,
+        expiry,
+        urlencoding::encode(client_id),
+        signature_util.key_id()
+    );
+    for (name, value) in params {
+        let _ = write!(url, "&{}={}", name, value);
+    }
+    url
+}
+
+/// languages from an Accept-Language header, best first. wildcards and q=0 are dropped
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.split(';');
+            let tag = pieces.next()?.trim().to_ascii_lowercase();
+            let quality = pieces
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+
+    // stable so equal weights keep the order the client sent them in
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    languages.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// marks the `#EXT-X-MEDIA` rendition that best matches the client's languages as
+/// `DEFAULT=YES,AUTOSELECT=YES` and the others in its group as `DEFAULT=NO`. groups without a
+/// match are left alone, None if nothing matched at all
+pub fn preselect_language(text: &str, accept_language: &str) -> Option<String> {
+    let preferred = parse_accept_language(accept_language);
+    if preferred.is_empty() {
+        return None;
+    }
+
+    let lines: Vec<&str> = text.lines().collect();
+
+    // best (score, line index) per TYPE + GROUP-ID, lower score is a better match
+    let mut best: Vec<((String, String), (usize, usize))> = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let Some(attrs) = media_attributes(line) else {
+            continue;
+        };
+        let (Some(kind), Some(group), Some(language)) = (
+            attribute(&attrs, "TYPE"),
+            attribute(&attrs, "GROUP-ID"),
+            attribute(&attrs, "LANGUAGE"),
+        ) else {
+            continue;
+        };
+        if kind != "AUDIO" && kind != "SUBTITLES" {
+            continue;
+        }
+        let Some(score) = language_score(&preferred, language) else {
+            continue;
+        };
+
+        let group_key = (kind.to_string(), group.to_string());
+        match best.iter_mut().find(|(key, _)| *key == group_key) {
+            Some((_, current)) if score < current.0 => *current = (score, index),
+            Some(_) => {}
+            None => best.push((group_key, (score, index))),
+        }
+    }
+
+    if best.is_empty() {
+        return None;
+    }
+
+    let rewritten: Vec<String> = lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| {
+            let Some(mut attrs) = media_attributes(line) else {
+                return line.to_string();
+            };
+            let group_key = (
+                attribute(&attrs, "TYPE").unwrap_or_default().to_string(),
+                attribute(&attrs, "GROUP-ID")
+                    .unwrap_or_default()
+                    .to_string(),
+            );
+            let Some((_, (_, chosen))) = best.iter().find(|(key, _)| *key == group_key) else {
+                return line.to_string();
+            };
+
+            if index == *chosen {
+                set_attribute(&mut attrs, "DEFAULT", "YES");
+                set_attribute(&mut attrs, "AUTOSELECT", "YES");
+            } else {
+                set_attribute(&mut attrs, "DEFAULT", "NO");
+            }
+
+            let joined: Vec<String> = attrs.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            format!("#EXT-X-MEDIA:{}", joined.join(","))
+        })
+        .collect();
+
+    Some(rewritten.join("\n"))
+}
+
+// 2 * position in the client's list, +1 if only the primary subtag matched (es vs es-MX)
+fn language_score(preferred: &[String], language: &str) -> Option<usize> {
+    let language = language.to_ascii_lowercase();
+    let primary = language.split('-').next().unwrap_or_default();
+
+    preferred.iter().enumerate().find_map(|(position, tag)| {
+        if *tag == language {
+            Some(position * 2)
+        } else if tag.split('-').next() == Some(primary) {
+            Some(position * 2 + 1)
+        } else {
+            None
+        }
+    })
+}
+
+// attribute list of an #EXT-X-MEDIA line, quoted values keep their quotes
+fn media_attributes(line: &str) -> Option<Vec<(String, String)>> {
+    let list = line.trim().strip_prefix("#EXT-X-MEDIA:")?;
+
+    let mut attrs = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in list.chars().chain(std::iter::once(',')) {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ',' if !quoted => {
+                if let Some((key, value)) = current.split_once('=') {
+                    attrs.push((key.trim().to_string(), value.trim().to_string()));
+                }
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+    }
+
+    Some(attrs)
+}
+
+fn attribute<'a>(attrs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attrs
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.trim_matches('"'))
+}
+
+fn set_attribute(attrs: &mut Vec<(String, String)>, name: &str, value: &str) {
+    match attrs.iter_mut().find(|(key, _)| key == name) {
+        Some((_, current)) => *current = value.to_string(),
+        None => attrs.push((name.to_string(), value.to_string())),
+    }
+}
@@ -1 +1,17 @@
+pub mod access_log_utils;
+pub mod buffer_pool_utils;
+pub mod clock_utils;
+pub mod cors_utils;
+pub mod decode_utils;
+pub mod encoding_utils;
+pub mod etag_utils;
+pub mod m3u8_utils;
+pub mod range_utils;
+pub mod request_id_utils;
+pub mod segment_decrypt_utils;
+pub mod segment_utils;
+pub mod sign_utils;
 pub mod signature_utils;
+pub mod stream_decrypt_utils;
+pub mod ttl_utils;
+pub mod upstream_utils;
@@ -0,0 +1,193 @@
+// client Range headers. normally the whole body is fetched and sliced here, but for big mp4 files
+// the range goes to the upstream instead and its 206 gets relayed as is
+use axum::http::{HeaderMap, StatusCode, header};
+
+/// a single `bytes=` range, ends are inclusive like in the header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteRange {
+    /// `bytes=500-999`
+    FromTo(u64, u64),
+    /// `bytes=500-`, everything from start on
+    From(u64),
+    /// `bytes=-500`, the last 500 bytes
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// None for anything that isn't a single well formed bytes range
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::parse_spec(value.trim().strip_prefix("bytes=")?)
+    }
+
+    /// every range in the header, one or more comma seperated ones. None when any of them is
+    /// malformed, the whole header gets ignored then
+    pub fn parse_list(value: &str) -> Option<Vec<Self>> {
+        value
+            .trim()
+            .strip_prefix("bytes=")?
+            .split(',')
+            .map(Self::parse_spec)
+            .collect()
+    }
+
+    // one `start-end` without the `bytes=` in front
+    fn parse_spec(spec: &str) -> Option<Self> {
+        let (start, end) = spec.trim().split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+
+        match (start.is_empty(), end.is_empty()) {
+            (true, true) => None,
+            (true, false) => end.parse().ok().map(Self::Suffix),
+            (false, true) => start.parse().ok().map(Self::From),
+            (false, false) => {
+                let start: u64 = start.parse().ok()?;
+                let end: u64 = end.parse().ok()?;
+                (start <= end).then_some(Self::FromTo(start, end))
+            }
+        }
+    }
+
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get(header::RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(Self::parse)
+    }
+
+    /// the inclusive (start, end) inside a body of `total` bytes, None when none of it is in there
+    pub fn resolve(&self, total: u64) -> Option<(u64, u64)> {
+        if total == 0 {
+            return None;
+        }
+        let last = total - 1;
+
+        match *self {
+            Self::FromTo(start, end) if start <= last => Some((start, end.min(last))),
+            Self::From(start) if start <= last => Some((start, last)),
+            Self::Suffix(len) if len > 0 => Some((total.saturating_sub(len), last)),
+            _ => None,
+        }
+    }
+
+    /// what gets sent upstream when the range is forwarded
+    pub fn header_value(&self) -> String {
+        match self {
+            Self::FromTo(start, end) => format!("bytes={}-{}", start, end),
+            Self::From(start) => format!("bytes={}-", start),
+            Self::Suffix(len) => format!("bytes=-{}", len),
+        }
+    }
+}
+
+pub fn content_range(start: u64, end: u64, total: u64) -> String {
+    format!("bytes {}-{}/{}", start, end, total)
+}
+
+/// more ranges than this in one header and the whole body goes back instead
+pub const MAX_RANGES: usize = 16;
+
+/// the resolved ranges of a multi range request sorted, with overlapping and touching ones merged.
+/// None when there are more than [`MAX_RANGES`] or they add up to more than the body, a client
+/// asking for the same bytes over and over just gets a 200 with the full body
+pub fn coalesce_ranges(ranges: &[(u64, u64)], total: u64) -> Option<Vec<(u64, u64)>> {
+    if ranges.len() > MAX_RANGES {
+        return None;
+    }
+    let requested: u64 = ranges.iter().map(|(start, end)| end - start + 1).sum();
+    if requested > total {
+        return None;
+    }
+
+    let mut sorted = ranges.to_vec();
+    sorted.sort_unstable();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(sorted.len());
+    for (start, end) in sorted {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                *last_end = (*last_end).max(end);
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    Some(merged)
+}
+
+/// a `multipart/byteranges` body, what a request for more than one range gets back
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultipartRanges {
+    pub boundary: String,
+    pub body: Vec<u8>,
+}
+
+impl MultipartRanges {
+    /// one part per (start, end) of `body`, each with its own Content-Type and Content-Range.
+    /// the ranges have to be resolved against `body` already
+    pub fn build(body: &[u8], ranges: &[(u64, u64)], content_type: &str, boundary: &str) -> Self {
+        let total = body.len() as u64;
+        let mut out = Vec::with_capacity(
+            ranges
+                .iter()
+                .map(|(start, end)| (end - start + 1) as usize + 128)
+                .sum(),
+        );
+
+        for (start, end) in ranges {
+            out.extend_from_slice(
+                format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                    boundary,
+                    content_type,
+                    content_range(*start, *end, total)
+                )
+                .as_bytes(),
+            );
+            out.extend_from_slice(&body[*start as usize..=*end as usize]);
+        }
+        out.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+        Self {
+            boundary: boundary.to_string(),
+            body: out,
+        }
+    }
+
+    /// a boundary that won't show up in video bytes by accident
+    pub fn new_boundary() -> String {
+        format!("reedstreams-{}", nanoid::nanoid!(24))
+    }
+
+    /// the top level Content-Type of the response
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+}
+
+/// what the upstream did with a forwarded range
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpstreamRangeReply {
+    /// a 206 (or a 416) that can go back to the client untouched
+    Relay {
+        status: StatusCode,
+        content_range: Option<String>,
+    },
+    /// it sent the whole body, so the range still has to be sliced here
+    Ignored,
+}
+
+impl UpstreamRangeReply {
+    pub fn from_response(status: StatusCode, headers: &HeaderMap) -> Self {
+        let content_range = headers
+            .get(header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+
+        match status {
+            StatusCode::PARTIAL_CONTENT | StatusCode::RANGE_NOT_SATISFIABLE => Self::Relay {
+                status,
+                content_range,
+            },
+            _ => Self::Ignored,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+// correlation id for a request, so every log line (and upstream request) made while serving it
+// can be tied back together
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use rand::Rng;
+use tracing::Instrument;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// ids sent by clients longer than this (or with anything but visible ascii) get replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// the id of the request being served on this task, None outside of a request (and in tasks
+/// spawned off of one, like prefetches)
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// 32 random hex chars
+pub fn generate() -> String {
+    format!("{:032x}", rand::rng().random::<u128>())
+}
+
+/// what the client sent if it's usable, a fresh one otherwise
+pub fn from_header(value: Option<&HeaderValue>) -> String {
+    value
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+        .map(|id| id.to_string())
+        .unwrap_or_else(generate)
+}
+
+/// forwards the current request's id to an upstream, unless the request already has one
+pub fn apply(request: &mut reqwest::Request) {
+    let Some(id) = current() else {
+        return;
+    };
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        request
+            .headers_mut()
+            .entry(REQUEST_ID_HEADER)
+            .or_insert(value);
+    }
+}
+
+/// reads or makes up the request's id, runs the rest of the request in a span carrying it and
+/// echoes it back on the response
+pub async fn track_request_id(request: Request, next: Next) -> Response {
+    let id = from_header(request.headers().get(REQUEST_ID_HEADER));
+    let span = tracing::info_span!("request", request_id = %id);
+
+    let mut response = REQUEST_ID
+        .scope(id.clone(), next.run(request).instrument(span))
+        .await;
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
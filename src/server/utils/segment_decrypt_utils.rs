@@ -0,0 +1,122 @@
+// AES-128 segment decryption for players that can't do HLS-AES themselves. the playlist gets its
+// EXT-X-KEY lines stripped and every segment url carries the key url and iv, the proxy then hands
+// out plaintext ts
+use aes::Aes128;
+use aes::cipher::{BlockDecryptMut, KeyIvInit, block_padding::Pkcs7};
+use tracing::error;
+
+use crate::server::error::{AppResult, Error};
+
+type Aes128CbcDec = cbc::Decryptor<Aes128>;
+
+/// AES-128 keys and ivs are both one block
+pub const AES_BLOCK_LEN: usize = 16;
+
+/// the `METHOD` of an `#EXT-X-KEY`
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyMethod {
+    None,
+    Aes128,
+    /// SAMPLE-AES and anything newer, those can't be undone by decrypting the whole segment
+    Other(String),
+}
+
+/// what an `#EXT-X-KEY` line says about the segments after it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentKey {
+    pub method: KeyMethod,
+    /// the `URI` as written in the playlist, not resolved yet
+    pub uri: Option<String>,
+    /// the explicit `IV`, segments use their media sequence number when there's none
+    pub iv: Option<[u8; AES_BLOCK_LEN]>,
+}
+
+impl SegmentKey {
+    /// None for anything that isn't an `#EXT-X-KEY` line
+    pub fn parse(line: &str) -> Option<Self> {
+        let attributes = line.trim().strip_prefix("#EXT-X-KEY:")?;
+
+        let mut method = KeyMethod::None;
+        let mut uri = None;
+        let mut iv = None;
+        for (name, value) in split_attributes(attributes) {
+            match name {
+                "METHOD" => {
+                    method = match value {
+                        "NONE" => KeyMethod::None,
+                        "AES-128" => KeyMethod::Aes128,
+                        other => KeyMethod::Other(other.to_string()),
+                    }
+                }
+                "URI" => uri = Some(value.to_string()),
+                "IV" => iv = parse_iv(value),
+                _ => {}
+            }
+        }
+
+        Some(Self { method, uri, iv })
+    }
+}
+
+// `NAME=value` pairs of a tag, quoted values can have commas in them
+fn split_attributes(attributes: &str) -> Vec<(&str, &str)> {
+    let mut pairs = Vec::new();
+    let mut rest = attributes;
+    while let Some((name, after)) = rest.split_once('=') {
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => match quoted.split_once('"') {
+                Some((value, next)) => (value, next),
+                None => (quoted, ""),
+            },
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        pairs.push((name.trim(), value));
+        rest = next.trim_start_matches(',');
+    }
+    pairs
+}
+
+/// a 128 bit hex iv, with or without the `0x` the playlists write it with. some write it short
+/// (`0x2`), those are zero padded on the left
+pub fn parse_iv(value: &str) -> Option<[u8; AES_BLOCK_LEN]> {
+    let value = value.trim();
+    let digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    if digits.is_empty() || digits.len() > AES_BLOCK_LEN * 2 {
+        return None;
+    }
+    hex::decode(format!("{:0>32}", digits))
+        .ok()?
+        .try_into()
+        .ok()
+}
+
+/// the iv a segment gets when its key has none, its media sequence number big endian
+pub fn sequence_iv(sequence: u64) -> [u8; AES_BLOCK_LEN] {
+    (sequence as u128).to_be_bytes()
+}
+
+/// AES-128-CBC with PKCS7 padding, how HLS encrypts whole segments
+pub fn decrypt_segment(
+    encrypted: &[u8],
+    key: &[u8],
+    iv: &[u8; AES_BLOCK_LEN],
+) -> AppResult<Vec<u8>> {
+    let decryptor = Aes128CbcDec::new_from_slices(key, iv).map_err(|_| {
+        error!("Segment key is {} bytes, not {}", key.len(), AES_BLOCK_LEN);
+        Error::InternalServerErrorWithContext("Invalid segment key".to_string())
+    })?;
+
+    let mut buffer = encrypted.to_vec();
+    let len = decryptor
+        .decrypt_padded_mut::<Pkcs7>(&mut buffer)
+        .map_err(|_| {
+            error!("Segment of {} bytes didn't decrypt", encrypted.len());
+            Error::InternalServerErrorWithContext("Segment decryption failed".to_string())
+        })?
+        .len();
+    buffer.truncate(len);
+    Ok(buffer)
+}
@@ -0,0 +1,78 @@
+// sanity checks for segment bodies before they go in the proxy cache. a cloudflare page or a cut
+// off body that gets cached is served to everyone until the ttl runs out
+use std::fmt;
+
+/// mpeg-ts packets are always this long and start with the sync byte
+pub const TS_PACKET_SIZE: usize = 188;
+pub const TS_SYNC_BYTE: u8 = 0x47;
+
+/// nothing real is smaller than a single ts packet
+pub const MIN_SEGMENT_BYTES: usize = TS_PACKET_SIZE;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SegmentProblem {
+    /// an error page came back with a success status
+    Html,
+    TooSmall(usize),
+    /// a ts packet at this offset doesn't start with the sync byte
+    BadTsSync(usize),
+    /// ts body that isn't a whole number of packets, usually a cut off download
+    TruncatedTs(usize),
+}
+
+impl fmt::Display for SegmentProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Html => write!(f, "body looks like an html page"),
+            Self::TooSmall(len) => write!(f, "body is only {} bytes", len),
+            Self::BadTsSync(offset) => write!(f, "missing ts sync byte at offset {}", offset),
+            Self::TruncatedTs(len) => write!(f, "ts body of {} bytes isn't whole packets", len),
+        }
+    }
+}
+
+fn looks_like_html(bytes: &[u8]) -> bool {
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    let head: Vec<u8> = bytes[start..]
+        .iter()
+        .take(16)
+        .map(|b| b.to_ascii_lowercase())
+        .collect();
+
+    head.starts_with(b"<!doctype") || head.starts_with(b"<html")
+}
+
+fn is_ts_url(url: &str) -> bool {
+    url.split(['?', '#'])
+        .next()
+        .unwrap_or(url)
+        .to_ascii_lowercase()
+        .ends_with(".ts")
+}
+
+/// `verify_ts_sync` also checks every packet of a `.ts` segment for the sync byte
+pub fn check_segment(url: &str, bytes: &[u8], verify_ts_sync: bool) -> Result<(), SegmentProblem> {
+    if looks_like_html(bytes) {
+        return Err(SegmentProblem::Html);
+    }
+    if bytes.len() < MIN_SEGMENT_BYTES {
+        return Err(SegmentProblem::TooSmall(bytes.len()));
+    }
+
+    if verify_ts_sync && is_ts_url(url) {
+        if let Some(offset) = (0..bytes.len())
+            .step_by(TS_PACKET_SIZE)
+            .find(|&offset| bytes[offset] != TS_SYNC_BYTE)
+        {
+            return Err(SegmentProblem::BadTsSync(offset));
+        }
+        if !bytes.len().is_multiple_of(TS_PACKET_SIZE) {
+            return Err(SegmentProblem::TruncatedTs(bytes.len()));
+        }
+    }
+
+    Ok(())
+}
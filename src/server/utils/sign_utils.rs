@@ -0,0 +1,64 @@
+// batch url signing for the sign endpoint, kept out of the controller so it can be tested
+use crate::server::{
+    dtos::sign_dto::{SignUrlsRequest, SignUrlsResponse, SignedUrlResult},
+    error::{AppResult, Error},
+    utils::{
+        m3u8_utils::sign_proxy_url_with, signature_utils::SignatureUtil,
+        upstream_utils::HostAllowlist,
+    },
+};
+
+/// most urls one request can sign, a front end prefetching a page of streams needs nowhere near this
+pub const MAX_BATCH_SIZE: usize = 50;
+
+/// signs every url for the client. bad entries (not http, host not allowed) are rejected one by
+/// one so the rest of the batch still comes back, only a bad schema or oversized batch fails it all
+pub fn sign_batch(
+    request: &SignUrlsRequest,
+    client_id: &str,
+    allowlist: &HostAllowlist,
+    signature_util: &SignatureUtil,
+    expiry: i64,
+) -> AppResult<SignUrlsResponse> {
+    if request.urls.is_empty() {
+        return Err(Error::BadRequest("No urls to sign".to_string()));
+    }
+    if request.urls.len() > MAX_BATCH_SIZE {
+        return Err(Error::BadRequest(format!(
+            "Too many urls, at most {} per request",
+            MAX_BATCH_SIZE
+        )));
+    }
+
+    let schema = request.schema.as_deref().unwrap_or("sports");
+    if schema.is_empty() || !schema.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(Error::BadRequest("Invalid schema".to_string()));
+    }
+
+    let urls = request
+        .urls
+        .iter()
+        .map(|url| {
+            let error = if !url.starts_with("http://") && !url.starts_with("https://") {
+                Some("Invalid URL format")
+            } else if !allowlist.is_allowed(url) {
+                Some("Host is not allowed")
+            } else {
+                None
+            };
+
+            SignedUrlResult {
+                url: url.clone(),
+                signed_url: error
+                    .is_none()
+                    .then(|| sign_proxy_url_with(url, schema, client_id, expiry, signature_util)),
+                error: error.map(|e| e.to_string()),
+            }
+        })
+        .collect();
+
+    Ok(SignUrlsResponse {
+        urls,
+        expires_at: expiry,
+    })
+}
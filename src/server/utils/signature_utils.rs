@@ -1,26 +1,107 @@
 use hex;
 use hmac::{Hmac, Mac};
-use sha2::Sha256;
-use std::time::{SystemTime, UNIX_EPOCH};
+use sha2::{Digest, Sha256};
+
+use crate::server::utils::clock_utils::{Clock, DynClock, SystemClock};
 
 type HmacSha256 = Hmac<Sha256>;
 
-pub struct SignatureUtil {
+/// query params that are signed together with `url`, in the order they're signed in. changing
+/// any of them breaks the signature, so a signed segment can't be pointed at another key or
+/// iv, a nested playlist can't have its depth reset to get around the nesting cap and a live
+/// segment can't be made to look like vod. `stream` is signed so nobody can file entries under
+/// (and get them invalidated with) a stream they don't belong to. `type` and `decrypt` change how
+/// the response is handled, so they can't be tacked onto a url either
+pub const SIGNED_PARAMS: &[&str] = &["key", "iv", "depth", "live", "stream", "type", "decrypt"];
+
+/// what a proxy url's signature covers, the encoded url followed by `&name=value` for every
+/// signed param the url has. urls without any of them are signed over just the url like before
+pub fn signed_payload<'a>(url: &str, param: impl Fn(&str) -> Option<&'a str>) -> String {
+    let mut payload = url.to_string();
+    for name in SIGNED_PARAMS {
+        if let Some(value) = param(name) {
+            payload.push('&');
+            payload.push_str(name);
+            payload.push('=');
+            payload.push_str(value);
+        }
+    }
+    payload
+}
+
+/// a secret and the short id urls signed with it carry as `kid`
+struct SigningKey {
+    id: String,
     secret: String,
 }
 
+impl SigningKey {
+    // the id comes from a hash of the secret so nobody has to hand them out, 8 hex chars are
+    // plenty to tell a handful of keys apart and say nothing about the secret
+    fn new(secret: String) -> Self {
+        let digest = Sha256::digest(format!("kid:{}", secret).as_bytes());
+        Self {
+            id: hex::encode(&digest[..4]),
+            secret,
+        }
+    }
+}
+
+pub struct SignatureUtil {
+    key: SigningKey,
+    /// older secrets that are still accepted but never signed with, for when two fleets are
+    /// live during a deploy or the secret was rotated and urls minted with the old one need to
+    /// keep working
+    fallback_keys: Vec<SigningKey>,
+    /// seconds past `exp` a signature is still accepted, so a viewer who started early isn't cut
+    /// off mid event. 0 means expired is expired
+    expiry_grace_seconds: i64,
+    clock: DynClock,
+}
+
 impl SignatureUtil {
     pub fn new(secret: String) -> Self {
-        Self { secret }
+        Self::with_fallback_secrets(secret, Vec::new())
+    }
+
+    /// `secret` is the primary key everything gets signed with, `fallback_secrets` are the
+    /// previous ones
+    pub fn with_fallback_secrets(secret: String, fallback_secrets: Vec<String>) -> Self {
+        Self {
+            key: SigningKey::new(secret),
+            fallback_keys: fallback_secrets.into_iter().map(SigningKey::new).collect(),
+            expiry_grace_seconds: 0,
+            clock: SystemClock::shared(),
+        }
+    }
+
+    /// what expiry is checked against, the system clock unless a test swaps it
+    pub fn with_clock(mut self, clock: DynClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_expiry_grace(mut self, grace_seconds: i64) -> Self {
+        self.expiry_grace_seconds = grace_seconds.max(0);
+        self
     }
 
     /// sig is based on: client_id + expiry + url + secret
     /// client_id is a hash of IP + User-Agent
     pub fn generate_signature(&self, client_id: &str, expiry: i64, url: &str) -> String {
+        Self::sign_with(&self.key.secret, client_id, expiry, url)
+    }
+
+    /// the `kid` that goes next to signatures from `generate_signature`
+    pub fn key_id(&self) -> &str {
+        &self.key.id
+    }
+
+    fn sign_with(secret: &str, client_id: &str, expiry: i64, url: &str) -> String {
         let message = format!("{}{}{}", client_id, expiry, url);
 
-        let mut mac = HmacSha256::new_from_slice(self.secret.as_bytes())
-            .expect("HMAC can take key of any size");
+        let mut mac =
+            HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
 
         mac.update(message.as_bytes());
 
@@ -30,6 +111,7 @@ impl SignatureUtil {
         hex::encode(code_bytes)
     }
 
+    /// checks against every key, for urls that don't say which one signed them
     pub fn verify_signature(
         &self,
         client_id: &str,
@@ -37,33 +119,51 @@ impl SignatureUtil {
         url: &str,
         signature: &str,
     ) -> bool {
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        self.verify_signature_with_kid(client_id, expiry, url, signature, None)
+    }
 
-        if current_time > expiry {
+    /// with a `kid` only the key it names is tried, a kid we don't know (a key that was
+    /// dropped) fails. without one every key is tried like `verify_signature`
+    pub fn verify_signature_with_kid(
+        &self,
+        client_id: &str,
+        expiry: i64,
+        url: &str,
+        signature: &str,
+        kid: Option<&str>,
+    ) -> bool {
+        let current_time = self.clock.now();
+
+        if current_time > expiry.saturating_add(self.expiry_grace_seconds) {
             return false;
         }
 
-        // see if we can regenerate the signature, if we can then it's valid
-        let expected_signature = self.generate_signature(client_id, expiry, url);
+        // see if we can regenerate the signature with any of the candidate secrets, if we can
+        // then it's valid. every candidate gets checked so timing doesn't say which one matched
+        std::iter::once(&self.key)
+            .chain(self.fallback_keys.iter())
+            .filter(|key| kid.is_none_or(|kid| key.id == kid))
+            .fold(false, |valid, key| {
+                let expected_signature = Self::sign_with(&key.secret, client_id, expiry, url);
+                Self::constant_time_eq(signature, &expected_signature) | valid
+            })
+    }
 
-        signature.len() == expected_signature.len()
-            && signature
-                .as_bytes()
+    fn constant_time_eq(a: &str, b: &str) -> bool {
+        a.len() == b.len()
+            && a.as_bytes()
                 .iter()
-                .zip(expected_signature.as_bytes().iter())
+                .zip(b.as_bytes().iter())
                 .fold(0, |acc, (a, b)| acc | (a ^ b))
                 == 0
     }
 
     pub fn generate_expiry(hours: i64) -> i64 {
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        SystemClock.now() + (hours * 3600)
+    }
 
-        current_time + (hours * 3600)
+    /// `generate_expiry` off this util's clock
+    pub fn expiry_in(&self, hours: i64) -> i64 {
+        self.clock.now() + (hours * 3600)
     }
 }
@@ -0,0 +1,418 @@
+// the ppvs.su video link decryption, split out of the service so the pipeline can be tested
+use std::fmt;
+use std::sync::Arc;
+
+use base64::Engine;
+use chacha20::ChaCha20;
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use tracing::{debug, info, warn};
+
+use crate::server::error::{AppResult, Error};
+
+pub type DynStreamDecryptor = Arc<dyn StreamDecryptor + Send + Sync>;
+
+/// turns what a provider's fetch endpoint sends back into the playable url. every provider (and
+/// every time one changes its scheme) gets its own
+pub trait StreamDecryptor {
+    /// `key_header` is the header value the provider sends the key in, `island` for ppvs.su
+    fn decrypt(&self, blob: &[u8], key_header: &str) -> AppResult<String>;
+}
+
+/// the 2024 ppvs.su pipeline, protobuf → ROT-n → base64 → chacha20, see `decrypt_stream_url`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ppvsu2024Decryptor {
+    variants: Vec<DecryptVariant>,
+    extensions: Vec<String>,
+}
+
+impl Ppvsu2024Decryptor {
+    pub fn new(variants: Vec<DecryptVariant>) -> Self {
+        Self {
+            variants,
+            extensions: default_stream_extensions(),
+        }
+    }
+
+    /// what the decrypted url may end in, see `parse_stream_extensions`
+    pub fn with_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+}
+
+impl Default for Ppvsu2024Decryptor {
+    fn default() -> Self {
+        Self::new(vec![DecryptVariant::CURRENT])
+    }
+}
+
+impl StreamDecryptor for Ppvsu2024Decryptor {
+    fn decrypt(&self, blob: &[u8], key_header: &str) -> AppResult<String> {
+        let (url, variant) =
+            decrypt_stream_url_with(blob, key_header, &self.variants, &self.extensions)?;
+        debug!("ppvsu 2024 pipeline decrypted with variant {}", variant);
+        Ok(url)
+    }
+}
+
+/// one way of running the pipeline. upstream has changed these before (the 2024 update moved the
+/// counter to 1), so a list of them is tried in order instead of a single hardcoded one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecryptVariant {
+    /// ROT-n applied to the custom charset before base64
+    pub rotation: u32,
+    /// chacha20 block counter the keystream starts at
+    pub counter: u64,
+}
+
+impl DecryptVariant {
+    /// the current pipeline, ROT-71 and counter=1
+    pub const CURRENT: Self = Self {
+        rotation: 71,
+        counter: 1,
+    };
+
+    /// parses `rotation:counter` pairs like "71:1,71:0". bad entries are skipped and the current
+    /// pipeline is used if nothing is left
+    pub fn parse_list(list: &str) -> Vec<Self> {
+        let variants: Vec<Self> = list
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .filter_map(|entry| {
+                let parsed = entry.split_once(':').and_then(|(rotation, counter)| {
+                    Some(Self {
+                        rotation: rotation.trim().parse().ok().filter(|r| *r < 94)?,
+                        counter: counter.trim().parse().ok()?,
+                    })
+                });
+
+                if parsed.is_none() {
+                    warn!("invalid decrypt variant '{}', skipping", entry);
+                }
+                parsed
+            })
+            .collect();
+
+        if variants.is_empty() {
+            vec![Self::CURRENT]
+        } else {
+            variants
+        }
+    }
+}
+
+impl fmt::Display for DecryptVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rot{}/counter{}", self.rotation, self.counter)
+    }
+}
+
+/// what a decrypted stream url can end in, hls first then dash and bare ts
+pub const DEFAULT_STREAM_EXTENSIONS: &[&str] = &[".m3u8", ".mpd", ".ts"];
+
+/// comma seperated extensions like ".m3u8,.mpd", the leading dot is optional. falls back to
+/// `DEFAULT_STREAM_EXTENSIONS` when nothing is left
+pub fn parse_stream_extensions(list: &str) -> Vec<String> {
+    let extensions: Vec<String> = list
+        .split(',')
+        .map(|s| s.trim().trim_start_matches('.').to_ascii_lowercase())
+        .filter(|s| !s.is_empty())
+        .map(|s| format!(".{}", s))
+        .collect();
+
+    if extensions.is_empty() {
+        default_stream_extensions()
+    } else {
+        extensions
+    }
+}
+
+fn default_stream_extensions() -> Vec<String> {
+    DEFAULT_STREAM_EXTENSIONS
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+/// ROT-n cipher over printable ASCII, 33 ('!') to 126 ('~') = 94 characters.
+/// with n=71 this transforms the custom charset to valid standard base64
+pub fn rot_decode(input: &str, rotation: u32) -> String {
+    input
+        .chars()
+        .map(|c| {
+            let code = c as u32;
+            if (33..=126).contains(&code) {
+                char::from_u32(33 + ((code - 33) + rotation) % 94).unwrap_or(c)
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Parse protobuf message with 2 length-delimited fields
+/// 1 (0x0a): Custom charset encoded ciphertext (requires ROT-71 → base64 → ChaCha20)
+/// 2 (0x12): stream name
+///
+/// these are upstream bytes so nothing is trusted, a truncated field or a varint longer than 64
+/// bits is a BadRequest instead of being skipped. fields with other numbers are skipped
+pub fn parse_protobuf(buffer: &[u8]) -> AppResult<(String, Option<String>)> {
+    let mut offset = 0;
+    let mut field1: Option<String> = None;
+    let mut field2: Option<String> = None;
+
+    while offset < buffer.len() {
+        let key = read_varint(buffer, &mut offset)?;
+
+        // the low 3 bits are the wire type, the rest is the field number
+        match key & 0x07 {
+            // varint
+            0 => {
+                read_varint(buffer, &mut offset)?;
+            }
+            // 64 bit
+            1 => {
+                take(buffer, &mut offset, 8)?;
+            }
+            // length delimited
+            2 => {
+                let length = read_varint(buffer, &mut offset)?;
+                let length = usize::try_from(length).map_err(|_| {
+                    Error::BadRequest(format!("protobuf field length {} is too big", length))
+                })?;
+                let field_data = take(buffer, &mut offset, length)?;
+
+                match key {
+                    0x0a => field1 = Some(String::from_utf8_lossy(field_data).to_string()),
+                    0x12 => field2 = Some(String::from_utf8_lossy(field_data).to_string()),
+                    _ => debug!("skipping protobuf field {}", key >> 3),
+                }
+            }
+            // 32 bit
+            5 => {
+                take(buffer, &mut offset, 4)?;
+            }
+            wire_type => {
+                return Err(Error::BadRequest(format!(
+                    "unsupported protobuf wire type {} at byte {}",
+                    wire_type, offset
+                )));
+            }
+        }
+    }
+
+    field1.map(|f1| (f1, field2)).ok_or_else(|| {
+        Error::InternalServerErrorWithContext("failed to extract field1 from protobuf".to_string())
+    })
+}
+
+// a base 128 varint, 7 bits per byte with the high bit set on every byte but the last
+fn read_varint(buffer: &[u8], offset: &mut usize) -> AppResult<u64> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = *buffer.get(*offset).ok_or_else(|| {
+            Error::BadRequest(format!("protobuf varint cut off at byte {}", offset))
+        })?;
+        *offset += 1;
+
+        if shift >= 64 {
+            return Err(Error::BadRequest(
+                "protobuf varint is longer than 64 bits".to_string(),
+            ));
+        }
+        value |= u64::from(byte & 0x7f) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+// the next `length` bytes, an error if there aren't that many left
+fn take<'a>(buffer: &'a [u8], offset: &mut usize, length: usize) -> AppResult<&'a [u8]> {
+    let end = offset
+        .checked_add(length)
+        .filter(|end| *end <= buffer.len())
+        .ok_or_else(|| {
+            Error::BadRequest(format!(
+                "protobuf field of {} bytes at byte {} runs past the end ({} bytes)",
+                length,
+                offset,
+                buffer.len()
+            ))
+        })?;
+
+    let data = &buffer[*offset..end];
+    *offset = end;
+    Ok(data)
+}
+
+/// ChaCha20 decryption
+/// Key: full `island` header (32 bytes UTF-8)
+/// Nonce: first 12 bytes of decoded ciphertext
+/// Counter: block the keystream starts at, currently 1 not 0 (critical for correct decryption)
+///
+/// gives back the raw plaintext, the url in it is found with `extract_stream_url`
+pub fn chacha20_decrypt(decoded_data: &[u8], key: &str, counter: u64) -> AppResult<Vec<u8>> {
+    if decoded_data.len() < 12 {
+        return Err(Error::InternalServerErrorWithContext(
+            "decoded data too short to contain nonce".to_string(),
+        ));
+    }
+
+    let key_bytes = key.as_bytes();
+    if key_bytes.len() != 32 {
+        return Err(Error::InternalServerErrorWithContext(format!(
+            "key must be 32 bytes, got {}",
+            key_bytes.len()
+        )));
+    }
+
+    // First 12 bytes are the nonce, rest is ciphertext
+    let nonce = &decoded_data[..12];
+    let ciphertext = &decoded_data[12..];
+
+    // Create cipher with 32-byte key and 12-byte nonce
+    let mut cipher = ChaCha20::new(key_bytes.into(), nonce.into());
+
+    // Seek to the starting block (64 bytes each), a counter from the config can be past the end
+    // of the keystream
+    let position = counter.checked_mul(64).ok_or_else(|| {
+        Error::InternalServerErrorWithContext(format!("counter {} is out of range", counter))
+    })?;
+    cipher.try_seek(position).map_err(|_| {
+        Error::InternalServerErrorWithContext(format!("counter {} is out of range", counter))
+    })?;
+
+    let mut buffer = ciphertext.to_vec();
+    cipher.apply_keystream(&mut buffer);
+
+    Ok(buffer)
+}
+
+/// the stream url in decrypted plaintext. it starts at `http` and ends at the first of
+/// `extensions` (tried in order) that gives a valid url, a query string right after the extension
+/// is kept. the plaintext can have trailing garbage so anything else after it is dropped
+pub fn extract_stream_url(plaintext: &[u8], extensions: &[String]) -> AppResult<String> {
+    let text = String::from_utf8_lossy(plaintext);
+    // same byte offsets as `text`, only ascii letters change
+    let lowercase = text.to_ascii_lowercase();
+
+    let start = lowercase.find("http").ok_or_else(|| {
+        Error::InternalServerErrorWithContext("no url in decrypted data".to_string())
+    })?;
+
+    extensions
+        .iter()
+        .flat_map(|extension| {
+            let extension = extension.to_ascii_lowercase();
+            lowercase[start..]
+                .match_indices(extension.as_str())
+                .map(|(index, _)| start + index + extension.len())
+                .collect::<Vec<_>>()
+        })
+        // `.ts` shouldn't match the start of `.tsx` or a host like `cdn.tstream.net`
+        .filter(|end| {
+            text[*end..]
+                .chars()
+                .next()
+                .is_none_or(|c| !c.is_ascii_alphanumeric())
+        })
+        .map(|end| {
+            let query = if text[end..].starts_with('?') {
+                text[end..]
+                    .find(|c: char| !c.is_ascii_graphic())
+                    .unwrap_or(text.len() - end)
+            } else {
+                0
+            };
+            &text[start..end + query]
+        })
+        .find(|candidate| is_valid_stream_url(candidate))
+        .map(|url| url.to_string())
+        .ok_or_else(|| {
+            Error::InternalServerErrorWithContext(format!(
+                "no url ending in one of {:?} in decrypted data",
+                extensions
+            ))
+        })
+}
+
+/// a wrong variant still "decrypts" into garbage, this is how the right one is told apart
+pub fn is_valid_stream_url(candidate: &str) -> bool {
+    url::Url::parse(candidate)
+        .map(|url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some())
+        .unwrap_or(false)
+}
+
+/// New decryption pipeline (2024 update)
+/// Parse protobuf → field1 (custom charset encoded)
+/// ROT-n decode field1 → standard base64
+/// Base64 decode → [nonce (12 bytes) || ciphertext]
+/// ChaCha20 decrypt with island header as key
+///
+/// every variant is tried in order and the first one that gives a valid url wins
+pub fn decrypt_stream_url(
+    encrypted_blob: &[u8],
+    island_header: &str,
+    variants: &[DecryptVariant],
+) -> AppResult<(String, DecryptVariant)> {
+    decrypt_stream_url_with(
+        encrypted_blob,
+        island_header,
+        variants,
+        &default_stream_extensions(),
+    )
+}
+
+/// same as `decrypt_stream_url` with the url extensions picked by the caller
+pub fn decrypt_stream_url_with(
+    encrypted_blob: &[u8],
+    island_header: &str,
+    variants: &[DecryptVariant],
+    extensions: &[String],
+) -> AppResult<(String, DecryptVariant)> {
+    // Step 1: Parse protobuf to extract field1 (encoded ciphertext)
+    let (encoded_ciphertext, _stream_name) = parse_protobuf(encrypted_blob)?;
+
+    for variant in variants {
+        // Step 2: ROT-n transform to get valid standard base64
+        let base64_ciphertext = rot_decode(&encoded_ciphertext, variant.rotation);
+
+        // Step 3: Base64 decode to get binary [nonce || ciphertext]
+        let decoded_data =
+            match base64::engine::general_purpose::STANDARD.decode(&base64_ciphertext) {
+                Ok(decoded_data) => decoded_data,
+                Err(e) => {
+                    debug!("variant {} failed to base64 decode: {}", variant, e);
+                    continue;
+                }
+            };
+
+        // Step 4: ChaCha20 decrypt (nonce is first 12 bytes)
+        let plaintext = match chacha20_decrypt(&decoded_data, island_header, variant.counter) {
+            Ok(plaintext) => plaintext,
+            Err(e) => {
+                debug!("variant {} failed to decrypt: {}", variant, e);
+                continue;
+            }
+        };
+
+        match extract_stream_url(&plaintext, extensions) {
+            Ok(decrypted_url) => {
+                info!("decrypted video link with variant {}", variant);
+                return Ok((decrypted_url, *variant));
+            }
+            Err(e) => debug!("variant {} did not produce a valid url: {}", variant, e),
+        }
+    }
+
+    Err(Error::InternalServerErrorWithContext(format!(
+        "none of the {} decrypt variants produced a valid url",
+        variants.len()
+    )))
+}
@@ -0,0 +1,62 @@
+// cache ttls with some randomness on them. everything cached in the same burst (a popular
+// stream's playlist and segments, the whole games list) would otherwise expire in the same
+// second and get refetched all at once
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// what the caches use unless told otherwise
+pub const DEFAULT_TTL_JITTER_PERCENT: u64 = 10;
+/// anything above this would have some keys expire right after being cached
+pub const MAX_TTL_JITTER_PERCENT: u64 = 50;
+
+/// moves a ttl by up to ±`percent` of it, 0 leaves ttls alone
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TtlJitter {
+    percent: u64,
+}
+
+impl TtlJitter {
+    /// `percent` is clamped to `MAX_TTL_JITTER_PERCENT`
+    pub fn new(percent: u64) -> Self {
+        Self {
+            percent: percent.min(MAX_TTL_JITTER_PERCENT),
+        }
+    }
+
+    pub fn percent(&self) -> u64 {
+        self.percent
+    }
+
+    /// the shortest and longest ttl `apply` can give for `base`
+    pub fn bounds(&self, base: u64) -> (u64, u64) {
+        let spread = base.saturating_mul(self.percent) / 100;
+        (base - spread, base.saturating_add(spread))
+    }
+
+    /// a random ttl within `bounds`, a 0 ttl (usually meaning off) stays 0
+    pub fn apply(&self, base: u64) -> u64 {
+        let (min, max) = self.bounds(base);
+        if min == max {
+            return base;
+        }
+        rand::rng().random_range(min..=max)
+    }
+
+    /// same as `apply` but always the same ttl for the same seed, for when the ttl is worked out
+    /// again on every read (like how long a fetch stays fresh) and every read has to agree
+    pub fn apply_seeded(&self, base: u64, seed: u64) -> u64 {
+        let (min, max) = self.bounds(base);
+        if min == max {
+            return base;
+        }
+        StdRng::seed_from_u64(seed).random_range(min..=max)
+    }
+
+    /// true once something stored at `stored_at` is older than its jittered `base` seconds. the
+    /// jitter is seeded with `stored_at` so every check (on every node) agrees on when it goes
+    /// stale
+    pub fn is_stale(&self, stored_at: i64, now: i64, base: u64) -> bool {
+        let ttl = self.apply_seeded(base, stored_at as u64);
+        now - stored_at > ttl as i64
+    }
+}
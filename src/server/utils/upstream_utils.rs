@@ -0,0 +1,723 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use futures::StreamExt;
+use rand::Rng;
+use reqwest::header::{self, CONNECTION, HeaderMap, HeaderName};
+use tracing::{info, warn};
+
+use crate::server::error::{AppResult, Error};
+
+/// what kind of upstream a proxied url is, picks the headers it's fetched with and how the
+/// response is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Schema {
+    #[default]
+    Sports,
+    Captions,
+}
+
+impl Schema {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sports => "sports",
+            Self::Captions => "captions",
+        }
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Schema {
+    type Err = Error;
+
+    /// exact names only, `Sports` or ` sports` are as unknown as anything else
+    fn from_str(schema: &str) -> Result<Self, Self::Err> {
+        match schema {
+            "sports" => Ok(Self::Sports),
+            "captions" => Ok(Self::Captions),
+            _ => Err(Error::BadRequest("Unknown schema".to_string())),
+        }
+    }
+}
+
+/// how outbound connections to an upstream get reused
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UpstreamConnection {
+    /// normal pooled keep-alive connections
+    #[default]
+    KeepAlive,
+    /// sends `Connection: close` so the pooled connection is dropped after the response, some
+    /// origins mishandle reused connections and this is nicer than turning pooling off globally
+    Close,
+}
+
+impl UpstreamConnection {
+    /// `close_schemas` is the comma seperated list from the config
+    pub fn for_schema(schema: &str, close_schemas: &str) -> Self {
+        if close_schemas.split(',').any(|s| s.trim() == schema) {
+            Self::Close
+        } else {
+            Self::KeepAlive
+        }
+    }
+
+    pub fn header_value(&self) -> &'static str {
+        match self {
+            Self::KeepAlive => "keep-alive",
+            Self::Close => "close",
+        }
+    }
+
+    pub fn apply(&self, request_builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request_builder.header(CONNECTION, self.header_value())
+    }
+}
+
+/// per request timeout budgets for upstream fetches. playlists are tiny and a live player stalls
+/// while it waits on one so they get a short budget, segments and blobs can be big and get longer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpstreamTimeouts {
+    pub playlist: Duration,
+    pub segment: Duration,
+}
+
+impl Default for UpstreamTimeouts {
+    fn default() -> Self {
+        Self {
+            playlist: Duration::from_secs(8),
+            segment: Duration::from_secs(60),
+        }
+    }
+}
+
+impl UpstreamTimeouts {
+    pub fn new(playlist: Duration, segment: Duration) -> Self {
+        Self { playlist, segment }
+    }
+
+    /// only looks at the path, the query string on these is usually a token
+    pub fn is_playlist_url(url: &str) -> bool {
+        let path = url
+            .split(['?', '#'])
+            .next()
+            .unwrap_or(url)
+            .to_ascii_lowercase();
+        path.ends_with(".m3u8") || path.ends_with(".m3u")
+    }
+
+    pub fn for_url(&self, url: &str) -> Duration {
+        if Self::is_playlist_url(url) {
+            self.playlist
+        } else {
+            self.segment
+        }
+    }
+
+    /// overrides the client wide timeout for this one request
+    pub fn apply(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+        url: &str,
+    ) -> reqwest::RequestBuilder {
+        request_builder.timeout(self.for_url(url))
+    }
+}
+
+/// retries for an upstream GET that failed in a way that might not happen again, no response at
+/// all (connection error, timeout) or a 502/503/504. 4xx and the other 5xx come back straight away
+#[derive(Debug, Clone, Copy)]
+pub struct UpstreamRetry {
+    /// retries after the first attempt, 0 turns them off
+    pub max_retries: u32,
+    /// wait before the first retry, doubled for each one after that with up to half of it added
+    /// on top as jitter so a flapping origin doesn't get every viewer back at once
+    pub base_delay: Duration,
+}
+
+impl Default for UpstreamRetry {
+    fn default() -> Self {
+        Self::new(0, Duration::from_millis(200))
+    }
+}
+
+impl UpstreamRetry {
+    pub fn new(max_retries: u32, base_delay: Duration) -> Self {
+        Self {
+            max_retries,
+            base_delay,
+        }
+    }
+
+    pub fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+        matches!(status.as_u16(), 502..=504)
+    }
+
+    pub fn is_retryable_error(error: &reqwest::Error) -> bool {
+        error.is_connect() || error.is_timeout()
+    }
+
+    /// how long to wait before retry number `attempt` (starting at 1)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16));
+        let jitter = rand::rng().random_range(0..=backoff.as_millis() as u64 / 2);
+        backoff + Duration::from_millis(jitter)
+    }
+
+    /// sends the request through `send` (so the attempt log and rate cap see every try) and
+    /// sends it again while it keeps failing in a retryable way. whatever the last try got back
+    /// is returned, a request that can't be cloned (a streamed body) only gets the one try
+    pub async fn send<F, Fut>(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+        mut send: F,
+    ) -> reqwest::Result<reqwest::Response>
+    where
+        F: FnMut(reqwest::RequestBuilder) -> Fut,
+        Fut: Future<Output = reqwest::Result<reqwest::Response>>,
+    {
+        let mut attempt = 0;
+        let mut request_builder = request_builder;
+
+        loop {
+            let retry = request_builder
+                .try_clone()
+                .filter(|_| attempt < self.max_retries);
+            let result = send(request_builder).await;
+
+            let problem = match &result {
+                Ok(response) if Self::is_retryable_status(response.status()) => {
+                    response.status().to_string()
+                }
+                Err(e) if Self::is_retryable_error(e) => e.to_string(),
+                _ => return result,
+            };
+            let Some(retry) = retry else {
+                return result;
+            };
+
+            attempt += 1;
+            let delay = self.delay(attempt);
+            warn!(
+                "Upstream request failed ({}), retrying {}/{} in {:?}",
+                problem, attempt, self.max_retries, delay
+            );
+            drop(result);
+            tokio::time::sleep(delay).await;
+            request_builder = retry;
+        }
+    }
+}
+
+/// client headers that never get forwarded no matter what the config says. hop-by-hop ones only
+/// mean something on the client's connection, credentials and the client's ip would leak. range
+/// and conditional ones get a 206 or 304 back, the proxy caches and ranges whole bodies itself
+/// and would store the partial one as the full segment
+const NEVER_FORWARDED: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "content-length",
+    "authorization",
+    "cookie",
+    "x-forwarded-for",
+    "x-real-ip",
+    "forwarded",
+    "cf-connecting-ip",
+    "range",
+    "if-range",
+    "if-match",
+    "if-none-match",
+    "if-modified-since",
+    "if-unmodified-since",
+];
+
+/// client headers that get passed through to the upstream, per schema
+#[derive(Debug, Clone, Default)]
+pub struct ForwardedHeaders {
+    by_schema: HashMap<String, Vec<HeaderName>>,
+}
+
+impl ForwardedHeaders {
+    /// comma seperated `schema:header` entries, e.g. `sports:accept-language,captions:accept`.
+    /// anything malformed or on the never forwarded list is logged and skipped
+    pub fn parse_list(entries: &str) -> Self {
+        let mut by_schema: HashMap<String, Vec<HeaderName>> = HashMap::new();
+
+        for entry in entries
+            .split(',')
+            .map(|e| e.trim())
+            .filter(|e| !e.is_empty())
+        {
+            let Some((schema, name)) = entry.split_once(':') else {
+                warn!(
+                    "Ignoring forwarded header entry without a schema: {}",
+                    entry
+                );
+                continue;
+            };
+            let Ok(name) = HeaderName::from_bytes(name.trim().as_bytes()) else {
+                warn!("Ignoring invalid forwarded header name: {}", entry);
+                continue;
+            };
+            if NEVER_FORWARDED.contains(&name.as_str()) {
+                warn!("Refusing to forward {} upstream", name);
+                continue;
+            }
+            by_schema
+                .entry(schema.trim().to_string())
+                .or_default()
+                .push(name);
+        }
+
+        Self { by_schema }
+    }
+
+    pub fn allowed(&self, schema: &str) -> &[HeaderName] {
+        self.by_schema
+            .get(schema)
+            .map(|v| v.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// adds the allowlisted client headers that the schema headers didn't already set, so the
+    /// fixed schema headers win on conflict
+    pub fn apply(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+        schema: &str,
+        client_headers: &HeaderMap,
+    ) -> reqwest::RequestBuilder {
+        let allowed = self.allowed(schema);
+        if allowed.is_empty() {
+            return request_builder;
+        }
+
+        // peek at what's been set so far, only fails for streaming bodies which a GET never has
+        let existing = match request_builder.try_clone().map(|b| b.build()) {
+            Some(Ok(request)) => request.headers().clone(),
+            _ => return request_builder,
+        };
+
+        allowed
+            .iter()
+            .filter(|name| !existing.contains_key(*name))
+            .fold(request_builder, |builder, name| {
+                client_headers
+                    .get_all(name)
+                    .iter()
+                    .fold(builder, |builder, value| {
+                        builder.header(name.clone(), value.clone())
+                    })
+            })
+    }
+}
+
+/// upstream hosts the proxy is allowed to sign for / fetch from
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostAllowlist {
+    any: bool,
+    hosts: Vec<String>,
+}
+
+impl Default for HostAllowlist {
+    fn default() -> Self {
+        Self::parse_list("*")
+    }
+}
+
+impl HostAllowlist {
+    /// comma seperated hosts, `*` allows anything. an entry starting with `.` matches the domain
+    /// and all of its subdomains (`.poocloud.in` matches `strm.poocloud.in`)
+    pub fn parse_list(hosts: &str) -> Self {
+        let hosts: Vec<String> = hosts
+            .split(',')
+            .map(|h| h.trim().to_ascii_lowercase())
+            .filter(|h| !h.is_empty())
+            .collect();
+
+        Self {
+            any: hosts.iter().any(|h| h == "*"),
+            hosts,
+        }
+    }
+
+    /// true when nothing is allowed at all
+    pub fn is_empty(&self) -> bool {
+        !self.any && self.hosts.is_empty()
+    }
+
+    pub fn is_allowed(&self, url: &str) -> bool {
+        if self.any {
+            return true;
+        }
+
+        let Some(host) = url::Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_ascii_lowercase()))
+        else {
+            return false;
+        };
+
+        self.hosts
+            .iter()
+            .any(|allowed| match allowed.strip_prefix('.') {
+                Some(domain) => host == domain || host.ends_with(allowed.as_str()),
+                None => host == *allowed,
+            })
+    }
+}
+
+/// the checks every proxied url goes through before anything is sent upstream. the signature only
+/// proves the url was signed, this keeps a leaked secret from turning the proxy into an open one
+pub fn validate_target(url: &str, allowed_hosts: &HostAllowlist) -> AppResult<()> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(Error::BadRequest("Invalid URL format".to_string()));
+    }
+    if !allowed_hosts.is_allowed(url) {
+        warn!("Refusing to proxy to a host that isn't allowed: {}", url);
+        return Err(Error::BadRequest("Host is not allowed".to_string()));
+    }
+    Ok(())
+}
+
+/// what an upstream body gets treated as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BodyKind {
+    M3u8,
+    Ts,
+    Mp4,
+}
+
+impl BodyKind {
+    fn parse(kind: &str) -> Option<Self> {
+        match kind.trim().to_ascii_lowercase().as_str() {
+            "m3u8" | "hls" => Some(Self::M3u8),
+            "ts" => Some(Self::Ts),
+            "mp4" => Some(Self::Mp4),
+            _ => None,
+        }
+    }
+
+    /// the normal detection, an `#EXT` body or a playlist content type is a playlist, anything
+    /// else that isn't mp4 is a segment
+    pub fn detect(content_type: &str, body: &[u8]) -> Self {
+        if content_type.contains("video/mp4") {
+            Self::Mp4
+        } else if body.starts_with(b"#EXT")
+            || content_type.contains("mpegurl")
+            || content_type.contains("m3u8")
+        {
+            Self::M3u8
+        } else {
+            Self::Ts
+        }
+    }
+
+    /// what the Content-Type says on its own, None when the body has to be looked at. a
+    /// playlist labeled as `video/mp2t` needs an override to be caught this early
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        if content_type.contains("video/mp4") {
+            Some(Self::Mp4)
+        } else if content_type.contains("mpegurl") || content_type.contains("m3u8") {
+            Some(Self::M3u8)
+        } else if content_type.contains("video/mp2t") {
+            Some(Self::Ts)
+        } else {
+            None
+        }
+    }
+
+    /// a guess from the url's extension alone, before anything was fetched
+    pub fn from_url(url: &str) -> Option<Self> {
+        let path = reqwest::Url::parse(url).ok()?.path().to_ascii_lowercase();
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension {
+            "mp4" | "m4s" | "m4v" => Some(Self::Mp4),
+            "ts" => Some(Self::Ts),
+            "m3u8" => Some(Self::M3u8),
+            _ => None,
+        }
+    }
+}
+
+/// per host body kinds that win over whatever the upstream's Content-Type says, for origins that
+/// send playlists as `text/plain` or segments as anything at all
+#[derive(Debug, Clone, Default)]
+pub struct ContentTypeOverrides {
+    rules: Vec<(HostAllowlist, BodyKind)>,
+}
+
+impl ContentTypeOverrides {
+    /// comma seperated `host:kind` entries where kind is m3u8, ts or mp4, e.g.
+    /// `.poocloud.in:ts,cdn.example.com:m3u8`. hosts match the same way the allowlist does, the
+    /// first matching entry wins and anything malformed is logged and skipped
+    pub fn parse_list(entries: &str) -> Self {
+        let rules = entries
+            .split(',')
+            .map(|e| e.trim())
+            .filter(|e| !e.is_empty())
+            .filter_map(|entry| {
+                let Some((host, kind)) = entry.rsplit_once(':') else {
+                    warn!("Ignoring content type override without a kind: {}", entry);
+                    return None;
+                };
+                let Some(kind) = BodyKind::parse(kind) else {
+                    warn!(
+                        "Ignoring content type override with unknown kind: {}",
+                        entry
+                    );
+                    return None;
+                };
+                Some((HostAllowlist::parse_list(host), kind))
+            })
+            .collect();
+
+        Self { rules }
+    }
+
+    pub fn for_url(&self, url: &str) -> Option<BodyKind> {
+        self.rules
+            .iter()
+            .find(|(hosts, _)| hosts.is_allowed(url))
+            .map(|(_, kind)| *kind)
+    }
+
+    /// the configured kind for the url's host if there is one, otherwise the normal detection
+    pub fn classify(&self, url: &str, content_type: &str, body: &[u8]) -> BodyKind {
+        self.for_url(url)
+            .unwrap_or_else(|| BodyKind::detect(content_type, body))
+    }
+
+    /// the kind before the request is sent, from the override or the url's extension
+    pub fn kind_from_url(&self, url: &str) -> Option<BodyKind> {
+        self.for_url(url).or_else(|| BodyKind::from_url(url))
+    }
+
+    /// the kind before the body is read, from the override or the Content-Type alone
+    pub fn kind_from_headers(&self, url: &str, content_type: &str) -> Option<BodyKind> {
+        self.for_url(url)
+            .or_else(|| BodyKind::from_content_type(content_type))
+    }
+}
+
+/// forwards the upstream body chunk by chunk as it comes in instead of collecting it first.
+/// `hold` lives until the body is done or the client goes away, e.g. the host's connection permit
+pub fn passthrough_body<T: Send + 'static>(
+    response: reqwest::Response,
+    hold: T,
+) -> axum::body::Body {
+    let stream = response.bytes_stream().map(move |chunk| {
+        let _ = &hold;
+        chunk
+    });
+    axum::body::Body::from_stream(stream)
+}
+
+/// the shared clients used for upstream fetches. prior knowledge h2c is a client wide setting in
+/// reqwest so the configured h2c hosts get a second client, everything else goes through the
+/// default one which negotiates h2 over tls when `http2` is on and falls back to HTTP/1.1
+#[derive(Debug, Clone)]
+pub struct UpstreamHttp {
+    client: reqwest::Client,
+    h2c: Option<reqwest::Client>,
+    h2c_hosts: HostAllowlist,
+}
+
+impl From<reqwest::Client> for UpstreamHttp {
+    fn from(client: reqwest::Client) -> Self {
+        Self {
+            client,
+            h2c: None,
+            h2c_hosts: HostAllowlist::parse_list(""),
+        }
+    }
+}
+
+impl UpstreamHttp {
+    /// `h2c_hosts` is the comma seperated list from the config, same matching as the host allowlist
+    pub fn new(http2: bool, h2c_hosts: &str) -> reqwest::Result<Self> {
+        let h2c_hosts = HostAllowlist::parse_list(h2c_hosts);
+
+        let mut builder = Self::builder();
+        builder = if http2 {
+            builder.http2_adaptive_window(true)
+        } else {
+            builder.http1_only()
+        };
+        let client = builder.build()?;
+
+        // only worth a second pool when there's something to send through it
+        let h2c = if h2c_hosts.is_empty() {
+            None
+        } else {
+            Some(
+                Self::builder()
+                    .http2_prior_knowledge()
+                    .http2_adaptive_window(true)
+                    .build()?,
+            )
+        };
+
+        Ok(Self {
+            client,
+            h2c,
+            h2c_hosts,
+        })
+    }
+
+    // High-performance HTTP client for 1000+ concurrent connections
+    // Tuned for video streaming with connection pooling and keep-alive
+    fn builder() -> reqwest::ClientBuilder {
+        reqwest::Client::builder()
+            // Pool size: enough for 1000+ concurrent upstream connections
+            .pool_max_idle_per_host(200)
+            // Connection timeout for establishing new connections
+            .connect_timeout(Duration::from_secs(10))
+            // Overall request timeout - must be longer than health checks
+            .timeout(Duration::from_secs(60))
+            // Idle connections live longer for streaming workloads
+            .pool_idle_timeout(Duration::from_secs(120))
+            // TCP keep-alive to prevent connection drops
+            .tcp_keepalive(Duration::from_secs(60))
+    }
+
+    pub fn client_for(&self, url: &str) -> &reqwest::Client {
+        match &self.h2c {
+            Some(h2c) if self.h2c_hosts.is_allowed(url) => h2c,
+            _ => &self.client,
+        }
+    }
+
+    pub fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        self.client_for(url).get(url)
+    }
+}
+
+/// the fixed headers each schema sends upstream. the proxy and segment prefetch both go through
+/// this so a prefetched segment looks exactly like the foreground fetch to the origin
+//
+// this should always be sports but I'll keep it here incase you want to switch sources to
+// streamed.pk or something and want to send their headers
+pub fn apply_schema_headers(
+    mut request_builder: reqwest::RequestBuilder,
+    schema: &str,
+    target_url: &str,
+) -> reqwest::RequestBuilder {
+    match schema {
+        // not needed for this case but it's here as another example
+        // "movie" => {
+        //     request_builder
+        //         .header(header::HOST, "storm.vodvidl.site")
+        //         .header(header::ORIGIN, "https://vidlink.pro")
+        //         .header(header::REFERER, "https://vidlink.pro/")
+        //         .header(
+        //             header::USER_AGENT,
+        //             "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:145.0) Gecko/20100101 Firefox/145.0",
+        //         )
+        //         .header(header::ACCEPT, "*/*")
+        //         .header(header::TE, "trailers")
+        // }
+        "sports" => {
+            // Always request compressed content from upstream - we handle decompression ourselves
+            // and will respect the client's Accept-Encoding when sending the response back
+            let accept_encoding = "gzip, deflate, br, zstd";
+
+            if target_url.contains("poocloud.in") {
+                request_builder = request_builder
+                    .header(header::ORIGIN, "https://ppvs.su")
+                    .header(header::ACCEPT, "*/*")
+                    .header(header::ACCEPT_LANGUAGE, "en-US,en;q=0.9")
+                    .header(header::ACCEPT_ENCODING, accept_encoding)
+                    .header(header::REFERER, "https://modistreams.org/")
+                    .header(
+                        header::USER_AGENT,
+                        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+                    )
+                    .header("Sec-GPC", "1")
+                    .header("Sec-Fetch-Dest", "empty")
+                    .header("Sec-Fetch-Mode", "cors")
+                    .header("Sec-Fetch-Site", "cross-site")
+                    .header("Priority", "u=4")
+                    .header(header::PRAGMA, "no-cache")
+                    .header(header::CACHE_CONTROL, "no-cache")
+            }
+            if target_url.contains("modifiles.fans") {
+                request_builder = request_builder
+                    .header(header::ORIGIN, "https://pooembed.eu")
+                    .header(header::ACCEPT, "*/*")
+                    .header(header::ACCEPT_LANGUAGE, "en-US,en;q=0.9")
+                    .header(header::ACCEPT_ENCODING, accept_encoding)
+                    .header(header::REFERER, "https://pooembed.eu/")
+                    .header(
+                        header::USER_AGENT,
+                        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+                    )
+                    .header("Sec-GPC", "1")
+                    .header("Sec-Fetch-Dest", "empty")
+                    .header("Sec-Fetch-Mode", "cors")
+                    .header("Sec-Fetch-Site", "cross-site")
+                    .header("Priority", "u=4")
+
+            }
+            else {
+                request_builder = request_builder
+                    .header(header::REFERER, "https://api.ppv.to/api/streams/")
+                    .header(header::ORIGIN, "https://api.ppv.to/api/streams")
+                    .header(
+                        header::USER_AGENT,
+                        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+                    )
+                    .header(header::ACCEPT_ENCODING, accept_encoding)
+                    .header(header::ACCEPT, "*/*");
+            }
+
+            // forward Range headers - we fetch full content, decompress, then serve the range ourselves
+            request_builder
+        }
+        "captions" => {
+            request_builder
+                .header(
+                    header::USER_AGENT,
+                    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:145.0) Gecko/20100101 Firefox/145.0",
+                )
+                .header(header::ACCEPT, "*/*")
+        }
+        _ => {
+            // default to sports if anything. the proxy only hands over schemas that went
+            // through the schema extractor, so this is for the other callers
+            info!("Unknown schema, falling back to sports headers");
+
+            // Always request compressed content from upstream
+            let accept_encoding = "gzip, deflate, br, zstd";
+
+            request_builder = request_builder
+                .header(header::REFERER, "https://api.ppv.to/api/streams/")
+                .header(header::ORIGIN, "https://api.ppv.to/api/streams")
+                .header(
+                    header::USER_AGENT,
+                    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+                )
+                .header(header::ACCEPT_ENCODING, accept_encoding)
+                .header(header::ACCEPT, "*/*");
+
+            // Don't forward Range headers - we fetch full content, decompress, then serve the range ourselves
+            request_builder
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use api::server::utils::access_log_utils::{AccessLogEntry, CacheOutcome, redact_query};
+use tracing_subscriber::fmt::MakeWriter;
+
+// collects everything the fmt layer writes so the test can look at the lines
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for CapturedLogs {
+    type Writer = CapturedLogs;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[test]
+fn test_one_access_log_line_per_request() {
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(logs.clone())
+        .with_ansi(false)
+        .finish();
+
+    tracing::subscriber::with_default(subscriber, || {
+        let mut entry = AccessLogEntry::new(
+            "GET",
+            "/api/v1/proxy?url=aHR0cHM6Ly9jZG4&schema=sports&sig=deadbeef&exp=1&client=abc123",
+            "abc123",
+        );
+        entry.upstream_host = Some("cdn.example.com".to_string());
+        entry.cache = CacheOutcome::Hit;
+        entry.finish(200, Some(1024));
+        entry.emit();
+    });
+
+    let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+
+    assert_eq!(lines.len(), 1);
+    let line = lines[0];
+    assert!(line.contains("access_log"));
+    assert!(line.contains("method=GET"));
+    assert!(line.contains("client_id=abc123"));
+    assert!(line.contains("upstream_host=cdn.example.com"));
+    assert!(line.contains("status=200"));
+    assert!(line.contains("bytes=1024"));
+    assert!(line.contains("duration_ms="));
+    assert!(line.contains("cache=hit"));
+    assert!(!line.contains("deadbeef"));
+}
+
+#[test]
+fn test_sensitive_query_params_are_redacted() {
+    assert_eq!(
+        redact_query("/api/v1/proxy?url=abc&schema=sports&sig=123&exp=9&client=xyz"),
+        "/api/v1/proxy?url=[redacted]&schema=sports&sig=[redacted]&exp=9&client=[redacted]"
+    );
+    assert_eq!(redact_query("/api/v1/health"), "/api/v1/health");
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use api::AppConfig;
+use api::server::api::admin_controller::{AdminController, ExemptionRequest};
+use api::server::error::Error;
+use api::server::extractors::AdminAuthentication;
+use api::server::services::edge_services::EdgeServices;
+use api::server::services::rate_limit_services::{RateLimitResult, UpstreamFailure};
+use axum::Json;
+use axum::extract::{FromRequestParts, Path};
+use axum::http::Request;
+
+const TOKEN: &str = "s3cret-admin-token";
+
+async fn services(admin_token: Option<&str>) -> EdgeServices {
+    common::services(AppConfig {
+        admin_token: admin_token.map(|t| t.to_string()),
+        ..AppConfig::default()
+    })
+    .await
+}
+
+async fn authenticate(
+    services: &EdgeServices,
+    authorization: Option<&str>,
+) -> Result<AdminAuthentication, Error> {
+    let mut request = Request::builder().uri("/api/v1/admin/ratelimit/viewer");
+    if let Some(authorization) = authorization {
+        request = request.header("authorization", authorization);
+    }
+    let (mut parts, _) = request.body(()).unwrap().into_parts();
+    parts.extensions.insert(services.clone());
+
+    AdminAuthentication::from_request_parts(&mut parts, &()).await
+}
+
+#[tokio::test]
+async fn test_rejects_missing_and_wrong_tokens() {
+    let services = services(Some(TOKEN)).await;
+
+    assert!(matches!(
+        authenticate(&services, None).await,
+        Err(Error::Unauthorized)
+    ));
+    assert!(matches!(
+        authenticate(&services, Some("Bearer not-the-token")).await,
+        Err(Error::Unauthorized)
+    ));
+    assert!(matches!(
+        authenticate(&services, Some(TOKEN)).await,
+        Err(Error::Unauthorized)
+    ));
+    assert!(
+        authenticate(&services, Some(&format!("Bearer {}", TOKEN)))
+            .await
+            .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_everything_is_rejected_without_a_configured_token() {
+    let services = services(None).await;
+
+    assert!(matches!(
+        authenticate(&services, Some("Bearer anything")).await,
+        Err(Error::Forbidden)
+    ));
+}
+
+#[tokio::test]
+async fn test_clearing_a_timeout_lets_the_client_back_in() {
+    let services = services(Some(TOKEN)).await;
+    let admin = || AdminAuthentication(services.clone());
+    for _ in 0..20 {
+        services
+            .rate_limit
+            .record_error("viewer", UpstreamFailure::ClientFault(403))
+            .await;
+    }
+    assert!(matches!(
+        services.rate_limit.check_rate_limit("viewer", None).await,
+        RateLimitResult::TimedOut { .. }
+    ));
+
+    let status = AdminController::client_rate_limit_endpoint(admin(), Path("viewer".to_string()))
+        .await
+        .unwrap();
+    assert!(status.timed_out);
+    assert!(status.timeout_reason.is_some());
+    assert!(status.error_score > 0.0);
+
+    let cleared =
+        AdminController::clear_client_timeout_endpoint(admin(), Path("viewer".to_string()))
+            .await
+            .unwrap();
+    assert!(cleared.cleared);
+
+    let status = AdminController::client_rate_limit_endpoint(admin(), Path("viewer".to_string()))
+        .await
+        .unwrap();
+    assert!(!status.timed_out);
+    assert_eq!(status.retry_after, None);
+    assert_eq!(status.error_score, 0.0);
+    assert!(matches!(
+        services.rate_limit.check_rate_limit("viewer", None).await,
+        RateLimitResult::Allowed { .. }
+    ));
+
+    // nothing left to clear the second time
+    let cleared =
+        AdminController::clear_client_timeout_endpoint(admin(), Path("viewer".to_string()))
+            .await
+            .unwrap();
+    assert!(!cleared.cleared);
+}
+
+#[tokio::test]
+async fn test_rejects_invalid_client_ids() {
+    let services = services(Some(TOKEN)).await;
+
+    let result = AdminController::client_rate_limit_endpoint(
+        AdminAuthentication(services),
+        Path("not a client id".to_string()),
+    )
+    .await;
+
+    assert!(matches!(result, Err(Error::BadRequest(_))));
+}
+#[tokio::test]
+async fn test_exempt_clients_are_not_timed_out() {
+    let services = services(Some(TOKEN)).await;
+
+    let status = AdminController::set_client_exemption_endpoint(
+        AdminAuthentication(services.clone()),
+        Path("partner".to_string()),
+        Json(ExemptionRequest { exempt: true }),
+    )
+    .await
+    .unwrap();
+    assert!(status.exempt);
+
+    for _ in 0..100 {
+        services
+            .rate_limit
+            .record_error("partner", UpstreamFailure::ClientFault(403))
+            .await;
+    }
+    assert!(matches!(
+        services.rate_limit.check_rate_limit("partner", None).await,
+        RateLimitResult::Allowed { .. }
+    ));
+    assert_eq!(services.rate_limit.get_error_score("partner").await, 0.0);
+}
@@ -0,0 +1,71 @@
+use api::server::utils::buffer_pool_utils::BufferPool;
+
+#[test]
+fn test_returned_buffers_are_recycled_empty() {
+    let pool = BufferPool::<Vec<u8>>::new(4, 1024);
+
+    let mut first = pool.take();
+    first.extend_from_slice(b"secret from the first request");
+    let capacity = first.capacity();
+    drop(first);
+    assert_eq!(pool.idle(), 1);
+
+    let second = pool.take();
+    assert_eq!(pool.recycled(), 1);
+    assert!(second.is_empty());
+    assert_eq!(second.capacity(), capacity);
+}
+
+#[test]
+fn test_oversized_buffers_are_not_kept() {
+    let pool = BufferPool::<Vec<u8>>::new(4, 1024);
+
+    let mut buffer = pool.take();
+    buffer.resize(4096, 0);
+    drop(buffer);
+
+    assert_eq!(pool.idle(), 0);
+    assert_eq!(pool.take().capacity(), 0);
+    assert_eq!(pool.recycled(), 0);
+}
+
+#[test]
+fn test_pool_keeps_at_most_max_buffers() {
+    let pool = BufferPool::<String>::new(2, 1024);
+
+    let buffers: Vec<_> = (0..5)
+        .map(|i| {
+            let mut buffer = pool.take();
+            buffer.push_str(&format!("playlist {}", i));
+            buffer
+        })
+        .collect();
+    drop(buffers);
+
+    assert_eq!(pool.idle(), 2);
+}
+
+#[test]
+fn test_kept_buffers_never_go_back() {
+    let pool = BufferPool::<Vec<u8>>::new(4, 1024);
+
+    let mut buffer = pool.take();
+    buffer.extend_from_slice(b"kept");
+    let kept = buffer.into_inner();
+
+    assert_eq!(kept, b"kept");
+    assert_eq!(pool.idle(), 0);
+}
+
+#[test]
+fn test_disabled_pool_never_recycles() {
+    let pool = BufferPool::<Vec<u8>>::disabled();
+
+    let mut buffer = pool.take();
+    buffer.extend_from_slice(b"body");
+    drop(buffer);
+
+    assert_eq!(pool.idle(), 0);
+    assert!(pool.take().is_empty());
+    assert_eq!(pool.recycled(), 0);
+}
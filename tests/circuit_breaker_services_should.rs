@@ -0,0 +1,213 @@
+mod common;
+
+use std::sync::Arc;
+
+use api::Database;
+use api::database::stream::{Game, StreamsRepository};
+use api::server::error::Error;
+use api::server::services::circuit_breaker_services::{
+    BreakerState, CircuitBreaker, CircuitBreakerConfig,
+};
+use api::server::services::ppvsu_services::{PpvsuService, PpvsuServiceTrait};
+use api::server::utils::clock_utils::MockClock;
+use common::{MockResponse, MockUpstream};
+
+const NOW: i64 = 1_700_000_000;
+const HOST: &str = "api.ppv.to";
+
+async fn breaker(failure_threshold: u32) -> (CircuitBreaker, Arc<MockClock>) {
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    let clock = Arc::new(MockClock::new(NOW));
+    let breaker = CircuitBreaker::new(
+        db,
+        CircuitBreakerConfig {
+            failure_threshold,
+            cooldown_seconds: 60,
+        },
+    )
+    .with_clock(clock.clone());
+    (breaker, clock)
+}
+
+async fn fail(breaker: &CircuitBreaker, times: usize) {
+    for _ in 0..times {
+        breaker.record_failure(HOST).await;
+    }
+}
+
+#[tokio::test]
+async fn test_opens_after_enough_failures_in_a_row() {
+    let (breaker, _) = breaker(3).await;
+
+    fail(&breaker, 2).await;
+    assert!(breaker.allow(HOST).await.is_ok());
+    assert_eq!(breaker.status(HOST).await.state, BreakerState::Closed);
+
+    fail(&breaker, 1).await;
+    assert_eq!(breaker.allow(HOST).await, Err(60));
+
+    let status = breaker.status(HOST).await;
+    assert_eq!(status.state, BreakerState::Open);
+    assert_eq!(status.failures, 3);
+    assert_eq!(status.retry_after_seconds, Some(60));
+
+    // other hosts don't care
+    assert!(breaker.allow("cdn.example.com").await.is_ok());
+}
+
+#[tokio::test]
+async fn test_a_success_resets_the_failures() {
+    let (breaker, _) = breaker(3).await;
+
+    fail(&breaker, 2).await;
+    breaker.record_success(HOST).await;
+    fail(&breaker, 2).await;
+
+    assert!(breaker.allow(HOST).await.is_ok());
+    assert_eq!(breaker.status(HOST).await.failures, 2);
+}
+
+#[tokio::test]
+async fn test_lets_one_probe_through_once_the_cooldown_is_over() {
+    let (breaker, clock) = breaker(3).await;
+    fail(&breaker, 3).await;
+
+    clock.advance(59);
+    assert_eq!(breaker.allow(HOST).await, Err(1));
+
+    clock.advance(1);
+    assert_eq!(breaker.status(HOST).await.state, BreakerState::HalfOpen);
+    assert!(breaker.allow(HOST).await.is_ok());
+    // the probe is still out, everyone else keeps waiting
+    assert!(breaker.allow(HOST).await.is_err());
+}
+
+#[tokio::test]
+async fn test_a_failed_probe_opens_it_for_another_cooldown() {
+    let (breaker, clock) = breaker(3).await;
+    fail(&breaker, 3).await;
+    clock.advance(60);
+
+    assert!(breaker.allow(HOST).await.is_ok());
+    breaker.record_failure(HOST).await;
+
+    assert_eq!(breaker.status(HOST).await.state, BreakerState::Open);
+    assert_eq!(breaker.allow(HOST).await, Err(60));
+
+    // and probes again after that one
+    clock.advance(60);
+    assert!(breaker.allow(HOST).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_a_successful_probe_closes_it() {
+    let (breaker, clock) = breaker(3).await;
+    fail(&breaker, 3).await;
+    clock.advance(60);
+
+    assert!(breaker.allow(HOST).await.is_ok());
+    breaker.record_success(HOST).await;
+
+    let status = breaker.status(HOST).await;
+    assert_eq!(status.state, BreakerState::Closed);
+    assert_eq!(status.failures, 0);
+    assert!(breaker.allow(HOST).await.is_ok());
+    assert!(breaker.allow(HOST).await.is_ok());
+
+    // back to needing the full count to open
+    fail(&breaker, 2).await;
+    assert!(breaker.allow(HOST).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_nodes_sharing_a_store_share_the_breaker() {
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    let config = CircuitBreakerConfig {
+        failure_threshold: 2,
+        cooldown_seconds: 60,
+    };
+    let clock = Arc::new(MockClock::new(NOW));
+    let first = CircuitBreaker::new(db.clone(), config).with_clock(clock.clone());
+    let second = CircuitBreaker::new(db, config).with_clock(clock.clone());
+
+    fail(&first, 2).await;
+    assert!(second.allow(HOST).await.is_err());
+
+    clock.advance(60);
+    assert!(second.allow(HOST).await.is_ok());
+    assert!(first.allow(HOST).await.is_err());
+}
+
+#[tokio::test]
+async fn test_a_zero_threshold_never_opens() {
+    let (breaker, _) = breaker(0).await;
+
+    fail(&breaker, 100).await;
+
+    assert!(breaker.allow(HOST).await.is_ok());
+    assert!(breaker.statuses().await.is_empty());
+}
+
+#[test]
+fn test_only_bans_and_server_errors_count() {
+    assert!(CircuitBreaker::is_failure_status(403));
+    assert!(CircuitBreaker::is_failure_status(429));
+    assert!(CircuitBreaker::is_failure_status(503));
+    assert!(!CircuitBreaker::is_failure_status(404));
+    assert!(!CircuitBreaker::is_failure_status(200));
+}
+
+fn cached_game(id: i64) -> Game {
+    Game {
+        id,
+        name: format!("game {}", id),
+        poster: String::new(),
+        start_time: 0,
+        end_time: 0,
+        cache_time: 0,
+        video_link: format!("https://embed.example.com/embed/{}", id),
+        category: "Football".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_ppvsu_stops_calling_a_banned_api_and_serves_stale_games() {
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    db.store_game("ppvsu", &cached_game(1)).await.unwrap();
+    db.set_last_fetch_time("ppvsu", 0).await.unwrap();
+
+    let upstream = MockUpstream::always(MockResponse::json(403)).await;
+    let breaker = Arc::new(CircuitBreaker::new(
+        db.clone(),
+        CircuitBreakerConfig {
+            failure_threshold: 2,
+            cooldown_seconds: 60,
+        },
+    ));
+    let service = PpvsuService::new(db.clone())
+        .with_api_base(upstream.base())
+        .with_circuit_breaker(breaker.clone());
+
+    // the cache is stale, each call tries upstream until the breaker opens
+    for _ in 0..2 {
+        let games = service.get_games_with_refresh().await.unwrap();
+        assert_eq!(games.len(), 1);
+    }
+    assert_eq!(upstream.hits(), 2);
+
+    // open now, the stale games come back without asking upstream
+    let games = service.get_games_with_refresh().await.unwrap();
+    assert_eq!(games[0].id, 1);
+    assert_eq!(upstream.hits(), 2);
+
+    assert!(matches!(
+        service.fetch_and_cache_games().await,
+        Err(Error::ServiceUnavailable { .. })
+    ));
+    assert_eq!(upstream.hits(), 2);
+
+    let statuses = breaker.statuses().await;
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(statuses[0].host, "127.0.0.1");
+    assert_eq!(statuses[0].state, BreakerState::Open);
+}
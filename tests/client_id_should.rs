@@ -0,0 +1,124 @@
+use api::server::extractors::{
+    ClientIdStrategy, derive_client_id, derive_keyed_client_id, generate_client_id,
+    generate_keyed_client_id,
+};
+
+const IP: Option<&str> = Some("203.0.113.7");
+const UA: Option<&str> = Some("Mozilla/5.0 (X11; Linux x86_64)");
+
+#[test]
+fn test_ip_strategy_ignores_user_agent() {
+    let a = derive_client_id(ClientIdStrategy::Ip, IP, UA, None);
+    let b = derive_client_id(ClientIdStrategy::Ip, IP, Some("curl/8.0"), None);
+    let other_ip = derive_client_id(ClientIdStrategy::Ip, Some("203.0.113.8"), UA, None);
+
+    assert_eq!(a, b);
+    assert_ne!(a, other_ip);
+}
+
+#[test]
+fn test_ip_user_agent_strategy_is_stable() {
+    let a = derive_client_id(ClientIdStrategy::IpUserAgent, IP, UA, None);
+
+    // same inputs always give the same id, it's a sha-256 so it won't move between builds
+    assert_eq!(a, generate_client_id(IP, UA));
+    assert_eq!(a.len(), 32);
+    assert!(a.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_ne!(
+        a,
+        derive_client_id(ClientIdStrategy::IpUserAgent, IP, Some("curl/8.0"), None)
+    );
+    // a header doesn't matter unless the header strategy is on
+    assert_eq!(
+        a,
+        derive_client_id(ClientIdStrategy::IpUserAgent, IP, UA, Some("partner-1"))
+    );
+}
+
+#[test]
+fn test_header_strategy_uses_partner_id() {
+    let a = derive_client_id(ClientIdStrategy::Header, IP, UA, Some("partner-1"));
+    let moved = derive_client_id(
+        ClientIdStrategy::Header,
+        Some("198.51.100.1"),
+        None,
+        Some("partner-1"),
+    );
+    let other = derive_client_id(ClientIdStrategy::Header, IP, UA, Some("partner-2"));
+
+    assert_eq!(a, moved);
+    assert_ne!(a, other);
+    assert_ne!(a, generate_client_id(IP, UA));
+}
+
+#[test]
+fn test_header_strategy_falls_back_without_valid_header() {
+    let fallback = generate_client_id(IP, UA);
+
+    assert_eq!(
+        derive_client_id(ClientIdStrategy::Header, IP, UA, None),
+        fallback
+    );
+    assert_eq!(
+        derive_client_id(ClientIdStrategy::Header, IP, UA, Some("  ")),
+        fallback
+    );
+    assert_eq!(
+        derive_client_id(ClientIdStrategy::Header, IP, UA, Some("bad id; drop")),
+        fallback
+    );
+}
+
+#[test]
+fn test_strategies_give_distinct_ids() {
+    let ip = derive_client_id(ClientIdStrategy::Ip, IP, UA, None);
+    let ip_ua = derive_client_id(ClientIdStrategy::IpUserAgent, IP, UA, None);
+    let header = derive_client_id(ClientIdStrategy::Header, IP, UA, Some("partner-1"));
+
+    assert_ne!(ip, ip_ua);
+    assert_ne!(ip, header);
+    assert_ne!(ip_ua, header);
+    assert_eq!(ClientIdStrategy::parse("IP"), ClientIdStrategy::Ip);
+    assert_eq!(
+        ClientIdStrategy::parse("nonsense"),
+        ClientIdStrategy::IpUserAgent
+    );
+}
+
+#[test]
+fn test_keyed_id_is_pinned() {
+    // hmac-sha256 over the length prefixed parts, cut to 128 bits. if this changes every signed
+    // url and rate limit bucket out there moves with it
+    assert_eq!(
+        generate_keyed_client_id("test_secret", IP, UA),
+        "cd97eaa9d22eb22ad4147ae741c7c603"
+    );
+    assert_eq!(
+        generate_client_id(IP, UA),
+        "b5f87ac59eeaeec75d379968b28311f7"
+    );
+}
+
+#[test]
+fn test_secret_changes_every_strategy() {
+    for (strategy, header) in [
+        (ClientIdStrategy::Ip, None),
+        (ClientIdStrategy::IpUserAgent, None),
+        (ClientIdStrategy::Header, Some("partner-1")),
+    ] {
+        let unkeyed = derive_client_id(strategy, IP, UA, header);
+        let keyed = derive_keyed_client_id("test_secret", strategy, IP, UA, header);
+        let other_key = derive_keyed_client_id("other_secret", strategy, IP, UA, header);
+
+        assert_eq!(
+            unkeyed,
+            derive_keyed_client_id("", strategy, IP, UA, header)
+        );
+        assert_ne!(keyed, unkeyed);
+        assert_ne!(keyed, other_key);
+        assert_eq!(
+            keyed,
+            derive_keyed_client_id("test_secret", strategy, IP, UA, header)
+        );
+    }
+}
@@ -0,0 +1,223 @@
+// the upstream the proxy, prefetch and ppvsu tests talk to. a raw http/1.1 server on a random
+// port, every connection gets one response and is closed, so the tests can count and look at
+// exactly what was sent. also the edge services most tests build on
+#![allow(dead_code)]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use api::server::services::edge_services::EdgeServices;
+use api::{AppConfig, Database};
+use axum::Router;
+use axum::http::StatusCode;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+/// what the mock answers one request with
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    delay: Duration,
+    declared_length: Option<usize>,
+}
+
+impl MockResponse {
+    /// an empty body with `status`
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+            delay: Duration::ZERO,
+            declared_length: None,
+        }
+    }
+
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self::status(200).body(body)
+    }
+
+    /// `{}` with a json content type
+    pub fn json(status: u16) -> Self {
+        Self::status(status)
+            .header("content-type", "application/json")
+            .body("{}")
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// waits this long before answering
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// claims a longer body than it has, the rest never comes and the connection stays open
+    pub fn stall_at(mut self, declared_length: usize) -> Self {
+        self.declared_length = Some(declared_length);
+        self
+    }
+
+    fn head(&self) -> String {
+        let reason = StatusCode::from_u16(self.status)
+            .ok()
+            .and_then(|s| s.canonical_reason())
+            .unwrap_or("Unknown");
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!(
+            "content-length: {}\r\n",
+            self.declared_length.unwrap_or(self.body.len())
+        ));
+        if self.declared_length.is_none() {
+            head.push_str("connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        head
+    }
+}
+
+pub struct MockUpstream {
+    addr: String,
+    hits: Arc<AtomicUsize>,
+    requests: Arc<Mutex<Vec<String>>>,
+    received: watch::Receiver<usize>,
+}
+
+impl MockUpstream {
+    /// answers with `responses` in order, one per connection, repeating the last one
+    pub async fn start(responses: Vec<MockResponse>) -> Self {
+        assert!(
+            !responses.is_empty(),
+            "the mock needs something to answer with"
+        );
+        Self::spawn(Some(responses)).await
+    }
+
+    /// answers everything with the same response
+    pub async fn always(response: MockResponse) -> Self {
+        Self::start(vec![response]).await
+    }
+
+    /// accepts connections and never answers, they're held open until the test ends
+    pub async fn hanging() -> Self {
+        Self::spawn(None).await
+    }
+
+    async fn spawn(responses: Option<Vec<MockResponse>>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let (count, received) = watch::channel(0);
+
+        let (counter, seen) = (hits.clone(), requests.clone());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let hit = counter.fetch_add(1, Ordering::SeqCst);
+                let Some(responses) = responses.as_ref() else {
+                    held.push(socket);
+                    continue;
+                };
+                let response = responses[hit.min(responses.len() - 1)].clone();
+
+                let mut buf = vec![0u8; 8192];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                seen.lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&buf[..n]).to_string());
+                count.send_modify(|count| *count += 1);
+
+                tokio::spawn(async move {
+                    tokio::time::sleep(response.delay).await;
+                    let _ = socket.write_all(response.head().as_bytes()).await;
+                    let _ = socket.write_all(&response.body).await;
+                    let _ = socket.flush().await;
+                    if response.declared_length.is_some() {
+                        tokio::time::sleep(Duration::from_secs(60)).await;
+                    }
+                });
+            }
+        });
+
+        Self {
+            addr,
+            hits,
+            requests,
+            received,
+        }
+    }
+
+    /// `http://127.0.0.1:port`, no trailing slash
+    pub fn base(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// `path` on the mock, it should start with a `/`
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base(), path)
+    }
+
+    /// connections accepted so far, hanging ones included
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+
+    /// the raw head of every request answered so far
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// just the `GET /path HTTP/1.1` line of every request
+    pub fn request_lines(&self) -> Vec<String> {
+        self.requests()
+            .iter()
+            .map(|request| request.lines().next().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    /// the headers of the `n`th request (from 0), lowercased and sorted. waits for it to come in
+    pub async fn request_headers(&self, n: usize) -> Vec<String> {
+        let mut received = self.received.clone();
+        received.wait_for(|count| *count > n).await.unwrap();
+
+        let mut headers: Vec<String> = self.requests()[n]
+            .lines()
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .map(|line| line.to_ascii_lowercase())
+            .collect();
+        headers.sort();
+        headers
+    }
+}
+
+/// serves `router` on a random port, `http://127.0.0.1:port` comes back
+pub async fn serve(router: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    format!("http://{}", addr)
+}
+
+/// edge services over a fresh in-memory database
+pub async fn services(config: AppConfig) -> EdgeServices {
+    let db = Database::in_memory().await.unwrap();
+    EdgeServices::new(db, Arc::new(config))
+}
@@ -0,0 +1,99 @@
+use api::{AppConfig, CargoEnv};
+
+fn load(env: &str, extra: &[&str]) -> AppConfig {
+    let mut args = vec![
+        "api",
+        "--cargo-env",
+        env,
+        "--access-token-secret",
+        "secret",
+        "--cors-origin",
+        "*",
+        "--preview-cors-origin",
+        "*",
+    ];
+    args.extend_from_slice(extra);
+    AppConfig::try_load_from(args).unwrap()
+}
+
+#[test]
+fn test_development_defaults_apply() {
+    let config = load("development", &[]);
+
+    assert!(matches!(config.cargo_env, CargoEnv::Development));
+    assert!(config.access_log);
+    assert_eq!(config.prefetch_max_concurrent, 2);
+    assert_eq!(config.denial_status, None);
+}
+
+#[test]
+fn test_production_defaults_apply() {
+    let config = load("production", &[]);
+
+    assert!(!config.access_log);
+    assert_eq!(config.prefetch_max_concurrent, 5);
+    assert_eq!(config.denial_status, Some(403));
+}
+
+#[test]
+fn test_explicit_values_win_over_env_defaults() {
+    let config = load("development", &["--prefetch-max-concurrent", "8"]);
+    assert_eq!(config.prefetch_max_concurrent, 8);
+
+    let config = load("production", &["--denial-message", "nope"]);
+    assert_eq!(config.denial_status, None);
+    assert_eq!(config.denial_message.as_deref(), Some("nope"));
+
+    let config = load("production", &["--denial-status", "404"]);
+    assert_eq!(config.denial_status, Some(404));
+}
+
+#[test]
+fn test_with_env_defaults_skips_explicit_fields() {
+    let config = AppConfig::default().with_env_defaults(|id| id == "access_log");
+
+    // default() is development with the access log off, and it was "set"
+    assert!(!config.access_log);
+    assert_eq!(config.prefetch_max_concurrent, 2);
+}
+
+#[test]
+fn test_unset_cache_and_rate_limit_values_keep_their_defaults() {
+    let config = load("production", &[]);
+    let defaults = AppConfig::default();
+
+    assert_eq!(
+        config.proxy_m3u8_ttl_seconds,
+        defaults.proxy_m3u8_ttl_seconds
+    );
+    assert_eq!(
+        config.proxy_segment_ttl_seconds,
+        defaults.proxy_segment_ttl_seconds
+    );
+    assert_eq!(
+        config.ppvsu_video_link_ttl_seconds,
+        defaults.ppvsu_video_link_ttl_seconds
+    );
+    assert_eq!(
+        config.rate_limit_max_requests,
+        defaults.rate_limit_max_requests
+    );
+    assert_eq!(
+        config.rate_limit_window_seconds,
+        defaults.rate_limit_window_seconds
+    );
+    assert_eq!(config.rate_limit_max_errors, defaults.rate_limit_max_errors);
+    assert_eq!(
+        config.rate_limit_max_error_ratio,
+        defaults.rate_limit_max_error_ratio
+    );
+    assert_eq!(
+        config.rate_limit_timeout_seconds,
+        defaults.rate_limit_timeout_seconds
+    );
+
+    assert_eq!(config.rate_limit_strategy, "fixed");
+
+    let config = load("production", &["--rate-limit-max-requests", "50"]);
+    assert_eq!(config.rate_limit_max_requests, 50);
+}
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use api::Database;
+use api::server::services::cookie_services::{CookieService, CookieServiceTrait, StoredCookie};
+use api::server::utils::clock_utils::MockClock;
+
+const DOMAIN: &str = "strm.poocloud.in";
+// Wed, 21 Oct 2015 07:28:00 GMT
+const NOW: i64 = 1445412480;
+
+async fn cookies() -> (CookieService, Arc<MockClock>) {
+    let db = Database::in_memory().await.unwrap();
+    let clock = Arc::new(MockClock::new(NOW));
+    (
+        CookieService::new(Arc::new(db)).with_clock(clock.clone()),
+        clock,
+    )
+}
+
+fn set_cookies(headers: &[&str]) -> Vec<String> {
+    headers.iter().map(|h| h.to_string()).collect()
+}
+
+#[test]
+fn test_attributes_are_parsed() {
+    let cookie = StoredCookie::parse(
+        "session=abc123; Path=/live; Domain=.poocloud.in; Secure; HttpOnly; Max-Age=60",
+        NOW,
+    )
+    .unwrap();
+
+    assert_eq!(cookie.name, "session");
+    assert_eq!(cookie.value, "abc123");
+    assert_eq!(cookie.path, "/live");
+    assert_eq!(cookie.domain.as_deref(), Some("poocloud.in"));
+    assert!(cookie.secure);
+    assert_eq!(cookie.expires_at, Some(NOW + 60));
+
+    // max-age wins over expires, whichever order they come in
+    let both = StoredCookie::parse(
+        "a=1; Expires=Wed, 21 Oct 2015 08:28:00 GMT; Max-Age=10",
+        NOW,
+    )
+    .unwrap();
+    assert_eq!(both.expires_at, Some(NOW + 10));
+
+    let expires = StoredCookie::parse("a=1; Expires=Wed, 21-Oct-2015 08:28:00 GMT", NOW).unwrap();
+    assert_eq!(expires.expires_at, Some(NOW + 3600));
+    assert_eq!(expires.path, "/");
+
+    assert_eq!(StoredCookie::parse("no value here", NOW), None);
+    assert_eq!(StoredCookie::parse("=nameless", NOW), None);
+}
+
+#[test]
+fn test_path_matching() {
+    let cookie = StoredCookie::parse("a=1; Path=/live", NOW).unwrap();
+
+    assert!(cookie.matches_path("/live"));
+    assert!(cookie.matches_path("/live/index.m3u8"));
+    assert!(!cookie.matches_path("/lively"));
+    assert!(!cookie.matches_path("/vod/index.m3u8"));
+    assert!(!cookie.matches_path("/"));
+
+    let root = StoredCookie::parse("a=1", NOW).unwrap();
+    assert!(root.matches_path("/anything/at/all"));
+    assert!(root.matches_path(""));
+}
+
+#[tokio::test]
+async fn test_path_scoped_cookies_only_go_to_their_paths() {
+    let (cookies, _clock) = cookies().await;
+
+    cookies
+        .store_cookies(
+            DOMAIN,
+            &set_cookies(&["cf=root; Path=/", "token=live; Path=/live; HttpOnly"]),
+        )
+        .await;
+
+    // the more specific path comes first
+    assert_eq!(
+        cookies.get_cookies(DOMAIN, "/live/index.m3u8").await,
+        Some("token=live; cf=root".to_string())
+    );
+    assert_eq!(
+        cookies.get_cookies(DOMAIN, "/vod/index.m3u8").await,
+        Some("cf=root".to_string())
+    );
+    assert_eq!(cookies.get_cookies("other.example.com", "/").await, None);
+}
+
+#[tokio::test]
+async fn test_new_value_replaces_the_same_name_and_path() {
+    let (cookies, _clock) = cookies().await;
+
+    cookies
+        .store_cookies(DOMAIN, &set_cookies(&["token=old; Path=/live", "cf=1"]))
+        .await;
+    cookies
+        .store_cookies(DOMAIN, &set_cookies(&["token=new; Path=/live"]))
+        .await;
+
+    assert_eq!(
+        cookies.get_cookies(DOMAIN, "/live/seg_001.ts").await,
+        Some("token=new; cf=1".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_max_age_zero_evicts_the_cookie() {
+    let (cookies, _clock) = cookies().await;
+
+    cookies
+        .store_cookies(DOMAIN, &set_cookies(&["token=abc", "cf=1"]))
+        .await;
+    cookies
+        .store_cookies(DOMAIN, &set_cookies(&["token=deleted; Max-Age=0"]))
+        .await;
+
+    assert_eq!(
+        cookies.get_cookies(DOMAIN, "/").await,
+        Some("cf=1".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_cookies_are_dropped_once_they_expire() {
+    let (cookies, clock) = cookies().await;
+
+    cookies
+        .store_cookies(DOMAIN, &set_cookies(&["short=1; Max-Age=30", "session=2"]))
+        .await;
+    assert_eq!(
+        cookies.get_cookies(DOMAIN, "/").await,
+        Some("short=1; session=2".to_string())
+    );
+
+    // well before the 24h entry ttl runs out
+    clock.advance(30);
+    assert_eq!(
+        cookies.get_cookies(DOMAIN, "/").await,
+        Some("session=2".to_string())
+    );
+}
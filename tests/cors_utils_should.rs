@@ -0,0 +1,55 @@
+use api::server::utils::cors_utils::CorsOrigins;
+
+fn origins() -> CorsOrigins {
+    CorsOrigins::parse(
+        "https://example.com, app.reedstreams.live:8443",
+        "-preview.pages.dev",
+    )
+}
+
+#[test]
+fn test_allows_listed_hosts_and_their_subdomains() {
+    let origins = origins();
+
+    assert!(origins.allows("https://example.com"));
+    assert!(origins.allows("http://example.com/"));
+    assert!(origins.allows("https://www.example.com"));
+    assert!(origins.allows("https://app.reedstreams.live:8443"));
+}
+
+#[test]
+fn test_rejects_lookalike_hosts() {
+    let origins = origins();
+
+    assert!(!origins.allows("https://evilexample.com"));
+    assert!(!origins.allows("https://example.com.evil.net"));
+    assert!(!origins.allows("https://app.reedstreams.live"));
+}
+
+#[test]
+fn test_rejects_garbage_origins() {
+    let origins = origins();
+
+    assert!(!origins.allows(""));
+    assert!(!origins.allows("null"));
+    assert!(!origins.allows("https://"));
+    assert!(!origins.allows("ftp://example.com"));
+}
+
+#[test]
+fn test_preview_origins_match_on_suffix() {
+    let origins = origins();
+
+    assert!(origins.allows("https://feature-x-preview.pages.dev"));
+    assert!(!origins.allows("https://feature-x.pages.dev"));
+}
+
+#[test]
+fn test_wildcard_allows_everything() {
+    assert_eq!(CorsOrigins::parse("*", ""), CorsOrigins::Any);
+    assert_eq!(
+        CorsOrigins::parse("https://example.com", "*"),
+        CorsOrigins::Any
+    );
+    assert!(CorsOrigins::parse("*", "").allows("https://anything.net"));
+}
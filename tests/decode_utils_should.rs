@@ -0,0 +1,205 @@
+mod common;
+
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use api::server::error::Error;
+use api::server::utils::buffer_pool_utils::BufferPool;
+use api::server::utils::decode_utils::{
+    DecodeError, decompress, is_malformed_body, read_decoded_body, read_decoded_body_pooled,
+};
+use api::server::utils::encoding_utils::ContentEncoding;
+use common::{MockResponse, MockUpstream};
+use flate2::Compression;
+use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+
+const PLAYLIST: &str =
+    "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6.0,\nseg_001.ts\n#EXTINF:6.0,\nseg_002.ts\n";
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+// serves `bodies` in order as gzip responses, one per connection, repeating the last one
+async fn upstream(bodies: Vec<Vec<u8>>) -> MockUpstream {
+    upstream_encoded("gzip", bodies).await
+}
+
+async fn upstream_encoded(encoding: &str, bodies: Vec<Vec<u8>>) -> MockUpstream {
+    MockUpstream::start(
+        bodies
+            .into_iter()
+            .map(|body| MockResponse::ok(body).header("content-encoding", encoding))
+            .collect(),
+    )
+    .await
+}
+
+#[test]
+fn test_decodes_brotli() {
+    let compressed = ContentEncoding::Brotli
+        .compress(PLAYLIST.as_bytes())
+        .unwrap();
+
+    assert_eq!(
+        decompress(Some("br"), &compressed).unwrap(),
+        PLAYLIST.as_bytes()
+    );
+}
+
+#[tokio::test]
+async fn test_brotli_playlist_from_upstream_is_decoded() {
+    let compressed = ContentEncoding::Brotli
+        .compress(PLAYLIST.as_bytes())
+        .unwrap();
+    let url = upstream_encoded("br", vec![compressed])
+        .await
+        .url("/index.m3u8");
+
+    let request = reqwest::Client::new().get(&url);
+    let retry = request.try_clone();
+    let response = request.send().await.unwrap();
+
+    let body = read_decoded_body(response, retry, 2).await.unwrap();
+
+    assert_eq!(body, PLAYLIST.as_bytes());
+}
+
+#[test]
+fn test_decodes_zlib_and_raw_deflate() {
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(PLAYLIST.as_bytes()).unwrap();
+    let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+    raw.write_all(PLAYLIST.as_bytes()).unwrap();
+
+    assert_eq!(
+        decompress(Some("deflate"), &zlib.finish().unwrap()).unwrap(),
+        PLAYLIST.as_bytes()
+    );
+    assert_eq!(
+        decompress(Some("deflate"), &raw.finish().unwrap()).unwrap(),
+        PLAYLIST.as_bytes()
+    );
+}
+
+#[test]
+fn test_truncated_gzip_is_retryable() {
+    let full = gzip(PLAYLIST.as_bytes());
+    let truncated = &full[..full.len() - 12];
+
+    let err = decompress(Some("gzip"), truncated).unwrap_err();
+    assert!(err.is_retryable(), "{:?}", err);
+
+    let err = decompress(Some("gzip"), b"definitely not gzip").unwrap_err();
+    assert!(matches!(err, DecodeError::Malformed(_)));
+    assert!(!err.is_retryable());
+
+    assert_eq!(
+        decompress(Some("gzip"), &full).unwrap(),
+        PLAYLIST.as_bytes()
+    );
+}
+
+#[tokio::test]
+async fn test_truncated_gzip_body_triggers_a_retry() {
+    let full = gzip(PLAYLIST.as_bytes());
+    let truncated = full[..full.len() - 12].to_vec();
+    let cdn = upstream(vec![truncated, full]).await;
+    let url = cdn.url("/index.m3u8");
+
+    let request = reqwest::Client::new().get(&url);
+    let retry = request.try_clone();
+    let response = request.send().await.unwrap();
+
+    let body = read_decoded_body(response, retry, 2).await.unwrap();
+
+    assert_eq!(body, PLAYLIST.as_bytes());
+    assert_eq!(cdn.hits(), 2);
+}
+
+#[tokio::test]
+async fn test_malformed_body_is_not_retried() {
+    let cdn = upstream(vec![b"definitely not gzip".to_vec()]).await;
+    let url = cdn.url("/index.m3u8");
+
+    let request = reqwest::Client::new().get(&url);
+    let retry = request.try_clone();
+    let response = request.send().await.unwrap();
+
+    let result = read_decoded_body(response, retry, 2).await;
+
+    assert!(matches!(
+        result,
+        Err(Error::UpstreamBody(DecodeError::Malformed(_)))
+    ));
+    assert!(is_malformed_body(&result.unwrap_err()));
+    assert_eq!(cdn.hits(), 1);
+}
+
+#[tokio::test]
+async fn test_gives_up_after_max_retries() {
+    let full = gzip(PLAYLIST.as_bytes());
+    let cdn = upstream(vec![full[..full.len() - 12].to_vec()]).await;
+    let url = cdn.url("/index.m3u8");
+
+    let request = reqwest::Client::new().get(&url);
+    let retry = request.try_clone();
+    let response = request.send().await.unwrap();
+
+    let error = read_decoded_body(response, retry, 2).await.unwrap_err();
+    assert!(matches!(
+        error,
+        Error::UpstreamBody(DecodeError::Truncated(_))
+    ));
+    assert!(!is_malformed_body(&error));
+    assert_eq!(cdn.hits(), 3);
+}
+
+#[tokio::test]
+async fn test_retries_go_out_through_the_given_sender() {
+    let full = gzip(PLAYLIST.as_bytes());
+    let truncated = full[..full.len() - 12].to_vec();
+    let cdn = upstream(vec![truncated, full]).await;
+    let url = cdn.url("/index.m3u8");
+    let sent = AtomicUsize::new(0);
+
+    let request = reqwest::Client::new().get(&url);
+    let retry = request.try_clone();
+    let response = request.send().await.unwrap();
+
+    let body = read_decoded_body_pooled(response, retry, 2, &BufferPool::disabled(), |request| {
+        sent.fetch_add(1, Ordering::SeqCst);
+        request.send()
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(&body[..], PLAYLIST.as_bytes());
+    assert_eq!(sent.load(Ordering::SeqCst), 1);
+    assert_eq!(cdn.hits(), 2);
+}
+
+#[tokio::test]
+async fn test_pooled_body_matches_and_goes_back_to_the_pool() {
+    let url = upstream(vec![gzip(PLAYLIST.as_bytes())])
+        .await
+        .url("/index.m3u8");
+    let buffers = BufferPool::<Vec<u8>>::new(2, 1024 * 1024);
+
+    for _ in 0..3 {
+        let request = reqwest::Client::new().get(&url);
+        let retry = request.try_clone();
+        let response = request.send().await.unwrap();
+
+        let body = read_decoded_body_pooled(response, retry, 2, &buffers, |request| request.send())
+            .await
+            .unwrap();
+        assert_eq!(&body[..], PLAYLIST.as_bytes());
+    }
+
+    // the first read allocated, the other two reused its buffer
+    assert_eq!(buffers.recycled(), 2);
+    assert_eq!(buffers.idle(), 1);
+}
@@ -0,0 +1,40 @@
+// own test binary since the denial response is process wide once installed
+use api::server::error::{DenialResponse, Error};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+
+async fn body_json(error: Error) -> (StatusCode, serde_json::Value) {
+    let response = error.into_response();
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[test]
+fn test_nothing_configured_keeps_default_behavior() {
+    assert!(DenialResponse::from_config(None, None).is_none());
+
+    let denial = DenialResponse::from_config(Some(9999), None).unwrap();
+    assert_eq!(denial.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_blocked_request_gets_configured_denial() {
+    DenialResponse::from_config(Some(404), Some("Not here".to_string()))
+        .unwrap()
+        .install();
+
+    for error in [Error::Unauthorized, Error::Forbidden] {
+        let (status, body) = body_json(error).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["errors"]["message"][0], "Not here");
+    }
+
+    // everything that isn't a denial is left alone
+    let (status, _) = body_json(Error::NotFound("missing".to_string())).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = body_json(Error::InternalServerError).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+}
@@ -0,0 +1,205 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use api::AppConfig;
+use api::server::error::Error;
+use api::server::extractors::{EdgeAuthentication, TrustedProxies, client_ip};
+use api::server::services::edge_services::EdgeServices;
+use api::server::utils::m3u8_utils::{sign_proxy_url, sign_proxy_url_with_params};
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::Request;
+
+const UA: &str = "Mozilla/5.0 (X11; Linux x86_64)";
+
+async fn services(strict_client_binding: bool) -> EdgeServices {
+    common::services(AppConfig {
+        strict_client_binding,
+        ..AppConfig::default()
+    })
+    .await
+}
+
+// the client id a request from `ip` gets
+async fn client_id_for(services: &EdgeServices, ip: &str) -> String {
+    authenticate(services, "/api/v1/proxy", ip)
+        .await
+        .map(|EdgeAuthentication(client_id, _)| client_id)
+        .unwrap()
+}
+
+async fn authenticate(
+    services: &EdgeServices,
+    uri: &str,
+    ip: &str,
+) -> Result<EdgeAuthentication, Error> {
+    let (mut parts, _) = Request::builder()
+        .uri(uri)
+        .header("x-forwarded-for", ip)
+        .header("user-agent", UA)
+        .body(())
+        .unwrap()
+        .into_parts();
+    parts.extensions.insert(services.clone());
+
+    EdgeAuthentication::from_request_parts(&mut parts, &()).await
+}
+
+async fn signed_for(services: &EdgeServices, ip: &str) -> String {
+    let client_id = client_id_for(services, ip).await;
+    sign_proxy_url(
+        "https://cdn.example.com/live/index.m3u8",
+        &client_id,
+        &services.signature_util,
+    )
+}
+
+#[tokio::test]
+async fn test_strict_mode_accepts_the_signing_client() {
+    let services = services(true).await;
+    let url = signed_for(&services, "203.0.113.7").await;
+
+    assert!(authenticate(&services, &url, "203.0.113.7").await.is_ok());
+}
+
+#[tokio::test]
+async fn test_strict_mode_rejects_url_from_another_ip() {
+    let services = services(true).await;
+    let url = signed_for(&services, "203.0.113.7").await;
+
+    assert!(matches!(
+        authenticate(&services, &url, "198.51.100.20").await,
+        Err(Error::Unauthorized)
+    ));
+}
+
+#[tokio::test]
+async fn test_strict_mode_rejects_unsigned_proxy_requests() {
+    let strict = services(true).await;
+    let unsigned = "/api/v1/proxy?url=https%3A%2F%2Fcdn.example.com%2Flive%2Findex.m3u8";
+
+    assert!(matches!(
+        authenticate(&strict, unsigned, "203.0.113.7").await,
+        Err(Error::Unauthorized)
+    ));
+    // routes that never get signed still work
+    assert!(
+        authenticate(&strict, "/api/v1/streams", "203.0.113.7")
+            .await
+            .is_ok()
+    );
+
+    let lenient = services(false).await;
+    assert!(
+        authenticate(&lenient, unsigned, "203.0.113.7")
+            .await
+            .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_stream_has_to_be_signed() {
+    let services = services(false).await;
+    let client_id = client_id_for(&services, "203.0.113.7").await;
+    let url = sign_proxy_url_with_params(
+        "https://cdn.example.com/live/index.m3u8",
+        "sports",
+        &client_id,
+        services.signature_util.expiry_in(1),
+        &services.signature_util,
+        &[("stream", "ppvsu-7".to_string())],
+    );
+    assert!(authenticate(&services, &url, "203.0.113.7").await.is_ok());
+
+    // moving it to another stream, or tagging a url that was signed without one
+    let moved = url.replace("ppvsu-7", "ppvsu-8");
+    let tagged = signed_for(&services, "203.0.113.7").await + "&stream=ppvsu-8";
+    let unsigned = "/api/v1/proxy?url=aHR0cHM6Ly9jZG4uZXhhbXBsZS5jb20&stream=ppvsu-8";
+    for uri in [moved.as_str(), tagged.as_str(), unsigned] {
+        assert!(matches!(
+            authenticate(&services, uri, "203.0.113.7").await,
+            Err(Error::Unauthorized)
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_lenient_mode_lets_client_move_between_ips() {
+    let services = services(false).await;
+    let url = signed_for(&services, "203.0.113.7").await;
+
+    assert!(authenticate(&services, &url, "198.51.100.20").await.is_ok());
+}
+
+fn ip_of(peer: Option<&str>, headers: &[(&str, &str)]) -> Option<String> {
+    let mut request = Request::builder().uri("/api/v1/proxy");
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let (mut parts, _) = request.body(()).unwrap().into_parts();
+    if let Some(peer) = peer {
+        parts
+            .extensions
+            .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+    }
+    let trusted = TrustedProxies::parse_list(&AppConfig::default().trusted_proxies);
+    client_ip(&parts, &trusted)
+}
+
+#[test]
+fn test_client_ip_skips_trusted_hops_from_the_right() {
+    // the client wrote the first hop itself, our proxy appended what it actually saw
+    assert_eq!(
+        ip_of(
+            Some("10.0.0.2:4000"),
+            &[("x-forwarded-for", "1.1.1.1, 203.0.113.7, 10.0.0.1")]
+        ),
+        Some("203.0.113.7".to_string())
+    );
+    assert_eq!(
+        ip_of(None, &[("x-real-ip", "198.51.100.20")]),
+        Some("198.51.100.20".to_string())
+    );
+    assert_eq!(ip_of(None, &[]), None);
+}
+
+#[test]
+fn test_client_ip_ignores_forwarded_headers_from_untrusted_peers() {
+    let spoofed = [("x-forwarded-for", "1.1.1.1"), ("x-real-ip", "1.1.1.1")];
+    assert_eq!(
+        ip_of(Some("203.0.113.7:51000"), &spoofed),
+        Some("203.0.113.7".to_string())
+    );
+}
+
+#[test]
+fn test_client_ip_from_a_trusted_peer_without_headers_is_the_peer() {
+    assert_eq!(
+        ip_of(Some("[::ffff:10.0.0.2]:4000"), &[]),
+        Some("10.0.0.2".to_string())
+    );
+}
+
+#[test]
+fn test_client_ip_is_the_first_hop_when_every_hop_is_trusted() {
+    assert_eq!(
+        ip_of(
+            Some("127.0.0.1:4000"),
+            &[("x-forwarded-for", "192.168.1.5, 10.0.0.1")]
+        ),
+        Some("192.168.1.5".to_string())
+    );
+}
+
+#[test]
+fn test_trusted_proxies_match_cidrs_and_plain_ips() {
+    let trusted =
+        TrustedProxies::parse_list("10.0.0.0/8, 2001:db8::/32, 198.51.100.9, nope, 1.2.3.4/33");
+    assert!(trusted.contains("10.200.1.1".parse().unwrap()));
+    assert!(trusted.contains("2001:db8::1".parse().unwrap()));
+    assert!(trusted.contains("198.51.100.9".parse().unwrap()));
+    assert!(!trusted.contains("198.51.100.10".parse().unwrap()));
+    assert!(!trusted.contains("11.0.0.1".parse().unwrap()));
+    assert!(!trusted.contains("1.2.3.4".parse().unwrap()));
+    assert!(TrustedProxies::parse_list("0.0.0.0/0").contains("8.8.8.8".parse().unwrap()));
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use api::server::services::edge_services::EdgeServices;
+use api::server::services::rate_limit_services::RateLimitResult;
+use api::{AppConfig, Database};
+use common::services;
+
+async fn ttl_of(db: &Database, prefix: &str) -> i64 {
+    match db {
+        Database::Memory(mem) => {
+            let keys = mem.store.scan(prefix).await.unwrap();
+            assert_eq!(keys.len(), 1, "{:?}", keys);
+            mem.store.ttl(&keys[0]).await.unwrap()
+        }
+        Database::Redis(_) => unreachable!("tests only run against the in-memory store"),
+    }
+}
+
+#[test]
+fn test_defaults_match_the_old_constants() {
+    let config = AppConfig::default();
+
+    assert_eq!(config.proxy_m3u8_ttl_seconds, 10);
+    assert_eq!(config.proxy_segment_ttl_seconds, 300);
+    assert_eq!(config.ppvsu_video_link_ttl_seconds, 300);
+    assert_eq!(config.rate_limit_max_requests, 500);
+    assert_eq!(config.rate_limit_window_seconds, 60);
+    assert_eq!(config.rate_limit_max_errors, 50);
+    assert_eq!(config.rate_limit_max_error_ratio, 0.2);
+    assert_eq!(config.rate_limit_ratio_min_requests, 250);
+    assert_eq!(config.rate_limit_error_window_seconds, 600);
+    assert_eq!(config.rate_limit_timeout_seconds, 300);
+}
+
+#[tokio::test]
+async fn test_rate_limiter_uses_configured_limit() {
+    let services = services(AppConfig {
+        rate_limit_max_requests: 2,
+        ..AppConfig::default()
+    })
+    .await;
+
+    for _ in 0..2 {
+        assert!(matches!(
+            services.rate_limit.check_rate_limit("client-1", None).await,
+            RateLimitResult::Allowed { .. }
+        ));
+    }
+    assert!(matches!(
+        services.rate_limit.check_rate_limit("client-1", None).await,
+        RateLimitResult::RateLimited { .. }
+    ));
+}
+
+#[tokio::test]
+async fn test_proxy_cache_uses_configured_ttls() {
+    // keeps the database to look at the ttls it was given
+    let db = Database::in_memory().await.unwrap();
+    let services = EdgeServices::new(
+        db.clone(),
+        Arc::new(AppConfig {
+            proxy_m3u8_ttl_seconds: 42,
+            proxy_segment_ttl_seconds: 900,
+            proxy_stale_if_error_seconds: 0,
+            ..AppConfig::default()
+        }),
+    );
+
+    services
+        .proxy_cache
+        .cache_m3u8("https://cdn.example.com/index.m3u8", "#EXTM3U")
+        .await;
+    let mut segment = vec![0xffu8; 188 * 10];
+    for packet in segment.chunks_mut(188) {
+        packet[0] = 0x47;
+    }
+    services
+        .proxy_cache
+        .cache_segment("https://cdn.example.com/seg_001.ts", &segment)
+        .await;
+
+    // the memory store rounds down, a segment's cached-at time shares its ttl
+    assert!((41..=42).contains(&ttl_of(&db, "pcache:m3u8:*").await));
+    assert!((899..=900).contains(&ttl_of(&db, "pcache:seg:at:*").await));
+}
+
+#[tokio::test]
+async fn test_concurrent_upstream_calls_are_held_to_the_configured_rate() {
+    let services = services(AppConfig {
+        upstream_max_requests_per_second: 10,
+        ..AppConfig::default()
+    })
+    .await;
+
+    let started = Instant::now();
+    let mut tasks = Vec::new();
+    for _ in 0..25 {
+        let services = services.clone();
+        tasks.push(tokio::spawn(async move {
+            services.acquire_upstream_permit().await;
+            started.elapsed()
+        }));
+    }
+    let mut sent_at = Vec::new();
+    for task in tasks {
+        sent_at.push(task.await.unwrap());
+    }
+    sent_at.sort();
+
+    // a full bucket goes out straight away, the other 15 get one token every 100ms
+    assert!(sent_at[9] < Duration::from_millis(50), "{:?}", sent_at);
+    assert!(sent_at[24] >= Duration::from_millis(1400), "{:?}", sent_at);
+    for (i, at) in sent_at.iter().enumerate().skip(10) {
+        let refills = (i - 9) as u32;
+        assert!(*at >= Duration::from_millis(100) * refills - Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,109 @@
+use api::server::utils::encoding_utils::ContentEncoding;
+use api::server::utils::m3u8_utils::{PlaylistOptions, rewrite_playlist};
+use api::server::utils::signature_utils::SignatureUtil;
+
+// a big rewritten vod playlist, the kind of text the levels matter for
+fn playlist() -> String {
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-TARGETDURATION:6\n");
+    for i in 0..2000 {
+        playlist.push_str(&format!("#EXTINF:6.0,\nseg_{}.ts\n", i));
+    }
+    playlist.push_str("#EXT-X-ENDLIST");
+
+    rewrite_playlist(
+        &playlist,
+        "https://cdn.example.com/vod/index.m3u8",
+        "client123",
+        &SignatureUtil::new("test_secret".to_string()),
+        &PlaylistOptions::default(),
+    )
+    .unwrap()
+}
+
+fn zstd_roundtrip(compressed: &[u8]) -> Vec<u8> {
+    zstd::decode_all(compressed).unwrap()
+}
+
+#[test]
+fn test_higher_zstd_level_compresses_playlist_smaller() {
+    let playlist = playlist();
+
+    let fast = ContentEncoding::Zstd
+        .compress_with_level(playlist.as_bytes(), 1)
+        .unwrap();
+    let small = ContentEncoding::Zstd
+        .compress_with_level(playlist.as_bytes(), 19)
+        .unwrap();
+
+    assert!(small.len() < fast.len());
+    assert_eq!(zstd_roundtrip(&fast), playlist.as_bytes());
+    assert_eq!(zstd_roundtrip(&small), playlist.as_bytes());
+}
+
+#[test]
+fn test_higher_gzip_level_compresses_playlist_smaller() {
+    let playlist = playlist();
+
+    let fast = ContentEncoding::Gzip
+        .compress_with_level(playlist.as_bytes(), 1)
+        .unwrap();
+    let small = ContentEncoding::Gzip
+        .compress_with_level(playlist.as_bytes(), 9)
+        .unwrap();
+
+    assert!(small.len() < fast.len());
+}
+
+#[test]
+fn test_out_of_range_levels_are_clamped() {
+    let data = b"#EXTM3U\n#EXT-X-TARGETDURATION:6\n";
+
+    assert_eq!(
+        ContentEncoding::Zstd
+            .compress_with_level(data, 100)
+            .unwrap(),
+        ContentEncoding::Zstd.compress_with_level(data, 22).unwrap()
+    );
+    assert_eq!(
+        ContentEncoding::Gzip.compress_with_level(data, -5).unwrap(),
+        ContentEncoding::Gzip.compress_with_level(data, 0).unwrap()
+    );
+    assert_eq!(
+        ContentEncoding::None.compress_with_level(data, 9).unwrap(),
+        data.to_vec()
+    );
+}
+
+#[test]
+fn test_negotiation_prefers_zstd_then_brotli_then_gzip() {
+    let negotiate = |v| ContentEncoding::from_accept_encoding(Some(v));
+
+    assert_eq!(negotiate("gzip, deflate, br, zstd"), ContentEncoding::Zstd);
+    assert_eq!(negotiate("gzip, deflate, br"), ContentEncoding::Brotli);
+    assert_eq!(negotiate("br;q=1.0, gzip;q=0.8"), ContentEncoding::Brotli);
+    assert_eq!(negotiate("gzip, deflate"), ContentEncoding::Gzip);
+    assert_eq!(negotiate("identity"), ContentEncoding::None);
+    assert_eq!(
+        ContentEncoding::from_accept_encoding(None),
+        ContentEncoding::None
+    );
+}
+
+#[test]
+fn test_brotli_compresses_playlist_and_roundtrips() {
+    let playlist = playlist();
+
+    let compressed = ContentEncoding::Brotli
+        .compress(playlist.as_bytes())
+        .unwrap();
+    let mut decompressed = Vec::new();
+    std::io::Read::read_to_end(
+        &mut brotli::Decompressor::new(&compressed[..], 4096),
+        &mut decompressed,
+    )
+    .unwrap();
+
+    assert!(compressed.len() < playlist.len());
+    assert_eq!(decompressed, playlist.as_bytes());
+    assert_eq!(ContentEncoding::Brotli.as_header_value(), Some("br"));
+}
@@ -0,0 +1,165 @@
+mod common;
+
+use api::server::EdgeApplicationServer;
+use api::server::error::Error;
+use api::server::utils::request_id_utils;
+use axum::Router;
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware;
+use axum::response::IntoResponse;
+use axum::routing::get;
+
+async fn respond(error: Error) -> (StatusCode, HeaderMap, serde_json::Value) {
+    let response = error.into_response();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, headers, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn test_too_many_requests_is_a_429_with_retry_after() {
+    let (status, headers, body) = respond(Error::TooManyRequests {
+        message: "Too many requests, slow down".to_string(),
+        retry_after: 42,
+    })
+    .await;
+
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(headers["retry-after"], "42");
+    assert_eq!(headers["x-ratelimit-remaining"], "0");
+    assert!(headers.contains_key("x-ratelimit-reset"));
+    assert_eq!(body["errors"]["message"][0], "Too many requests, slow down");
+    assert_eq!(body["retry_after"], 42);
+    assert_eq!(body["error"]["code"], "rate_limited");
+    assert_eq!(body["error"]["message"], "Too many requests, slow down");
+}
+
+#[tokio::test]
+async fn test_service_unavailable_is_a_503_with_retry_after() {
+    let (status, headers, body) = respond(Error::ServiceUnavailable {
+        message: "Upstream is busy".to_string(),
+        retry_after: 5,
+    })
+    .await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(headers["retry-after"], "5");
+    assert!(!headers.contains_key("x-ratelimit-remaining"));
+    assert_eq!(body["errors"]["message"][0], "Upstream is busy");
+    assert_eq!(body["retry_after"], 5);
+    assert_eq!(body["error"]["code"], "service_unavailable");
+}
+
+#[tokio::test]
+async fn test_other_errors_keep_the_plain_error_body() {
+    let (status, headers, body) = respond(Error::BadRequest("bad url".to_string())).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(!headers.contains_key("retry-after"));
+    assert_eq!(body["errors"]["message"][0], "bad url");
+}
+
+#[tokio::test]
+async fn test_every_body_has_a_stable_code_next_to_the_message() {
+    let cases = [
+        (
+            Error::Unauthorized,
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+        ),
+        (Error::Forbidden, StatusCode::FORBIDDEN, "forbidden"),
+        (
+            Error::NotFound("no such game".to_string()),
+            StatusCode::NOT_FOUND,
+            "not_found",
+        ),
+        (
+            Error::BadRequest("Invalid URL format".to_string()),
+            StatusCode::BAD_REQUEST,
+            "bad_request",
+        ),
+        (
+            Error::BadGateway("Api returned an invalid response".to_string()),
+            StatusCode::BAD_GATEWAY,
+            "bad_gateway",
+        ),
+        (
+            Error::GatewayTimeout("upstream took too long".to_string()),
+            StatusCode::GATEWAY_TIMEOUT,
+            "gateway_timeout",
+        ),
+        (
+            Error::InternalServerError,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+        ),
+    ];
+
+    for (error, expected_status, expected_code) in cases {
+        let message = match &error {
+            Error::InternalServerError => "An internal error occured".to_string(),
+            other => other.to_string(),
+        };
+        let (status, _, body) = respond(error).await;
+
+        assert_eq!(status, expected_status);
+        assert_eq!(body["error"]["code"], expected_code);
+        assert_eq!(body["error"]["message"], message.as_str());
+        assert_eq!(body["errors"]["message"][0], message.as_str());
+        // not inside a request here
+        assert!(body["error"]["request_id"].is_null());
+    }
+}
+
+#[tokio::test]
+async fn test_validation_errors_keep_their_fields_and_get_a_code() {
+    let mut errors = validator::ValidationErrors::new();
+    errors.add("url", validator::ValidationError::new("length"));
+    let (status, _, body) = respond(Error::ValidationError(errors)).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "validation_failed");
+    assert!(body["errors"]["url"].is_array());
+}
+
+#[tokio::test]
+async fn test_body_carries_the_request_id() {
+    let router = Router::new()
+        .route(
+            "/",
+            get(|| async { Err::<(), _>(Error::NotFound("gone".to_string())) }),
+        )
+        .layer(middleware::from_fn(request_id_utils::track_request_id));
+    let base = common::serve(router).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/", base))
+        .header("x-request-id", "abc-123")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    assert_eq!(body["error"]["request_id"], "abc-123");
+    assert_eq!(body["error"]["code"], "not_found");
+}
+
+#[tokio::test]
+async fn test_unknown_routes_get_the_same_envelope() {
+    let router = Router::new()
+        .route("/", get(|| async { "ok" }))
+        .fallback(EdgeApplicationServer::handle_404);
+    let base = common::serve(router).await;
+
+    let response = reqwest::get(format!("{}/nope", base)).await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    assert_eq!(body["error"]["code"], "not_found");
+    assert_eq!(body["error"]["message"], "This resource doesn't exist.");
+    assert_eq!(body["errors"]["message"][0], "This resource doesn't exist.");
+}
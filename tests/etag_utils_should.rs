@@ -0,0 +1,85 @@
+use api::server::services::proxy_cache_services::ProxyCacheService;
+use api::server::utils::etag_utils::{if_none_match, insert_etag, not_modified};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::IntoResponse;
+
+const SEGMENT_URL: &str = "https://cdn.example.com/live/seg_001.ts";
+
+fn segment_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("video/mp2t"));
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("public, max-age=300"),
+    );
+    headers
+}
+
+#[test]
+fn test_segment_etag_follows_the_url_and_length() {
+    let etag = ProxyCacheService::segment_etag(SEGMENT_URL, 188 * 100);
+
+    assert_eq!(
+        etag,
+        ProxyCacheService::segment_etag(SEGMENT_URL, 188 * 100)
+    );
+    assert_ne!(etag, ProxyCacheService::segment_etag(SEGMENT_URL, 188 * 99));
+    assert_ne!(
+        etag,
+        ProxyCacheService::segment_etag("https://cdn.example.com/live/seg_002.ts", 188 * 100)
+    );
+    assert!(HeaderValue::from_str(&etag).is_ok());
+}
+
+#[tokio::test]
+async fn test_fresh_request_gets_the_body_and_an_etag() {
+    let etag = ProxyCacheService::segment_etag(SEGMENT_URL, 188);
+    let request = HeaderMap::new();
+    assert!(!if_none_match(&request, &etag));
+
+    let mut response = (StatusCode::OK, segment_headers(), vec![0x47u8; 188]).into_response();
+    insert_etag(response.headers_mut(), &etag);
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::ETAG], etag.as_str());
+}
+
+#[tokio::test]
+async fn test_repeat_request_with_the_etag_gets_a_304() {
+    let etag = ProxyCacheService::segment_etag(SEGMENT_URL, 188);
+    let mut request = HeaderMap::new();
+    request.insert(header::IF_NONE_MATCH, HeaderValue::from_str(&etag).unwrap());
+    assert!(if_none_match(&request, &etag));
+
+    let response = not_modified(&etag, &segment_headers());
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()[header::ETAG], etag.as_str());
+    assert_eq!(
+        response.headers()[header::CACHE_CONTROL],
+        "public, max-age=300"
+    );
+    assert!(response.headers().get(header::CONTENT_TYPE).is_none());
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert!(body.is_empty());
+}
+
+#[test]
+fn test_if_none_match_lists_and_wildcards() {
+    let etag = ProxyCacheService::segment_etag(SEGMENT_URL, 188);
+    let strong = etag.trim_start_matches("W/").to_string();
+    let matches = |value: &str| {
+        let mut request = HeaderMap::new();
+        request.insert(header::IF_NONE_MATCH, HeaderValue::from_str(value).unwrap());
+        if_none_match(&request, &etag)
+    };
+
+    assert!(matches(&format!("\"abc-1\", {}", etag)));
+    // weak comparison, the W/ doesn't matter either way
+    assert!(matches(&strong));
+    assert!(matches("*"));
+    assert!(!matches("\"abc-1\""));
+    assert!(!matches(&format!("W/\"{}-189\"", "0".repeat(64))));
+}
@@ -0,0 +1,201 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use api::Database;
+use api::database::stream::{Game, StreamsRepository};
+use api::server::error::Error;
+use api::server::services::games_refresh_services::GamesRefreshTask;
+use api::server::services::ppvsu_services::MockPpvsuServiceTrait;
+use api::server::services::refresh_health_services::RefreshTracker;
+use api::server::utils::clock_utils::MockClock;
+
+const NOW: i64 = 1_700_000_000;
+
+// a game that started an hour before it ended
+fn game(id: i64, end_time: i64) -> Game {
+    Game {
+        id,
+        name: format!("game {}", id),
+        poster: String::new(),
+        start_time: end_time - 3600,
+        end_time,
+        cache_time: 0,
+        video_link: format!("https://embed.example.com/embed/{}", id),
+        category: "Football".to_string(),
+    }
+}
+
+// a ppvsu service that counts how often the games were fetched
+fn counting_ppvsu(calls: Arc<AtomicUsize>) -> MockPpvsuServiceTrait {
+    let mut ppvsu = MockPpvsuServiceTrait::new();
+    ppvsu.expect_fetch_and_cache_games().returning(move || {
+        calls.fetch_add(1, Ordering::SeqCst);
+        Ok(Vec::new())
+    });
+    ppvsu
+}
+
+fn task(ppvsu: MockPpvsuServiceTrait, db: Arc<Database>, interval: Duration) -> GamesRefreshTask {
+    GamesRefreshTask::new(Arc::new(ppvsu), db, "ppvsu", interval, Duration::ZERO)
+        .with_clock(Arc::new(MockClock::new(NOW)))
+}
+
+#[tokio::test]
+async fn test_refetches_the_games_every_interval() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    let task = Arc::new(task(
+        counting_ppvsu(calls.clone()),
+        db,
+        Duration::from_millis(100),
+    ));
+
+    let handle = task.clone().spawn();
+
+    // nothing until the first interval passed
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let ran = calls.load(Ordering::SeqCst);
+    assert!((2..=4).contains(&ran), "ran {} times", ran);
+
+    task.stop();
+    tokio::time::timeout(Duration::from_secs(1), handle)
+        .await
+        .unwrap()
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(calls.load(Ordering::SeqCst), ran);
+}
+
+#[tokio::test]
+async fn test_only_one_node_refetches_per_round() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let db = Arc::new(Database::in_memory().await.unwrap());
+
+    let first = task(
+        counting_ppvsu(calls.clone()),
+        db.clone(),
+        Duration::from_millis(200),
+    );
+    let second = task(
+        counting_ppvsu(calls.clone()),
+        db.clone(),
+        Duration::from_millis(200),
+    );
+
+    assert!(first.refresh_once().await);
+    assert!(!second.refresh_once().await);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // the lock runs out with the round, then whoever comes first gets the next one
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert!(second.refresh_once().await);
+    assert!(!first.refresh_once().await);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_a_refresh_marks_the_cache_fresh() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    let tracker = Arc::new(RefreshTracker::new());
+
+    let task = task(counting_ppvsu(calls), db.clone(), Duration::from_secs(60))
+        .with_refresh_tracker(tracker.clone());
+
+    assert!(task.refresh_once().await);
+    assert_eq!(db.get_last_fetch_time("ppvsu").await.unwrap(), Some(NOW));
+    assert_eq!(tracker.snapshot().last_success, Some(NOW));
+}
+
+#[tokio::test]
+async fn test_a_failed_refresh_is_recorded() {
+    let mut ppvsu = MockPpvsuServiceTrait::new();
+    ppvsu
+        .expect_fetch_and_cache_games()
+        .times(1)
+        .returning(|| Err(Error::Forbidden));
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    let tracker = Arc::new(RefreshTracker::new());
+
+    let task =
+        task(ppvsu, db.clone(), Duration::from_secs(60)).with_refresh_tracker(tracker.clone());
+
+    assert!(!task.refresh_once().await);
+    assert_eq!(db.get_last_fetch_time("ppvsu").await.unwrap(), None);
+
+    let state = tracker.snapshot();
+    assert_eq!(state.last_success, None);
+    assert_eq!(state.last_error_at, Some(NOW));
+}
+
+#[tokio::test]
+async fn test_a_refresh_deletes_games_that_ended_long_ago() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    db.store_game("ppvsu", &game(1, NOW - 3 * 3600))
+        .await
+        .unwrap();
+    db.store_game("ppvsu", &game(2, NOW - 600)).await.unwrap();
+    db.store_game("ppvsu", &game(3, NOW + 3600)).await.unwrap();
+
+    let task = task(counting_ppvsu(calls), db.clone(), Duration::from_secs(60))
+        .with_expiry_grace(Duration::from_secs(3600));
+
+    assert!(task.refresh_once().await);
+
+    let mut left: Vec<i64> = db
+        .get_games("ppvsu")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|g| g.id)
+        .collect();
+    left.sort();
+    assert_eq!(left, vec![2, 3]);
+}
+
+#[tokio::test]
+async fn test_a_refresh_rebuilds_the_live_index() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    // stored against the wall clock, long after both ended, so neither went into the index
+    db.store_game("ppvsu", &game(1, NOW - 600)).await.unwrap();
+    db.store_game("ppvsu", &game(2, NOW + 1800)).await.unwrap();
+    assert!(db.get_live_games("ppvsu").await.unwrap().is_empty());
+
+    let task = task(counting_ppvsu(calls), db.clone(), Duration::from_secs(60));
+
+    assert!(task.refresh_once().await);
+
+    let live: Vec<i64> = db
+        .get_live_games("ppvsu")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|g| g.id)
+        .collect();
+    assert_eq!(live, vec![2]);
+}
+
+#[tokio::test]
+async fn test_a_failed_refresh_still_deletes_expired_games() {
+    let mut ppvsu = MockPpvsuServiceTrait::new();
+    ppvsu
+        .expect_fetch_and_cache_games()
+        .returning(|| Err(Error::Forbidden));
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    db.store_game("ppvsu", &game(1, NOW - 3 * 3600))
+        .await
+        .unwrap();
+
+    let task = task(ppvsu, db.clone(), Duration::from_secs(60))
+        .with_expiry_grace(Duration::from_secs(3600));
+
+    assert!(!task.refresh_once().await);
+    assert!(db.get_game("ppvsu", 1).await.unwrap().is_none());
+}
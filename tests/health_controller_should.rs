@@ -0,0 +1,101 @@
+mod common;
+
+use std::sync::atomic::Ordering;
+
+use api::AppConfig;
+use api::server::api::health_controller::{health_endpoint, live_endpoint, ready_endpoint};
+use api::server::dtos::health_dto::HealthStatus;
+use axum::Extension;
+use axum::http::StatusCode;
+use common::services;
+
+#[tokio::test]
+async fn test_ready_while_accepting_traffic() {
+    let services = services(AppConfig::default()).await;
+
+    let (status, response) = ready_endpoint(Extension(services)).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.status, HealthStatus::Healthy);
+    assert_eq!(
+        response.redis.as_ref().unwrap().status,
+        HealthStatus::Healthy
+    );
+}
+
+#[tokio::test]
+async fn test_not_ready_once_draining() {
+    let services = services(AppConfig::default()).await;
+    services.accepting_traffic.store(false, Ordering::SeqCst);
+
+    let (status, response) = ready_endpoint(Extension(services)).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.status, HealthStatus::Draining);
+}
+
+#[tokio::test]
+async fn test_still_live_while_draining() {
+    let services = services(AppConfig::default()).await;
+    services.accepting_traffic.store(false, Ordering::SeqCst);
+
+    let (status, response) = live_endpoint(Extension(services)).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.status, HealthStatus::Healthy);
+    assert!(response.redis.is_none());
+}
+
+#[tokio::test]
+async fn test_full_health_reports_draining() {
+    let services = services(AppConfig::default()).await;
+
+    let (status, _) = health_endpoint(Extension(services.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+
+    services.accepting_traffic.store(false, Ordering::SeqCst);
+    let (status, response) = health_endpoint(Extension(services)).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.status, HealthStatus::Draining);
+
+    let json = serde_json::to_value(&*response).unwrap();
+    assert_eq!(json["status"], "draining");
+}
+
+#[tokio::test]
+async fn test_full_health_reports_the_edge_database_as_disabled() {
+    let services = services(AppConfig::default()).await;
+
+    let (status, response) = health_endpoint(Extension(services)).await;
+
+    // no database doesn't make the node unhealthy
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.status, HealthStatus::Healthy);
+    assert_eq!(response.services.database.status, HealthStatus::Disabled);
+
+    let json = serde_json::to_value(&*response).unwrap();
+    assert_eq!(json["services"]["database"]["status"], "disabled");
+}
+
+#[tokio::test]
+async fn test_full_health_reports_open_circuit_breakers() {
+    let services = services(AppConfig::default()).await;
+
+    let (_, response) = health_endpoint(Extension(services.clone())).await;
+    assert!(response.services.circuit_breakers.is_empty());
+
+    for _ in 0..services.config.ppvsu_breaker_failures {
+        services.circuit_breaker.record_failure("api.ppv.to").await;
+    }
+    let (status, response) = health_endpoint(Extension(services)).await;
+
+    // still up, just serving stale games
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.status, HealthStatus::Degraded);
+
+    let json = serde_json::to_value(&*response).unwrap();
+    let breaker = &json["services"]["circuit_breakers"][0];
+    assert_eq!(breaker["host"], "api.ppv.to");
+    assert_eq!(breaker["state"], "open");
+}
@@ -0,0 +1,47 @@
+use api::server::dtos::health_dto::{DatabaseHealth, HealthStatus, RefreshHealth};
+use api::server::services::refresh_health_services::RefreshState;
+
+#[test]
+fn test_edge_mode_database_reports_disabled() {
+    let db_health = DatabaseHealth::disabled();
+    assert_eq!(db_health.status, HealthStatus::Disabled);
+
+    let json = serde_json::to_value(&db_health).unwrap();
+    assert_eq!(json["status"], "disabled");
+}
+
+#[test]
+fn test_stale_last_refresh_reports_degraded() {
+    let now = 1_700_000_000;
+    let state = RefreshState {
+        last_success: Some(now - 7201),
+        last_error: Some("ppvs.su API returned 403".to_string()),
+        last_error_at: Some(now - 60),
+    };
+
+    let refresh = RefreshHealth::evaluate(&state, now, 7200);
+    assert_eq!(refresh.status, HealthStatus::Degraded);
+    assert_eq!(refresh.age_seconds, Some(7201));
+
+    let json = serde_json::to_value(&refresh).unwrap();
+    assert_eq!(json["status"], "degraded");
+    assert_eq!(json["last_error"], "ppvs.su API returned 403");
+}
+
+#[test]
+fn test_recent_refresh_reports_healthy() {
+    let now = 1_700_000_000;
+    let state = RefreshState {
+        last_success: Some(now - 60),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        RefreshHealth::evaluate(&state, now, 7200).status,
+        HealthStatus::Healthy
+    );
+    assert_eq!(
+        RefreshHealth::evaluate(&RefreshState::default(), now, 7200).status,
+        HealthStatus::Disabled
+    );
+}
@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use api::server::services::host_health_services::HostHealthService;
+
+// grab a free port and let it go so nothing is listening on it
+fn closed_port_url() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    format!("http://127.0.0.1:{}/index.m3u8", port)
+}
+
+#[tokio::test]
+async fn test_connection_failure_marks_host_and_next_request_fails_fast() {
+    let health = HostHealthService::new(Duration::from_secs(10));
+    let url = closed_port_url();
+
+    let err = reqwest::Client::new().get(&url).send().await.unwrap_err();
+
+    assert!(HostHealthService::is_connection_failure(&err));
+    assert!(health.record_send_error("127.0.0.1", &err));
+
+    let remaining = health.unreachable_for("127.0.0.1").unwrap();
+    assert!(remaining <= Duration::from_secs(10));
+    assert!(health.unreachable_for("cdn.example.com").is_none());
+}
+
+#[test]
+fn test_marker_expires_after_cooldown() {
+    let health = HostHealthService::new(Duration::from_millis(20));
+    health.mark_unreachable("cdn.example.com");
+    assert!(health.unreachable_for("cdn.example.com").is_some());
+
+    std::thread::sleep(Duration::from_millis(30));
+    assert!(health.unreachable_for("cdn.example.com").is_none());
+}
+
+#[test]
+fn test_successful_response_clears_marker() {
+    let health = HostHealthService::new(Duration::from_secs(10));
+    health.mark_unreachable("cdn.example.com");
+    health.mark_reachable("cdn.example.com");
+
+    assert!(health.unreachable_for("cdn.example.com").is_none());
+}
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use api::database::stream::Game;
+use api::server::error::{AppResult, Error};
+use api::server::services::link_resolver_services::LinkResolver;
+use api::server::services::ppvsu_services::{DynPpvsuService, PpvsuServiceTrait};
+
+// hands out a fixed list of games and fails every link ending in a multiple of 3, keeping track
+// of how many fetches were in flight at once
+#[derive(Default)]
+struct FakePpvsu {
+    games: Vec<Game>,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+    fetches: AtomicUsize,
+}
+
+#[async_trait::async_trait]
+impl PpvsuServiceTrait for FakePpvsu {
+    async fn fetch_and_cache_games(&self) -> AppResult<Vec<Game>> {
+        Ok(self.games.clone())
+    }
+
+    async fn fetch_video_link(&self, iframe_url: &str) -> AppResult<String> {
+        let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+        self.fetches.fetch_add(1, Ordering::SeqCst);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        let id: usize = iframe_url.rsplit('/').next().unwrap().parse().unwrap();
+        if id.is_multiple_of(3) {
+            Err(Error::InternalServerErrorWithContext(
+                "fetch failed".to_string(),
+            ))
+        } else {
+            Ok(format!("https://cdn.example.com/{}/index.m3u8", id))
+        }
+    }
+
+    async fn get_games_with_refresh(&self) -> AppResult<Vec<Game>> {
+        Ok(self.games.clone())
+    }
+
+    async fn get_game_by_id(&self, game_id: i64) -> AppResult<Game> {
+        Err(Error::NotFound(format!("game {} not found", game_id)))
+    }
+
+    async fn clear_cache(&self) -> AppResult<()> {
+        Ok(())
+    }
+
+    async fn get_current_timestamp(&self) -> AppResult<i64> {
+        Ok(chrono::Utc::now().timestamp())
+    }
+
+    async fn is_cache_stale(&self, _cache_time: i64, _current_time: i64) -> bool {
+        false
+    }
+}
+
+fn game(id: i64, live: bool) -> Game {
+    let now = chrono::Utc::now().timestamp();
+    let (start_time, end_time) = if live {
+        (now - 600, now + 3600)
+    } else {
+        (now + 3600, now + 7200)
+    };
+    Game {
+        id,
+        name: format!("game {}", id),
+        poster: String::new(),
+        start_time,
+        end_time,
+        cache_time: now,
+        video_link: format!("https://embed.example.com/embed/{}", id),
+        category: "Football".to_string(),
+    }
+}
+
+fn fake(live: i64, upcoming: i64) -> Arc<FakePpvsu> {
+    let games = (1..=live)
+        .map(|id| game(id, true))
+        .chain((100..100 + upcoming).map(|id| game(id, false)))
+        .collect();
+    Arc::new(FakePpvsu {
+        games,
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn test_resolves_live_games_with_bounded_concurrency() {
+    let ppvsu = fake(10, 4);
+    let resolver = LinkResolver::new(ppvsu.clone() as DynPpvsuService, 3, Duration::ZERO);
+
+    let progress = resolver.resolve_live().await.unwrap();
+
+    // upcoming games are left alone
+    assert_eq!(ppvsu.fetches.load(Ordering::SeqCst), 10);
+    assert!(ppvsu.max_in_flight.load(Ordering::SeqCst) <= 3);
+    assert!(ppvsu.max_in_flight.load(Ordering::SeqCst) > 1);
+
+    // 3, 6 and 9 fail
+    assert!(!progress.running);
+    assert_eq!(progress.total, 10);
+    assert_eq!(progress.completed, 10);
+    assert_eq!(progress.succeeded, 7);
+    assert_eq!(progress.failed, 3);
+    assert!(progress.finished_at.is_some());
+    assert_eq!(resolver.progress(), progress);
+}
+
+#[tokio::test]
+async fn test_only_one_run_at_a_time() {
+    let ppvsu = fake(4, 0);
+    let resolver = Arc::new(LinkResolver::new(
+        ppvsu.clone() as DynPpvsuService,
+        1,
+        Duration::ZERO,
+    ));
+
+    let started = resolver.start().unwrap();
+    assert!(started.running);
+    assert!(matches!(
+        resolver.resolve_live().await,
+        Err(Error::ObjectConflict(_))
+    ));
+
+    while resolver.progress().running {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // game 3 fails
+    assert_eq!(resolver.progress().succeeded, 3);
+    assert_eq!(resolver.progress().failed, 1);
+    assert_eq!(ppvsu.max_in_flight.load(Ordering::SeqCst), 1);
+
+    // free again once the run is over
+    assert!(resolver.resolve_live().await.is_ok());
+}
@@ -0,0 +1,69 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use api::{LogFormat, Logger};
+use tracing_subscriber::layer::SubscriberExt;
+
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Captured {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect()
+    }
+}
+
+fn log_with(log_format: LogFormat) -> Vec<String> {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber =
+        tracing_subscriber::registry().with(Logger::fmt_layer(log_format, move || writer.clone()));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("proxy", client_id = "viewer");
+        let _entered = span.enter();
+        tracing::info!(status = 200, "served \"segment\"\nwith a newline");
+    });
+
+    captured.lines()
+}
+
+#[test]
+fn test_json_lines_parse_as_json() {
+    let lines = log_with(LogFormat::Json);
+
+    assert_eq!(lines.len(), 1);
+    let line: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+    assert_eq!(line["level"], "INFO");
+    assert_eq!(
+        line["fields"]["message"],
+        "served \"segment\"\nwith a newline"
+    );
+    assert_eq!(line["fields"]["status"], 200);
+    assert_eq!(line["span"]["name"], "proxy");
+    assert_eq!(line["span"]["client_id"], "viewer");
+}
+
+#[test]
+fn test_text_lines_are_not_json() {
+    let lines = log_with(LogFormat::Text);
+
+    assert!(!lines.is_empty());
+    assert!(serde_json::from_str::<serde_json::Value>(&lines[0]).is_err());
+    assert!(lines[0].contains("served"));
+}
@@ -0,0 +1,1032 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use api::server::utils::buffer_pool_utils::BufferPool;
+use api::server::utils::clock_utils::MockClock;
+use api::server::utils::m3u8_utils::{
+    MAX_PLAYLIST_DEPTH, PlaylistKind, PlaylistOptions, is_master_playlist, parse_accept_language,
+    playlist_cache_control, playlist_kind, preselect_language, rewrite_playlist,
+    rewrite_playlist_into, rewrite_playlist_offloaded, sign_proxy_url,
+};
+use api::server::utils::signature_utils::{SignatureUtil, signed_payload};
+
+const MASTER: &str = "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=800000\nlow/index.m3u8\n#EXT-X-STREAM-INF:BANDWIDTH=2400000\nhigh/index.m3u8";
+const MEDIA: &str =
+    "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6.0,\nseg_001.ts\n#EXTINF:6.0,\nseg_002.ts";
+
+fn util() -> SignatureUtil {
+    SignatureUtil::new("test_secret".to_string())
+}
+
+fn at_depth(depth: u32) -> PlaylistOptions {
+    PlaylistOptions {
+        depth,
+        ..Default::default()
+    }
+}
+
+fn uri_lines(playlist: &str) -> Vec<&str> {
+    playlist.lines().filter(|l| !l.starts_with('#')).collect()
+}
+
+#[test]
+fn test_classifies_playlist_per_fetch() {
+    assert!(is_master_playlist(MASTER));
+    assert!(!is_master_playlist(MEDIA));
+}
+
+#[test]
+fn test_nested_master_variants_carry_next_depth() {
+    // top level master points at another master, which points at the media playlists
+    let top = rewrite_playlist(
+        MASTER,
+        "https://cdn.example.com/master.m3u8",
+        "client123",
+        &util(),
+        &at_depth(0),
+    )
+    .unwrap();
+    for line in uri_lines(&top) {
+        assert!(line.starts_with("/api/v1/proxy?url="));
+        assert!(line.contains("&depth=1&type=playlist"));
+    }
+
+    let nested = rewrite_playlist(
+        MASTER,
+        "https://cdn.example.com/low/index.m3u8",
+        "client123",
+        &util(),
+        &at_depth(1),
+    )
+    .unwrap();
+    for line in uri_lines(&nested) {
+        assert!(line.contains("&depth=2&type=playlist"));
+    }
+}
+
+#[test]
+fn test_media_playlist_segments_have_no_depth() {
+    let media = rewrite_playlist(
+        MEDIA,
+        "https://cdn.example.com/low/index.m3u8",
+        "client123",
+        &util(),
+        &at_depth(2),
+    )
+    .unwrap();
+    let uris = uri_lines(&media);
+
+    assert_eq!(uris.len(), 2);
+    assert!(uris.iter().all(|l| !l.contains("depth=")));
+}
+
+#[test]
+fn test_master_past_depth_limit_is_rejected() {
+    let result = rewrite_playlist(
+        MASTER,
+        "https://cdn.example.com/loop.m3u8",
+        "client123",
+        &util(),
+        &at_depth(MAX_PLAYLIST_DEPTH),
+    );
+    assert!(result.is_err());
+
+    // media playlists are never nested further so the limit doesn't apply to them
+    assert!(
+        rewrite_playlist(
+            MEDIA,
+            "https://cdn.example.com/loop.m3u8",
+            "client123",
+            &util(),
+            &at_depth(MAX_PLAYLIST_DEPTH)
+        )
+        .is_ok()
+    );
+}
+
+// the old Vec<String> + join version, kept here to compare the single buffer path against
+fn rewrite_with_join(text: &str, base_path: &str, client_id: &str, util: &SignatureUtil) -> String {
+    text.lines()
+        .filter(|line| !line.trim().starts_with("##"))
+        .map(|line| {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                return line.to_string();
+            }
+            let full_url = if trimmed.starts_with("http://") || trimmed.starts_with("https://") {
+                trimmed.to_string()
+            } else {
+                url::Url::parse(base_path)
+                    .unwrap()
+                    .join(trimmed)
+                    .unwrap()
+                    .to_string()
+            };
+            sign_proxy_url(&full_url, client_id, util)
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// expiry comes from the clock so the signature can change between the two runs
+fn without_signatures(playlist: &str) -> String {
+    regex::Regex::new(r"sig=[0-9a-f]+&exp=\d+")
+        .unwrap()
+        .replace_all(playlist, "sig=_&exp=_")
+        .into_owned()
+}
+
+#[test]
+fn test_large_playlist_matches_join_output() {
+    let mut playlist =
+        String::from("#EXTM3U\r\n## processed by something\r\n#EXT-X-TARGETDURATION:6\r\n");
+    for i in 0..20_000 {
+        playlist.push_str("#EXTINF:6.0,\r\n");
+        if i % 100 == 0 {
+            playlist.push_str(&format!("https://other.example.com/abs/seg_{}.ts\r\n", i));
+        } else {
+            playlist.push_str(&format!("seg_{}.ts\r\n", i));
+        }
+    }
+    playlist.push_str("#EXT-X-ENDLIST\r\n");
+
+    let util = util();
+    let rewritten = rewrite_playlist(
+        &playlist,
+        "https://cdn.example.com/vod/index.m3u8",
+        "client123",
+        &util,
+        &at_depth(0),
+    )
+    .unwrap();
+
+    let expected = rewrite_with_join(
+        &playlist,
+        "https://cdn.example.com/vod/",
+        "client123",
+        &util,
+    );
+
+    assert_eq!(
+        without_signatures(&rewritten),
+        without_signatures(&expected)
+    );
+    assert!(!rewritten.contains('\r'));
+    assert!(!rewritten.ends_with('\n'));
+}
+
+const MULTI_LANGUAGE_MASTER: &str = "#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"English\",LANGUAGE=\"en\",DEFAULT=YES,AUTOSELECT=YES,URI=\"en/audio.m3u8\"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"Espanol, Latino\",LANGUAGE=\"es\",DEFAULT=NO,URI=\"es/audio.m3u8\"
+#EXT-X-STREAM-INF:BANDWIDTH=2400000,AUDIO=\"aud\"
+video/index.m3u8";
+
+fn media_line<'a>(playlist: &'a str, language: &str) -> &'a str {
+    playlist
+        .lines()
+        .find(|l| {
+            l.starts_with("#EXT-X-MEDIA") && l.contains(&format!("LANGUAGE=\"{}\"", language))
+        })
+        .unwrap()
+}
+
+#[test]
+fn test_accept_language_es_defaults_to_spanish() {
+    let preselected = preselect_language(MULTI_LANGUAGE_MASTER, "es").unwrap();
+
+    let spanish = media_line(&preselected, "es");
+    assert!(spanish.contains("DEFAULT=YES"));
+    assert!(spanish.contains("AUTOSELECT=YES"));
+    // quoted commas survive the attribute parsing
+    assert!(spanish.contains("NAME=\"Espanol, Latino\""));
+    assert!(media_line(&preselected, "en").contains("DEFAULT=NO"));
+}
+
+#[test]
+fn test_accept_language_is_applied_when_rewriting_master() {
+    let options = PlaylistOptions {
+        accept_language: Some("es-MX,es;q=0.9,en;q=0.5".to_string()),
+        ..Default::default()
+    };
+    let rewritten = rewrite_playlist(
+        MULTI_LANGUAGE_MASTER,
+        "https://cdn.example.com/master.m3u8",
+        "client123",
+        &util(),
+        &options,
+    )
+    .unwrap();
+
+    assert!(media_line(&rewritten, "es").contains("DEFAULT=YES"));
+    assert!(media_line(&rewritten, "en").contains("DEFAULT=NO"));
+}
+
+#[test]
+fn test_unmatched_language_leaves_renditions_alone() {
+    assert!(preselect_language(MULTI_LANGUAGE_MASTER, "fr-FR, de;q=0.8").is_none());
+    assert!(preselect_language(MULTI_LANGUAGE_MASTER, "*").is_none());
+}
+
+#[test]
+fn test_accept_language_orders_by_quality() {
+    assert_eq!(
+        parse_accept_language("en;q=0.5, es-MX, fr;q=0, es;q=0.9"),
+        vec!["es-mx", "es", "en"]
+    );
+}
+
+#[test]
+fn test_live_and_vod_playlists_get_different_cache_control() {
+    let live = MEDIA;
+    let vod = format!("{}\n#EXT-X-ENDLIST", MEDIA);
+    let vod_by_type = "#EXTM3U\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXTINF:6.0,\nseg_001.ts";
+
+    assert_eq!(playlist_kind(live), PlaylistKind::Live);
+    assert_eq!(playlist_kind(&vod), PlaylistKind::Vod);
+    assert_eq!(playlist_kind(vod_by_type), PlaylistKind::Vod);
+    // masters never end, they're treated as live
+    assert_eq!(playlist_kind(MASTER), PlaylistKind::Live);
+
+    let live_header = playlist_cache_control(live, "no-store", "public, max-age=30");
+    let vod_header = playlist_cache_control(&vod, "no-store", "public, max-age=30");
+    assert_eq!(live_header, "no-store");
+    assert_eq!(vod_header, "public, max-age=30");
+    assert_ne!(live_header, vod_header);
+}
+
+const LL_HLS_MEDIA: &str = "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXT-X-PART-INF:PART-TARGET=1.0
+#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=3.0
+#EXTINF:4.0,
+seg_100.ts
+#EXT-X-PART:DURATION=1.0,URI=\"seg_101.0.ts\",INDEPENDENT=YES
+#EXT-X-PART:DURATION=1.0,URI=\"https://parts.example.com/seg_101.1.ts\",BYTERANGE-START=0
+#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"seg_101.2.ts\",BYTERANGE-START=1024,LAST-PART=YES
+#EXT-X-RENDITION-REPORT:URI=\"../high/index.m3u8\",LAST-MSN=101,LAST-PART=1";
+
+// pulls the upstream url back out of a proxied URI="..." attribute
+fn proxied_uri(line: &str) -> String {
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+
+    let start = line.find("URI=\"").unwrap() + 5;
+    let end = start + line[start..].find('"').unwrap();
+    let uri = &line[start..end];
+    assert!(
+        uri.starts_with("/api/v1/proxy?url="),
+        "not proxied: {}",
+        uri
+    );
+
+    let encoded = uri["/api/v1/proxy?url=".len()..].split('&').next().unwrap();
+    String::from_utf8(URL_SAFE_NO_PAD.decode(encoded).unwrap()).unwrap()
+}
+
+fn rewrite_ll_hls() -> String {
+    rewrite_playlist(
+        LL_HLS_MEDIA,
+        "https://cdn.example.com/live/low/index.m3u8",
+        "client123",
+        &util(),
+        &at_depth(0),
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_ll_hls_parts_and_preload_hint_are_proxied() {
+    let rewritten = rewrite_ll_hls();
+    let tag_lines = |tag: &str| -> Vec<String> {
+        rewritten
+            .lines()
+            .filter(|l| l.starts_with(tag))
+            .map(String::from)
+            .collect()
+    };
+
+    let parts = tag_lines("#EXT-X-PART:");
+    assert_eq!(parts.len(), 2);
+    assert_eq!(
+        proxied_uri(&parts[0]),
+        "https://cdn.example.com/live/low/seg_101.0.ts"
+    );
+    assert_eq!(
+        proxied_uri(&parts[1]),
+        "https://parts.example.com/seg_101.1.ts"
+    );
+
+    let hint = &tag_lines("#EXT-X-PRELOAD-HINT:")[0];
+    assert_eq!(
+        proxied_uri(hint),
+        "https://cdn.example.com/live/low/seg_101.2.ts"
+    );
+
+    let report = &tag_lines("#EXT-X-RENDITION-REPORT:")[0];
+    assert_eq!(
+        proxied_uri(report),
+        "https://cdn.example.com/live/high/index.m3u8"
+    );
+}
+
+#[test]
+fn test_ll_hls_part_attributes_are_preserved() {
+    let rewritten = rewrite_ll_hls();
+    let lines: Vec<&str> = rewritten.lines().collect();
+
+    assert!(lines[6].starts_with("#EXT-X-PART:DURATION=1.0,URI=\"/api/v1/proxy?"));
+    assert!(lines[6].ends_with("\",INDEPENDENT=YES"));
+    assert!(lines[7].ends_with("\",BYTERANGE-START=0"));
+    assert!(lines[8].starts_with("#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"/api/v1/proxy?"));
+    assert!(lines[8].ends_with("\",BYTERANGE-START=1024,LAST-PART=YES"));
+    assert!(lines[9].ends_with("\",LAST-MSN=101,LAST-PART=1"));
+
+    // the other tags and the regular segment are untouched / rewritten as before
+    assert_eq!(lines[2], "#EXT-X-PART-INF:PART-TARGET=1.0");
+    assert_eq!(
+        lines[3],
+        "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=3.0"
+    );
+    assert!(lines[5].starts_with("/api/v1/proxy?url="));
+}
+
+#[test]
+fn test_only_live_media_segments_are_tagged_live() {
+    let rewrite = |text: &str| {
+        rewrite_playlist(
+            text,
+            "https://cdn.example.com/low/index.m3u8",
+            "client123",
+            &util(),
+            &at_depth(0),
+        )
+        .unwrap()
+    };
+
+    let live = rewrite(MEDIA);
+    assert!(uri_lines(&live).iter().all(|l| l.ends_with("&live=true")));
+
+    let vod = rewrite(&format!("{}\n#EXT-X-ENDLIST", MEDIA));
+    assert!(uri_lines(&vod).iter().all(|l| !l.contains("live=")));
+
+    let master = rewrite(MASTER);
+    assert!(uri_lines(&master).iter().all(|l| !l.contains("live=")));
+}
+
+fn huge_vod_playlist(segments: usize) -> String {
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-TARGETDURATION:6\n");
+    for i in 0..segments {
+        playlist.push_str(&format!("#EXTINF:6.0,\nseg_{:06}.ts\n", i));
+    }
+    playlist.push_str("#EXT-X-ENDLIST");
+    playlist
+}
+
+// rewrites the playlist on a single threaded runtime while another task ticks every millisecond,
+// the ticker only gets to run if the rewrite isn't holding the one async worker
+async fn ticks_during_rewrite(playlist: &str, blocking_threshold: usize) -> (usize, String) {
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let ticker = {
+        let ticks = ticks.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(1));
+            loop {
+                interval.tick().await;
+                ticks.fetch_add(1, Ordering::SeqCst);
+            }
+        })
+    };
+    // let the ticker get its first tick in
+    while ticks.load(Ordering::SeqCst) == 0 {
+        tokio::task::yield_now().await;
+    }
+
+    let before = ticks.load(Ordering::SeqCst);
+    let rewritten = rewrite_playlist_offloaded(
+        playlist,
+        "https://cdn.example.com/vod/index.m3u8",
+        "client123",
+        &Arc::new(util()),
+        &PlaylistOptions::default(),
+        blocking_threshold,
+        BufferPool::disabled().take(),
+    )
+    .await
+    .unwrap()
+    .into_inner();
+    let during = ticks.load(Ordering::SeqCst) - before;
+
+    ticker.abort();
+    (during, rewritten)
+}
+
+#[tokio::test]
+async fn test_large_playlist_is_rewritten_off_the_async_worker() {
+    let playlist = huge_vod_playlist(20_000);
+
+    let (inline_ticks, inline) = ticks_during_rewrite(&playlist, 0).await;
+    let (offloaded_ticks, offloaded) = ticks_during_rewrite(&playlist, 1024).await;
+
+    assert_eq!(inline_ticks, 0);
+    assert!(offloaded_ticks > 0);
+    assert_eq!(inline, offloaded);
+}
+
+#[tokio::test]
+async fn test_small_playlist_stays_inline() {
+    let (ticks, rewritten) = ticks_during_rewrite(MEDIA, 1024).await;
+
+    assert_eq!(ticks, 0);
+    assert_eq!(uri_lines(&rewritten).len(), 2);
+}
+
+#[test]
+fn test_reused_buffer_gives_the_same_playlist() {
+    let pool = BufferPool::<String>::new(1, 1024 * 1024);
+    let rewrite = |text: &str| {
+        let mut output = pool.take();
+        rewrite_playlist_into(
+            text,
+            "https://cdn.example.com/live/index.m3u8",
+            "client123",
+            &util(),
+            &PlaylistOptions::default(),
+            &mut output,
+        )
+        .unwrap();
+        without_signatures(&output)
+    };
+
+    let master = rewrite(MASTER);
+    let media = rewrite(MEDIA);
+
+    // the media rewrite got the master's buffer back, none of the master may be left in it
+    assert_eq!(pool.recycled(), 1);
+    assert_eq!(
+        media,
+        without_signatures(
+            &rewrite_playlist(
+                MEDIA,
+                "https://cdn.example.com/live/index.m3u8",
+                "client123",
+                &util(),
+                &PlaylistOptions::default(),
+            )
+            .unwrap()
+        )
+    );
+    assert!(!media.contains("depth="));
+    assert!(master.contains("depth=1"));
+}
+
+const ENCRYPTED_MEDIA: &str = "#EXTM3U
+#EXT-X-TARGETDURATION:6
+#EXT-X-KEY:METHOD=AES-128,URI=\"keys/key_1.bin\",IV=0x00000000000000000000000000000001
+#EXTINF:6.0,
+seg_001.ts
+#EXT-X-KEY:METHOD=AES-128,URI=\"https://keys.example.com/key_2.bin\",IV=0x2,KEYFORMAT=\"identity\"
+#EXTINF:6.0,
+seg_002.ts
+#EXT-X-KEY:METHOD=NONE
+#EXTINF:6.0,
+seg_003.ts";
+
+fn tag_lines(playlist: &str, tag: &str) -> Vec<String> {
+    playlist
+        .lines()
+        .filter(|l| l.starts_with(tag))
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn test_aes_key_uris_are_proxied() {
+    let rewritten = rewrite_playlist(
+        ENCRYPTED_MEDIA,
+        "https://cdn.example.com/live/low/index.m3u8",
+        "client123",
+        &util(),
+        &at_depth(0),
+    )
+    .unwrap();
+    let keys = tag_lines(&rewritten, "#EXT-X-KEY:");
+
+    assert_eq!(keys.len(), 3);
+    assert_eq!(
+        proxied_uri(&keys[0]),
+        "https://cdn.example.com/live/low/keys/key_1.bin"
+    );
+    assert_eq!(proxied_uri(&keys[1]), "https://keys.example.com/key_2.bin");
+    // keys aren't segments, they're never tagged live
+    assert!(!keys[0].contains("live="));
+    assert!(!rewritten.contains("keys.example.com"));
+}
+
+#[test]
+fn test_aes_key_attributes_are_preserved() {
+    let rewritten = rewrite_playlist(
+        ENCRYPTED_MEDIA,
+        "https://cdn.example.com/live/low/index.m3u8",
+        "client123",
+        &util(),
+        &at_depth(0),
+    )
+    .unwrap();
+    let keys = tag_lines(&rewritten, "#EXT-X-KEY:");
+
+    assert!(keys[0].starts_with("#EXT-X-KEY:METHOD=AES-128,URI=\"/api/v1/proxy?"));
+    assert!(keys[0].ends_with("\",IV=0x00000000000000000000000000000001"));
+    assert!(keys[1].ends_with("\",IV=0x2,KEYFORMAT=\"identity\""));
+    assert_eq!(keys[2], "#EXT-X-KEY:METHOD=NONE");
+}
+
+#[test]
+fn test_session_keys_and_renditions_in_master_are_proxied() {
+    let master = format!(
+        "{}\n#EXT-X-SESSION-KEY:METHOD=AES-128,URI=\"/keys/session.key\"\n#EXT-X-SESSION-KEY:METHOD=SAMPLE-AES,URI=\"skd://fairplay-id\",KEYFORMAT=\"com.apple.streamingkeydelivery\"",
+        MULTI_LANGUAGE_MASTER
+    );
+    let rewritten = rewrite_playlist(
+        &master,
+        "https://cdn.example.com/event/master.m3u8",
+        "client123",
+        &util(),
+        &at_depth(0),
+    )
+    .unwrap();
+
+    let session_keys = tag_lines(&rewritten, "#EXT-X-SESSION-KEY:");
+    assert_eq!(
+        proxied_uri(&session_keys[0]),
+        "https://cdn.example.com/keys/session.key"
+    );
+    assert!(!session_keys[0].contains("depth="));
+    // fairplay keys aren't fetched over http, they stay as they are
+    assert!(session_keys[1].contains("URI=\"skd://fairplay-id\""));
+
+    let english = media_line(&rewritten, "en");
+    assert_eq!(
+        proxied_uri(english),
+        "https://cdn.example.com/event/en/audio.m3u8"
+    );
+    assert!(english.contains("&depth=1"));
+    assert!(english.starts_with(
+        "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"English\",LANGUAGE=\"en\",DEFAULT=YES,AUTOSELECT=YES,URI=\"/api/v1/proxy?"
+    ));
+}
+
+const CMAF_MASTER: &str = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=2400000,CODECS=\"avc1.640028,mp4a.40.2\"
+cmaf/index.m3u8";
+
+const CMAF_MEDIA: &str = "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-TARGETDURATION:4
+#EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"720@0\"
+#EXTINF:4.0,
+seg_001.m4s
+#EXT-X-DISCONTINUITY
+#EXT-X-MAP:URI=\"https://ads.example.com/ad/init.mp4\"
+#EXTINF:4.0,
+https://ads.example.com/ad/seg_001.m4s";
+
+#[test]
+fn test_cmaf_init_segments_are_proxied() {
+    let master = rewrite_playlist(
+        CMAF_MASTER,
+        "https://cdn.example.com/live/master.m3u8",
+        "client123",
+        &util(),
+        &at_depth(0),
+    )
+    .unwrap();
+    assert!(uri_lines(&master)[0].starts_with("/api/v1/proxy?url="));
+
+    let media = rewrite_playlist(
+        CMAF_MEDIA,
+        "https://cdn.example.com/live/cmaf/index.m3u8",
+        "client123",
+        &util(),
+        &at_depth(1),
+    )
+    .unwrap();
+    let maps = tag_lines(&media, "#EXT-X-MAP:");
+
+    assert_eq!(maps.len(), 2);
+    assert_eq!(
+        proxied_uri(&maps[0]),
+        "https://cdn.example.com/live/cmaf/init.mp4"
+    );
+    assert!(maps[0].starts_with("#EXT-X-MAP:URI=\"/api/v1/proxy?"));
+    assert!(maps[0].ends_with("&live=true\",BYTERANGE=\"720@0\""));
+    assert_eq!(proxied_uri(&maps[1]), "https://ads.example.com/ad/init.mp4");
+    assert!(!media.contains("URI=\"init.mp4\""));
+}
+
+// the `exp=` of a proxied url
+fn expiry(proxied: &str) -> i64 {
+    proxied
+        .split('&')
+        .find_map(|p| p.strip_prefix("exp="))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn test_master_variants_are_marked_as_playlists() {
+    let clock = Arc::new(MockClock::new(1_700_000_000));
+    let util = SignatureUtil::new("test_secret".to_string()).with_clock(clock);
+    let options = PlaylistOptions {
+        playlist_expiry_hours: Some(2),
+        ..Default::default()
+    };
+
+    let master = rewrite_playlist(
+        MASTER,
+        "https://cdn.example.com/master.m3u8",
+        "client123",
+        &util,
+        &options,
+    )
+    .unwrap();
+    for line in uri_lines(&master) {
+        assert!(line.contains("&type=playlist"));
+        assert_eq!(expiry(line), 1_700_000_000 + 2 * 3600);
+    }
+
+    let media = rewrite_playlist(
+        MEDIA,
+        "https://cdn.example.com/low/index.m3u8",
+        "client123",
+        &util,
+        &options,
+    )
+    .unwrap();
+    for line in uri_lines(&media) {
+        assert!(!line.contains("type="));
+        assert_eq!(expiry(line), 1_700_000_000 + 12 * 3600);
+    }
+}
+
+#[test]
+fn test_renditions_are_marked_as_playlists() {
+    let rewritten = rewrite_playlist(
+        MULTI_LANGUAGE_MASTER,
+        "https://cdn.example.com/master.m3u8",
+        "client123",
+        &util(),
+        &at_depth(0),
+    )
+    .unwrap();
+
+    assert!(media_line(&rewritten, "en").contains("&depth=1&type=playlist\""));
+    assert!(media_line(&rewritten, "es").contains("&depth=1&type=playlist\""));
+    assert!(uri_lines(&rewritten)[0].ends_with("&depth=1&type=playlist"));
+}
+
+fn query_param<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    line.split(['?', '&', '"'])
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn decrypting() -> PlaylistOptions {
+    PlaylistOptions {
+        decrypt: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_decrypt_strips_aes_keys_and_tags_segments() {
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+
+    let rewritten = rewrite_playlist(
+        ENCRYPTED_MEDIA,
+        "https://cdn.example.com/live/low/index.m3u8",
+        "client123",
+        &util(),
+        &decrypting(),
+    )
+    .unwrap();
+    let segments = uri_lines(&rewritten);
+
+    // the METHOD=NONE one stays, it doesn't hide anything from the player
+    assert_eq!(
+        tag_lines(&rewritten, "#EXT-X-KEY:"),
+        vec!["#EXT-X-KEY:METHOD=NONE"]
+    );
+    let key_url = |line: &str| {
+        let encoded = query_param(line, "key").unwrap();
+        String::from_utf8(URL_SAFE_NO_PAD.decode(encoded).unwrap()).unwrap()
+    };
+    assert_eq!(
+        key_url(segments[0]),
+        "https://cdn.example.com/live/low/keys/key_1.bin"
+    );
+    assert_eq!(
+        query_param(segments[0], "iv"),
+        Some("00000000000000000000000000000001")
+    );
+    assert_eq!(key_url(segments[1]), "https://keys.example.com/key_2.bin");
+    assert_eq!(
+        query_param(segments[1], "iv"),
+        Some("00000000000000000000000000000002")
+    );
+    assert_eq!(query_param(segments[2], "key"), None);
+}
+
+// what the edge extractor checks a proxied url's signature against
+fn signature_valid(line: &str) -> bool {
+    let param = |name: &str| query_param(line, name);
+    let payload = signed_payload(param("url").unwrap(), param);
+    util().verify_signature(
+        param("client").unwrap(),
+        param("exp").unwrap().parse().unwrap(),
+        &payload,
+        param("sig").unwrap(),
+    )
+}
+
+#[test]
+fn test_decrypt_key_and_iv_are_signed() {
+    let rewritten = rewrite_playlist(
+        ENCRYPTED_MEDIA,
+        "https://cdn.example.com/live/low/index.m3u8",
+        "client123",
+        &util(),
+        &decrypting(),
+    )
+    .unwrap();
+    let segment = uri_lines(&rewritten)[0];
+    assert!(signature_valid(segment));
+
+    // pointing a signed segment at another iv or key breaks it
+    let other_iv = segment.replace(
+        "iv=00000000000000000000000000000001",
+        "iv=ffffffffffffffffffffffffffffffff",
+    );
+    assert!(!signature_valid(&other_iv));
+    let key = query_param(segment, "key").unwrap();
+    let other_key = segment.replace(key, "aHR0cHM6Ly9ldmlsLmV4YW1wbGUuY29tL2tleQ");
+    assert!(!signature_valid(&other_key));
+
+    // plain segments don't have a key or iv to sign
+    assert!(signature_valid(uri_lines(&rewritten)[2]));
+}
+
+#[test]
+fn test_playlist_depth_is_signed() {
+    let rewritten = rewrite_playlist(
+        MASTER,
+        "https://cdn.example.com/master.m3u8",
+        "client123",
+        &util(),
+        &at_depth(1),
+    )
+    .unwrap();
+    let variant = uri_lines(&rewritten)[0];
+    assert!(signature_valid(variant));
+
+    // resetting the depth to get past the nesting cap breaks it, so does dropping it
+    assert!(!signature_valid(&variant.replace("&depth=2", "&depth=0")));
+    assert!(!signature_valid(&variant.replace("&depth=2", "")));
+}
+
+#[test]
+fn test_stream_is_signed_on_every_url() {
+    let options = PlaylistOptions {
+        stream: Some("ppvsu-7".to_string()),
+        ..Default::default()
+    };
+    for (text, target) in [
+        (MASTER, "https://cdn.example.com/master.m3u8"),
+        (MEDIA, "https://cdn.example.com/low/index.m3u8"),
+    ] {
+        let rewritten = rewrite_playlist(text, target, "client123", &util(), &options).unwrap();
+        for line in uri_lines(&rewritten) {
+            assert_eq!(query_param(line, "stream"), Some("ppvsu-7"));
+            assert!(signature_valid(line));
+            // filing it under another stream breaks it
+            assert!(!signature_valid(&line.replace("ppvsu-7", "ppvsu-8")));
+        }
+    }
+}
+
+#[test]
+fn test_playlist_marker_and_decrypt_are_signed() {
+    let rewritten = rewrite_playlist(
+        MASTER,
+        "https://cdn.example.com/master.m3u8",
+        "client123",
+        &util(),
+        &decrypting(),
+    )
+    .unwrap();
+    let variant = uri_lines(&rewritten)[0];
+    assert!(variant.contains("&type=playlist&decrypt=true"));
+    assert!(signature_valid(variant));
+    assert!(!signature_valid(&variant.replace("&decrypt=true", "")));
+    assert!(!signature_valid(&variant.replace("&type=playlist", "")));
+
+    // neither can be put on a segment to change how the proxy handles it
+    let media = rewrite_playlist(
+        MEDIA,
+        "https://cdn.example.com/low/index.m3u8",
+        "client123",
+        &util(),
+        &at_depth(0),
+    )
+    .unwrap();
+    let segment = uri_lines(&media)[0];
+    assert!(signature_valid(segment));
+    assert!(!signature_valid(&format!("{}&type=playlist", segment)));
+    assert!(!signature_valid(&format!("{}&decrypt=true", segment)));
+}
+
+#[test]
+fn test_live_flag_is_signed() {
+    let rewrite = |text: &str| {
+        rewrite_playlist(
+            text,
+            "https://cdn.example.com/low/index.m3u8",
+            "client123",
+            &util(),
+            &at_depth(0),
+        )
+        .unwrap()
+    };
+    let live = rewrite(MEDIA);
+    let live_segment = uri_lines(&live)[0];
+    assert!(signature_valid(live_segment));
+    let vod = rewrite(&format!("{}\n#EXT-X-ENDLIST", MEDIA));
+    let vod_segment = uri_lines(&vod)[0];
+    assert!(signature_valid(vod_segment));
+
+    // a live segment can't be passed off as vod to keep it cached, or the other way round
+    assert!(!signature_valid(&live_segment.replace("&live=true", "")));
+    assert!(!signature_valid(&format!("{}&live=true", vod_segment)));
+}
+
+#[test]
+fn test_decrypt_keeps_keys_of_ll_hls_playlists() {
+    let playlist = "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXT-X-PART-INF:PART-TARGET=1.0
+#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x1
+#EXTINF:4.0,
+seg_100.ts
+#EXT-X-PART:DURATION=1.0,URI=\"seg_101.0.ts\"
+#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"seg_101.1.ts\"";
+
+    let rewritten = rewrite_playlist(
+        playlist,
+        "https://cdn.example.com/live/index.m3u8",
+        "client123",
+        &util(),
+        &decrypting(),
+    )
+    .unwrap();
+
+    // parts can't be decrypted by the proxy, the player gets the (proxied) key instead
+    let keys = tag_lines(&rewritten, "#EXT-X-KEY:");
+    assert_eq!(keys.len(), 1);
+    assert_eq!(
+        proxied_uri(&keys[0]),
+        "https://cdn.example.com/live/key.bin"
+    );
+    assert_eq!(query_param(uri_lines(&rewritten)[0], "key"), None);
+    for part in tag_lines(&rewritten, "#EXT-X-PART:")
+        .iter()
+        .chain(&tag_lines(&rewritten, "#EXT-X-PRELOAD-HINT:"))
+    {
+        assert_eq!(query_param(part, "key"), None);
+    }
+}
+
+#[test]
+fn test_decrypt_uses_the_media_sequence_without_an_iv() {
+    let playlist = "#EXTM3U
+#EXT-X-TARGETDURATION:6
+#EXT-X-MEDIA-SEQUENCE:41
+#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"
+#EXTINF:6.0,
+seg_041.ts
+#EXTINF:6.0,
+seg_042.ts";
+
+    let rewritten = rewrite_playlist(
+        playlist,
+        "https://cdn.example.com/live/index.m3u8",
+        "client123",
+        &util(),
+        &decrypting(),
+    )
+    .unwrap();
+    let segments = uri_lines(&rewritten);
+
+    assert_eq!(
+        query_param(segments[0], "iv"),
+        Some("00000000000000000000000000000029")
+    );
+    assert_eq!(
+        query_param(segments[1], "iv"),
+        Some("0000000000000000000000000000002a")
+    );
+}
+
+#[test]
+fn test_decrypt_leaves_sample_aes_to_the_player() {
+    let playlist = "#EXTM3U
+#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"https://keys.example.com/key.bin\"
+#EXTINF:6.0,
+seg_001.ts";
+
+    let rewritten = rewrite_playlist(
+        playlist,
+        "https://cdn.example.com/live/index.m3u8",
+        "client123",
+        &util(),
+        &decrypting(),
+    )
+    .unwrap();
+
+    assert_eq!(tag_lines(&rewritten, "#EXT-X-KEY:").len(), 1);
+    assert_eq!(query_param(uri_lines(&rewritten)[0], "key"), None);
+}
+
+#[test]
+fn test_decrypt_is_carried_onto_variants() {
+    let rewritten = rewrite_playlist(
+        MASTER,
+        "https://cdn.example.com/master.m3u8",
+        "client123",
+        &util(),
+        &decrypting(),
+    )
+    .unwrap();
+
+    for line in uri_lines(&rewritten) {
+        assert_eq!(query_param(line, "decrypt"), Some("true"));
+    }
+    let plain = rewrite_playlist(
+        MASTER,
+        "https://cdn.example.com/master.m3u8",
+        "client123",
+        &util(),
+        &at_depth(0),
+    )
+    .unwrap();
+    assert!(!plain.contains("decrypt="));
+}
+
+// the upstream url a proxied segment line points at
+fn upstream_url(line: &str) -> String {
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+
+    let encoded = query_param(line, "url").unwrap();
+    String::from_utf8(URL_SAFE_NO_PAD.decode(encoded).unwrap()).unwrap()
+}
+
+#[test]
+fn test_protocol_relative_absolute_and_relative_uris_resolve() {
+    let playlist = "#EXTM3U
+#EXT-X-TARGETDURATION:6
+#EXT-X-MAP:URI=\"//init.example.net/init.mp4\"
+#EXTINF:6.0,
+//edge.example.net/live/seg_1.ts
+#EXTINF:6.0,
+/abs/seg_2.ts
+#EXTINF:6.0,
+rel/seg_3.ts
+#EXTINF:6.0,
+../up/seg_4.ts";
+
+    let rewritten = rewrite_playlist(
+        playlist,
+        "http://cdn.example.com/live/low/index.m3u8",
+        "client123",
+        &util(),
+        &at_depth(0),
+    )
+    .unwrap();
+    let resolved: Vec<String> = uri_lines(&rewritten)
+        .into_iter()
+        .map(upstream_url)
+        .collect();
+
+    // protocol relative ones keep the playlist's scheme, not https
+    assert_eq!(
+        resolved,
+        vec![
+            "http://edge.example.net/live/seg_1.ts",
+            "http://cdn.example.com/abs/seg_2.ts",
+            "http://cdn.example.com/live/low/rel/seg_3.ts",
+            "http://cdn.example.com/live/up/seg_4.ts",
+        ]
+    );
+    assert_eq!(
+        proxied_uri(&tag_lines(&rewritten, "#EXT-X-MAP:")[0]),
+        "http://init.example.net/init.mp4"
+    );
+}
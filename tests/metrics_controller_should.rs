@@ -0,0 +1,88 @@
+mod common;
+
+use std::sync::Arc;
+
+use api::Database;
+use api::server::api::metrics_controller::MetricsController;
+use api::server::services::proxy_cache_services::{
+    ProxyCacheConfig, ProxyCacheService, ProxyCacheServiceTrait,
+};
+use api::server::services::rate_limit_services::{
+    EdgeRateLimitService, RateLimitConfig, RateLimitServiceTrait,
+};
+use api::server::services::upstream_attempt_services::UpstreamAttemptLog;
+use common::{MockResponse, MockUpstream};
+use metrics_exporter_prometheus::PrometheusBuilder;
+
+const SEGMENT_URL: &str = "https://cdn.example.com/live/seg_001.ts";
+
+// an upstream that answers every request with a 503
+async fn unavailable_upstream() -> String {
+    MockUpstream::always(MockResponse::status(503))
+        .await
+        .url("/index.m3u8")
+}
+
+#[tokio::test]
+async fn test_metrics_endpoint_exposes_cache_upstream_and_rate_limit_metrics() {
+    // the one global recorder for this test binary
+    let handle = PrometheusBuilder::new().install_recorder().unwrap();
+
+    let db = Arc::new(Database::in_memory().await.unwrap());
+
+    let cache = ProxyCacheService::new(
+        db.clone(),
+        reqwest::Client::new(),
+        ProxyCacheConfig::default(),
+    );
+    cache.get_cached(SEGMENT_URL, false).await;
+    cache.cache_segment(SEGMENT_URL, &[0x47u8; 188]).await;
+    cache.get_cached(SEGMENT_URL, false).await;
+
+    let limiter = EdgeRateLimitService::with_config(
+        db,
+        RateLimitConfig {
+            max_requests_per_window: 1,
+            ..Default::default()
+        },
+    );
+    limiter.check_rate_limit("viewer", None).await;
+    limiter.check_rate_limit("viewer", None).await;
+
+    let attempts = UpstreamAttemptLog::new(10, false);
+    let upstream = unavailable_upstream().await;
+    attempts
+        .send("proxy", reqwest::Client::new().get(&upstream))
+        .await
+        .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, MetricsController::app(handle))
+            .await
+            .unwrap();
+    });
+
+    let response = reqwest::get(format!("http://{}/metrics", addr))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let body = response.text().await.unwrap();
+
+    for expected in [
+        "proxy_cache_misses_total",
+        "proxy_cache_hits_total{kind=\"segment\"}",
+        "rate_limit_checks_total{result=\"allowed\"}",
+        "rate_limit_checks_total{result=\"limited\"}",
+        "upstream_requests_total{source=\"proxy\",status=\"503\"}",
+        "upstream_request_duration_seconds",
+    ] {
+        assert!(
+            body.contains(expected),
+            "{} missing from\n{}",
+            expected,
+            body
+        );
+    }
+}
@@ -0,0 +1,228 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use api::Database;
+use api::database::stream::{Game, StreamsRepository};
+use api::server::error::Error;
+use api::server::services::ppvsu_services::{
+    PpvsuService, PpvsuServiceTrait, check_refetch_status,
+};
+use api::server::services::refresh_health_services::RefreshRetry;
+use api::server::utils::clock_utils::MockClock;
+use api::server::utils::ttl_utils::TtlJitter;
+use common::{MockResponse, MockUpstream};
+use reqwest::StatusCode;
+
+fn cached_game(id: i64, cache_time: i64) -> Game {
+    Game {
+        id,
+        name: format!("game {}", id),
+        poster: String::new(),
+        start_time: 0,
+        end_time: 0,
+        cache_time,
+        video_link: format!("https://embed.example.com/embed/{}", id),
+        category: "Football".to_string(),
+    }
+}
+
+// well past the one hour cache window
+fn stale_game(id: i64) -> Game {
+    cached_game(id, 0)
+}
+
+// answers every request with the given status and an empty json body
+async fn upstream(status: u16) -> String {
+    MockUpstream::always(MockResponse::json(status))
+        .await
+        .base()
+}
+
+async fn service(status: u16) -> (Arc<Database>, PpvsuService) {
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    db.store_game("ppvsu", &stale_game(42)).await.unwrap();
+    let service = PpvsuService::new(db.clone()).with_api_base(upstream(status).await);
+    (db, service)
+}
+
+#[test]
+fn test_upstream_404_is_not_found() {
+    assert!(matches!(
+        check_refetch_status(42, StatusCode::NOT_FOUND),
+        Err(Error::NotFound(_))
+    ));
+}
+
+#[test]
+fn test_upstream_failures_are_transient() {
+    for status in [
+        StatusCode::INTERNAL_SERVER_ERROR,
+        StatusCode::BAD_GATEWAY,
+        StatusCode::SERVICE_UNAVAILABLE,
+        StatusCode::TOO_MANY_REQUESTS,
+    ] {
+        assert!(
+            matches!(
+                check_refetch_status(42, status),
+                Err(Error::InternalServerErrorWithContext(_))
+            ),
+            "{} should be transient",
+            status
+        );
+    }
+}
+
+#[test]
+fn test_success_passes() {
+    assert!(check_refetch_status(42, StatusCode::OK).is_ok());
+}
+
+#[tokio::test]
+async fn test_cached_game_is_fresh_for_an_hour_on_the_clock() {
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    db.store_game("ppvsu", &cached_game(42, 1_700_000_000))
+        .await
+        .unwrap();
+
+    let clock = Arc::new(MockClock::new(1_700_000_000));
+    // no jitter so the hour is exact
+    let service = PpvsuService::new(db.clone())
+        .with_clock(clock.clone())
+        .with_games_ttl(3600, TtlJitter::new(0));
+
+    // still fresh, upstream isn't asked
+    clock.advance(3600);
+    assert_eq!(service.get_game_by_id(42).await.unwrap().id, 42);
+    assert_eq!(
+        service.get_current_timestamp().await.unwrap(),
+        1_700_003_600
+    );
+}
+
+#[tokio::test]
+async fn test_true_404_removes_game_from_cache() {
+    let (db, service) = service(404).await;
+
+    let result = service.get_game_by_id(42).await;
+
+    assert!(matches!(result, Err(Error::NotFound(_))));
+    assert!(db.get_game("ppvsu", 42).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_transient_failure_serves_stale_copy() {
+    let (db, service) = service(500).await;
+
+    let game = service.get_game_by_id(42).await.unwrap();
+
+    assert_eq!(game.id, 42);
+    assert_eq!(game.cache_time, 0);
+    assert!(db.get_game("ppvsu", 42).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_transient_failure_without_cache_is_not_found() {
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    let service = PpvsuService::new(db.clone()).with_api_base(upstream(503).await);
+
+    let result = service.get_game_by_id(7).await;
+
+    assert!(matches!(result, Err(Error::NotFound(_))));
+}
+
+#[tokio::test]
+async fn test_cached_game_goes_stale_after_an_hour_on_the_clock() {
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    db.store_game("ppvsu", &cached_game(42, 1_700_000_000))
+        .await
+        .unwrap();
+
+    let clock = Arc::new(MockClock::new(1_700_003_601));
+    // no jitter so the hour is exact
+    let service = PpvsuService::new(db.clone())
+        .with_api_base(upstream(404).await)
+        .with_clock(clock.clone())
+        .with_games_ttl(3600, TtlJitter::new(0));
+
+    // stale now, the refetch finds it gone upstream
+    let result = service.get_game_by_id(42).await;
+    assert!(matches!(result, Err(Error::NotFound(_))));
+    assert!(db.get_game("ppvsu", 42).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_refetch_goes_to_the_configured_base_and_provider_key() {
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    db.store_game("mirror", &stale_game(42)).await.unwrap();
+    db.store_game("ppvsu", &stale_game(42)).await.unwrap();
+    let api = MockUpstream::always(MockResponse::json(404)).await;
+    let service = PpvsuService::new(db.clone())
+        .with_api_base(api.base())
+        .with_provider_key("mirror");
+
+    let result = service.get_game_by_id(42).await;
+
+    assert!(matches!(result, Err(Error::NotFound(_))));
+    assert_eq!(
+        api.request_lines(),
+        vec!["GET /api/streams/42 HTTP/1.1".to_string()]
+    );
+    // only the configured provider's copy is dropped
+    assert!(db.get_game("mirror", 42).await.unwrap().is_none());
+    assert!(db.get_game("ppvsu", 42).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_game_list_comes_from_the_configured_base() {
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    let api = MockUpstream::always(MockResponse::json(503)).await;
+    let service = PpvsuService::new(db).with_api_base(api.base());
+
+    assert!(service.fetch_and_cache_games().await.is_err());
+    assert_eq!(
+        api.request_lines(),
+        vec!["GET /api/streams HTTP/1.1".to_string()]
+    );
+}
+
+#[tokio::test]
+async fn test_failed_refresh_serves_stale_games_and_retries_in_the_background() {
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    db.store_game("ppvsu", &stale_game(42)).await.unwrap();
+    db.set_last_fetch_time("ppvsu", 0).await.unwrap();
+    let api = MockUpstream::always(MockResponse::json(503)).await;
+    let retry = Arc::new(RefreshRetry::new(Duration::from_millis(50)));
+    let service = PpvsuService::new(db.clone())
+        .with_api_base(api.base())
+        .with_refresh_retry(retry.clone());
+
+    for _ in 0..2 {
+        let games = service.get_games_with_refresh().await.unwrap();
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].id, 42);
+    }
+    assert!(retry.is_scheduled());
+    assert_eq!(api.hits(), 2);
+
+    // both requests served stale, still only one retry
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!retry.is_scheduled());
+    assert_eq!(api.hits(), 3);
+
+    // the failed retry kept the stale games too
+    assert!(db.get_game("ppvsu", 42).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_failed_refresh_without_cache_is_an_error() {
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    let retry = Arc::new(RefreshRetry::new(Duration::from_millis(50)));
+    let service = PpvsuService::new(db)
+        .with_api_base(upstream(503).await)
+        .with_refresh_retry(retry.clone());
+
+    assert!(service.get_games_with_refresh().await.is_err());
+    assert!(!retry.is_scheduled());
+}
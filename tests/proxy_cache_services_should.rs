@@ -0,0 +1,924 @@
+mod common;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use api::server::services::proxy_cache_services::{
+    CacheBypassPattern, InflightRegistry, M3u8Fill, M3u8FillLock, PrefetchScheduler,
+    ProxyCacheConfig, ProxyCacheService, ProxyCacheServiceTrait, SegmentMemoryCache,
+};
+use api::server::services::shutdown_services::ShutdownHook;
+use api::server::utils::m3u8_utils::{PlaylistOptions, rewrite_playlist};
+use api::server::utils::segment_utils::{SegmentProblem, check_segment};
+use api::server::utils::signature_utils::SignatureUtil;
+use api::server::utils::upstream_utils::{HostAllowlist, UpstreamConnection, apply_schema_headers};
+use api::{Database, RedisDatabase};
+use common::{MockResponse, MockUpstream};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn cache_with_bypass(patterns: &str) -> (ProxyCacheService, Database) {
+    let db = Database::in_memory().await.unwrap();
+    let config = ProxyCacheConfig {
+        bypass_patterns: CacheBypassPattern::parse_list(patterns),
+        ..Default::default()
+    };
+    let cache = ProxyCacheService::new(Arc::new(db.clone()), reqwest::Client::new(), config);
+    (cache, db)
+}
+
+// whole mpeg-ts packets, sync byte then filler
+fn ts_segment(packets: usize) -> Vec<u8> {
+    let mut bytes = vec![0xffu8; packets * 188];
+    for packet in bytes.chunks_mut(188) {
+        packet[0] = 0x47;
+    }
+    bytes
+}
+
+async fn stored_keys(db: &Database) -> Vec<String> {
+    match db {
+        Database::Memory(mem) => mem.store.scan("pcache:*").await.unwrap(),
+        Database::Redis(_) => unreachable!("only used with the in-memory store"),
+    }
+}
+
+#[tokio::test]
+async fn test_bypassed_segment_is_not_stored_or_looked_up() {
+    let (cache, db) = cache_with_bypass("adsegment,re:/keyframe_\\d+\\.ts$").await;
+
+    let ad_url = "https://cdn.example.com/adsegment/001.ts";
+    let keyframe_url = "https://cdn.example.com/live/keyframe_12.ts";
+
+    cache.cache_segment(ad_url, b"ad bytes").await;
+    cache.cache_segment(keyframe_url, b"keyframe bytes").await;
+    cache.cache_m3u8(ad_url, "#EXTM3U").await;
+
+    assert!(stored_keys(&db).await.is_empty());
+    assert_eq!(cache.get_cached(ad_url, false).await, (None, None));
+    assert_eq!(cache.get_cached(keyframe_url, false).await, (None, None));
+}
+
+#[tokio::test]
+async fn test_non_matching_segment_is_cached_normally() {
+    let (cache, db) = cache_with_bypass("adsegment").await;
+
+    let url = "https://cdn.example.com/live/segment_001.ts";
+    cache.cache_segment(url, &ts_segment(2)).await;
+
+    // the segment and when it was cached
+    assert_eq!(stored_keys(&db).await.len(), 2);
+    assert_eq!(
+        cache.get_cached(url, false).await,
+        (None, Some(ts_segment(2)))
+    );
+}
+
+#[test]
+fn test_invalid_bypass_regex_is_skipped() {
+    let patterns = CacheBypassPattern::parse_list("re:([,plain, ,");
+    // "re:([" is invalid and dropped, the blank entries are ignored
+    assert_eq!(patterns.len(), 1);
+    assert!(patterns[0].matches("https://host/plain.ts"));
+}
+
+async fn cache_with_stale_if_error(seconds: u64) -> (ProxyCacheService, Database) {
+    let db = Database::in_memory().await.unwrap();
+    let config = ProxyCacheConfig {
+        stale_if_error_seconds: seconds,
+        ..Default::default()
+    };
+    let cache = ProxyCacheService::new(Arc::new(db.clone()), reqwest::Client::new(), config);
+    (cache, db)
+}
+
+#[tokio::test]
+async fn test_expired_playlist_is_served_when_upstream_fails() {
+    let (cache, db) = cache_with_stale_if_error(30).await;
+    let url = "https://cdn.example.com/live/index.m3u8";
+    let playlist = "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6.0,\nseg_001.ts";
+
+    cache.cache_m3u8(url, playlist).await;
+
+    // the normal copy expires after a few seconds, drop it like the ttl ran out
+    let fresh_keys: Vec<String> = stored_keys(&db)
+        .await
+        .into_iter()
+        .filter(|k| k.starts_with("pcache:m3u8:") && !k.starts_with("pcache:m3u8:stale:"))
+        .collect();
+    assert_eq!(fresh_keys.len(), 1);
+    match &db {
+        Database::Memory(mem) => {
+            mem.store.del(&fresh_keys[0]).await.unwrap();
+        }
+        Database::Redis(_) => unreachable!("only used with the in-memory store"),
+    }
+    assert_eq!(cache.get_cached(url, false).await, (None, None));
+
+    // what the proxy falls back to when the upstream fetch fails, re-signed for the client
+    let stale = cache.get_stale_m3u8(url).await.unwrap();
+    let served = rewrite_playlist(
+        &stale,
+        url,
+        "client-1",
+        &SignatureUtil::new("test_secret".to_string()),
+        &PlaylistOptions::default(),
+    )
+    .unwrap();
+    assert!(served.contains("/api/v1/proxy?url="));
+    assert!(served.contains("client=client-1"));
+}
+
+#[tokio::test]
+async fn test_stale_playlist_is_not_kept_when_disabled() {
+    let (cache, db) = cache_with_stale_if_error(0).await;
+    let url = "https://cdn.example.com/live/index.m3u8";
+
+    cache.cache_m3u8(url, "#EXTM3U").await;
+
+    assert_eq!(stored_keys(&db).await.len(), 1);
+    assert_eq!(cache.get_stale_m3u8(url).await, None);
+}
+
+#[tokio::test]
+async fn test_prefetches_from_two_clients_interleave() {
+    let scheduler = Arc::new(PrefetchScheduler::new(2, 1));
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    // client a queues a big playlist first, client b shows up right after with a small one
+    let mut tasks = Vec::new();
+    for (client, count) in [("a", 6), ("b", 3)] {
+        for _ in 0..count {
+            let scheduler = scheduler.clone();
+            let order = order.clone();
+            tasks.push(tokio::spawn(async move {
+                let _permits = scheduler.acquire(client).await;
+                order.lock().unwrap().push(client);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }));
+        }
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let order = order.lock().unwrap().clone();
+    // b finishes well before a's backlog does instead of waiting behind all of it
+    let last_b = order.iter().rposition(|c| *c == "b").unwrap();
+    assert!(last_b < order.len() - 2, "b was starved: {:?}", order);
+}
+
+#[tokio::test]
+async fn test_prefetch_sends_same_headers_as_foreground_fetch() {
+    let cdn = MockUpstream::always(MockResponse::ok(*b"G\0\0\0")).await;
+    let url = cdn.url("/strm.poocloud.in/live/seg_001.ts");
+    let http = reqwest::Client::new();
+
+    // what the proxy controller sends for a sports segment
+    apply_schema_headers(
+        UpstreamConnection::default().apply(http.get(&url)),
+        "sports",
+        &url,
+    )
+    .send()
+    .await
+    .unwrap();
+    let foreground = cdn.request_headers(0).await;
+
+    let db = Database::in_memory().await.unwrap();
+    let cache = ProxyCacheService::new(Arc::new(db), http, ProxyCacheConfig::default());
+    cache
+        .prefetch_segments("client-1", "sports", vec![url.clone()])
+        .await;
+    let prefetch = cdn.request_headers(1).await;
+
+    assert!(foreground.iter().any(|h| h.starts_with("referer:")));
+    assert_eq!(prefetch, foreground);
+}
+
+#[tokio::test]
+async fn test_prefetch_skips_hosts_that_are_not_allowed() {
+    let cdn = MockUpstream::always(MockResponse::ok(*b"G\0\0\0")).await;
+    let url = cdn.url("/live/seg_001.ts");
+
+    let db = Database::in_memory().await.unwrap();
+    let config = ProxyCacheConfig {
+        allowed_hosts: HostAllowlist::parse_list(".poocloud.in"),
+        ..Default::default()
+    };
+    let cache = ProxyCacheService::new(Arc::new(db.clone()), reqwest::Client::new(), config);
+    cache
+        .prefetch_segments("client-1", "sports", vec![url.clone()])
+        .await;
+
+    assert_eq!(cdn.hits(), 0);
+    assert!(stored_keys(&db).await.is_empty());
+}
+
+async fn cache_verifying_ts() -> (ProxyCacheService, Database) {
+    let db = Database::in_memory().await.unwrap();
+    let config = ProxyCacheConfig {
+        verify_ts_sync: true,
+        ..Default::default()
+    };
+    let cache = ProxyCacheService::new(Arc::new(db.clone()), reqwest::Client::new(), config);
+    (cache, db)
+}
+
+#[tokio::test]
+async fn test_html_error_body_is_not_cached() {
+    let (cache, db) = cache_with_bypass("").await;
+    let url = "https://cdn.example.com/live/segment_001.ts";
+
+    let mut page = b"\n<!DOCTYPE html><html><head><title>Just a moment...</title>".to_vec();
+    page.resize(4096, b' ');
+    cache.cache_segment(url, &page).await;
+    cache.cache_segment(url, b"tiny").await;
+
+    assert!(stored_keys(&db).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_malformed_ts_is_not_cached() {
+    let (cache, db) = cache_verifying_ts().await;
+
+    let mut bad_sync = ts_segment(4);
+    bad_sync[188 * 2] = 0x00;
+    cache
+        .cache_segment("https://cdn.example.com/live/bad_sync.ts", &bad_sync)
+        .await;
+
+    let mut truncated = ts_segment(4);
+    truncated.truncate(188 * 3 + 20);
+    cache
+        .cache_segment("https://cdn.example.com/live/truncated.ts", &truncated)
+        .await;
+
+    assert!(stored_keys(&db).await.is_empty());
+
+    cache
+        .cache_segment(
+            "https://cdn.example.com/live/good.ts?token=abc",
+            &ts_segment(4),
+        )
+        .await;
+    assert_eq!(stored_keys(&db).await.len(), 2);
+}
+
+#[test]
+fn test_segment_problems_are_reported() {
+    assert_eq!(
+        check_segment("https://cdn/a.ts", b"<html><body>403</body></html>", false),
+        Err(SegmentProblem::Html)
+    );
+    assert_eq!(
+        check_segment("https://cdn/a.ts", &[0x47; 10], false),
+        Err(SegmentProblem::TooSmall(10))
+    );
+    // sync isn't checked for other containers or when turned off
+    let mut not_ts = ts_segment(2);
+    not_ts[188] = 0x00;
+    assert_eq!(check_segment("https://cdn/a.m4s", &not_ts, true), Ok(()));
+    assert_eq!(check_segment("https://cdn/a.ts", &not_ts, false), Ok(()));
+    assert_eq!(
+        check_segment("https://cdn/a.ts", &not_ts, true),
+        Err(SegmentProblem::BadTsSync(188))
+    );
+}
+
+async fn cache_with_live_max_age(seconds: u64) -> ProxyCacheService {
+    let db = Database::in_memory().await.unwrap();
+    let config = ProxyCacheConfig {
+        live_segment_max_age_seconds: seconds,
+        ..Default::default()
+    };
+    ProxyCacheService::new(Arc::new(db), reqwest::Client::new(), config)
+}
+
+#[tokio::test]
+async fn test_live_segment_older_than_max_age_is_a_miss() {
+    let cache = cache_with_live_max_age(1).await;
+    let url = "https://cdn.example.com/live/segment_001.ts";
+
+    cache.cache_segment(url, &ts_segment(2)).await;
+    assert_eq!(
+        cache.get_cached(url, true).await,
+        (None, Some(ts_segment(2)))
+    );
+
+    tokio::time::sleep(Duration::from_millis(1200)).await;
+
+    assert_eq!(cache.get_cached(url, true).await, (None, None));
+    // same entry through a vod playlist is still within its TTL
+    assert_eq!(
+        cache.get_cached(url, false).await,
+        (None, Some(ts_segment(2)))
+    );
+}
+
+#[tokio::test]
+async fn test_live_max_age_of_zero_serves_the_full_ttl() {
+    let cache = cache_with_live_max_age(0).await;
+    let url = "https://cdn.example.com/live/segment_002.ts";
+
+    cache.cache_segment(url, &ts_segment(2)).await;
+
+    assert_eq!(
+        cache.get_cached(url, true).await,
+        (None, Some(ts_segment(2)))
+    );
+}
+
+#[tokio::test]
+async fn test_invalidating_a_stream_removes_only_its_entries() {
+    let (cache, db) = cache_with_bypass("").await;
+
+    let game = [
+        "https://cdn.example.com/live/game42/index.m3u8".to_string(),
+        "https://cdn.example.com/live/game42/seg_1.ts".to_string(),
+    ];
+    let other = [
+        "https://cdn.example.com/live/game7/index.m3u8".to_string(),
+        "https://cdn.example.com/live/game7/seg_1.ts".to_string(),
+    ];
+
+    cache.cache_m3u8(&game[0], "#EXTM3U").await;
+    cache.cache_segment(&game[1], &ts_segment(2)).await;
+    cache.index_stream("ppvsu-42", &game).await;
+
+    cache.cache_m3u8(&other[0], "#EXTM3U").await;
+    cache.cache_segment(&other[1], &ts_segment(2)).await;
+    cache.index_stream("ppvsu-7", &other).await;
+
+    let before = stored_keys(&db).await;
+
+    assert_eq!(cache.invalidate_stream("ppvsu-42").await.unwrap(), 2);
+
+    assert_eq!(cache.get_cached(&game[0], false).await, (None, None));
+    assert_eq!(cache.get_cached(&game[1], false).await, (None, None));
+    assert_eq!(
+        cache.get_cached(&other[0], false).await,
+        (Some("#EXTM3U".to_string()), None)
+    );
+    assert_eq!(
+        cache.get_cached(&other[1], false).await,
+        (None, Some(ts_segment(2)))
+    );
+
+    // the playlist, the segment and its timestamp, and the index itself
+    let after = stored_keys(&db).await;
+    assert_eq!(before.len() - after.len(), 4);
+    assert!(after.contains(&"pcache:stream:ppvsu-7".to_string()));
+    assert!(!after.contains(&"pcache:stream:ppvsu-42".to_string()));
+
+    // nothing left to drop the second time
+    assert_eq!(cache.invalidate_stream("ppvsu-42").await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_panicking_prefetch_still_clears_its_inflight_entry() {
+    let inflight = InflightRegistry::default();
+    let url = "https://cdn.example.com/seg_001.ts";
+
+    let guard = inflight.register(url);
+    let waiter = inflight.waiter(url).unwrap();
+    let woken = tokio::spawn(async move {
+        tokio::time::timeout(Duration::from_secs(1), waiter.notified())
+            .await
+            .is_ok()
+    });
+    tokio::task::yield_now().await;
+
+    let fetch = tokio::spawn(async move {
+        let _guard = guard;
+        panic!("prefetch blew up");
+    });
+    assert!(fetch.await.unwrap_err().is_panic());
+
+    assert!(inflight.is_empty());
+    assert!(inflight.waiter(url).is_none());
+    assert!(woken.await.unwrap());
+}
+
+#[tokio::test]
+async fn test_orphaned_inflight_entries_are_reaped() {
+    let inflight = InflightRegistry::new(Duration::from_millis(50));
+    let url = "https://cdn.example.com/seg_001.ts";
+
+    // a fetch that hangs forever and never lets go of its guard
+    let stuck = inflight.register(url);
+    assert!(inflight.waiter(url).is_some());
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(inflight.waiter(url).is_none());
+    assert!(inflight.is_empty());
+
+    // a new registration isn't removed when the stuck guard finally goes away
+    let fresh = inflight.register(url);
+    drop(stuck);
+    assert_eq!(inflight.len(), 1);
+    drop(fresh);
+    assert!(inflight.is_empty());
+}
+
+async fn cache_with_m3u8_lock(lock_ms: u64) -> Arc<ProxyCacheService> {
+    let db = Database::in_memory().await.unwrap();
+    let config = ProxyCacheConfig {
+        m3u8_lock_ms: lock_ms,
+        ..Default::default()
+    };
+    Arc::new(ProxyCacheService::new(
+        Arc::new(db),
+        reqwest::Client::new(),
+        config,
+    ))
+}
+
+#[tokio::test]
+async fn test_urls_cached_as_playlists_get_the_lock_without_saying_so() {
+    let cache = cache_with_m3u8_lock(3000).await;
+    let url = "https://cdn.example.com/live/index.m3u8";
+
+    // nothing known about it yet, a segment looks just the same
+    assert_eq!(
+        cache.wait_for_m3u8_fill(url, false).await,
+        M3u8Fill::Fetch(None)
+    );
+
+    cache.cache_m3u8(url, "#EXTM3U").await;
+    assert!(matches!(
+        cache.wait_for_m3u8_fill(url, false).await,
+        M3u8Fill::Fetch(Some(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_dropping_the_fill_lock_lets_it_go() {
+    let cache = cache_with_m3u8_lock(3000).await;
+    let url = "https://cdn.example.com/live/index.m3u8";
+
+    let M3u8Fill::Fetch(Some(token)) = cache.wait_for_m3u8_fill(url, true).await else {
+        panic!("the first request should get the lock");
+    };
+    drop(M3u8FillLock::new(cache.clone(), url, token));
+
+    // the waiter doesn't sit out the 3 seconds
+    let next = tokio::time::timeout(
+        Duration::from_millis(500),
+        cache.wait_for_m3u8_fill(url, true),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(next, M3u8Fill::Fetch(_)));
+}
+
+#[tokio::test]
+async fn test_without_m3u8_lock_every_request_fetches() {
+    let cache = cache_with_m3u8_lock(0).await;
+
+    assert_eq!(
+        cache
+            .wait_for_m3u8_fill("https://cdn.example.com/live/index.m3u8", true)
+            .await,
+        M3u8Fill::Fetch(None)
+    );
+    assert_eq!(
+        cache
+            .wait_for_m3u8_fill("https://cdn.example.com/live/index.m3u8", true)
+            .await,
+        M3u8Fill::Fetch(None)
+    );
+}
+
+#[tokio::test]
+async fn test_m3u8_lock_is_only_released_with_its_token() {
+    let cache = cache_with_m3u8_lock(3000).await;
+    let url = "https://cdn.example.com/live/index.m3u8";
+
+    let M3u8Fill::Fetch(Some(token)) = cache.wait_for_m3u8_fill(url, true).await else {
+        panic!("the first request should get the lock");
+    };
+
+    // someone whose lock ran out doesn't get to let go of this one
+    cache.release_m3u8_lock(url, "not-the-token").await;
+    let waited = tokio::time::timeout(
+        Duration::from_millis(200),
+        cache.wait_for_m3u8_fill(url, true),
+    )
+    .await;
+    assert!(waited.is_err());
+
+    cache.release_m3u8_lock(url, &token).await;
+    let next = tokio::time::timeout(
+        Duration::from_millis(200),
+        cache.wait_for_m3u8_fill(url, true),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(next, M3u8Fill::Fetch(Some(other)) if other != token));
+}
+
+#[test]
+fn test_memory_cache_evicts_least_recently_used_under_byte_cap() {
+    let l1 = SegmentMemoryCache::new(300);
+    let ttl = Duration::from_secs(60);
+
+    l1.insert("a", &[1; 100], 0, ttl);
+    l1.insert("b", &[2; 100], 0, ttl);
+    l1.insert("c", &[3; 100], 0, ttl);
+    // a was used last, so b is the one that goes
+    assert!(l1.get("a").is_some());
+    l1.insert("d", &[4; 100], 0, ttl);
+
+    assert_eq!(l1.size_bytes(), 300);
+    assert!(l1.get("b").is_none());
+    assert_eq!(l1.get("a").unwrap().0, vec![1; 100]);
+    assert!(l1.get("c").is_some());
+    assert!(l1.get("d").is_some());
+
+    // bigger than the whole cache is never kept
+    l1.insert("huge", &[5; 301], 0, ttl);
+    assert!(l1.get("huge").is_none());
+    assert_eq!(l1.len(), 3);
+}
+
+#[tokio::test]
+async fn test_memory_cache_entries_expire_with_the_segment_ttl() {
+    let l1 = SegmentMemoryCache::new(1024);
+
+    l1.insert("a", &[1; 10], 0, Duration::from_millis(50));
+    assert!(l1.get("a").is_some());
+
+    tokio::time::sleep(Duration::from_millis(80)).await;
+    assert!(l1.get("a").is_none());
+    assert_eq!(l1.size_bytes(), 0);
+}
+
+async fn cache_with_l1(max_bytes: usize) -> (ProxyCacheService, Database) {
+    let db = Database::in_memory().await.unwrap();
+    let config = ProxyCacheConfig {
+        l1_max_bytes: max_bytes,
+        ..Default::default()
+    };
+    let cache = ProxyCacheService::new(Arc::new(db.clone()), reqwest::Client::new(), config);
+    (cache, db)
+}
+
+async fn clear_store(db: &Database) {
+    match db {
+        Database::Memory(mem) => {
+            let keys = mem.store.scan("pcache:*").await.unwrap();
+            mem.store.del_multiple(&keys).await.unwrap();
+        }
+        Database::Redis(_) => unreachable!("only used with the in-memory store"),
+    }
+}
+
+#[tokio::test]
+async fn test_memory_cache_hit_skips_the_store() {
+    let (cache, db) = cache_with_l1(1024 * 1024).await;
+    let url = "https://cdn.example.com/live/seg_1.ts";
+    let segment = ts_segment(10);
+
+    cache.cache_segment(url, &segment).await;
+    // gone from the store, only the memory copy can answer now
+    clear_store(&db).await;
+
+    assert_eq!(cache.get_cached(url, false).await, (None, Some(segment)));
+}
+
+#[tokio::test]
+async fn test_without_memory_cache_hits_come_from_the_store() {
+    let (cache, db) = cache_with_l1(0).await;
+    let url = "https://cdn.example.com/live/seg_1.ts";
+
+    cache.cache_segment(url, &ts_segment(10)).await;
+    clear_store(&db).await;
+
+    assert_eq!(cache.get_cached(url, false).await, (None, None));
+}
+
+#[tokio::test]
+async fn test_invalidating_a_stream_drops_memory_copies() {
+    let (cache, _db) = cache_with_l1(1024 * 1024).await;
+    let url = "https://cdn.example.com/live/seg_1.ts";
+
+    cache.cache_segment(url, &ts_segment(10)).await;
+    cache.index_stream("ppvsu-1", &[url.to_string()]).await;
+    assert_eq!(cache.invalidate_stream("ppvsu-1").await.unwrap(), 1);
+
+    assert_eq!(cache.get_cached(url, false).await, (None, None));
+}
+
+#[tokio::test]
+async fn test_shutdown_cancels_running_prefetches() {
+    let cdn = MockUpstream::hanging().await;
+    let db = Database::in_memory().await.unwrap();
+    let scheduler = Arc::new(PrefetchScheduler::new(5, 5));
+    let cache = Arc::new(ProxyCacheService::new(
+        Arc::new(db.clone()),
+        reqwest::Client::new(),
+        ProxyCacheConfig {
+            prefetch_scheduler: scheduler.clone(),
+            ..Default::default()
+        },
+    ));
+    let urls: Vec<String> = (0..3).map(|i| cdn.url(&format!("/seg_{}.ts", i))).collect();
+
+    let prefetch = {
+        let cache = cache.clone();
+        let urls = urls.clone();
+        tokio::spawn(async move { cache.prefetch_segments("client", "sports", urls).await })
+    };
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!prefetch.is_finished());
+
+    scheduler.on_shutdown().await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(1), prefetch)
+        .await
+        .expect("prefetch kept running after shutdown")
+        .unwrap();
+    assert!(stored_keys(&db).await.is_empty());
+
+    // nothing new starts once it's shut down
+    let before = cdn.hits();
+    cache.prefetch_segments("client", "sports", urls).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(cdn.hits(), before);
+}
+
+// just enough of redis for the prefetch path, keeps what's SET so it can be read back and counts
+// MULTI/EXEC transactions, which is what a batched segment write goes out as
+#[derive(Clone, Default)]
+struct FakeRedis {
+    values: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    transactions: Arc<AtomicUsize>,
+}
+
+impl FakeRedis {
+    async fn start() -> (Self, Database) {
+        let fake = Self::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("redis://{}", listener.local_addr().unwrap());
+
+        let server = fake.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(server.clone().serve(socket));
+            }
+        });
+
+        let db = Database::Redis(RedisDatabase::connect(&addr).await.unwrap());
+        (fake, db)
+    }
+
+    async fn serve(self, mut socket: TcpStream) {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 8192];
+        let mut queued: Option<Vec<Vec<Vec<u8>>>> = None;
+        loop {
+            let read = match socket.read(&mut chunk).await {
+                Ok(0) | Err(_) => return,
+                Ok(read) => read,
+            };
+            buf.extend_from_slice(&chunk[..read]);
+
+            let mut reply = Vec::new();
+            while let Some((args, used)) = parse_command(&buf) {
+                buf.drain(..used);
+                let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+                match (name.as_str(), queued.is_some()) {
+                    ("MULTI", _) => {
+                        self.transactions.fetch_add(1, Ordering::SeqCst);
+                        queued = Some(Vec::new());
+                        reply.extend_from_slice(b"+OK\r\n");
+                    }
+                    ("EXEC", true) => {
+                        let commands = queued.take().unwrap();
+                        reply.extend_from_slice(format!("*{}\r\n", commands.len()).as_bytes());
+                        for args in commands {
+                            reply.extend(self.run(&args));
+                        }
+                    }
+                    (_, true) => {
+                        queued.as_mut().unwrap().push(args);
+                        reply.extend_from_slice(b"+QUEUED\r\n");
+                    }
+                    (_, false) => reply.extend(self.run(&args)),
+                }
+            }
+            if !reply.is_empty() && socket.write_all(&reply).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn run(&self, args: &[Vec<u8>]) -> Vec<u8> {
+        let mut values = self.values.lock().unwrap();
+        match String::from_utf8_lossy(&args[0])
+            .to_ascii_uppercase()
+            .as_str()
+        {
+            "SET" => {
+                values.insert(args[1].clone(), args[2].clone());
+                b"+OK\r\n".to_vec()
+            }
+            "GET" => match values.get(&args[1]) {
+                Some(value) => {
+                    let mut reply = format!("${}\r\n", value.len()).into_bytes();
+                    reply.extend_from_slice(value);
+                    reply.extend_from_slice(b"\r\n");
+                    reply
+                }
+                None => b"$-1\r\n".to_vec(),
+            },
+            "EXISTS" => format!(":{}\r\n", values.contains_key(&args[1]) as u8).into_bytes(),
+            _ => b"+OK\r\n".to_vec(),
+        }
+    }
+
+    fn stored(&self) -> usize {
+        self.values.lock().unwrap().len()
+    }
+}
+
+fn read_line(buf: &[u8], from: usize) -> Option<(&str, usize)> {
+    let end = buf[from..].windows(2).position(|w| w == b"\r\n")? + from;
+    Some((std::str::from_utf8(&buf[from..end]).ok()?, end + 2))
+}
+
+// the arguments of the first full command in `buf` and how many bytes it took up
+fn parse_command(buf: &[u8]) -> Option<(Vec<Vec<u8>>, usize)> {
+    let (count, mut at) = read_line(buf, 0)?;
+    let count: usize = count.strip_prefix('*')?.parse().ok()?;
+
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (len, start) = read_line(buf, at)?;
+        let len: usize = len.strip_prefix('$')?.parse().ok()?;
+        if buf.len() < start + len + 2 {
+            return None;
+        }
+        args.push(buf[start..start + len].to_vec());
+        at = start + len + 2;
+    }
+
+    Some((args, at))
+}
+
+// serves a valid ts segment for every path after `delay`
+async fn segment_upstream(delay: Duration) -> String {
+    MockUpstream::always(MockResponse::ok(ts_segment(10)).delay(delay))
+        .await
+        .base()
+}
+
+async fn prefetch_round_trips(batch_size: usize) -> usize {
+    let base = segment_upstream(Duration::ZERO).await;
+    let (redis, db) = FakeRedis::start().await;
+    let cache = ProxyCacheService::new(
+        Arc::new(db),
+        reqwest::Client::new(),
+        ProxyCacheConfig {
+            prefetch_scheduler: Arc::new(PrefetchScheduler::new(10, 10)),
+            prefetch_write_batch_size: batch_size,
+            prefetch_write_batch_ms: 60_000,
+            ..Default::default()
+        },
+    );
+    let urls: Vec<String> = (0..10).map(|i| format!("{}/seg_{}.ts", base, i)).collect();
+
+    cache.prefetch_segments("client", "sports", urls).await;
+
+    // a segment and its timestamp each
+    assert_eq!(redis.stored(), 20);
+    redis.transactions.load(Ordering::SeqCst)
+}
+
+#[tokio::test]
+async fn test_prefetched_segments_are_written_in_batches() {
+    assert_eq!(prefetch_round_trips(1).await, 10);
+    assert_eq!(prefetch_round_trips(4).await, 3);
+    assert_eq!(prefetch_round_trips(10).await, 1);
+}
+
+#[tokio::test]
+async fn test_inflight_waiters_wake_after_the_batched_write() {
+    let base = segment_upstream(Duration::from_millis(200)).await;
+    let (_redis, db) = FakeRedis::start().await;
+    let cache = Arc::new(ProxyCacheService::new(
+        Arc::new(db),
+        reqwest::Client::new(),
+        ProxyCacheConfig {
+            prefetch_scheduler: Arc::new(PrefetchScheduler::new(10, 10)),
+            prefetch_write_batch_size: 10,
+            prefetch_write_batch_ms: 60_000,
+            ..Default::default()
+        },
+    ));
+    let urls: Vec<String> = (0..5).map(|i| format!("{}/seg_{}.ts", base, i)).collect();
+
+    let prefetch = {
+        let cache = cache.clone();
+        let urls = urls.clone();
+        tokio::spawn(async move { cache.prefetch_segments("client", "sports", urls).await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // only woken once the whole batch is in the store, so the read finds it
+    assert_eq!(
+        cache.wait_for_inflight(&urls[0]).await,
+        Some(ts_segment(10))
+    );
+    prefetch.await.unwrap();
+}
+
+async fn cache_with_negative_ttl(seconds: u64) -> ProxyCacheService {
+    let db = Database::in_memory().await.unwrap();
+    let config = ProxyCacheConfig {
+        negative_ttl_seconds: seconds,
+        ..Default::default()
+    };
+    ProxyCacheService::new(Arc::new(db), reqwest::Client::new(), config)
+}
+
+#[tokio::test]
+async fn test_missing_segment_is_not_fetched_again() {
+    let cdn = MockUpstream::always(MockResponse::status(404)).await;
+    let cache = cache_with_negative_ttl(5).await;
+    let url = cdn.url("/ad_break/seg_1.ts");
+
+    cache
+        .prefetch_segments("client", "sports", vec![url.clone()])
+        .await;
+    assert_eq!(cdn.hits(), 1);
+    assert_eq!(cache.get_missing(&url).await, Some(404));
+
+    cache
+        .prefetch_segments("client", "sports", vec![url.clone()])
+        .await;
+    assert_eq!(cdn.hits(), 1);
+}
+
+#[tokio::test]
+async fn test_gone_segments_are_remembered_too() {
+    let cache = cache_with_negative_ttl(5).await;
+    let url = "https://cdn.example.com/live/seg_2.ts";
+
+    cache.cache_missing(url, 410).await;
+
+    assert_eq!(cache.get_missing(url).await, Some(410));
+}
+
+#[tokio::test]
+async fn test_server_errors_are_not_remembered() {
+    let cdn = MockUpstream::always(MockResponse::status(503)).await;
+    let cache = cache_with_negative_ttl(5).await;
+    let url = cdn.url("/live/seg_1.ts");
+
+    cache
+        .prefetch_segments("client", "sports", vec![url.clone()])
+        .await;
+    assert_eq!(cache.get_missing(&url).await, None);
+
+    cache
+        .prefetch_segments("client", "sports", vec![url.clone()])
+        .await;
+    assert_eq!(cdn.hits(), 2);
+}
+
+#[tokio::test]
+async fn test_missing_segment_is_retried_once_expired() {
+    let cdn = MockUpstream::always(MockResponse::status(404)).await;
+    let cache = cache_with_negative_ttl(1).await;
+    let url = cdn.url("/ad_break/seg_1.ts");
+
+    cache
+        .prefetch_segments("client", "sports", vec![url.clone()])
+        .await;
+    assert_eq!(cache.get_missing(&url).await, Some(404));
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(cache.get_missing(&url).await, None);
+
+    cache
+        .prefetch_segments("client", "sports", vec![url.clone()])
+        .await;
+    assert_eq!(cdn.hits(), 2);
+}
+
+#[tokio::test]
+async fn test_playlists_and_disabled_ttl_are_never_negative() {
+    let cache = cache_with_negative_ttl(5).await;
+    let playlist = "https://cdn.example.com/live/index.m3u8?token=abc";
+    cache.cache_missing(playlist, 404).await;
+    assert_eq!(cache.get_missing(playlist).await, None);
+
+    let cache = cache_with_negative_ttl(0).await;
+    let segment = "https://cdn.example.com/live/seg_1.ts";
+    cache.cache_missing(segment, 404).await;
+    assert_eq!(cache.get_missing(segment).await, None);
+}
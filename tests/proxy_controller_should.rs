@@ -0,0 +1,177 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use api::AppConfig;
+use api::server::api::proxy_controller::ProxyController;
+use api::server::extractors::EdgeAuthentication;
+use api::server::services::edge_services::EdgeServices;
+use api::server::utils::m3u8_utils::sign_proxy_url_with_params;
+use axum::extract::FromRequestParts;
+use axum::http::{Request, StatusCode};
+use axum::{Extension, Router, body::Body};
+use common::{MockResponse, MockUpstream, services};
+use tower::ServiceExt;
+
+const UA: &str = "Mozilla/5.0 (X11; Linux x86_64)";
+const IP: &str = "203.0.113.7";
+const PLAYLIST: &str = "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6.0,\nseg_1.ts";
+
+fn playlist_upstream() -> MockResponse {
+    MockResponse::ok(PLAYLIST).header("content-type", "application/vnd.apple.mpegurl")
+}
+
+fn request(uri: &str) -> Request<Body> {
+    Request::builder()
+        .uri(uri)
+        .header("user-agent", UA)
+        .header("x-forwarded-for", IP)
+        .body(Body::empty())
+        .unwrap()
+}
+
+// a proxy url for `upstream_url` and `params` signed for the client the test's requests come from
+async fn signed(services: &EdgeServices, upstream_url: &str, params: &[(&str, String)]) -> String {
+    let (mut parts, _) = request("/api/v1/proxy").into_parts();
+    parts.extensions.insert(services.clone());
+    let EdgeAuthentication(client_id, _) = EdgeAuthentication::from_request_parts(&mut parts, &())
+        .await
+        .unwrap();
+
+    sign_proxy_url_with_params(
+        upstream_url,
+        "sports",
+        &client_id,
+        services.signature_util.expiry_in(1),
+        &services.signature_util,
+        params,
+    )
+}
+
+fn flagged_playlist() -> [(&'static str, String); 1] {
+    [("type", "playlist".to_string())]
+}
+
+async fn get(services: &EdgeServices, uri: &str) -> StatusCode {
+    let app = Router::new()
+        .nest("/api/v1/proxy", ProxyController::app())
+        .layer(Extension(services.clone()));
+    app.oneshot(request(uri)).await.unwrap().status()
+}
+
+// only the playlist, the prefetch of its segment goes to the same mock
+fn playlist_fetches(upstream: &MockUpstream) -> usize {
+    upstream
+        .request_lines()
+        .iter()
+        .filter(|line| line.contains("/live/index.m3u8"))
+        .count()
+}
+
+#[tokio::test]
+async fn test_many_requests_for_a_playlist_fetch_it_once() {
+    let upstream =
+        MockUpstream::always(playlist_upstream().delay(Duration::from_millis(200))).await;
+    let services = services(AppConfig::default()).await;
+    let uri = signed(
+        &services,
+        &upstream.url("/live/index.m3u8"),
+        &flagged_playlist(),
+    )
+    .await;
+
+    let mut tasks = Vec::new();
+    for _ in 0..50 {
+        let services = services.clone();
+        let uri = uri.clone();
+        tasks.push(tokio::spawn(async move { get(&services, &uri).await }));
+    }
+    for task in tasks {
+        assert_eq!(task.await.unwrap(), StatusCode::OK);
+    }
+
+    assert_eq!(playlist_fetches(&upstream), 1);
+}
+
+#[tokio::test]
+async fn test_failed_playlist_fetch_lets_go_of_the_lock() {
+    let upstream = MockUpstream::start(vec![MockResponse::status(500), playlist_upstream()]).await;
+    let services = services(AppConfig {
+        proxy_stale_if_error_seconds: 0,
+        ..AppConfig::default()
+    })
+    .await;
+    let uri = signed(
+        &services,
+        &upstream.url("/live/index.m3u8"),
+        &flagged_playlist(),
+    )
+    .await;
+
+    assert_eq!(get(&services, &uri).await, StatusCode::BAD_GATEWAY);
+
+    // the next request doesn't sit out the 3 second lock the failed one took
+    let started = Instant::now();
+    assert_eq!(get(&services, &uri).await, StatusCode::OK);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_entry_playlists_are_locked_once_seen() {
+    let upstream =
+        MockUpstream::always(playlist_upstream().delay(Duration::from_millis(200))).await;
+    let services = services(AppConfig {
+        proxy_m3u8_ttl_seconds: 1,
+        cache_ttl_jitter_percent: 0,
+        ..AppConfig::default()
+    })
+    .await;
+    // like the urls the stream endpoints hand out, nothing says it's a playlist
+    let uri = signed(&services, &upstream.url("/live/index.m3u8"), &[]).await;
+
+    assert_eq!(get(&services, &uri).await, StatusCode::OK);
+    // the cached copy expires, everyone comes back at once
+    tokio::time::sleep(Duration::from_millis(1200)).await;
+
+    let mut tasks = Vec::new();
+    for _ in 0..20 {
+        let services = services.clone();
+        let uri = uri.clone();
+        tasks.push(tokio::spawn(async move { get(&services, &uri).await }));
+    }
+    for task in tasks {
+        assert_eq!(task.await.unwrap(), StatusCode::OK);
+    }
+
+    assert_eq!(playlist_fetches(&upstream), 2);
+}
+
+#[tokio::test]
+async fn test_cached_mp4_segments_keep_their_content_type() {
+    let upstream = MockUpstream::always(MockResponse::status(500)).await;
+    let overridden = services(AppConfig {
+        upstream_content_type_overrides: "127.0.0.1:mp4".to_string(),
+        ..AppConfig::default()
+    })
+    .await;
+    let plain = services(AppConfig::default()).await;
+
+    // one is mp4 by its extension, the other only by the host's override
+    for (services, path, content_type) in [
+        (&plain, "/vod/part_1.m4s", "video/mp4"),
+        (&overridden, "/vod/part_1", "video/mp4"),
+        (&plain, "/vod/part_1", "video/mp2t"),
+    ] {
+        let url = upstream.url(path);
+        services.proxy_cache.cache_segment(&url, &[0u8; 4096]).await;
+        let uri = signed(services, &url, &[]).await;
+
+        let app = Router::new()
+            .nest("/api/v1/proxy", ProxyController::app())
+            .layer(Extension(services.clone()));
+        let response = app.oneshot(request(&uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], content_type, "{}", path);
+    }
+    assert!(upstream.requests().is_empty());
+}
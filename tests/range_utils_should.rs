@@ -0,0 +1,232 @@
+use api::server::utils::range_utils::{self, ByteRange, MultipartRanges, UpstreamRangeReply};
+use api::server::utils::upstream_utils::{BodyKind, ContentTypeOverrides};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+
+fn range_headers(value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::RANGE, HeaderValue::from_str(value).unwrap());
+    headers
+}
+
+#[test]
+fn test_open_range_from_zero_covers_the_whole_body() {
+    let range = ByteRange::from_headers(&range_headers("bytes=0-")).unwrap();
+
+    assert_eq!(range, ByteRange::From(0));
+    assert_eq!(range.header_value(), "bytes=0-");
+    assert_eq!(range.resolve(2000), Some((0, 1999)));
+    assert_eq!(
+        range_utils::content_range(0, 1999, 2000),
+        "bytes 0-1999/2000"
+    );
+}
+
+#[test]
+fn test_bounded_range_is_clamped_to_the_body() {
+    let range = ByteRange::parse("bytes=500-999").unwrap();
+
+    assert_eq!(range, ByteRange::FromTo(500, 999));
+    assert_eq!(range.header_value(), "bytes=500-999");
+    assert_eq!(range.resolve(2000), Some((500, 999)));
+    assert_eq!(range.resolve(800), Some((500, 799)));
+    // starts past the end
+    assert_eq!(range.resolve(500), None);
+}
+
+#[test]
+fn test_suffix_range_takes_the_last_bytes() {
+    let range = ByteRange::parse("bytes=-500").unwrap();
+
+    assert_eq!(range, ByteRange::Suffix(500));
+    assert_eq!(range.header_value(), "bytes=-500");
+    assert_eq!(range.resolve(2000), Some((1500, 1999)));
+    // asking for more than there is gives all of it
+    assert_eq!(range.resolve(300), Some((0, 299)));
+    assert_eq!(ByteRange::Suffix(0).resolve(300), None);
+}
+
+#[test]
+fn test_malformed_ranges_are_ignored() {
+    for value in [
+        "bytes=-",
+        "bytes=abc-",
+        "bytes=999-500",
+        "items=0-10",
+        "0-10",
+    ] {
+        assert_eq!(ByteRange::parse(value), None, "{}", value);
+    }
+    assert_eq!(ByteRange::from_headers(&HeaderMap::new()), None);
+}
+
+#[test]
+fn test_partial_content_from_upstream_is_relayed() {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_RANGE,
+        HeaderValue::from_static("bytes 500-999/2000"),
+    );
+
+    assert_eq!(
+        UpstreamRangeReply::from_response(StatusCode::PARTIAL_CONTENT, &headers),
+        UpstreamRangeReply::Relay {
+            status: StatusCode::PARTIAL_CONTENT,
+            content_range: Some("bytes 500-999/2000".to_string()),
+        }
+    );
+    assert!(matches!(
+        UpstreamRangeReply::from_response(StatusCode::RANGE_NOT_SATISFIABLE, &HeaderMap::new()),
+        UpstreamRangeReply::Relay { .. }
+    ));
+}
+
+#[test]
+fn test_full_body_from_upstream_falls_back_to_slicing() {
+    assert_eq!(
+        UpstreamRangeReply::from_response(StatusCode::OK, &HeaderMap::new()),
+        UpstreamRangeReply::Ignored
+    );
+}
+
+#[test]
+fn test_only_mp4_urls_are_forwarded() {
+    let overrides = ContentTypeOverrides::parse_list("vod.example.com:mp4");
+
+    assert_eq!(
+        overrides.kind_from_url("https://cdn.example.com/movie.MP4?token=abc"),
+        Some(BodyKind::Mp4)
+    );
+    assert_eq!(
+        overrides.kind_from_url("https://vod.example.com/play/123"),
+        Some(BodyKind::Mp4)
+    );
+    assert_eq!(
+        overrides.kind_from_url("https://cdn.example.com/seg_001.ts"),
+        Some(BodyKind::Ts)
+    );
+    assert_eq!(
+        overrides.kind_from_url("https://cdn.example.com/index.m3u8"),
+        Some(BodyKind::M3u8)
+    );
+    assert_eq!(
+        overrides.kind_from_url("https://cdn.example.com/play/123"),
+        None
+    );
+}
+
+// 0, 1, 2, ... 255, 0, 1, ... so every slice is recognizable
+fn body(len: usize) -> Vec<u8> {
+    (0..len).map(|i| i as u8).collect()
+}
+
+fn expected_part(boundary: &str, body: &[u8], start: u64, end: u64) -> Vec<u8> {
+    let mut part = format!(
+        "\r\n--{}\r\nContent-Type: video/mp2t\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+        boundary,
+        start,
+        end,
+        body.len()
+    )
+    .into_bytes();
+    part.extend_from_slice(&body[start as usize..=end as usize]);
+    part
+}
+
+fn resolve_all(header: &str, total: u64) -> Vec<(u64, u64)> {
+    ByteRange::parse_list(header)
+        .unwrap()
+        .iter()
+        .filter_map(|range| range.resolve(total))
+        .collect()
+}
+
+#[test]
+fn test_two_ranges_become_two_parts() {
+    let body = body(1000);
+    let ranges = resolve_all("bytes=0-99,200-299", 1000);
+    assert_eq!(ranges, vec![(0, 99), (200, 299)]);
+    // a single range parser wouldn't take this
+    assert_eq!(ByteRange::parse("bytes=0-99,200-299"), None);
+
+    let multipart = MultipartRanges::build(&body, &ranges, "video/mp2t", "test-boundary");
+
+    let mut expected = expected_part("test-boundary", &body, 0, 99);
+    expected.extend(expected_part("test-boundary", &body, 200, 299));
+    expected.extend_from_slice(b"\r\n--test-boundary--\r\n");
+    assert_eq!(multipart.body, expected);
+    assert_eq!(
+        multipart.content_type(),
+        "multipart/byteranges; boundary=test-boundary"
+    );
+}
+
+#[test]
+fn test_three_ranges_keep_their_order_and_kinds() {
+    let body = body(1000);
+    let ranges = resolve_all("bytes=900-, 0-9, -50", 1000);
+    assert_eq!(ranges, vec![(900, 999), (0, 9), (950, 999)]);
+
+    let multipart = MultipartRanges::build(&body, &ranges, "video/mp2t", "b");
+
+    let mut expected = expected_part("b", &body, 900, 999);
+    expected.extend(expected_part("b", &body, 0, 9));
+    expected.extend(expected_part("b", &body, 950, 999));
+    expected.extend_from_slice(b"\r\n--b--\r\n");
+    assert_eq!(multipart.body, expected);
+}
+
+#[test]
+fn test_malformed_range_list_is_ignored_entirely() {
+    // any bad entry throws out the whole header, the full body goes back with a 200
+    for value in [
+        "bytes=0-99,abc",
+        "bytes=0-99,",
+        "bytes=0-99,-",
+        "0-99,200-299",
+    ] {
+        assert_eq!(ByteRange::parse_list(value), None, "{}", value);
+        assert_eq!(ByteRange::parse(value), None, "{}", value);
+    }
+}
+
+#[test]
+fn test_boundaries_are_unique() {
+    let first = MultipartRanges::new_boundary();
+    let second = MultipartRanges::new_boundary();
+
+    assert_ne!(first, second);
+    assert!(first.len() <= 70);
+}
+
+#[test]
+fn test_overlapping_and_touching_ranges_are_merged() {
+    let ranges = resolve_all("bytes=200-299,0-99,50-149,300-399", 1000);
+
+    assert_eq!(
+        range_utils::coalesce_ranges(&ranges, 1000),
+        Some(vec![(0, 149), (200, 399)])
+    );
+}
+
+#[test]
+fn test_too_many_ranges_fall_back_to_the_full_body() {
+    let header = format!(
+        "bytes={}",
+        (0..=range_utils::MAX_RANGES)
+            .map(|i| format!("{}-{}", i * 10, i * 10 + 1))
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    let ranges = resolve_all(&header, 1000);
+    assert_eq!(ranges.len(), range_utils::MAX_RANGES + 1);
+
+    assert_eq!(range_utils::coalesce_ranges(&ranges, 1000), None);
+    assert!(range_utils::coalesce_ranges(&ranges[1..], 1000).is_some());
+}
+
+#[test]
+fn test_ranges_adding_up_to_more_than_the_body_fall_back() {
+    let ranges = resolve_all("bytes=0-,0-,0-", 1000);
+
+    assert_eq!(range_utils::coalesce_ranges(&ranges, 1000), None);
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use api::AppConfig;
+use api::server::error::Error;
+use api::server::extractors::{CheckedRateLimit, ClientIdStrategy, derive_keyed_client_id};
+use api::server::services::edge_services::EdgeServices;
+use api::server::services::rate_limit_services::RateLimitResult;
+use axum::extract::FromRequestParts;
+use axum::http::{Request, StatusCode};
+use axum::response::IntoResponse;
+
+const UA: &str = "AppleCoreMedia/1.0.0.21E236 (iPhone; U; CPU OS 17_4 like Mac OS X)";
+const IP: &str = "203.0.113.7";
+
+async fn services(max_requests: u32) -> EdgeServices {
+    common::services(AppConfig {
+        rate_limit_max_requests: max_requests,
+        ..AppConfig::default()
+    })
+    .await
+}
+
+async fn check(services: &EdgeServices) -> Result<RateLimitResult, Error> {
+    let (mut parts, _) = Request::builder()
+        .uri("/api/v1/proxy?url=x")
+        .header("user-agent", UA)
+        .header("x-forwarded-for", IP)
+        .body(())
+        .unwrap()
+        .into_parts();
+    parts.extensions.insert(services.clone());
+
+    CheckedRateLimit::from_request_parts(&mut parts, &())
+        .await
+        .map(|CheckedRateLimit(result)| result)
+}
+
+#[tokio::test]
+async fn test_lets_clients_under_the_limit_through() {
+    let services = services(2).await;
+
+    assert!(matches!(
+        check(&services).await,
+        Ok(RateLimitResult::Allowed { remaining: 1, .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_answers_clients_over_the_limit_with_a_429() {
+    let services = services(2).await;
+    check(&services).await.unwrap();
+    check(&services).await.unwrap();
+
+    let error = check(&services).await.err().unwrap();
+    assert!(matches!(error, Error::TooManyRequests { retry_after, .. } if retry_after > 0));
+
+    let response = error.into_response();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let headers = response.headers();
+    let retry_after: i64 = headers["retry-after"].to_str().unwrap().parse().unwrap();
+    assert!(retry_after > 0);
+    assert_eq!(headers["x-ratelimit-remaining"], "0");
+    let reset: i64 = headers["x-ratelimit-reset"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(reset >= chrono::Utc::now().timestamp());
+}
+
+#[tokio::test]
+async fn test_answers_timed_out_clients_with_a_429() {
+    let services = services(500).await;
+    let client_id = derive_keyed_client_id(
+        &services.config.access_token_secret,
+        ClientIdStrategy::IpUserAgent,
+        Some(IP),
+        Some(UA),
+        None,
+    );
+    services
+        .rate_limit
+        .timeout_user(&client_id, "too many errors", 120)
+        .await;
+
+    let error = check(&services).await.err().unwrap();
+    assert!(matches!(error, Error::TooManyRequests { .. }));
+    assert_eq!(
+        error.into_response().status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
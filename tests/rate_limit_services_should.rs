@@ -0,0 +1,734 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use api::Database;
+use api::server::extractors::generate_client_id;
+use api::server::services::rate_limit_services::{
+    EdgeRateLimitService, ErrorScore, ErrorScoreEntry, ErrorWeights, ProxyErrorKind,
+    RateLimitConfig, RateLimitResult, RateLimitServiceTrait, RateLimitSnapshot, RateLimitStrategy,
+    TimeoutEntry, UpstreamFailure,
+};
+use api::server::utils::clock_utils::MockClock;
+
+async fn limiter() -> EdgeRateLimitService {
+    let db = Database::in_memory().await.unwrap();
+    let config = RateLimitConfig {
+        max_errors_before_timeout: 5,
+        max_error_ratio: 0.2,
+        ratio_min_requests: 50,
+        ..Default::default()
+    };
+    EdgeRateLimitService::with_config(Arc::new(db), config)
+}
+
+async fn simulate(limiter: &EdgeRateLimitService, client_id: &str, requests: u32, errors: u32) {
+    for _ in 0..requests {
+        limiter.check_rate_limit(client_id, None).await;
+    }
+    for _ in 0..errors {
+        limiter
+            .record_error(client_id, UpstreamFailure::ClientFault(403))
+            .await;
+    }
+}
+
+#[tokio::test]
+async fn test_high_volume_low_error_rate_client_is_not_timed_out() {
+    let limiter = limiter().await;
+
+    // 10 errors is a score well over the threshold but a small share of the traffic
+    simulate(&limiter, "busy", 200, 10).await;
+
+    assert!(limiter.get_error_score("busy").await > 5.0);
+    assert!(limiter.is_user_timed_out("busy").await.is_none());
+}
+
+#[tokio::test]
+async fn test_low_volume_high_error_rate_client_is_timed_out() {
+    let limiter = limiter().await;
+
+    simulate(&limiter, "abuser", 5, 5).await;
+
+    assert!(limiter.is_user_timed_out("abuser").await.is_some());
+}
+
+#[tokio::test]
+async fn test_high_volume_client_over_ratio_is_timed_out() {
+    let limiter = limiter().await;
+
+    simulate(&limiter, "broken", 60, 20).await;
+
+    assert!(limiter.is_user_timed_out("broken").await.is_some());
+}
+
+#[tokio::test]
+async fn test_request_count_starts_over_after_the_error_window() {
+    let db = Database::in_memory().await.unwrap();
+    let config = RateLimitConfig {
+        max_errors_before_timeout: 5,
+        max_error_ratio: 0.2,
+        ratio_min_requests: 50,
+        error_window_seconds: 1,
+        ..Default::default()
+    };
+    let limiter = EdgeRateLimitService::with_config(Arc::new(db), config);
+
+    // the requests from before the window don't keep covering for errors after it
+    simulate(&limiter, "was_busy", 200, 0).await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    simulate(&limiter, "was_busy", 5, 10).await;
+
+    assert!(limiter.is_user_timed_out("was_busy").await.is_some());
+}
+
+#[test]
+fn test_absolute_floor_applies_below_ratio_volume() {
+    let config = RateLimitConfig::default();
+
+    let max = config.max_errors_before_timeout as f64;
+
+    assert!(!config.should_timeout(max - 0.5, 0));
+    assert!(config.should_timeout(max, 10));
+    assert!(!config.should_timeout(max, 10_000));
+}
+
+#[tokio::test]
+async fn test_status_reflects_consumed_quota_without_counting() {
+    let limiter = limiter().await;
+
+    simulate(&limiter, "viewer", 3, 0).await;
+
+    let status = limiter.peek_rate_limit("viewer").await;
+    assert_eq!(status.used, 3);
+    assert_eq!(status.remaining, status.limit - 3);
+    assert!(status.reset_at > chrono::Utc::now().timestamp());
+
+    // peeking again doesn't use up anything
+    assert_eq!(
+        limiter.peek_rate_limit("viewer").await.remaining,
+        status.remaining
+    );
+    assert!(limiter.is_user_timed_out("viewer").await.is_none());
+    assert_eq!(limiter.get_error_score("viewer").await, 0.0);
+}
+
+#[tokio::test]
+async fn test_status_for_unseen_client_has_full_quota() {
+    let limiter = limiter().await;
+
+    let status = limiter.peek_rate_limit("new").await;
+    assert_eq!(status.used, 0);
+    assert_eq!(status.remaining, status.limit);
+}
+
+#[tokio::test]
+async fn test_export_clear_import_restores_state() {
+    let limiter = limiter()
+        .await
+        .with_clock(Arc::new(MockClock::new(1_700_000_000)));
+
+    limiter.timeout_user("banned1", "scraping", 600).await;
+    limiter.timeout_user("banned2", "abuse", 1200).await;
+    limiter
+        .record_error("flaky", UpstreamFailure::ClientFault(403))
+        .await;
+
+    let exported = limiter.export_state().await.unwrap();
+    assert_eq!(exported.timeouts.len(), 2);
+    assert_eq!(exported.error_scores.len(), 1);
+
+    limiter.clear_timeout("banned1").await;
+    limiter.clear_timeout("banned2").await;
+    assert!(limiter.is_user_timed_out("banned1").await.is_none());
+
+    let imported = limiter.import_state(exported.clone()).await.unwrap();
+    assert_eq!(imported, 3);
+
+    let (reason, _) = limiter.is_user_timed_out("banned1").await.unwrap();
+    assert_eq!(reason, "scraping");
+    let (reason, _) = limiter.is_user_timed_out("banned2").await.unwrap();
+    assert_eq!(reason, "abuse");
+    assert_eq!(limiter.get_error_score("flaky").await, 1.0);
+
+    let mut restored = limiter.export_state().await.unwrap();
+    let mut original = exported;
+    restored
+        .timeouts
+        .sort_by(|a, b| a.client_id.cmp(&b.client_id));
+    original
+        .timeouts
+        .sort_by(|a, b| a.client_id.cmp(&b.client_id));
+    for (restored, original) in restored.timeouts.iter().zip(original.timeouts.iter()) {
+        assert_eq!(restored.client_id, original.client_id);
+        assert_eq!(restored.reason, original.reason);
+        assert!(restored.ttl_seconds <= original.ttl_seconds);
+    }
+}
+
+#[tokio::test]
+async fn test_invalid_import_writes_nothing() {
+    let limiter = limiter().await;
+
+    let snapshot = RateLimitSnapshot {
+        timeouts: vec![
+            TimeoutEntry {
+                client_id: "good".to_string(),
+                reason: "abuse".to_string(),
+                ttl_seconds: 600,
+            },
+            TimeoutEntry {
+                client_id: "bad id with spaces".to_string(),
+                reason: "abuse".to_string(),
+                ttl_seconds: 600,
+            },
+        ],
+        ..Default::default()
+    };
+
+    assert!(limiter.import_state(snapshot).await.is_err());
+    assert!(limiter.is_user_timed_out("good").await.is_none());
+
+    let zero_ttl = RateLimitSnapshot {
+        timeouts: vec![TimeoutEntry {
+            client_id: "good".to_string(),
+            reason: "abuse".to_string(),
+            ttl_seconds: 0,
+        }],
+        ..Default::default()
+    };
+    assert!(zero_ttl.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_reset_times_come_from_the_injected_clock() {
+    let clock = Arc::new(MockClock::new(1_700_000_000));
+    let limiter = limiter().await.with_clock(clock.clone());
+    let window = RateLimitConfig::default().window_seconds as i64;
+
+    match limiter.check_rate_limit("viewer", None).await {
+        RateLimitResult::Allowed { reset_at, .. } => assert_eq!(reset_at, 1_700_000_000 + window),
+        other => panic!("expected the request to be allowed, got {:?}", other),
+    }
+
+    // the remaining ttl comes from the store, only the "now" it's added to is ours
+    clock.advance(10);
+    let reset_in = limiter.peek_rate_limit("viewer").await.reset_at - 1_700_000_010;
+    assert!(reset_in > 0 && reset_in <= window);
+}
+
+#[tokio::test]
+async fn test_only_client_faults_count_toward_a_timeout() {
+    let limiter = limiter()
+        .await
+        .with_clock(Arc::new(MockClock::new(1_700_000_000)));
+
+    for _ in 0..10 {
+        limiter
+            .record_error("viewer", UpstreamFailure::from_status(429))
+            .await;
+        limiter
+            .record_error("viewer", UpstreamFailure::Network)
+            .await;
+        limiter
+            .record_error("viewer", UpstreamFailure::from_status(451))
+            .await;
+        limiter
+            .record_error("viewer", UpstreamFailure::from_status(503))
+            .await;
+    }
+    assert_eq!(limiter.get_error_score("viewer").await, 0.0);
+    assert!(limiter.is_user_timed_out("viewer").await.is_none());
+
+    limiter
+        .record_error("viewer", UpstreamFailure::from_status(403))
+        .await;
+    assert_eq!(limiter.get_error_score("viewer").await, 1.0);
+}
+
+#[test]
+fn test_upstream_statuses_are_bucketed() {
+    assert_eq!(
+        UpstreamFailure::from_status(403),
+        UpstreamFailure::ClientFault(403)
+    );
+    assert_eq!(
+        UpstreamFailure::from_status(404),
+        UpstreamFailure::ClientFault(404)
+    );
+    assert_eq!(
+        UpstreamFailure::from_status(429),
+        UpstreamFailure::NotClientFault(429)
+    );
+    assert_eq!(
+        UpstreamFailure::from_status(451),
+        UpstreamFailure::NotClientFault(451)
+    );
+    assert_eq!(
+        UpstreamFailure::from_status(502),
+        UpstreamFailure::Server(502)
+    );
+    assert_eq!(
+        UpstreamFailure::Network.kind(),
+        ProxyErrorKind::RequestFailed
+    );
+    assert_eq!(
+        UpstreamFailure::Decode.kind(),
+        ProxyErrorKind::DecodeFailure
+    );
+}
+
+const NOW: i64 = 1_700_000_000;
+
+#[test]
+fn test_error_score_halves_every_half_life() {
+    let score = ErrorScore {
+        score: 8.0,
+        updated_at: NOW,
+        ..Default::default()
+    };
+
+    assert_eq!(score.decayed(NOW, 120), 8.0);
+    assert_eq!(score.decayed(NOW + 120, 120), 4.0);
+    assert_eq!(score.decayed(NOW + 360, 120), 1.0);
+    // a clock that went backwards doesn't grow it
+    assert_eq!(score.decayed(NOW - 60, 120), 8.0);
+}
+
+#[test]
+fn test_not_found_costs_less_than_forbidden() {
+    let weights = ErrorWeights::default();
+    let forbidden =
+        ErrorScore::default().record(UpstreamFailure::ClientFault(403), &weights, NOW, 120);
+    let not_found =
+        ErrorScore::default().record(UpstreamFailure::ClientFault(404), &weights, NOW, 120);
+
+    assert_eq!(forbidden.score, 1.0);
+    assert_eq!(not_found.score, 0.5);
+    assert_eq!(weights.weight(UpstreamFailure::from_status(429)), 0.0);
+}
+
+#[test]
+fn test_repeated_failures_get_more_expensive() {
+    let weights = ErrorWeights::default();
+    let mut score = ErrorScore::default();
+    let mut added = Vec::new();
+    for _ in 0..5 {
+        let before = score.score;
+        score = score.record(UpstreamFailure::ClientFault(403), &weights, NOW, 120);
+        added.push(score.score - before);
+    }
+    // doubles each time, capped at 4x
+    assert_eq!(added, vec![1.0, 2.0, 4.0, 4.0, 4.0]);
+
+    // a different status starts over
+    let other = score.record(UpstreamFailure::ClientFault(401), &weights, NOW, 120);
+    assert_eq!(other.score - score.score, 1.0);
+
+    // so does the same one after a quiet half life
+    let later = score.record(UpstreamFailure::ClientFault(403), &weights, NOW + 121, 120);
+    assert_eq!(later.repeats, 0);
+}
+
+async fn scoring_limiter(clock: Arc<MockClock>) -> EdgeRateLimitService {
+    let db = Database::in_memory().await.unwrap();
+    let config = RateLimitConfig {
+        max_errors_before_timeout: 50,
+        // the ratio never kicks in, only the score matters
+        ratio_min_requests: u32::MAX,
+        error_half_life_seconds: 120,
+        ..Default::default()
+    };
+    EdgeRateLimitService::with_config(Arc::new(db), config).with_clock(clock)
+}
+
+// a client sitting at 49, one plain error away from the threshold
+async fn seed_score(limiter: &EdgeRateLimitService, client_id: &str) {
+    limiter
+        .import_state(RateLimitSnapshot {
+            error_scores: vec![ErrorScoreEntry {
+                client_id: client_id.to_string(),
+                score: ErrorScore {
+                    score: 49.0,
+                    updated_at: NOW,
+                    ..Default::default()
+                },
+                ttl_seconds: 600,
+            }],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_one_more_error_right_away_times_out() {
+    let limiter = scoring_limiter(Arc::new(MockClock::new(NOW))).await;
+    seed_score(&limiter, "viewer").await;
+
+    limiter
+        .record_error("viewer", UpstreamFailure::ClientFault(403))
+        .await;
+
+    assert!(limiter.is_user_timed_out("viewer").await.is_some());
+}
+
+#[tokio::test]
+async fn test_score_decays_after_behaving() {
+    let clock = Arc::new(MockClock::new(NOW));
+    let limiter = scoring_limiter(clock.clone()).await;
+    seed_score(&limiter, "viewer").await;
+
+    // 9 quiet minutes, four and a half half lives
+    clock.advance(540);
+    let decayed = limiter.get_error_score("viewer").await;
+    assert!((decayed - 49.0 * 0.5f64.powf(4.5)).abs() < 1e-9);
+
+    limiter
+        .record_error("viewer", UpstreamFailure::ClientFault(403))
+        .await;
+
+    assert!(limiter.is_user_timed_out("viewer").await.is_none());
+    assert!((limiter.get_error_score("viewer").await - (decayed + 1.0)).abs() < 1e-9);
+}
+
+#[test]
+fn test_error_weights_parse_overrides_over_the_defaults() {
+    let weights = ErrorWeights::parse_list(
+        "decode_failure=5, UPSTREAM_SERVER_ERROR=0.25,request_failed=-1,bogus=2,upstream_client_error",
+    );
+
+    assert_eq!(weights.get(ProxyErrorKind::DecodeFailure), 5.0);
+    assert_eq!(weights.get(ProxyErrorKind::UpstreamServerError), 0.25);
+    // negative, unknown and weightless entries are skipped
+    assert_eq!(weights.get(ProxyErrorKind::RequestFailed), 0.0);
+    assert_eq!(weights.get(ProxyErrorKind::UpstreamClientError), 1.0);
+    assert_eq!(ErrorWeights::parse_list(""), ErrorWeights::default());
+}
+
+// how many failures in a row it takes to get timed out, none of them repeats of the last
+async fn errors_until_timeout(failure: impl Fn(u32) -> UpstreamFailure) -> u32 {
+    let db = Database::in_memory().await.unwrap();
+    let config = RateLimitConfig {
+        max_errors_before_timeout: 12,
+        ratio_min_requests: u32::MAX,
+        ..Default::default()
+    };
+    let limiter = EdgeRateLimitService::with_config(Arc::new(db), config)
+        .with_clock(Arc::new(MockClock::new(NOW)));
+
+    for n in 1..=100 {
+        limiter.record_error("viewer", failure(n)).await;
+        if limiter.is_user_timed_out("viewer").await.is_some() {
+            return n;
+        }
+    }
+    panic!("never timed out");
+}
+
+#[tokio::test]
+async fn test_decode_failures_time_out_faster_than_client_errors() {
+    let decode = errors_until_timeout(|_| UpstreamFailure::Decode).await;
+    // a different 4xx every time so none of them count double
+    let client = errors_until_timeout(|n| UpstreamFailure::ClientFault(400 + n as u16 % 2)).await;
+
+    assert_eq!(decode, 4);
+    assert_eq!(client, 12);
+}
+
+#[tokio::test]
+async fn test_weightless_failures_never_time_out() {
+    let db = Database::in_memory().await.unwrap();
+    let config = RateLimitConfig {
+        max_errors_before_timeout: 1,
+        error_weights: ErrorWeights::parse_list("decode_failure=0"),
+        ..Default::default()
+    };
+    let limiter = EdgeRateLimitService::with_config(Arc::new(db), config);
+
+    for _ in 0..20 {
+        limiter
+            .record_error("viewer", UpstreamFailure::Decode)
+            .await;
+        limiter
+            .record_error("viewer", UpstreamFailure::Server(502))
+            .await;
+    }
+
+    assert_eq!(limiter.get_error_score("viewer").await, 0.0);
+    assert!(limiter.is_user_timed_out("viewer").await.is_none());
+}
+
+async fn window_limiter(strategy: RateLimitStrategy) -> EdgeRateLimitService {
+    let db = Database::in_memory().await.unwrap();
+    let config = RateLimitConfig {
+        strategy,
+        max_requests_per_window: 5,
+        window_seconds: 1,
+        ..Default::default()
+    };
+    EdgeRateLimitService::with_config(Arc::new(db), config)
+}
+
+// one request to start the window, most of the limit just before it ends and the whole limit
+// again just after. returns how many of that last burst got through
+async fn boundary_burst(limiter: &EdgeRateLimitService) -> usize {
+    limiter.check_rate_limit("viewer", None).await;
+    tokio::time::sleep(Duration::from_millis(700)).await;
+    for _ in 0..4 {
+        assert!(matches!(
+            limiter.check_rate_limit("viewer", None).await,
+            RateLimitResult::Allowed { .. }
+        ));
+    }
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    let mut allowed = 0;
+    for _ in 0..5 {
+        if let RateLimitResult::Allowed { .. } = limiter.check_rate_limit("viewer", None).await {
+            allowed += 1;
+        }
+    }
+    allowed
+}
+
+#[tokio::test]
+async fn test_fixed_window_lets_a_burst_through_around_the_reset() {
+    let limiter = window_limiter(RateLimitStrategy::FixedWindow).await;
+
+    // 9 requests in well under a second with a limit of 5
+    assert_eq!(boundary_burst(&limiter).await, 5);
+}
+
+#[tokio::test]
+async fn test_sliding_window_rejects_a_burst_around_the_reset() {
+    let limiter = window_limiter(RateLimitStrategy::SlidingWindow).await;
+
+    // only the first request aged out, the 4 from right before are still in the window
+    assert_eq!(boundary_burst(&limiter).await, 1);
+}
+
+#[tokio::test]
+async fn test_sliding_window_frees_up_as_requests_age_out() {
+    let clock = Arc::new(MockClock::new(1_700_000_000));
+    let db = Database::in_memory().await.unwrap();
+    let config = RateLimitConfig {
+        strategy: RateLimitStrategy::SlidingWindow,
+        max_requests_per_window: 3,
+        window_seconds: 60,
+        ..Default::default()
+    };
+    let limiter = EdgeRateLimitService::with_config(Arc::new(db), config).with_clock(clock.clone());
+
+    for _ in 0..3 {
+        limiter.check_rate_limit("viewer", None).await;
+        clock.advance(10);
+    }
+    assert_eq!(limiter.peek_rate_limit("viewer").await.used, 3);
+
+    // 30s in, the first request is still 30s from leaving the window
+    match limiter.check_rate_limit("viewer", None).await {
+        RateLimitResult::RateLimited { retry_after } => assert_eq!(retry_after, 30),
+        other => panic!("expected the request to be limited, got {:?}", other),
+    }
+
+    // everything logged so far, the rejected one included, has left the window by now
+    clock.advance(60);
+    assert!(matches!(
+        limiter.check_rate_limit("viewer", None).await,
+        RateLimitResult::Allowed { remaining: 2, .. }
+    ));
+}
+
+#[test]
+fn test_rate_limit_strategy_parses_with_a_fixed_fallback() {
+    assert_eq!(
+        RateLimitStrategy::parse("sliding"),
+        RateLimitStrategy::SlidingWindow
+    );
+    assert_eq!(
+        RateLimitStrategy::parse(" FIXED "),
+        RateLimitStrategy::FixedWindow
+    );
+    assert_eq!(
+        RateLimitStrategy::parse("leaky"),
+        RateLimitStrategy::FixedWindow
+    );
+}
+
+// 5 requests a minute, nothing ages out while a test runs
+async fn minute_limiter(strategy: RateLimitStrategy) -> EdgeRateLimitService {
+    let db = Database::in_memory().await.unwrap();
+    let config = RateLimitConfig {
+        strategy,
+        max_requests_per_window: 5,
+        window_seconds: 60,
+        ..Default::default()
+    };
+    EdgeRateLimitService::with_config(Arc::new(db), config)
+}
+
+async fn limited(limiter: &EdgeRateLimitService, client_id: &str) -> bool {
+    matches!(
+        limiter.check_rate_limit(client_id, None).await,
+        RateLimitResult::RateLimited { .. }
+    )
+}
+
+#[tokio::test]
+async fn test_exempt_client_bypasses_a_tripped_limit() {
+    for strategy in [
+        RateLimitStrategy::FixedWindow,
+        RateLimitStrategy::SlidingWindow,
+    ] {
+        let limiter = minute_limiter(strategy).await;
+
+        for _ in 0..5 {
+            limiter.check_rate_limit("backend", None).await;
+            limiter.check_rate_limit("viewer", None).await;
+        }
+        assert!(limited(&limiter, "backend").await);
+        assert!(limited(&limiter, "viewer").await);
+
+        limiter.set_exempt("backend", true).await;
+        assert!(limiter.is_exempt("backend").await);
+        assert!(!limiter.is_exempt("viewer").await);
+
+        assert!(!limited(&limiter, "backend").await);
+        assert!(limited(&limiter, "viewer").await);
+    }
+}
+
+#[tokio::test]
+async fn test_exemption_can_be_taken_back() {
+    let limiter = minute_limiter(RateLimitStrategy::FixedWindow).await;
+    limiter.set_exempt("backend", true).await;
+
+    // none of these count while it's exempt
+    for _ in 0..10 {
+        assert!(!limited(&limiter, "backend").await);
+    }
+    assert_eq!(limiter.peek_rate_limit("backend").await.used, 0);
+
+    limiter.set_exempt("backend", false).await;
+    assert!(!limiter.is_exempt("backend").await);
+    for _ in 0..5 {
+        limiter.check_rate_limit("backend", None).await;
+    }
+    assert!(limited(&limiter, "backend").await);
+}
+
+#[tokio::test]
+async fn test_exemptions_survive_export_and_import() {
+    let limiter = limiter().await;
+    limiter.set_exempt("backend", true).await;
+
+    let exported = limiter.export_state().await.unwrap();
+    assert_eq!(exported.exemptions, vec!["backend".to_string()]);
+
+    limiter.set_exempt("backend", false).await;
+    assert!(!limiter.is_exempt("backend").await);
+
+    assert_eq!(limiter.import_state(exported).await.unwrap(), 1);
+    assert!(limiter.is_exempt("backend").await);
+}
+
+async fn ip_limiter(strategy: RateLimitStrategy, per_ip: u32) -> EdgeRateLimitService {
+    let db = Database::in_memory().await.unwrap();
+    let config = RateLimitConfig {
+        strategy,
+        max_requests_per_window: 5,
+        max_requests_per_ip_window: per_ip,
+        ..Default::default()
+    };
+    EdgeRateLimitService::with_config(Arc::new(db), config)
+}
+
+// one request per user agent from the same ip, each gets its own client id. returns how many
+// got through
+async fn rotate_user_agents(limiter: &EdgeRateLimitService, ip: Option<&str>) -> usize {
+    let mut allowed = 0;
+    for n in 0..20 {
+        let user_agent = format!("Mozilla/5.0 (rotated {})", n);
+        let client_id = generate_client_id(Some("203.0.113.7"), Some(&user_agent));
+        if let RateLimitResult::Allowed { .. } = limiter.check_rate_limit(&client_id, ip).await {
+            allowed += 1;
+        }
+    }
+    allowed
+}
+
+#[tokio::test]
+async fn test_rotating_user_agents_no_longer_evades_the_limit() {
+    for strategy in [
+        RateLimitStrategy::FixedWindow,
+        RateLimitStrategy::SlidingWindow,
+    ] {
+        // every request is a fresh client bucket, only the ip's stops them
+        let limiter = ip_limiter(strategy, 10).await;
+        assert_eq!(rotate_user_agents(&limiter, None).await, 20);
+
+        let limiter = ip_limiter(strategy, 10).await;
+        assert_eq!(rotate_user_agents(&limiter, Some("203.0.113.7")).await, 10);
+    }
+}
+
+#[tokio::test]
+async fn test_ip_limit_reports_the_stricter_bucket() {
+    let limiter = ip_limiter(RateLimitStrategy::FixedWindow, 8).await;
+    let ip = Some("203.0.113.7");
+
+    // the client bucket has less left
+    for _ in 0..3 {
+        limiter.check_rate_limit("viewer", ip).await;
+    }
+    match limiter.check_rate_limit("viewer", ip).await {
+        RateLimitResult::Allowed { remaining, .. } => assert_eq!(remaining, 1),
+        other => panic!("expected allowed, got {:?}", other),
+    }
+
+    // now the ip's has, from another client on it
+    for _ in 0..3 {
+        limiter.check_rate_limit("other", ip).await;
+    }
+    match limiter.check_rate_limit("third", ip).await {
+        RateLimitResult::Allowed { remaining, .. } => assert_eq!(remaining, 0),
+        other => panic!("expected allowed, got {:?}", other),
+    }
+    assert!(matches!(
+        limiter.check_rate_limit("fourth", ip).await,
+        RateLimitResult::RateLimited { .. }
+    ));
+
+    // other ips are their own bucket
+    assert!(matches!(
+        limiter
+            .check_rate_limit("fifth", Some("198.51.100.20"))
+            .await,
+        RateLimitResult::Allowed { .. }
+    ));
+}
+
+#[tokio::test]
+async fn test_zero_ip_limit_turns_it_off() {
+    let limiter = ip_limiter(RateLimitStrategy::FixedWindow, 0).await;
+
+    assert_eq!(rotate_user_agents(&limiter, Some("203.0.113.7")).await, 20);
+}
+
+#[tokio::test]
+async fn test_exempt_clients_skip_the_ip_limit_too() {
+    let limiter = ip_limiter(RateLimitStrategy::FixedWindow, 2).await;
+    limiter.set_exempt("backend", true).await;
+
+    for _ in 0..10 {
+        assert!(matches!(
+            limiter.check_rate_limit("backend", Some("10.0.0.1")).await,
+            RateLimitResult::Allowed { .. }
+        ));
+    }
+    // and didn't use any of it up
+    assert!(matches!(
+        limiter.check_rate_limit("viewer", Some("10.0.0.1")).await,
+        RateLimitResult::Allowed { .. }
+    ));
+}
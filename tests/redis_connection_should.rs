@@ -0,0 +1,120 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use api::RedisDatabase;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// just enough of a redis server, PING gets PONG and everything else (the client's setup
+// commands) gets OK. when `drop_next` is set the next command closes the connection instead
+struct FakeRedis {
+    addr: String,
+    connections: Arc<AtomicUsize>,
+    drop_next: Arc<AtomicBool>,
+}
+
+impl FakeRedis {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("redis://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let drop_next = Arc::new(AtomicBool::new(false));
+
+        let (count, dropping) = (connections.clone(), drop_next.clone());
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                count.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(Self::serve(socket, dropping.clone()));
+            }
+        });
+
+        Self {
+            addr,
+            connections,
+            drop_next,
+        }
+    }
+
+    async fn serve(mut socket: TcpStream, drop_next: Arc<AtomicBool>) {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 1024];
+        loop {
+            let read = match socket.read(&mut chunk).await {
+                Ok(0) | Err(_) => return,
+                Ok(read) => read,
+            };
+            buf.extend_from_slice(&chunk[..read]);
+
+            while let Some((command, used)) = parse_command(&buf) {
+                buf.drain(..used);
+                if drop_next.swap(false, Ordering::SeqCst) {
+                    return;
+                }
+                let reply: &[u8] = if command.eq_ignore_ascii_case("PING") {
+                    b"+PONG\r\n"
+                } else {
+                    b"+OK\r\n"
+                };
+                if socket.write_all(reply).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn read_line(buf: &[u8], from: usize) -> Option<(&str, usize)> {
+    let end = buf[from..].windows(2).position(|w| w == b"\r\n")? + from;
+    Some((std::str::from_utf8(&buf[from..end]).ok()?, end + 2))
+}
+
+// the command name of the first full command in `buf` and how many bytes it took up
+fn parse_command(buf: &[u8]) -> Option<(String, usize)> {
+    let (count, mut at) = read_line(buf, 0)?;
+    let count: usize = count.strip_prefix('*')?.parse().ok()?;
+
+    let mut name = None;
+    for _ in 0..count {
+        let (len, start) = read_line(buf, at)?;
+        let len: usize = len.strip_prefix('$')?.parse().ok()?;
+        if buf.len() < start + len + 2 {
+            return None;
+        }
+        name.get_or_insert_with(|| String::from_utf8_lossy(&buf[start..start + len]).to_string());
+        at = start + len + 2;
+    }
+
+    Some((name?, at))
+}
+
+#[tokio::test]
+async fn test_recovers_from_a_dropped_connection() {
+    let redis = FakeRedis::start().await;
+    let db = RedisDatabase::connect(&redis.addr).await.unwrap();
+
+    assert!(db.health_check().await.is_ok());
+    assert_eq!(redis.connections.load(Ordering::SeqCst), 1);
+    let mut conn = db.connection.clone();
+
+    redis.drop_next.store(true, Ordering::SeqCst);
+    assert!(db.health_check().await.is_err());
+    assert_eq!(db.reconnect_attempts(), 1);
+
+    let mut recovered = false;
+    for _ in 0..20 {
+        if db.health_check().await.is_ok() {
+            recovered = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    assert!(recovered);
+    assert_eq!(db.reconnect_attempts(), 0);
+    assert!(redis.connections.load(Ordering::SeqCst) >= 2);
+
+    // clones handed out before the drop use the new connection too
+    let pong: String = redis::cmd("PING").query_async(&mut conn).await.unwrap();
+    assert_eq!(pong, "PONG");
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use api::server::services::upstream_attempt_services::UpstreamAttemptLog;
+use api::server::utils::request_id_utils::{self, REQUEST_ID_HEADER};
+use axum::Router;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::middleware;
+use axum::routing::get;
+use common::serve;
+
+// an upstream that answers with the request id it was sent, and the edge route in front of it
+async fn edge() -> String {
+    let upstream = serve(Router::new().route(
+        "/",
+        get(|headers: HeaderMap| async move {
+            headers
+                .get(REQUEST_ID_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("none")
+                .to_string()
+        }),
+    ))
+    .await;
+
+    serve(
+        Router::new()
+            .route(
+                "/proxy",
+                get(move || {
+                    let upstream = upstream.clone();
+                    async move {
+                        let log = UpstreamAttemptLog::new(0, false);
+                        let response = log
+                            .send("proxy", reqwest::Client::new().get(&upstream))
+                            .await
+                            .unwrap();
+                        response.text().await.unwrap()
+                    }
+                }),
+            )
+            .layer(middleware::from_fn(request_id_utils::track_request_id)),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn test_echoes_and_forwards_a_provided_request_id() {
+    let base = edge().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/proxy", base))
+        .header("x-request-id", "abc-123")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.headers()["x-request-id"], "abc-123");
+    assert_eq!(response.text().await.unwrap(), "abc-123");
+}
+
+#[tokio::test]
+async fn test_generates_a_request_id_when_absent() {
+    let base = edge().await;
+
+    let response = reqwest::get(format!("{}/proxy", base)).await.unwrap();
+
+    let id = response.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(id.len(), 32);
+    assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_eq!(response.text().await.unwrap(), id);
+}
+
+#[test]
+fn test_replaces_unusable_request_ids() {
+    let too_long = HeaderValue::from_str(&"a".repeat(200)).unwrap();
+    let with_spaces = HeaderValue::from_static("has spaces");
+
+    assert_ne!(
+        request_id_utils::from_header(Some(&too_long)),
+        "a".repeat(200)
+    );
+    assert_ne!(
+        request_id_utils::from_header(Some(&with_spaces)),
+        "has spaces"
+    );
+    assert_eq!(request_id_utils::from_header(None).len(), 32);
+    assert_ne!(request_id_utils::generate(), request_id_utils::generate());
+}
+
+#[test]
+fn test_no_request_id_outside_a_request() {
+    assert_eq!(request_id_utils::current(), None);
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use std::time::Duration;
+
+use api::EdgeApplicationServer;
+use axum::Router;
+use axum::routing::{get, post};
+
+async fn serve(request_timeout: Duration, max_body_bytes: usize) -> String {
+    let router = Router::new()
+        .route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                "done"
+            }),
+        )
+        .route("/fast", get(|| async { "done" }))
+        .route("/echo", post(|body: String| async move { body }));
+    common::serve(EdgeApplicationServer::with_request_limits(
+        router,
+        request_timeout,
+        max_body_bytes,
+    ))
+    .await
+}
+
+#[tokio::test]
+async fn test_slow_requests_get_a_gateway_timeout() {
+    let base = serve(Duration::from_millis(100), 1024).await;
+
+    let response = reqwest::get(format!("{}/slow", base)).await.unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::GATEWAY_TIMEOUT);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(
+        body["errors"]["message"][0]
+            .as_str()
+            .unwrap()
+            .contains("timeout")
+    );
+}
+
+#[tokio::test]
+async fn test_fast_requests_are_left_alone() {
+    let base = serve(Duration::from_millis(100), 1024).await;
+
+    let response = reqwest::get(format!("{}/fast", base)).await.unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "done");
+}
+
+#[tokio::test]
+async fn test_rejects_bodies_over_the_limit() {
+    let base = serve(Duration::from_secs(5), 16).await;
+    let client = reqwest::Client::new();
+
+    let small = client
+        .post(format!("{}/echo", base))
+        .body("tiny")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(small.status(), reqwest::StatusCode::OK);
+
+    let large = client
+        .post(format!("{}/echo", base))
+        .body("x".repeat(1024))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(large.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+}
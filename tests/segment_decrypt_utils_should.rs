@@ -0,0 +1,88 @@
+use api::server::error::Error;
+use api::server::utils::segment_decrypt_utils::{
+    KeyMethod, SegmentKey, decrypt_segment, parse_iv, sequence_iv,
+};
+
+// one ts packet encrypted with openssl: aes-128-cbc, KEY, and the iv of media sequence 7
+const KEY: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+const ENCRYPTED_PACKET: &str = concat!(
+    "1b67ae07759381cd1f9ffd456b3e54a4386fe6113027e6ddc207e57392530c7a",
+    "e63087c0a91440e12622d9cf82941f8fd16326b687b9f7d9837db514984513cc",
+    "0c435238abdde64f68cf64e4337eb52997c4b3602232f950f42af13e0a7e4d9b",
+    "84d0ce4c31e0da9b27c0e5a5460ee2e88f49c9f180a890e505894cd7a17131df",
+    "7eaf86e0167593a67c4d31ac3f90cd3a7bd119ce8c549fac0cd3884216d24ba5",
+    "95ae372df587caa32d236961364ca80db45c1ea021ac04a28abc41ad07793506",
+);
+
+fn plain_packet() -> Vec<u8> {
+    let mut packet = vec![0x47, 0x40, 0x00, 0x10];
+    packet.extend((0..184u32).map(|i| (i * 7 % 256) as u8));
+    packet
+}
+
+#[test]
+fn test_decrypts_a_known_segment() {
+    let encrypted = hex::decode(ENCRYPTED_PACKET).unwrap();
+
+    let decrypted = decrypt_segment(&encrypted, &KEY, &sequence_iv(7)).unwrap();
+
+    assert_eq!(decrypted, plain_packet());
+    assert_eq!(decrypted[0], 0x47);
+}
+
+#[test]
+fn test_refuses_wrong_keys_and_broken_bodies() {
+    let encrypted = hex::decode(ENCRYPTED_PACKET).unwrap();
+
+    assert!(matches!(
+        decrypt_segment(&encrypted, &KEY[..8], &sequence_iv(7)),
+        Err(Error::InternalServerErrorWithContext(_))
+    ));
+    // not whole blocks, a cut off download
+    assert!(decrypt_segment(&encrypted[..100], &KEY, &sequence_iv(7)).is_err());
+    // the wrong key leaves garbage padding behind
+    assert!(decrypt_segment(&encrypted, &[9; 16], &sequence_iv(7)).is_err());
+}
+
+#[test]
+fn test_sequence_iv_is_the_number_big_endian() {
+    let mut expected = [0u8; 16];
+    expected[15] = 7;
+    assert_eq!(sequence_iv(7), expected);
+    expected[14] = 1;
+    assert_eq!(sequence_iv(263), expected);
+}
+
+#[test]
+fn test_parses_ivs_with_or_without_prefix() {
+    let iv = parse_iv("0x00000000000000000000000000000007").unwrap();
+    assert_eq!(iv, sequence_iv(7));
+    assert_eq!(parse_iv("00000000000000000000000000000007"), Some(iv));
+    assert_eq!(parse_iv("0x7"), Some(iv));
+    assert_eq!(parse_iv("0x"), None);
+    assert_eq!(parse_iv(&format!("0x{}", "1".repeat(33))), None);
+    assert_eq!(parse_iv("not hex"), None);
+}
+
+#[test]
+fn test_parses_key_tags() {
+    let key = SegmentKey::parse(
+        r#"#EXT-X-KEY:METHOD=AES-128,URI="https://keys.example.com/k?id=1,2",IV=0x00000000000000000000000000000007"#,
+    )
+    .unwrap();
+    assert_eq!(key.method, KeyMethod::Aes128);
+    assert_eq!(
+        key.uri.as_deref(),
+        Some("https://keys.example.com/k?id=1,2")
+    );
+    assert_eq!(key.iv, Some(sequence_iv(7)));
+
+    let none = SegmentKey::parse("#EXT-X-KEY:METHOD=NONE").unwrap();
+    assert_eq!(none.method, KeyMethod::None);
+    assert_eq!(none.uri, None);
+
+    let sample = SegmentKey::parse(r#"#EXT-X-KEY:METHOD=SAMPLE-AES,URI="skd://key""#).unwrap();
+    assert_eq!(sample.method, KeyMethod::Other("SAMPLE-AES".to_string()));
+
+    assert_eq!(SegmentKey::parse("#EXTINF:6.0,"), None);
+}
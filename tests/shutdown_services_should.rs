@@ -0,0 +1,165 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use api::EdgeApplicationServer;
+use api::server::services::shutdown_services::{ShutdownHook, ShutdownHooks};
+use axum::Router;
+use axum::routing::get;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+
+struct FlagHook {
+    ran: Arc<AtomicBool>,
+}
+
+#[async_trait::async_trait]
+impl ShutdownHook for FlagHook {
+    fn name(&self) -> &str {
+        "flag"
+    }
+
+    async fn on_shutdown(&self) -> anyhow::Result<()> {
+        self.ran.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+struct HangingHook;
+
+#[async_trait::async_trait]
+impl ShutdownHook for HangingHook {
+    fn name(&self) -> &str {
+        "hanging"
+    }
+
+    async fn on_shutdown(&self) -> anyhow::Result<()> {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_registered_hook_runs_on_graceful_shutdown() {
+    let ran = Arc::new(AtomicBool::new(false));
+    let hooks = ShutdownHooks::new();
+    hooks.register(Arc::new(FlagHook { ran: ran.clone() }));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let router = Router::new().route("/", get(|| async { "ok" }));
+    let (stop, stopped) = oneshot::channel::<()>();
+
+    let server = tokio::spawn(EdgeApplicationServer::serve_until(
+        listener,
+        router,
+        async move {
+            let _ = stopped.await;
+        },
+        hooks,
+        Duration::from_secs(5),
+        Duration::from_secs(1),
+    ));
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!ran.load(Ordering::SeqCst));
+
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+
+    assert!(ran.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_hanging_hook_is_cut_off_at_the_timeout() {
+    let ran = Arc::new(AtomicBool::new(false));
+    let hooks = ShutdownHooks::new();
+    hooks.register(Arc::new(HangingHook));
+    hooks.register(Arc::new(FlagHook { ran: ran.clone() }));
+
+    let started = Instant::now();
+    let finished = hooks.run(Duration::from_millis(100)).await;
+
+    assert_eq!(finished, 0);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    // hooks run side by side, the quick one still got to do its thing
+    assert!(ran.load(Ordering::SeqCst));
+}
+
+// a server with a `/slow` route that takes `delay` to answer, stopped by the returned sender
+async fn slow_server(
+    delay: Duration,
+    drain_timeout: Duration,
+) -> (
+    String,
+    oneshot::Sender<()>,
+    tokio::task::JoinHandle<anyhow::Result<()>>,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let router = Router::new().route(
+        "/slow",
+        get(move || async move {
+            tokio::time::sleep(delay).await;
+            "done"
+        }),
+    );
+    let (stop, stopped) = oneshot::channel::<()>();
+
+    let server = tokio::spawn(EdgeApplicationServer::serve_until(
+        listener,
+        router,
+        async move {
+            let _ = stopped.await;
+        },
+        ShutdownHooks::new(),
+        drain_timeout,
+        Duration::from_secs(1),
+    ));
+
+    (addr, stop, server)
+}
+
+// the raw response
+async fn get_slow(addr: String) -> String {
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    socket
+        .write_all(b"GET /slow HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    let _ = socket.read_to_string(&mut response).await;
+    response
+}
+
+#[tokio::test]
+async fn test_in_flight_request_completes_during_shutdown() {
+    let (addr, stop, server) =
+        slow_server(Duration::from_millis(300), Duration::from_secs(5)).await;
+
+    let request = tokio::spawn(get_slow(addr));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    stop.send(()).unwrap();
+
+    let response = request.await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("done"));
+    server.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_requests_past_the_drain_timeout_are_cut_off() {
+    let (addr, stop, server) =
+        slow_server(Duration::from_secs(30), Duration::from_millis(100)).await;
+
+    let request = tokio::spawn(get_slow(addr));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let started = Instant::now();
+    stop.send(()).unwrap();
+
+    server.await.unwrap().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    // still stuck in the handler, the process exiting is what ends it for real
+    assert!(!request.is_finished());
+    request.abort();
+}
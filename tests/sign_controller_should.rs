@@ -0,0 +1,49 @@
+mod common;
+
+use api::AppConfig;
+use api::server::api::sign_controller::SignController;
+use api::server::services::edge_services::EdgeServices;
+use axum::Extension;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use tower::ServiceExt;
+
+const TOKEN: &str = "s3cret-admin-token";
+
+async fn services() -> EdgeServices {
+    common::services(AppConfig {
+        admin_token: Some(TOKEN.to_string()),
+        ..AppConfig::default()
+    })
+    .await
+}
+
+async fn sign(authorization: Option<&str>) -> StatusCode {
+    let app = SignController::app().layer(Extension(services().await));
+
+    let mut request = Request::builder()
+        .method("POST")
+        .uri("/")
+        .header("content-type", "application/json");
+    if let Some(authorization) = authorization {
+        request = request.header("authorization", authorization);
+    }
+    let request = request
+        .body(Body::from(r#"{"urls":["https://cdn.example.com/a.m3u8"]}"#))
+        .unwrap();
+
+    app.oneshot(request).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn test_signing_needs_the_admin_token() {
+    assert_eq!(sign(None).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        sign(Some("Bearer not-the-token")).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        sign(Some(&format!("Bearer {}", TOKEN))).await,
+        StatusCode::OK
+    );
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use api::server::dtos::sign_dto::SignUrlsRequest;
+use api::server::error::Error;
+use api::server::utils::sign_utils::{MAX_BATCH_SIZE, sign_batch};
+use api::server::utils::signature_utils::SignatureUtil;
+use api::server::utils::upstream_utils::HostAllowlist;
+
+fn util() -> SignatureUtil {
+    SignatureUtil::new("test_secret".to_string())
+}
+
+fn request(urls: &[&str]) -> SignUrlsRequest {
+    SignUrlsRequest {
+        urls: urls.iter().map(|u| u.to_string()).collect(),
+        schema: None,
+    }
+}
+
+fn query(signed_url: &str) -> HashMap<String, String> {
+    let url = url::Url::parse(&format!("http://localhost{}", signed_url)).unwrap();
+    url.query_pairs()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_signed_urls_verify_for_the_client() {
+    let util = util();
+    let expiry = SignatureUtil::generate_expiry(12);
+    let response = sign_batch(
+        &request(&[
+            "https://strm.poocloud.in/live/index.m3u8",
+            "https://cdn.poocloud.in/live/other.m3u8",
+        ]),
+        "client-1",
+        &HostAllowlist::parse_list(".poocloud.in"),
+        &util,
+        expiry,
+    )
+    .unwrap();
+
+    assert_eq!(response.expires_at, expiry);
+    assert_eq!(response.urls.len(), 2);
+    for result in &response.urls {
+        let signed = result.signed_url.as_deref().unwrap();
+        assert!(signed.starts_with("/api/v1/proxy?"));
+
+        let params = query(signed);
+        assert_eq!(params["schema"], "sports");
+        assert_eq!(params["client"], "client-1");
+        assert_eq!(params["kid"], util.key_id());
+        assert!(util.verify_signature("client-1", expiry, &params["url"], &params["sig"]));
+        assert!(!util.verify_signature("client-2", expiry, &params["url"], &params["sig"]));
+    }
+}
+
+#[test]
+fn test_disallowed_host_is_rejected_within_batch() {
+    let response = sign_batch(
+        &request(&[
+            "https://strm.poocloud.in/live/index.m3u8",
+            "https://evil.example.com/index.m3u8",
+            "ftp://strm.poocloud.in/index.m3u8",
+        ]),
+        "client-1",
+        &HostAllowlist::parse_list(".poocloud.in"),
+        &util(),
+        SignatureUtil::generate_expiry(12),
+    )
+    .unwrap();
+
+    assert!(response.urls[0].signed_url.is_some());
+    assert!(response.urls[0].error.is_none());
+    assert!(response.urls[1].signed_url.is_none());
+    assert_eq!(
+        response.urls[1].error.as_deref(),
+        Some("Host is not allowed")
+    );
+    assert!(response.urls[2].signed_url.is_none());
+    assert_eq!(
+        response.urls[2].error.as_deref(),
+        Some("Invalid URL format")
+    );
+}
+
+#[test]
+fn test_oversized_batch_or_bad_schema_is_rejected() {
+    let urls = vec!["https://cdn.example.com/index.m3u8"; MAX_BATCH_SIZE + 1];
+    let result = sign_batch(
+        &request(&urls),
+        "client-1",
+        &HostAllowlist::default(),
+        &util(),
+        SignatureUtil::generate_expiry(12),
+    );
+    assert!(matches!(result, Err(Error::BadRequest(_))));
+
+    let mut bad_schema = request(&["https://cdn.example.com/index.m3u8"]);
+    bad_schema.schema = Some("sports&sig=x".to_string());
+    let result = sign_batch(
+        &bad_schema,
+        "client-1",
+        &HostAllowlist::default(),
+        &util(),
+        SignatureUtil::generate_expiry(12),
+    );
+    assert!(matches!(result, Err(Error::BadRequest(_))));
+}
@@ -1,5 +1,8 @@
 // alot of tests are gone here because they depend on the database, let me know within two weeks if
 // you would like more
+use std::sync::Arc;
+
+use api::server::utils::clock_utils::MockClock;
 use api::server::utils::signature_utils::SignatureUtil;
 
 #[test]
@@ -42,3 +45,139 @@ fn test_expired_signature() {
     // expired signature should fail even if signature is correct
     assert!(!util.verify_signature(client_id, past_expiry, url, &signature));
 }
+
+#[test]
+fn test_fallback_secret_verifies_but_primary_signs() {
+    let old_fleet = SignatureUtil::new("old_secret".to_string());
+    let util = SignatureUtil::with_fallback_secrets(
+        "new_secret".to_string(),
+        vec!["old_secret".to_string()],
+    );
+    let new_fleet = SignatureUtil::new("new_secret".to_string());
+    let expiry = SignatureUtil::generate_expiry(12);
+    let url = "https://example.com";
+    let client_id = "client123";
+
+    // a url minted by the old fleet still works
+    let old_signature = old_fleet.generate_signature(client_id, expiry, url);
+    assert!(util.verify_signature(client_id, expiry, url, &old_signature));
+
+    // new urls only come from the primary secret
+    let signature = util.generate_signature(client_id, expiry, url);
+    assert_eq!(
+        signature,
+        new_fleet.generate_signature(client_id, expiry, url)
+    );
+    assert_ne!(signature, old_signature);
+
+    // something signed with neither still fails
+    let other = SignatureUtil::new("other_secret".to_string());
+    let other_signature = other.generate_signature(client_id, expiry, url);
+    assert!(!util.verify_signature(client_id, expiry, url, &other_signature));
+}
+
+fn seconds_ago(seconds: i64) -> i64 {
+    SignatureUtil::generate_expiry(0) - seconds
+}
+
+#[test]
+fn test_just_expired_signature_passes_within_grace() {
+    let util = SignatureUtil::new("test_secret".to_string()).with_expiry_grace(300);
+    let expiry = seconds_ago(60);
+    let signature = util.generate_signature("client123", expiry, "https://example.com");
+
+    assert!(util.verify_signature("client123", expiry, "https://example.com", &signature));
+
+    // no grace by default
+    let strict = SignatureUtil::new("test_secret".to_string());
+    assert!(!strict.verify_signature("client123", expiry, "https://example.com", &signature));
+}
+
+#[test]
+fn test_old_signature_fails_past_grace() {
+    let util = SignatureUtil::new("test_secret".to_string()).with_expiry_grace(300);
+    let expiry = seconds_ago(3600);
+    let signature = util.generate_signature("client123", expiry, "https://example.com");
+
+    assert!(!util.verify_signature("client123", expiry, "https://example.com", &signature));
+}
+
+#[test]
+fn test_expiry_follows_the_injected_clock() {
+    let clock = Arc::new(MockClock::new(1_700_000_000));
+    let util = SignatureUtil::new("test_secret".to_string())
+        .with_expiry_grace(30)
+        .with_clock(clock.clone());
+
+    let expiry = util.expiry_in(1);
+    assert_eq!(expiry, 1_700_000_000 + 3600);
+    let signature = util.generate_signature("client123", expiry, "https://example.com");
+
+    clock.advance(3600);
+    assert!(util.verify_signature("client123", expiry, "https://example.com", &signature));
+
+    // inside the grace window
+    clock.advance(30);
+    assert!(util.verify_signature("client123", expiry, "https://example.com", &signature));
+
+    clock.advance(1);
+    assert!(!util.verify_signature("client123", expiry, "https://example.com", &signature));
+
+    // and back again if the clock jumps backwards
+    clock.set(1_700_000_000);
+    assert!(util.verify_signature("client123", expiry, "https://example.com", &signature));
+}
+
+#[test]
+fn test_rotated_key_still_verifies_by_kid() {
+    let key_a = SignatureUtil::new("key_a".to_string());
+    let expiry = SignatureUtil::generate_expiry(12);
+    let url = "https://example.com";
+    let signature = key_a.generate_signature("client123", expiry, url);
+    let kid = key_a.key_id().to_string();
+
+    // rotate, B signs now and A is only accepted
+    let rotated =
+        SignatureUtil::with_fallback_secrets("key_b".to_string(), vec!["key_a".to_string()]);
+    assert_ne!(rotated.key_id(), kid);
+    assert!(rotated.verify_signature_with_kid("client123", expiry, url, &signature, Some(&kid)));
+    // old urls without a kid try every key
+    assert!(rotated.verify_signature_with_kid("client123", expiry, url, &signature, None));
+    // a kid only ever picks its own key
+    assert!(!rotated.verify_signature_with_kid(
+        "client123",
+        expiry,
+        url,
+        &signature,
+        Some(rotated.key_id())
+    ));
+}
+
+#[test]
+fn test_revoked_key_fails() {
+    let key_a = SignatureUtil::new("key_a".to_string());
+    let expiry = SignatureUtil::generate_expiry(12);
+    let url = "https://example.com";
+    let signature = key_a.generate_signature("client123", expiry, url);
+
+    // A was dropped from the fallbacks
+    let revoked =
+        SignatureUtil::with_fallback_secrets("key_b".to_string(), vec!["key_c".to_string()]);
+    assert!(!revoked.verify_signature_with_kid(
+        "client123",
+        expiry,
+        url,
+        &signature,
+        Some(key_a.key_id())
+    ));
+    assert!(!revoked.verify_signature("client123", expiry, url, &signature));
+}
+
+#[test]
+fn test_key_id_is_stable_per_secret() {
+    let a = SignatureUtil::new("key_a".to_string());
+
+    assert_eq!(a.key_id(), SignatureUtil::new("key_a".to_string()).key_id());
+    assert_eq!(a.key_id().len(), 8);
+    assert!(!a.key_id().contains("key_a"));
+}
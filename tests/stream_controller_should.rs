@@ -0,0 +1,121 @@
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use api::AppConfig;
+use api::database::stream::Game;
+use api::server::api::stream_controller::StreamController;
+use api::server::error::Error;
+use api::server::extractors::EdgeAuthentication;
+use api::server::services::edge_services::EdgeServices;
+use api::server::services::ppvsu_services::MockPpvsuServiceTrait;
+use axum::extract::Path;
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+
+const CLIENT_ID: &str = "client123";
+const PLAYLIST: &str = "https://cdn.example.com/live/nfl/index.m3u8?token=abc";
+
+fn game(id: i64, video_link: &str) -> Game {
+    Game {
+        id,
+        name: format!("game {}", id),
+        poster: String::new(),
+        start_time: 0,
+        end_time: 0,
+        cache_time: 0,
+        video_link: video_link.to_string(),
+        category: "Football".to_string(),
+    }
+}
+
+async fn services(ppvsu: MockPpvsuServiceTrait) -> EdgeServices {
+    let mut services = common::services(AppConfig::default()).await;
+    services.ppvsu = Arc::new(ppvsu);
+    services
+}
+
+fn query(signed_url: &str) -> HashMap<String, String> {
+    let url = url::Url::parse(&format!("http://localhost{}", signed_url)).unwrap();
+    url.query_pairs().into_owned().collect()
+}
+
+#[tokio::test]
+async fn test_resolve_signs_the_decrypted_link() {
+    let mut ppvsu = MockPpvsuServiceTrait::new();
+    ppvsu
+        .expect_get_game_by_id()
+        .withf(|id| *id == 7)
+        .returning(|id| Ok(game(id, "https://embed.example.com/embed/nfl/buf-den")));
+    ppvsu
+        .expect_fetch_video_link()
+        .withf(|iframe| iframe == "https://embed.example.com/embed/nfl/buf-den")
+        .times(1)
+        .returning(|_| Ok(PLAYLIST.to_string()));
+    let services = services(ppvsu).await;
+
+    let response = StreamController::resolve_game_endpoint(
+        EdgeAuthentication(CLIENT_ID.to_string(), services.clone()),
+        Path(7),
+    )
+    .await
+    .unwrap();
+
+    assert!(response.signed_url.starts_with("/api/v1/proxy?"));
+    let params = query(&response.signed_url);
+    assert_eq!(
+        URL_SAFE_NO_PAD.decode(&params["url"]).unwrap(),
+        PLAYLIST.as_bytes()
+    );
+    assert_eq!(params["stream"], "ppvsu-7");
+    assert_eq!(params["exp"], response.expires_at.to_string());
+    // the stream tag is signed along with the url
+    assert!(services.signature_util.verify_signature_with_kid(
+        CLIENT_ID,
+        response.expires_at,
+        &format!("{}&stream={}", params["url"], params["stream"]),
+        &params["sig"],
+        Some(&params["kid"]),
+    ));
+}
+
+#[tokio::test]
+async fn test_resolve_uses_a_direct_playlist_as_is() {
+    let mut ppvsu = MockPpvsuServiceTrait::new();
+    ppvsu
+        .expect_get_game_by_id()
+        .returning(|id| Ok(game(id, PLAYLIST)));
+    ppvsu.expect_fetch_video_link().never();
+    let services = services(ppvsu).await;
+
+    let response = StreamController::resolve_game_endpoint(
+        EdgeAuthentication(CLIENT_ID.to_string(), services),
+        Path(3),
+    )
+    .await
+    .unwrap();
+
+    let params = query(&response.signed_url);
+    assert_eq!(
+        URL_SAFE_NO_PAD.decode(&params["url"]).unwrap(),
+        PLAYLIST.as_bytes()
+    );
+    assert_eq!(params["stream"], "ppvsu-3");
+}
+
+#[tokio::test]
+async fn test_resolve_passes_on_a_missing_game() {
+    let mut ppvsu = MockPpvsuServiceTrait::new();
+    ppvsu
+        .expect_get_game_by_id()
+        .returning(|id| Err(Error::NotFound(format!("game {} not found", id))));
+    let services = services(ppvsu).await;
+
+    let result = StreamController::resolve_game_endpoint(
+        EdgeAuthentication(CLIENT_ID.to_string(), services),
+        Path(404),
+    )
+    .await;
+
+    assert!(matches!(result, Err(Error::NotFound(_))));
+}
@@ -0,0 +1,215 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use api::Database;
+use api::database::stream::{DynStreamsRepository, Game, StreamsRepository};
+use api::server::dtos::stream_dto::{CategoryDto, GameFilter};
+use api::server::error::Error;
+use api::server::services::ppvsu_services::MockPpvsuServiceTrait;
+use api::server::services::refresh_health_services::RefreshRetry;
+use api::server::services::stream_services::{StreamsService, StreamsServiceTrait};
+use api::server::utils::clock_utils::MockClock;
+
+const NOW: i64 = 1_700_000_000;
+
+fn game(id: i64, category: &str, start_time: i64, end_time: i64) -> Game {
+    Game {
+        id,
+        name: format!("game {}", id),
+        poster: String::new(),
+        start_time,
+        end_time,
+        cache_time: NOW,
+        video_link: format!("https://embed.example.com/embed/{}", id),
+        category: category.to_string(),
+    }
+}
+
+fn games() -> Vec<Game> {
+    vec![
+        game(1, "Football", NOW - 600, NOW + 3600),
+        game(2, "Football", NOW + 3600, NOW + 7200),
+        game(3, "Basketball", NOW - 600, NOW + 3600),
+        game(4, "Basketball", NOW - 7200, NOW - 3600),
+    ]
+}
+
+// the upstream gets asked once, whatever it returns is what gets filtered
+async fn service(games: Vec<Game>) -> StreamsService {
+    let mut ppvsu = MockPpvsuServiceTrait::new();
+    ppvsu
+        .expect_fetch_and_cache_games()
+        .times(1)
+        .returning(move || Ok(games.clone()));
+
+    let db = Arc::new(Database::in_memory().await.unwrap()) as DynStreamsRepository;
+    StreamsService::new(db, Arc::new(ppvsu)).with_clock(Arc::new(MockClock::new(NOW)))
+}
+
+fn ids(categories: &[CategoryDto]) -> Vec<(String, Vec<i64>)> {
+    categories
+        .iter()
+        .map(|c| {
+            let mut ids: Vec<i64> = c.games.iter().map(|g| g.id).collect();
+            ids.sort();
+            (c.category.clone(), ids)
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_no_filter_returns_every_game_by_category() {
+    let service = service(games()).await;
+
+    let categories = service.get_all_games(GameFilter::default()).await.unwrap();
+
+    assert_eq!(
+        ids(&categories),
+        vec![
+            ("Basketball".to_string(), vec![3, 4]),
+            ("Football".to_string(), vec![1, 2]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_filters_by_category_ignoring_case() {
+    let service = service(games()).await;
+
+    let categories = service
+        .get_all_games(GameFilter {
+            category: Some("football".to_string()),
+            only_live: false,
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(ids(&categories), vec![("Football".to_string(), vec![1, 2])]);
+}
+
+#[tokio::test]
+async fn test_only_live_drops_upcoming_and_finished_games() {
+    let service = service(games()).await;
+
+    let categories = service
+        .get_all_games(GameFilter {
+            category: None,
+            only_live: true,
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(
+        ids(&categories),
+        vec![
+            ("Basketball".to_string(), vec![3]),
+            ("Football".to_string(), vec![1]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_category_and_only_live_combine() {
+    let service = service(games()).await;
+
+    let categories = service
+        .get_all_games(GameFilter {
+            category: Some("Basketball".to_string()),
+            only_live: true,
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(ids(&categories), vec![("Basketball".to_string(), vec![3])]);
+}
+
+#[tokio::test]
+async fn test_filters_cached_games_without_refetching() {
+    let db = Database::in_memory().await.unwrap();
+    for game in games() {
+        db.store_game("ppvsu", &game).await.unwrap();
+    }
+    db.set_last_fetch_time("ppvsu", NOW - 60).await.unwrap();
+
+    let mut ppvsu = MockPpvsuServiceTrait::new();
+    ppvsu.expect_fetch_and_cache_games().never();
+    let service = StreamsService::new(Arc::new(db), Arc::new(ppvsu))
+        .with_clock(Arc::new(MockClock::new(NOW)));
+
+    let categories = service
+        .get_all_games(GameFilter {
+            category: Some("Football".to_string()),
+            only_live: true,
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(ids(&categories), vec![("Football".to_string(), vec![1])]);
+}
+
+#[tokio::test]
+async fn test_failed_refetch_serves_stale_games_and_retries_later() {
+    let db = Arc::new(Database::in_memory().await.unwrap());
+    for game in games() {
+        db.store_game("ppvsu", &game).await.unwrap();
+    }
+    db.set_last_fetch_time("ppvsu", NOW - 7200).await.unwrap();
+
+    // upstream is down for the request, back by the time the retry runs
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut ppvsu = MockPpvsuServiceTrait::new();
+    let counted = calls.clone();
+    ppvsu
+        .expect_fetch_and_cache_games()
+        .times(2)
+        .returning(move || {
+            if counted.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err(Error::Forbidden)
+            } else {
+                Ok(games())
+            }
+        });
+
+    let retry = Arc::new(RefreshRetry::new(Duration::from_millis(50)));
+    let service = StreamsService::new(db.clone(), Arc::new(ppvsu))
+        .with_clock(Arc::new(MockClock::new(NOW)))
+        .with_refresh_retry(retry.clone());
+
+    let categories = service.get_all_games(GameFilter::default()).await.unwrap();
+    assert_eq!(
+        ids(&categories),
+        vec![
+            ("Basketball".to_string(), vec![3, 4]),
+            ("Football".to_string(), vec![1, 2]),
+        ]
+    );
+    assert!(retry.is_scheduled());
+    assert_eq!(
+        db.get_last_fetch_time("ppvsu").await.unwrap(),
+        Some(NOW - 7200)
+    );
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert!(!retry.is_scheduled());
+    assert_eq!(db.get_last_fetch_time("ppvsu").await.unwrap(), Some(NOW));
+}
+
+#[tokio::test]
+async fn test_failed_refetch_without_cache_is_an_error() {
+    let mut ppvsu = MockPpvsuServiceTrait::new();
+    ppvsu
+        .expect_fetch_and_cache_games()
+        .times(1)
+        .returning(|| Err(Error::Forbidden));
+
+    let retry = Arc::new(RefreshRetry::new(Duration::from_millis(50)));
+    let db = Arc::new(Database::in_memory().await.unwrap()) as DynStreamsRepository;
+    let service = StreamsService::new(db, Arc::new(ppvsu))
+        .with_clock(Arc::new(MockClock::new(NOW)))
+        .with_refresh_retry(retry.clone());
+
+    assert!(service.get_all_games(GameFilter::default()).await.is_err());
+    assert!(!retry.is_scheduled());
+}
@@ -0,0 +1,290 @@
+use api::Database;
+use api::database::stream::{Game, StreamsRepository};
+use chrono::Utc;
+
+fn game(id: i64, category: &str, start_time: i64, end_time: i64) -> Game {
+    Game {
+        id,
+        name: format!("game {}", id),
+        poster: String::new(),
+        start_time,
+        end_time,
+        cache_time: 0,
+        video_link: format!("https://embed.example.com/embed/{}", id),
+        category: category.to_string(),
+    }
+}
+
+fn ids(mut games: Vec<Game>) -> Vec<i64> {
+    games.sort_by_key(|g| g.id);
+    games.into_iter().map(|g| g.id).collect()
+}
+
+#[tokio::test]
+async fn test_store_game_updates_indexes() {
+    let db = Database::in_memory().await.unwrap();
+    let now = Utc::now().timestamp();
+
+    db.store_game("ppvsu", &game(1, "Football", now - 60, now + 3600))
+        .await
+        .unwrap();
+    db.store_game("ppvsu", &game(2, "Football", now + 3600, now + 7200))
+        .await
+        .unwrap();
+    db.store_game("ppvsu", &game(3, "Basketball", now - 60, now + 3600))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        ids(db.get_games_by_category("ppvsu", "Football").await.unwrap()),
+        vec![1, 2]
+    );
+    assert_eq!(
+        ids(db
+            .get_games_by_category("ppvsu", "Basketball")
+            .await
+            .unwrap()),
+        vec![3]
+    );
+    assert_eq!(ids(db.get_live_games("ppvsu").await.unwrap()), vec![1, 3]);
+
+    // the indexes don't leak into the plain game listing
+    assert_eq!(ids(db.get_games("ppvsu").await.unwrap()), vec![1, 2, 3]);
+}
+
+#[tokio::test]
+async fn test_category_change_moves_game_between_indexes() {
+    let db = Database::in_memory().await.unwrap();
+    let now = Utc::now().timestamp();
+
+    db.store_game("ppvsu", &game(1, "Football", now - 60, now + 3600))
+        .await
+        .unwrap();
+    db.store_game("ppvsu", &game(1, "Soccer", now - 60, now + 3600))
+        .await
+        .unwrap();
+
+    assert!(
+        db.get_games_by_category("ppvsu", "Football")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(
+        ids(db.get_games_by_category("ppvsu", "Soccer").await.unwrap()),
+        vec![1]
+    );
+}
+
+#[tokio::test]
+async fn test_delete_game_removes_index_entries() {
+    let db = Database::in_memory().await.unwrap();
+    let now = Utc::now().timestamp();
+
+    db.store_game("ppvsu", &game(1, "Football", now - 60, now + 3600))
+        .await
+        .unwrap();
+    db.delete_game("ppvsu", 1).await.unwrap();
+
+    assert!(
+        db.get_games_by_category("ppvsu", "Football")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(db.get_live_games("ppvsu").await.unwrap().is_empty());
+
+    let Database::Memory(mem) = &db else {
+        unreachable!("tests only run against the in-memory store");
+    };
+    assert!(mem.store.scan("ppvsu:*").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_rebuilding_the_live_index_follows_the_clock() {
+    let db = Database::in_memory().await.unwrap();
+    let now = Utc::now().timestamp();
+
+    // live when stored and over by `later`, upcoming when stored and started by `later`
+    db.store_game("ppvsu", &game(1, "Football", now - 60, now + 600))
+        .await
+        .unwrap();
+    db.store_game("ppvsu", &game(2, "Football", now + 300, now + 3600))
+        .await
+        .unwrap();
+    assert_eq!(ids(db.get_live_games("ppvsu").await.unwrap()), vec![1]);
+
+    let later = now + 1200;
+    assert_eq!(db.rebuild_live_index("ppvsu", later).await.unwrap(), 1);
+    assert_eq!(ids(db.get_live_games("ppvsu").await.unwrap()), vec![2]);
+
+    // nothing live empties it
+    assert_eq!(db.rebuild_live_index("ppvsu", now + 7200).await.unwrap(), 0);
+    assert!(db.get_live_games("ppvsu").await.unwrap().is_empty());
+}
+
+// games 1..=5 starting an hour apart, stored out of order
+async fn paged_db() -> Database {
+    let db = Database::in_memory().await.unwrap();
+    let now = Utc::now().timestamp();
+    for id in [3, 1, 5, 2, 4] {
+        let start_time = now + id * 3600;
+        db.store_game(
+            "ppvsu",
+            &game(id, "Football", start_time, start_time + 3600),
+        )
+        .await
+        .unwrap();
+    }
+    db
+}
+
+fn in_order(games: Vec<Game>) -> Vec<i64> {
+    games.into_iter().map(|g| g.id).collect()
+}
+
+#[tokio::test]
+async fn test_pages_through_games_by_start_time() {
+    let db = paged_db().await;
+
+    let first = db.get_games_paginated("ppvsu", 0, 2).await.unwrap();
+    let second = db.get_games_paginated("ppvsu", 2, 2).await.unwrap();
+    let last = db.get_games_paginated("ppvsu", 4, 2).await.unwrap();
+
+    assert_eq!(in_order(first), vec![1, 2]);
+    assert_eq!(in_order(second), vec![3, 4]);
+    assert_eq!(in_order(last), vec![5]);
+}
+
+#[tokio::test]
+async fn test_pagination_boundaries() {
+    let db = paged_db().await;
+
+    assert_eq!(
+        in_order(db.get_games_paginated("ppvsu", 0, 5).await.unwrap()),
+        vec![1, 2, 3, 4, 5]
+    );
+    assert_eq!(
+        in_order(
+            db.get_games_paginated("ppvsu", 0, usize::MAX)
+                .await
+                .unwrap()
+        ),
+        vec![1, 2, 3, 4, 5]
+    );
+    assert!(
+        db.get_games_paginated("ppvsu", 5, 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(
+        db.get_games_paginated("ppvsu", 0, 0)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(
+        db.get_games_paginated("other", 0, 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_pages_follow_deletes_and_start_time_changes() {
+    let db = paged_db().await;
+    let now = Utc::now().timestamp();
+
+    db.delete_game("ppvsu", 2).await.unwrap();
+    // moved to the front
+    db.store_game("ppvsu", &game(5, "Football", now - 60, now + 3600))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        in_order(db.get_games_paginated("ppvsu", 0, 10).await.unwrap()),
+        vec![5, 1, 3, 4]
+    );
+}
+
+#[tokio::test]
+async fn test_category_filter_only_returns_that_category() {
+    let db = paged_db().await;
+    let now = Utc::now().timestamp();
+    db.store_game("ppvsu", &game(6, "Basketball", now, now + 3600))
+        .await
+        .unwrap();
+    db.store_game("other", &game(7, "Basketball", now, now + 3600))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        ids(db
+            .get_games_by_category("ppvsu", "Basketball")
+            .await
+            .unwrap()),
+        vec![6]
+    );
+    assert_eq!(
+        ids(db.get_games_by_category("ppvsu", "Football").await.unwrap()),
+        vec![1, 2, 3, 4, 5]
+    );
+    assert!(
+        db.get_games_by_category("ppvsu", "Hockey")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    // the pagination index doesn't leak into the plain game listing either
+    assert_eq!(
+        ids(db.get_games("ppvsu").await.unwrap()),
+        vec![1, 2, 3, 4, 5, 6]
+    );
+}
+
+#[tokio::test]
+async fn test_delete_expired_games_keeps_live_and_upcoming_ones() {
+    let db = Database::in_memory().await.unwrap();
+    let now = Utc::now().timestamp();
+
+    // ended three hours ago, ended half an hour ago, live, upcoming, no end time
+    let stored = [
+        game(1, "Football", now - 4 * 3600, now - 3 * 3600),
+        game(2, "Football", now - 3600, now - 1800),
+        game(3, "Football", now - 60, now + 3600),
+        game(4, "Basketball", now + 3600, now + 7200),
+        game(5, "Basketball", now - 4 * 3600, 0),
+    ];
+    for game in &stored {
+        db.store_game("ppvsu", game).await.unwrap();
+    }
+
+    let deleted = db.delete_expired_games("ppvsu", now, 3600).await.unwrap();
+
+    assert_eq!(deleted, 1);
+    assert!(db.get_game("ppvsu", 1).await.unwrap().is_none());
+    assert_eq!(ids(db.get_games("ppvsu").await.unwrap()), vec![2, 3, 4, 5]);
+
+    // and it's gone from the indexes as well
+    assert_eq!(
+        ids(db.get_games_by_category("ppvsu", "Football").await.unwrap()),
+        vec![2, 3]
+    );
+    assert_eq!(
+        in_order(db.get_games_paginated("ppvsu", 0, 10).await.unwrap()),
+        vec![5, 2, 3, 4]
+    );
+}
+
+#[tokio::test]
+async fn test_delete_expired_games_with_nothing_expired() {
+    let db = paged_db().await;
+    let now = Utc::now().timestamp();
+
+    assert_eq!(db.delete_expired_games("ppvsu", now, 0).await.unwrap(), 0);
+    assert_eq!(db.delete_expired_games("other", now, 0).await.unwrap(), 0);
+    assert_eq!(ids(db.get_games("ppvsu").await.unwrap()).len(), 5);
+}
@@ -0,0 +1,80 @@
+use std::collections::HashSet;
+
+use api::server::utils::ttl_utils::{MAX_TTL_JITTER_PERCENT, TtlJitter};
+
+#[test]
+fn test_jittered_ttls_stay_within_the_percent() {
+    let jitter = TtlJitter::new(10);
+    assert_eq!(jitter.bounds(300), (270, 330));
+
+    let ttls: Vec<u64> = (0..10_000).map(|_| jitter.apply(300)).collect();
+    assert!(ttls.iter().all(|ttl| (270..=330).contains(ttl)));
+
+    // actually spread out, and to both sides of the base
+    let distinct: HashSet<u64> = ttls.iter().copied().collect();
+    assert!(distinct.len() > 30, "only {} distinct ttls", distinct.len());
+    assert!(ttls.iter().any(|ttl| *ttl < 300));
+    assert!(ttls.iter().any(|ttl| *ttl > 300));
+}
+
+#[test]
+fn test_short_ttls_are_jittered_too() {
+    let jitter = TtlJitter::new(10);
+
+    let ttls: HashSet<u64> = (0..1_000).map(|_| jitter.apply(10)).collect();
+    assert_eq!(ttls, HashSet::from([9, 10, 11]));
+}
+
+#[test]
+fn test_no_jitter_keeps_the_ttl() {
+    let jitter = TtlJitter::new(0);
+
+    assert!((0..100).all(|_| jitter.apply(300) == 300));
+    assert_eq!(jitter.apply_seeded(300, 42), 300);
+}
+
+#[test]
+fn test_a_zero_ttl_stays_zero() {
+    let jitter = TtlJitter::new(25);
+
+    assert_eq!(jitter.bounds(0), (0, 0));
+    assert_eq!(jitter.apply(0), 0);
+}
+
+#[test]
+fn test_percent_is_capped() {
+    let jitter = TtlJitter::new(200);
+
+    assert_eq!(jitter.percent(), MAX_TTL_JITTER_PERCENT);
+    assert_eq!(jitter.bounds(100), (50, 150));
+}
+
+#[test]
+fn test_seeded_ttls_are_the_same_for_the_same_seed() {
+    let jitter = TtlJitter::new(10);
+
+    for seed in 1_700_000_000..1_700_001_000u64 {
+        let ttl = jitter.apply_seeded(3600, seed);
+        assert!((3240..=3960).contains(&ttl));
+        assert_eq!(jitter.apply_seeded(3600, seed), ttl);
+    }
+
+    let spread: HashSet<u64> = (1_700_000_000..1_700_001_000u64)
+        .map(|seed| jitter.apply_seeded(3600, seed))
+        .collect();
+    assert!(spread.len() > 100);
+}
+
+#[test]
+fn test_staleness_flips_once_at_the_seeded_ttl() {
+    let jitter = TtlJitter::new(10);
+    let stored_at = 1_700_000_000;
+    let ttl = jitter.apply_seeded(3600, stored_at as u64) as i64;
+
+    assert!(!jitter.is_stale(stored_at, stored_at + ttl, 3600));
+    assert!(jitter.is_stale(stored_at, stored_at + ttl + 1, 3600));
+
+    // never before the shortest or after the longest window
+    assert!(!jitter.is_stale(stored_at, stored_at + 3240, 3600));
+    assert!(jitter.is_stale(stored_at, stored_at + 3961, 3600));
+}
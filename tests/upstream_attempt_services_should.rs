@@ -0,0 +1,82 @@
+mod common;
+
+use std::time::Duration;
+
+use api::server::services::upstream_attempt_services::UpstreamAttemptLog;
+use common::{MockResponse, MockUpstream};
+
+#[test]
+fn test_recent_attempts_come_back_in_order() {
+    let log = UpstreamAttemptLog::new(10, false);
+
+    log.record("proxy", "a.example.com", Ok(200), Duration::from_millis(12));
+    log.record(
+        "prefetch",
+        "b.example.com",
+        Ok(403),
+        Duration::from_millis(30),
+    );
+    log.record(
+        "ppvsu",
+        "api.ppv.to",
+        Err("connection reset".to_string()),
+        Duration::from_millis(5),
+    );
+
+    let attempts = log.recent(usize::MAX);
+    let hosts: Vec<&str> = attempts.iter().map(|a| a.host.as_str()).collect();
+    assert_eq!(hosts, ["a.example.com", "b.example.com", "api.ppv.to"]);
+
+    assert_eq!(attempts[1].source, "prefetch");
+    assert_eq!(attempts[1].status, Some(403));
+    assert_eq!(attempts[1].latency_ms, 30);
+    assert_eq!(attempts[2].status, None);
+    assert_eq!(attempts[2].error.as_deref(), Some("connection reset"));
+    assert!(attempts.iter().all(|a| !a.via_proxy));
+    assert!(attempts.windows(2).all(|w| w[0].at <= w[1].at));
+
+    // the limit keeps the newest ones
+    let last_two: Vec<String> = log.recent(2).into_iter().map(|a| a.host).collect();
+    assert_eq!(last_two, ["b.example.com", "api.ppv.to"]);
+}
+
+#[test]
+fn test_buffer_is_capped() {
+    let log = UpstreamAttemptLog::new(3, true);
+
+    for i in 0..5 {
+        log.record("proxy", &format!("host{}", i), Ok(200), Duration::ZERO);
+    }
+
+    let hosts: Vec<String> = log.recent(usize::MAX).into_iter().map(|a| a.host).collect();
+    assert_eq!(hosts, ["host2", "host3", "host4"]);
+    assert!(log.recent(usize::MAX).iter().all(|a| a.via_proxy));
+}
+
+#[tokio::test]
+async fn test_send_records_the_response_status() {
+    let upstream = MockUpstream::always(MockResponse::status(429)).await;
+
+    let log = UpstreamAttemptLog::new(10, false);
+    let http = reqwest::Client::new();
+
+    let response = log
+        .send("proxy", http.get(upstream.url("/live/index.m3u8")))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 429);
+
+    // nothing is listening on port 1
+    assert!(
+        log.send("proxy", http.get("http://127.0.0.1:1/"))
+            .await
+            .is_err()
+    );
+
+    let attempts = log.recent(usize::MAX);
+    assert_eq!(attempts.len(), 2);
+    assert_eq!(attempts[0].host, "127.0.0.1");
+    assert_eq!(attempts[0].status, Some(429));
+    assert_eq!(attempts[1].status, None);
+    assert!(attempts[1].error.is_some());
+}
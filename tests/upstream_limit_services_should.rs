@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use api::server::error::Error;
+use api::server::services::upstream_limit_services::{UpstreamLimiter, UpstreamRateLimit};
+
+#[tokio::test]
+async fn test_concurrent_fetches_to_one_host_are_capped() {
+    let limiter = Arc::new(UpstreamLimiter::new(2, Duration::from_secs(5)));
+    let active = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+
+    let mut tasks = Vec::new();
+    for _ in 0..8 {
+        let limiter = limiter.clone();
+        let active = active.clone();
+        let peak = peak.clone();
+        tasks.push(tokio::spawn(async move {
+            let _permit = limiter.acquire("cdn.example.com").await.unwrap();
+            let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            active.fetch_sub(1, Ordering::SeqCst);
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    assert_eq!(peak.load(Ordering::SeqCst), 2);
+    assert_eq!(limiter.active("cdn.example.com"), 0);
+}
+
+#[tokio::test]
+async fn test_other_hosts_run_while_one_is_full() {
+    let limiter = UpstreamLimiter::new(1, Duration::from_millis(20));
+
+    let _held = limiter.acquire("busy.example.com").await.unwrap();
+
+    // a different host isn't affected by the busy one
+    let other = limiter.acquire("other.example.com").await.unwrap();
+    assert!(other.is_some());
+    assert_eq!(limiter.active("busy.example.com"), 1);
+    assert_eq!(limiter.active("other.example.com"), 1);
+
+    // the busy host gives up after the queue wait with a 503
+    let result = limiter.acquire("busy.example.com").await;
+    assert!(matches!(result, Err(Error::ServiceUnavailable { .. })));
+}
+
+#[tokio::test]
+async fn test_idle_hosts_are_dropped() {
+    let limiter = UpstreamLimiter::new(1, Duration::from_millis(20));
+
+    let held = limiter.acquire("busy.example.com").await.unwrap();
+    for i in 0..50 {
+        let _permit = limiter
+            .acquire(&format!("cdn{}.example.com", i))
+            .await
+            .unwrap();
+    }
+
+    // the last host plus the one still holding a slot
+    assert_eq!(limiter.tracked_hosts(), 2);
+    assert_eq!(limiter.active("busy.example.com"), 1);
+
+    drop(held);
+    limiter.acquire("new.example.com").await.unwrap();
+    assert_eq!(limiter.tracked_hosts(), 1);
+    assert_eq!(limiter.active("busy.example.com"), 0);
+}
+
+#[tokio::test]
+async fn test_zero_disables_the_cap() {
+    let limiter = UpstreamLimiter::new(0, Duration::from_millis(20));
+
+    for _ in 0..10 {
+        assert!(limiter.acquire("cdn.example.com").await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_rate_limit_of_zero_never_waits() {
+    let rate = UpstreamRateLimit::new(0);
+
+    tokio::time::timeout(Duration::from_millis(100), async {
+        for _ in 0..1000 {
+            rate.acquire().await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_rate_limit_bucket_refills_up_to_its_size() {
+    let rate = UpstreamRateLimit::new(20);
+    for _ in 0..20 {
+        rate.acquire().await;
+    }
+    assert_eq!(rate.available(), 0);
+
+    // 50ms per token, a long wait still only fills the bucket back up to a second's worth
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(rate.available(), 20);
+}
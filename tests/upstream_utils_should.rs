@@ -0,0 +1,478 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use api::server::error::Error;
+use api::server::utils::upstream_utils::{
+    self, BodyKind, ContentTypeOverrides, ForwardedHeaders, HostAllowlist, Schema,
+    UpstreamConnection, UpstreamHttp, UpstreamRetry, UpstreamTimeouts, validate_target,
+};
+use common::{MockResponse, MockUpstream};
+use futures::StreamExt;
+use reqwest::header::{ACCEPT, ACCEPT_LANGUAGE, CONNECTION, COOKIE, HeaderMap, HeaderValue};
+
+fn outbound_connection_header(schema: &str, close_schemas: &str) -> String {
+    let policy = UpstreamConnection::for_schema(schema, close_schemas);
+    let request = policy
+        .apply(reqwest::Client::new().get("https://cdn.example.com/index.m3u8"))
+        .build()
+        .unwrap();
+
+    request
+        .headers()
+        .get(CONNECTION)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string()
+}
+
+#[test]
+fn test_configured_schema_closes_connection() {
+    assert_eq!(outbound_connection_header("captions", "captions"), "close");
+    assert_eq!(
+        outbound_connection_header("sports", "captions, sports"),
+        "close"
+    );
+}
+
+#[test]
+fn test_unconfigured_schema_keeps_pooling() {
+    assert_eq!(outbound_connection_header("sports", ""), "keep-alive");
+    assert_eq!(
+        outbound_connection_header("sports", "captions"),
+        "keep-alive"
+    );
+}
+
+fn outbound_timeout(url: &str) -> Option<Duration> {
+    let timeouts = UpstreamTimeouts::new(Duration::from_secs(5), Duration::from_secs(45));
+    let request = timeouts
+        .apply(reqwest::Client::new().get(url), url)
+        .build()
+        .unwrap();
+
+    request.timeout().copied()
+}
+
+#[test]
+fn test_playlist_fetch_uses_shorter_budget() {
+    assert_eq!(
+        outbound_timeout("https://cdn.example.com/live/index.m3u8?token=abc"),
+        Some(Duration::from_secs(5))
+    );
+    assert_eq!(
+        outbound_timeout("https://cdn.example.com/live/INDEX.M3U8"),
+        Some(Duration::from_secs(5))
+    );
+}
+
+#[test]
+fn test_segment_fetch_uses_longer_budget() {
+    assert_eq!(
+        outbound_timeout("https://cdn.example.com/live/seg-001.ts"),
+        Some(Duration::from_secs(45))
+    );
+    // a .m3u8 inside the query string doesn't make it a playlist
+    assert_eq!(
+        outbound_timeout("https://cdn.example.com/blob?src=index.m3u8"),
+        Some(Duration::from_secs(45))
+    );
+}
+
+#[test]
+fn test_allowlisted_client_header_is_forwarded() {
+    let forwarded =
+        ForwardedHeaders::parse_list("sports:accept-language, sports:dnt, sports:cookie");
+    let mut client_headers = HeaderMap::new();
+    client_headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("en-US"));
+    client_headers.insert(COOKIE, HeaderValue::from_static("session=secret"));
+    client_headers.insert(ACCEPT, HeaderValue::from_static("text/html"));
+
+    let schema_headers = reqwest::Client::new()
+        .get("https://cdn.example.com/seg_001.ts")
+        .header(ACCEPT, "*/*");
+    let request = forwarded
+        .apply(schema_headers, "sports", &client_headers)
+        .build()
+        .unwrap();
+
+    assert_eq!(request.headers().get(ACCEPT_LANGUAGE).unwrap(), "en-US");
+    // cookie is never forwarded even when configured
+    assert!(request.headers().get(COOKIE).is_none());
+    // accept isn't allowlisted and the schema value stays as the only one
+    assert_eq!(request.headers().get_all(ACCEPT).iter().count(), 1);
+    assert_eq!(request.headers().get(ACCEPT).unwrap(), "*/*");
+}
+
+#[test]
+fn test_schema_header_wins_over_forwarded_one() {
+    let forwarded = ForwardedHeaders::parse_list("sports:accept,captions:accept-language");
+    let mut client_headers = HeaderMap::new();
+    client_headers.insert(ACCEPT, HeaderValue::from_static("text/html"));
+    client_headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("en-US"));
+
+    let request = forwarded
+        .apply(
+            reqwest::Client::new()
+                .get("https://cdn.example.com/index.m3u8")
+                .header(ACCEPT, "*/*"),
+            "sports",
+            &client_headers,
+        )
+        .build()
+        .unwrap();
+
+    assert_eq!(request.headers().get_all(ACCEPT).iter().count(), 1);
+    assert_eq!(request.headers().get(ACCEPT).unwrap(), "*/*");
+    // accept-language is only allowlisted for captions
+    assert!(request.headers().get(ACCEPT_LANGUAGE).is_none());
+}
+
+#[test]
+fn test_range_and_conditional_headers_are_never_forwarded() {
+    // the proxy caches whole bodies and answers ranges itself, a 206 or 304 from upstream would
+    // end up cached as the segment
+    let forwarded = ForwardedHeaders::parse_list(
+        "sports:range,sports:if-range,sports:if-none-match,sports:if-modified-since",
+    );
+
+    assert!(forwarded.allowed("sports").is_empty());
+}
+
+// echoes back the protocol version the request came in over, axum serves both HTTP/1.1 and
+// prior knowledge h2c on the same port
+async fn version_echo_server() -> String {
+    let router = axum::Router::new().route(
+        "/",
+        axum::routing::get(|request: axum::extract::Request| async move {
+            format!("{:?}", request.version())
+        }),
+    );
+    format!("{}/", common::serve(router).await)
+}
+
+#[tokio::test]
+async fn test_h2c_host_negotiates_http2() {
+    let url = version_echo_server().await;
+    let http = UpstreamHttp::new(true, "127.0.0.1").unwrap();
+
+    let response = http.get(&url).send().await.unwrap();
+
+    assert_eq!(response.version(), reqwest::Version::HTTP_2);
+    assert_eq!(response.text().await.unwrap(), "HTTP/2.0");
+}
+
+#[tokio::test]
+async fn test_other_hosts_stay_on_http1() {
+    let url = version_echo_server().await;
+
+    for http in [
+        UpstreamHttp::new(true, "cdn.example.com").unwrap(),
+        UpstreamHttp::new(false, "").unwrap(),
+        UpstreamHttp::from(reqwest::Client::new()),
+    ] {
+        let response = http.get(&url).send().await.unwrap();
+
+        assert_eq!(response.version(), reqwest::Version::HTTP_11);
+        assert_eq!(response.text().await.unwrap(), "HTTP/1.1");
+    }
+}
+
+#[test]
+fn test_host_forced_to_ts_ignores_text_plain() {
+    let overrides = ContentTypeOverrides::parse_list(".badcdn.net:ts, cdn.example.com:m3u8");
+    let segment = [0x47u8; 188];
+
+    // without an override a text/plain body that starts with #EXT is a playlist
+    assert_eq!(BodyKind::detect("text/plain", b"#EXTM3U"), BodyKind::M3u8);
+    assert_eq!(
+        overrides.classify(
+            "https://edge1.badcdn.net/seg_001.ts",
+            "text/plain",
+            b"#EXTM3U"
+        ),
+        BodyKind::Ts
+    );
+    assert_eq!(
+        overrides.classify("https://badcdn.net/seg_001.ts", "text/plain", &segment),
+        BodyKind::Ts
+    );
+    assert_eq!(
+        overrides.classify(
+            "https://cdn.example.com/index",
+            "application/octet-stream",
+            b"not a playlist"
+        ),
+        BodyKind::M3u8
+    );
+}
+
+#[test]
+fn test_unconfigured_hosts_keep_normal_detection() {
+    let overrides = ContentTypeOverrides::parse_list(".badcdn.net:ts, broken, cdn.example.com:gif");
+
+    assert_eq!(
+        overrides.for_url("https://cdn.example.com/index.m3u8"),
+        None
+    );
+    assert_eq!(
+        overrides.classify("https://other.net/a.mp4", "video/mp4", b"...."),
+        BodyKind::Mp4
+    );
+    assert_eq!(
+        overrides.classify(
+            "https://other.net/index",
+            "application/vnd.apple.mpegurl",
+            b""
+        ),
+        BodyKind::M3u8
+    );
+    assert_eq!(
+        overrides.classify("https://other.net/seg.ts", "text/plain", &[0x47u8; 188]),
+        BodyKind::Ts
+    );
+}
+
+#[test]
+fn test_kind_from_headers_only_trusts_clear_content_types() {
+    let overrides = ContentTypeOverrides::parse_list(".badcdn.net:ts");
+
+    assert_eq!(
+        overrides.kind_from_headers("https://other.net/a.mp4", "video/mp4"),
+        Some(BodyKind::Mp4)
+    );
+    assert_eq!(
+        overrides.kind_from_headers("https://other.net/seg.ts", "video/mp2t"),
+        Some(BodyKind::Ts)
+    );
+    assert_eq!(
+        overrides.kind_from_headers("https://other.net/index", "application/x-mpegurl"),
+        Some(BodyKind::M3u8)
+    );
+    // these need the body to tell
+    assert_eq!(
+        overrides.kind_from_headers("https://other.net/seg", "application/octet-stream"),
+        None
+    );
+    assert_eq!(
+        overrides.kind_from_headers("https://other.net/seg", ""),
+        None
+    );
+    assert_eq!(
+        overrides.kind_from_headers("https://edge.badcdn.net/seg", "text/plain"),
+        Some(BodyKind::Ts)
+    );
+}
+
+// claims a 64MB segment, sends the first chunk and then never sends the rest
+async fn stalled_segment_server() -> String {
+    MockUpstream::always(
+        MockResponse::ok(vec![0x47u8; 188 * 100])
+            .header("content-type", "video/mp2t")
+            .stall_at(64 * 1024 * 1024),
+    )
+    .await
+    .url("/seg_001.ts")
+}
+
+#[tokio::test]
+async fn test_passthrough_forwards_before_the_body_is_complete() {
+    let url = stalled_segment_server().await;
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.content_length(), Some(64 * 1024 * 1024));
+
+    let mut body = upstream_utils::passthrough_body(response, ()).into_data_stream();
+
+    // collecting would wait on the other 64MB forever
+    let chunk = tokio::time::timeout(Duration::from_secs(5), body.next())
+        .await
+        .expect("first chunk should be forwarded while upstream is still sending")
+        .unwrap()
+        .unwrap();
+    assert!(!chunk.is_empty());
+    assert!(chunk.iter().all(|b| *b == 0x47));
+}
+
+#[tokio::test]
+async fn test_passthrough_holds_on_until_the_body_is_dropped() {
+    let url = stalled_segment_server().await;
+    let response = reqwest::get(&url).await.unwrap();
+    let permit = Arc::new(());
+
+    let mut body = upstream_utils::passthrough_body(response, permit.clone()).into_data_stream();
+    tokio::time::timeout(Duration::from_secs(5), body.next())
+        .await
+        .unwrap();
+    assert_eq!(Arc::strong_count(&permit), 2);
+
+    // the client going away lets go of it
+    drop(body);
+    assert_eq!(Arc::strong_count(&permit), 1);
+}
+
+#[test]
+fn test_allowed_hosts_are_proxied() {
+    let allowed = HostAllowlist::parse_list(".poocloud.in, cdn.example.com");
+
+    for url in [
+        "https://strm.poocloud.in/live/index.m3u8",
+        "https://poocloud.in/live/seg_001.ts",
+        "http://CDN.example.com/seg_001.ts",
+    ] {
+        assert!(validate_target(url, &allowed).is_ok(), "{}", url);
+    }
+}
+
+#[test]
+fn test_other_hosts_are_refused() {
+    let allowed = HostAllowlist::parse_list(".poocloud.in, cdn.example.com");
+
+    for url in [
+        "https://evil.example.org/index.m3u8",
+        "https://notpoocloud.in/seg_001.ts",
+        "https://poocloud.in.evil.org/seg_001.ts",
+        "https://sub.cdn.example.com/seg_001.ts",
+        "http://169.254.169.254/latest/meta-data",
+    ] {
+        assert!(
+            matches!(validate_target(url, &allowed), Err(Error::BadRequest(_))),
+            "{}",
+            url
+        );
+    }
+    // nothing configured means nothing is allowed
+    assert!(
+        validate_target(
+            "https://cdn.example.com/a.ts",
+            &HostAllowlist::parse_list("")
+        )
+        .is_err()
+    );
+}
+
+#[test]
+fn test_wildcard_allows_any_host_but_still_needs_http() {
+    let allowed = HostAllowlist::parse_list("*");
+
+    assert!(validate_target("https://anything.example.org/index.m3u8", &allowed).is_ok());
+    assert!(validate_target("http://10.0.0.1:8080/seg.ts", &allowed).is_ok());
+    assert!(matches!(
+        validate_target("file:///etc/passwd", &allowed),
+        Err(Error::BadRequest(_))
+    ));
+}
+
+// answers with `statuses` in order, one per connection, repeating the last one
+async fn flaky_server(statuses: Vec<u16>) -> (String, MockUpstream) {
+    let upstream = MockUpstream::start(
+        statuses
+            .into_iter()
+            .map(|status| MockResponse::status(status).body("ok"))
+            .collect(),
+    )
+    .await;
+    (upstream.url("/index.m3u8"), upstream)
+}
+
+fn quick_retry(max_retries: u32) -> UpstreamRetry {
+    UpstreamRetry::new(max_retries, Duration::from_millis(1))
+}
+
+#[tokio::test]
+async fn test_retry_gets_through_after_two_bad_gateways() {
+    let (url, upstream) = flaky_server(vec![503, 502, 200]).await;
+    let client = reqwest::Client::new();
+
+    let response = quick_retry(2)
+        .send(client.get(&url), |request| request.send())
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(upstream.hits(), 3);
+}
+
+#[tokio::test]
+async fn test_retry_gives_up_with_the_last_response() {
+    let (url, upstream) = flaky_server(vec![503, 503, 504]).await;
+    let client = reqwest::Client::new();
+
+    let response = quick_retry(2)
+        .send(client.get(&url), |request| request.send())
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 504);
+    assert_eq!(upstream.hits(), 3);
+}
+
+#[tokio::test]
+async fn test_client_errors_and_other_server_errors_are_not_retried() {
+    for status in [403, 404, 500] {
+        let (url, upstream) = flaky_server(vec![status, 200]).await;
+        let client = reqwest::Client::new();
+
+        let response = quick_retry(3)
+            .send(client.get(&url), |request| request.send())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), status);
+        assert_eq!(upstream.hits(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_connection_errors_are_retried() {
+    // nothing listens on the port once the listener is gone
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/seg_001.ts", listener.local_addr().unwrap());
+    drop(listener);
+
+    let client = reqwest::Client::new();
+    let tries = AtomicUsize::new(0);
+    let result = quick_retry(2)
+        .send(client.get(&url), |request| {
+            tries.fetch_add(1, Ordering::SeqCst);
+            request.send()
+        })
+        .await;
+
+    assert!(result.unwrap_err().is_connect());
+    assert_eq!(tries.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_retry_delay_doubles_with_jitter() {
+    let retry = UpstreamRetry::new(5, Duration::from_millis(100));
+
+    for (attempt, base) in [(1, 100), (2, 200), (3, 400)] {
+        let delay = retry.delay(attempt);
+        assert!(delay >= Duration::from_millis(base), "{:?}", delay);
+        assert!(
+            delay <= Duration::from_millis(base + base / 2),
+            "{:?}",
+            delay
+        );
+    }
+}
+
+#[test]
+fn test_schema_parses_known_names_only() {
+    assert_eq!("sports".parse::<Schema>().unwrap(), Schema::Sports);
+    assert_eq!("captions".parse::<Schema>().unwrap(), Schema::Captions);
+
+    for unknown in ["", "movie", "Sports", " sports", "sports,captions"] {
+        assert!(matches!(
+            unknown.parse::<Schema>(),
+            Err(Error::BadRequest(_))
+        ));
+    }
+
+    // round trips through the name the signed urls carry
+    for schema in [Schema::Sports, Schema::Captions] {
+        assert_eq!(schema.to_string().parse::<Schema>().unwrap(), schema);
+    }
+}
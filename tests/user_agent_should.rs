@@ -0,0 +1,152 @@
+mod common;
+
+use api::AppConfig;
+use api::server::error::Error;
+use api::server::extractors::{CheckedUserAgent, UserAgentClass, UserAgentPolicy};
+use api::server::services::edge_services::EdgeServices;
+use axum::extract::FromRequestParts;
+use axum::http::Request;
+
+const SAFARI_PLAYER: &str = "AppleCoreMedia/1.0.0.21E236 (iPhone; U; CPU OS 17_4 like Mac OS X)";
+const EXOPLAYER: &str = "ExoPlayerLib/2.19.1 (Linux; Android 14) okhttp/4.12.0";
+const GOOGLEBOT: &str = "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)";
+const FIREFOX: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+
+async fn services(enforce: bool, allow: &str, deny: &str) -> EdgeServices {
+    common::services(AppConfig {
+        enforce_user_agent_policy: enforce,
+        user_agent_allow_list: allow.to_string(),
+        user_agent_deny_list: deny.to_string(),
+        ..AppConfig::default()
+    })
+    .await
+}
+
+async fn check(services: &EdgeServices, user_agent: Option<&str>) -> Result<UserAgentClass, Error> {
+    let mut request = Request::builder().uri("/api/v1/proxy?url=x");
+    if let Some(user_agent) = user_agent {
+        request = request.header("user-agent", user_agent);
+    }
+    let (mut parts, _) = request.body(()).unwrap().into_parts();
+    parts.extensions.insert(services.clone());
+
+    CheckedUserAgent::from_request_parts(&mut parts, &())
+        .await
+        .map(|CheckedUserAgent(class)| class)
+}
+
+#[test]
+fn test_classifies_user_agents() {
+    assert_eq!(
+        UserAgentClass::classify(Some(SAFARI_PLAYER)),
+        UserAgentClass::Player
+    );
+    assert_eq!(
+        UserAgentClass::classify(Some(EXOPLAYER)),
+        UserAgentClass::Player
+    );
+    assert_eq!(
+        UserAgentClass::classify(Some("VLC/3.0.20 LibVLC/3.0.20")),
+        UserAgentClass::Player
+    );
+    assert_eq!(
+        UserAgentClass::classify(Some(GOOGLEBOT)),
+        UserAgentClass::Bot
+    );
+    assert_eq!(
+        UserAgentClass::classify(Some("curl/8.5.0")),
+        UserAgentClass::Bot
+    );
+    assert_eq!(
+        UserAgentClass::classify(Some("python-requests/2.31.0")),
+        UserAgentClass::Bot
+    );
+    assert_eq!(
+        UserAgentClass::classify(Some(FIREFOX)),
+        UserAgentClass::Other
+    );
+    // a phone, not a bot
+    assert_eq!(
+        UserAgentClass::classify(Some("Mozilla/5.0 (Linux; Android 10; CUBOT X30) Mobile")),
+        UserAgentClass::Other
+    );
+    assert_eq!(UserAgentClass::classify(None), UserAgentClass::Missing);
+    assert_eq!(
+        UserAgentClass::classify(Some("  ")),
+        UserAgentClass::Missing
+    );
+}
+
+#[tokio::test]
+async fn test_allows_players_and_browsers() {
+    let services = services(true, "", "").await;
+
+    assert_eq!(
+        check(&services, Some(SAFARI_PLAYER)).await.unwrap(),
+        UserAgentClass::Player
+    );
+    assert_eq!(
+        check(&services, Some(EXOPLAYER)).await.unwrap(),
+        UserAgentClass::Player
+    );
+    assert_eq!(
+        check(&services, Some(FIREFOX)).await.unwrap(),
+        UserAgentClass::Other
+    );
+}
+
+#[tokio::test]
+async fn test_refuses_a_missing_or_empty_user_agent() {
+    let services = services(true, "", "").await;
+
+    assert!(matches!(
+        check(&services, None).await,
+        Err(Error::Unauthorized)
+    ));
+    assert!(matches!(
+        check(&services, Some("")).await,
+        Err(Error::Unauthorized)
+    ));
+}
+
+#[tokio::test]
+async fn test_refuses_crawlers() {
+    let services = services(true, "", "").await;
+
+    assert!(matches!(
+        check(&services, Some(GOOGLEBOT)).await,
+        Err(Error::Unauthorized)
+    ));
+    assert!(matches!(
+        check(&services, Some("curl/8.5.0")).await,
+        Err(Error::Unauthorized)
+    ));
+}
+
+#[tokio::test]
+async fn test_lets_everything_through_when_off() {
+    let services = services(false, "", "").await;
+
+    assert_eq!(
+        check(&services, None).await.unwrap(),
+        UserAgentClass::Missing
+    );
+    assert_eq!(
+        check(&services, Some(GOOGLEBOT)).await.unwrap(),
+        UserAgentClass::Bot
+    );
+}
+
+#[test]
+fn test_allow_and_deny_lists_come_before_the_classes() {
+    let policy = UserAgentPolicy::new(true, "MonitoringBot/, ", "Firefox");
+
+    // allowed by name even though it's a bot
+    assert!(policy.allows(Some("MonitoringBot/1.0 (+https://status.example.com)")));
+    // refused by name even though it's a browser
+    assert!(!policy.allows(Some(FIREFOX)));
+    // the blank entry doesn't allow everything
+    assert!(!policy.allows(None));
+    assert!(!policy.allows(Some(GOOGLEBOT)));
+    assert!(policy.allows(Some(SAFARI_PLAYER)));
+}
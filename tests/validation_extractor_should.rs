@@ -0,0 +1,170 @@
+mod common;
+
+use api::AppConfig;
+use api::server::dtos::proxy_dto::{MAX_PROXY_URL_LEN, ProxyQuery};
+use api::server::error::Error;
+use api::server::extractors::{SchemaExtractor, ValidatedQuery};
+use api::server::services::edge_services::EdgeServices;
+use api::server::utils::upstream_utils::Schema;
+use axum::extract::FromRequestParts;
+use axum::http::Request;
+
+async fn services(strict_schema: bool) -> EdgeServices {
+    common::services(AppConfig {
+        strict_schema,
+        ..AppConfig::default()
+    })
+    .await
+}
+
+async fn schema(services: &EdgeServices, uri: &str) -> Result<Schema, Error> {
+    let (mut parts, _) = Request::builder().uri(uri).body(()).unwrap().into_parts();
+    parts.extensions.insert(services.clone());
+
+    SchemaExtractor::from_request_parts(&mut parts, &())
+        .await
+        .map(|SchemaExtractor(schema)| schema)
+}
+
+#[tokio::test]
+async fn test_known_schemas_are_accepted() {
+    for strict in [false, true] {
+        let services = services(strict).await;
+
+        assert_eq!(
+            schema(&services, "/api/v1/proxy?url=x&schema=sports")
+                .await
+                .unwrap(),
+            Schema::Sports
+        );
+        assert_eq!(
+            schema(&services, "/api/v1/proxy?url=x&schema=captions")
+                .await
+                .unwrap(),
+            Schema::Captions
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_missing_schema_is_sports() {
+    for strict in [false, true] {
+        let services = services(strict).await;
+
+        assert_eq!(
+            schema(&services, "/api/v1/proxy?url=x").await.unwrap(),
+            Schema::Sports
+        );
+        assert_eq!(
+            schema(&services, "/api/v1/proxy").await.unwrap(),
+            Schema::Sports
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_strict_mode_rejects_unknown_schemas() {
+    let services = services(true).await;
+
+    for uri in [
+        "/api/v1/proxy?url=x&schema=movie",
+        "/api/v1/proxy?url=x&schema=Sports",
+        "/api/v1/proxy?url=x&schema=",
+        "/api/v1/proxy?url=x&schema=%3Cscript%3E",
+    ] {
+        assert!(
+            matches!(schema(&services, uri).await, Err(Error::BadRequest(_))),
+            "{} was accepted",
+            uri
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_lenient_mode_falls_back_to_sports() {
+    let services = services(false).await;
+
+    assert_eq!(
+        schema(&services, "/api/v1/proxy?url=x&schema=movie")
+            .await
+            .unwrap(),
+        Schema::Sports
+    );
+    assert_eq!(
+        schema(&services, "/api/v1/proxy?url=x&schema=")
+            .await
+            .unwrap(),
+        Schema::Sports
+    );
+}
+
+async fn proxy_query(uri: &str) -> Result<ProxyQuery, Error> {
+    let (mut parts, _) = Request::builder().uri(uri).body(()).unwrap().into_parts();
+
+    ValidatedQuery::<ProxyQuery>::from_request_parts(&mut parts, &())
+        .await
+        .map(|ValidatedQuery(query)| query)
+}
+
+// the fields a validation error was about
+fn failed_fields(result: Result<ProxyQuery, Error>) -> Vec<String> {
+    let Err(Error::ValidationError(errors)) = result else {
+        panic!("expected a validation error");
+    };
+    let mut fields: Vec<String> = errors
+        .field_errors()
+        .keys()
+        .map(|field| field.to_string())
+        .collect();
+    fields.sort();
+    fields
+}
+
+#[tokio::test]
+async fn test_valid_proxy_query_is_extracted() {
+    let query = proxy_query(
+        "/api/v1/proxy?url=aHR0cHM6Ly9jZG4uZXhhbXBsZS5jb20&schema=sports&depth=1&live=true&type=playlist",
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(query.url, "aHR0cHM6Ly9jZG4uZXhhbXBsZS5jb20");
+    assert_eq!(query.schema.as_deref(), Some("sports"));
+    assert_eq!(query.depth, Some(1));
+    assert_eq!(query.live, Some(true));
+    assert_eq!(query.kind.as_deref(), Some("playlist"));
+    assert_eq!(query.stream, None);
+}
+
+#[tokio::test]
+async fn test_too_long_url_is_rejected() {
+    let url = "a".repeat(MAX_PROXY_URL_LEN as usize + 1);
+    let uri = format!("/api/v1/proxy?url={}", url);
+
+    assert_eq!(failed_fields(proxy_query(&uri).await), vec!["url"]);
+
+    // right at the limit is fine
+    let url = "a".repeat(MAX_PROXY_URL_LEN as usize);
+    let uri = format!("/api/v1/proxy?url={}", url);
+    assert!(proxy_query(&uri).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_empty_url_and_odd_schema_are_rejected() {
+    assert_eq!(
+        failed_fields(proxy_query("/api/v1/proxy?url=&schema=%3Cscript%3E").await),
+        vec!["schema", "url"]
+    );
+}
+
+#[tokio::test]
+async fn test_query_that_does_not_deserialize_is_a_bad_request() {
+    assert!(matches!(
+        proxy_query("/api/v1/proxy?schema=sports").await,
+        Err(Error::BadRequest(_))
+    ));
+    assert!(matches!(
+        proxy_query("/api/v1/proxy?url=x&depth=deep").await,
+        Err(Error::BadRequest(_))
+    ));
+}